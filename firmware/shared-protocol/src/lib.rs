@@ -4,39 +4,1323 @@
 pub const ANGLE_CLOSED: u8 = 90;
 pub const ANGLE_OPEN: u8 = 180;
 
+/// Schema version written at the front of top-level payloads (status
+/// beacons, poll reports, neighbor tables), so a coordinator in a mixed
+/// fleet can tell layouts apart before decoding. Payloads from firmware
+/// that predates the version byte read as version 0.
+pub const PROTOCOL_VERSION: u8 = 1;
+
 /// Vent operating states.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum VentState {
-    Open,
-    Closed,
-    Partial,
-    Moving,
+pub enum VentState {
+    Open,
+    Closed,
+    Partial,
+    Moving,
+}
+
+impl VentState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VentState::Open => "open",
+            VentState::Closed => "closed",
+            VentState::Partial => "partial",
+            VentState::Moving => "moving",
+        }
+    }
+
+    pub fn from_angle(angle: u8) -> Self {
+        match angle {
+            ANGLE_CLOSED => VentState::Closed,
+            ANGLE_OPEN => VentState::Open,
+            _ => VentState::Partial,
+        }
+    }
+
+    /// Wire code used by the compact binary payloads.
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            VentState::Open => 0,
+            VentState::Closed => 1,
+            VentState::Partial => 2,
+            VentState::Moving => 3,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(VentState::Open),
+            1 => Some(VentState::Closed),
+            2 => Some(VentState::Partial),
+            3 => Some(VentState::Moving),
+            _ => None,
+        }
+    }
+}
+
+/// Snapshot of the vent position, including the target of an in-progress move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VentStatus {
+    /// Current servo angle.
+    pub angle: u8,
+    /// Angle the vent is moving toward (equals `angle` when idle).
+    pub target_angle: u8,
+    pub state: VentState,
+    pub moving: bool,
+    /// Open fraction in thousandths (0 = closed, 1000 = open), independent
+    /// of the servo angle convention.
+    pub open_fraction_permille: u16,
+    /// Estimated time until `target_angle` is reached, 0 when idle.
+    pub eta_ms: u32,
+}
+
+/// Representation a client prefers for the vent position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    /// Servo angle in degrees.
+    #[default]
+    Angle,
+    /// Open fraction, 0–100.
+    PercentOpen,
+    /// Open fraction in thousandths, 0–1000.
+    Permille,
+}
+
+impl Units {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Units::Angle => "angle",
+            Units::PercentOpen => "percent_open",
+            Units::Permille => "permille",
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Units::Angle => 0,
+            Units::PercentOpen => 1,
+            Units::Permille => 2,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Units::Angle),
+            1 => Some(Units::PercentOpen),
+            2 => Some(Units::Permille),
+            _ => None,
+        }
+    }
+}
+
+/// How the vent should be presented by a hub: as a window covering or as
+/// a duct damper. Only changes the entity class chosen, not behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeviceType {
+    #[default]
+    Cover,
+    Damper,
+}
+
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceType::Cover => "cover",
+            DeviceType::Damper => "damper",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cover" => Some(DeviceType::Cover),
+            "damper" => Some(DeviceType::Damper),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            DeviceType::Cover => 0,
+            DeviceType::Damper => 1,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(DeviceType::Cover),
+            1 => Some(DeviceType::Damper),
+            _ => None,
+        }
+    }
+}
+
+/// How far a reported position can be trusted, so a coordinator can
+/// weight automation decisions on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Confidence {
+    /// After a power-loss recovery or a timed-out move.
+    Low,
+    /// Open loop: the last move finished cleanly but nothing sensed it.
+    #[default]
+    Medium,
+    /// Position feedback confirms the angle.
+    High,
+}
+
+impl Confidence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Confidence::Low => 0,
+            Confidence::Medium => 1,
+            Confidence::High => 2,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Confidence::Low),
+            1 => Some(Confidence::Medium),
+            2 => Some(Confidence::High),
+            _ => None,
+        }
+    }
+}
+
+/// Canonical position value in the configured units, tagged so a minimal
+/// client knows how to read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionReport {
+    pub units: Units,
+    pub value: u16,
+    pub confidence: Confidence,
+}
+
+impl PositionReport {
+    /// Report `status` in `units`. Percent is derived from the permille
+    /// open fraction, so a remapped midpoint carries through. Confidence
+    /// starts at the open-loop default.
+    pub fn from_status(status: &VentStatus, units: Units) -> Self {
+        let value = match units {
+            Units::Angle => status.angle as u16,
+            Units::PercentOpen => (status.open_fraction_permille + 5) / 10,
+            Units::Permille => status.open_fraction_permille,
+        };
+        Self {
+            units,
+            value,
+            confidence: Confidence::default(),
+        }
+    }
+
+    pub fn with_confidence(mut self, confidence: Confidence) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    pub const ENCODED_LEN: usize = 4;
+
+    /// Encoded as units, the value as u16 LE, then confidence.
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let value = self.value.to_le_bytes();
+        [self.units.as_u8(), value[0], value[1], self.confidence.as_u8()]
+    }
+
+    /// Decode a report. None for a short buffer or an unknown units or
+    /// confidence byte.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() < Self::ENCODED_LEN {
+            return None;
+        }
+        Some(Self {
+            units: Units::from_u8(raw[0])?,
+            value: u16::from_le_bytes([raw[1], raw[2]]),
+            confidence: Confidence::from_u8(raw[3])?,
+        })
+    }
+}
+
+/// Field indices a client wants in a thin response, e.g. from a
+/// `fields=0,2,5` Uri-Query. Indices follow the struct's declaration order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldMask(u8);
+
+impl FieldMask {
+    /// Every field, for clients that don't send a selector.
+    pub const ALL: FieldMask = FieldMask(0xff);
+
+    pub fn from_bits(bits: u8) -> Self {
+        FieldMask(bits)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn includes(&self, index: u8) -> bool {
+        index < 8 && self.0 & (1 << index) != 0
+    }
+
+    /// Parse a `fields=` query value. None if the key is wrong, the list is
+    /// empty, or an index is not a number below 8.
+    pub fn parse_query(query: &str) -> Option<Self> {
+        let list = query.strip_prefix("fields=")?;
+        let mut bits = 0u8;
+        for item in list.split(',') {
+            let index: u8 = item.trim().parse().ok()?;
+            if index >= 8 {
+                return None;
+            }
+            bits |= 1 << index;
+        }
+        Some(FieldMask(bits))
+    }
+}
+
+/// A `VentStatus` decoded from a thin response; unselected fields are None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PartialVentStatus {
+    pub angle: Option<u8>,
+    pub target_angle: Option<u8>,
+    pub state: Option<VentState>,
+    pub moving: Option<bool>,
+    pub open_fraction_permille: Option<u16>,
+    pub eta_ms: Option<u32>,
+}
+
+impl VentStatus {
+    /// Bits for the six fields, indices 0–5 in declaration order.
+    pub const FIELD_BITS: u8 = 0x3f;
+
+    /// Encode only the fields in `mask`: a byte holding the included bits,
+    /// then each included field in index order (u16/u32 little-endian).
+    #[cfg(feature = "std")]
+    pub fn to_bytes_selected(&self, mask: FieldMask) -> Vec<u8> {
+        let bits = mask.bits() & Self::FIELD_BITS;
+        let mut out = vec![bits];
+        let mask = FieldMask::from_bits(bits);
+        if mask.includes(0) {
+            out.push(self.angle);
+        }
+        if mask.includes(1) {
+            out.push(self.target_angle);
+        }
+        if mask.includes(2) {
+            out.push(self.state.as_u8());
+        }
+        if mask.includes(3) {
+            out.push(self.moving as u8);
+        }
+        if mask.includes(4) {
+            out.extend_from_slice(&self.open_fraction_permille.to_le_bytes());
+        }
+        if mask.includes(5) {
+            out.extend_from_slice(&self.eta_ms.to_le_bytes());
+        }
+        out
+    }
+}
+
+impl PartialVentStatus {
+    /// Decode a thin response. None for a short buffer or an invalid state
+    /// byte.
+    ///
+    /// Field bits this build doesn't know are skipped rather than rejected:
+    /// newer fields take higher indices, so their bytes follow every known
+    /// field and can be left unread. This keeps older firmware and
+    /// coordinators compatible across a rolling upgrade.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let (&bits, mut rest) = raw.split_first()?;
+        let mask = FieldMask::from_bits(bits & VentStatus::FIELD_BITS);
+        let mut take = |n: usize| -> Option<&[u8]> {
+            if rest.len() < n {
+                return None;
+            }
+            let (field, tail) = rest.split_at(n);
+            rest = tail;
+            Some(field)
+        };
+        let mut status = PartialVentStatus::default();
+        if mask.includes(0) {
+            status.angle = Some(take(1)?[0]);
+        }
+        if mask.includes(1) {
+            status.target_angle = Some(take(1)?[0]);
+        }
+        if mask.includes(2) {
+            status.state = Some(VentState::from_u8(take(1)?[0])?);
+        }
+        if mask.includes(3) {
+            status.moving = Some(take(1)?[0] != 0);
+        }
+        if mask.includes(4) {
+            let b = take(2)?;
+            status.open_fraction_permille = Some(u16::from_le_bytes([b[0], b[1]]));
+        }
+        if mask.includes(5) {
+            let b = take(4)?;
+            status.eta_ms = Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        }
+        Some(status)
+    }
+}
+
+/// Firmware build metadata, for pinpointing exactly which build a vent runs.
+///
+/// Encoded as `PROTOCOL_VERSION`, the build timestamp (u64 LE), then the
+/// firmware version, git hash and target chip, each as a length byte and
+/// UTF-8 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildInfo<'a> {
+    pub firmware_version: &'a str,
+    /// Short git commit hash, or "unknown" outside a git checkout.
+    pub git_hash: &'a str,
+    /// Build time as Unix seconds.
+    pub build_timestamp: u64,
+    pub target_chip: &'a str,
+}
+
+impl<'a> BuildInfo<'a> {
+    /// Strings longer than 255 bytes are truncated.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(9 + 3 + 32);
+        out.push(PROTOCOL_VERSION);
+        out.extend_from_slice(&self.build_timestamp.to_le_bytes());
+        for field in [self.firmware_version, self.git_hash, self.target_chip] {
+            let bytes = &field.as_bytes()[..field.len().min(u8::MAX as usize)];
+            out.push(bytes.len() as u8);
+            out.extend_from_slice(bytes);
+        }
+        out
+    }
+
+    /// Decode build info. None for an unknown version, a length that
+    /// overruns the buffer, a non-UTF-8 string or trailing bytes.
+    pub fn from_bytes(raw: &'a [u8]) -> Option<Self> {
+        let (&version, rest) = raw.split_first()?;
+        if version != PROTOCOL_VERSION {
+            return None;
+        }
+        let build_timestamp = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?);
+        let mut rest = &rest[8..];
+        let mut fields = [""; 3];
+        for field in fields.iter_mut() {
+            let (&len, tail) = rest.split_first()?;
+            *field = core::str::from_utf8(tail.get(..len as usize)?).ok()?;
+            rest = &tail[len as usize..];
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        let [firmware_version, git_hash, target_chip] = fields;
+        Some(Self {
+            firmware_version,
+            git_hash,
+            build_timestamp,
+            target_chip,
+        })
+    }
+}
+
+impl core::fmt::Display for BuildInfo<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "v{} ({}, {}, built {})",
+            self.firmware_version, self.git_hash, self.target_chip, self.build_timestamp
+        )
+    }
+}
+
+/// Duct differential pressure, as reported to clients.
+///
+/// Encoded as `PROTOCOL_VERSION`, pascals (i32 LE), age (u32 LE), a flag
+/// byte set when pressure hold is active, then the target (i32 LE, 0 when
+/// the flag is clear).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PressureData {
+    pub pascals: i32,
+    /// Milliseconds since the reading was taken.
+    pub age_ms: u32,
+    /// Pressure the vent is modulating toward, if pressure hold is active.
+    pub target_pa: Option<i32>,
+}
+
+impl PressureData {
+    pub const ENCODED_LEN: usize = 14;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut out = [0u8; Self::ENCODED_LEN];
+        out[0] = PROTOCOL_VERSION;
+        out[1..5].copy_from_slice(&self.pascals.to_le_bytes());
+        out[5..9].copy_from_slice(&self.age_ms.to_le_bytes());
+        out[9] = self.target_pa.is_some() as u8;
+        out[10..14].copy_from_slice(&self.target_pa.unwrap_or(0).to_le_bytes());
+        out
+    }
+
+    /// Decode a reading. None for a buffer of the wrong length or an
+    /// unknown version.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != Self::ENCODED_LEN || raw[0] != PROTOCOL_VERSION {
+            return None;
+        }
+        let target = i32::from_le_bytes([raw[10], raw[11], raw[12], raw[13]]);
+        Some(Self {
+            pascals: i32::from_le_bytes([raw[1], raw[2], raw[3], raw[4]]),
+            age_ms: u32::from_le_bytes([raw[5], raw[6], raw[7], raw[8]]),
+            target_pa: (raw[9] != 0).then_some(target),
+        })
+    }
+}
+
+/// Service-scheduling data: when the vent was installed and how long it
+/// has been powered.
+///
+/// Encoded as `PROTOCOL_VERSION`, a flag byte set when the install time is
+/// known, the install time (u64 LE, 0 when unknown), then the runtime
+/// hours (u32 LE).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceInfo {
+    /// First-commission time as Unix seconds, or None if the clock had not
+    /// been set by then.
+    pub install_unix_s: Option<u64>,
+    /// Total powered-on hours across reboots.
+    pub runtime_hours: u32,
+}
+
+impl MaintenanceInfo {
+    pub const ENCODED_LEN: usize = 14;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut out = [0u8; Self::ENCODED_LEN];
+        out[0] = PROTOCOL_VERSION;
+        out[1] = self.install_unix_s.is_some() as u8;
+        out[2..10].copy_from_slice(&self.install_unix_s.unwrap_or(0).to_le_bytes());
+        out[10..14].copy_from_slice(&self.runtime_hours.to_le_bytes());
+        out
+    }
+
+    /// Decode maintenance data. None for a buffer of the wrong length or
+    /// an unknown version.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != Self::ENCODED_LEN || raw[0] != PROTOCOL_VERSION {
+            return None;
+        }
+        let install = u64::from_le_bytes(raw[2..10].try_into().ok()?);
+        Some(Self {
+            install_unix_s: (raw[1] != 0).then_some(install),
+            runtime_hours: u32::from_le_bytes([raw[10], raw[11], raw[12], raw[13]]),
+        })
+    }
+}
+
+/// Hardware and mesh addresses, for matching a vent in the border router's
+/// topology to its label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceAddresses {
+    /// Base MAC burned into eFuse.
+    pub efuse_mac: [u8; 6],
+    /// EUI-64 the device identifies itself by.
+    pub eui64: [u8; 8],
+    /// OpenThread extended address, which may be randomized per network;
+    /// None before the stack is up.
+    pub thread_ext_addr: Option<[u8; 8]>,
+}
+
+impl DeviceAddresses {
+    pub const ENCODED_LEN: usize = 23;
+
+    /// Encoded as the MAC, the EUI-64, a byte that is 1 when the extended
+    /// address follows, then the extended address (zeros when absent).
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut out = [0u8; Self::ENCODED_LEN];
+        out[..6].copy_from_slice(&self.efuse_mac);
+        out[6..14].copy_from_slice(&self.eui64);
+        if let Some(ext) = self.thread_ext_addr {
+            out[14] = 1;
+            out[15..].copy_from_slice(&ext);
+        }
+        out
+    }
+
+    /// Decode addresses. None for a short buffer.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() < Self::ENCODED_LEN {
+            return None;
+        }
+        let mut efuse_mac = [0u8; 6];
+        efuse_mac.copy_from_slice(&raw[..6]);
+        let mut eui64 = [0u8; 8];
+        eui64.copy_from_slice(&raw[6..14]);
+        let thread_ext_addr = (raw[14] != 0).then(|| {
+            let mut ext = [0u8; 8];
+            ext.copy_from_slice(&raw[15..23]);
+            ext
+        });
+        Some(Self {
+            efuse_mac,
+            eui64,
+            thread_ext_addr,
+        })
+    }
+}
+
+/// Periodic multicast status beacon for passive fleet monitoring.
+///
+/// Encoded as 6 fixed bytes: version, angle, state, moving, battery
+/// percent (0xFF = unknown), RSSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusBeacon {
+    pub angle: u8,
+    pub state: VentState,
+    pub moving: bool,
+    /// None when running from USB or the level is unknown.
+    pub battery_pct: Option<u8>,
+    /// Parent link RSSI in dBm.
+    pub rssi: i8,
+}
+
+impl StatusBeacon {
+    pub const VERSION: u8 = PROTOCOL_VERSION;
+    pub const ENCODED_LEN: usize = 6;
+
+    /// Schema version of an encoded beacon, without decoding the rest.
+    pub fn peek_version(raw: &[u8]) -> Option<u8> {
+        raw.first().copied()
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        [
+            Self::VERSION,
+            self.angle,
+            self.state.as_u8(),
+            self.moving as u8,
+            self.battery_pct.unwrap_or(0xff),
+            self.rssi as u8,
+        ]
+    }
+
+    /// Decode a beacon. None for a short buffer, an unknown version or an
+    /// invalid state byte.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() < Self::ENCODED_LEN || raw[0] != Self::VERSION {
+            return None;
+        }
+        Some(Self {
+            angle: raw[1],
+            state: VentState::from_u8(raw[2])?,
+            moving: raw[3] != 0,
+            battery_pct: if raw[4] == 0xff { None } else { Some(raw[4]) },
+            rssi: raw[5] as i8,
+        })
+    }
+}
+
+/// Minimal health snapshot for sleepy devices, where every byte on the
+/// radio costs wake time.
+///
+/// Encoded as 3 fixed bytes: battery percent (0xFF = unknown), RSSI, state.
+/// Fault flags ride in the state byte's top bits, which older firmware
+/// always leaves clear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthMin {
+    pub battery_pct: Option<u8>,
+    /// Parent link RSSI in dBm.
+    pub rssi: i8,
+    pub state: VentState,
+    /// The switched servo rail failed to report power-good.
+    pub servo_power_fault: bool,
+    /// The wall clock is unsynced or stale, so schedules are paused.
+    pub clock_unsynced: bool,
+    /// Booted in minimal mode after a crash loop.
+    pub crash_loop: bool,
+    /// Matter failed to start; the vent is reachable over CoAP only.
+    pub matter_unavailable: bool,
+}
+
+impl HealthMin {
+    pub const ENCODED_LEN: usize = 3;
+    const FLAG_SERVO_POWER_FAULT: u8 = 0x80;
+    const FLAG_CLOCK_UNSYNCED: u8 = 0x40;
+    const FLAG_CRASH_LOOP: u8 = 0x20;
+    const FLAG_MATTER_UNAVAILABLE: u8 = 0x10;
+    const STATE_MASK: u8 = 0x0f;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut state = self.state.as_u8();
+        if self.servo_power_fault {
+            state |= Self::FLAG_SERVO_POWER_FAULT;
+        }
+        if self.clock_unsynced {
+            state |= Self::FLAG_CLOCK_UNSYNCED;
+        }
+        if self.crash_loop {
+            state |= Self::FLAG_CRASH_LOOP;
+        }
+        if self.matter_unavailable {
+            state |= Self::FLAG_MATTER_UNAVAILABLE;
+        }
+        [self.battery_pct.unwrap_or(0xff), self.rssi as u8, state]
+    }
+
+    /// Decode a snapshot. None for a short buffer or an invalid state byte.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() < Self::ENCODED_LEN {
+            return None;
+        }
+        Some(Self {
+            battery_pct: if raw[0] == 0xff { None } else { Some(raw[0]) },
+            rssi: raw[1] as i8,
+            state: VentState::from_u8(raw[2] & Self::STATE_MASK)?,
+            servo_power_fault: raw[2] & Self::FLAG_SERVO_POWER_FAULT != 0,
+            clock_unsynced: raw[2] & Self::FLAG_CLOCK_UNSYNCED != 0,
+            crash_loop: raw[2] & Self::FLAG_CRASH_LOOP != 0,
+            matter_unavailable: raw[2] & Self::FLAG_MATTER_UNAVAILABLE != 0,
+        })
+    }
+}
+
+impl HealthMin {
+    /// Bits for the three bytes, indices 0–2 in encoding order. The fault
+    /// flags travel with the state byte.
+    pub const FIELD_BITS: u8 = 0x07;
+
+    /// Encode only the bytes in `mask`, after a byte holding the included
+    /// bits, the same layout as `VentStatus::to_bytes_selected`.
+    #[cfg(feature = "std")]
+    pub fn to_bytes_selected(&self, mask: FieldMask) -> Vec<u8> {
+        let bits = mask.bits() & Self::FIELD_BITS;
+        let full = self.to_bytes();
+        let mut out = vec![bits];
+        out.extend((0..3).filter(|&i| FieldMask::from_bits(bits).includes(i)).map(|i| full[i as usize]));
+        out
+    }
+}
+
+/// A `HealthMin` decoded from a thin response; unselected fields are None.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PartialHealthMin {
+    /// `Some(None)` when the battery byte was sent as unknown.
+    pub battery_pct: Option<Option<u8>>,
+    pub rssi: Option<i8>,
+    pub state: Option<VentState>,
+    pub servo_power_fault: Option<bool>,
+    pub clock_unsynced: Option<bool>,
+    pub crash_loop: Option<bool>,
+    pub matter_unavailable: Option<bool>,
+}
+
+impl PartialHealthMin {
+    /// Decode a thin response. None for a short buffer or an invalid state
+    /// byte. Unknown field bits are skipped, as in `PartialVentStatus`.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let (&bits, rest) = raw.split_first()?;
+        let mask = FieldMask::from_bits(bits & HealthMin::FIELD_BITS);
+        let mut rest = rest.iter().copied();
+        let mut health = PartialHealthMin::default();
+        if mask.includes(0) {
+            let b = rest.next()?;
+            health.battery_pct = Some(if b == 0xff { None } else { Some(b) });
+        }
+        if mask.includes(1) {
+            health.rssi = Some(rest.next()? as i8);
+        }
+        if mask.includes(2) {
+            let b = rest.next()?;
+            health.state = Some(VentState::from_u8(b & HealthMin::STATE_MASK)?);
+            health.servo_power_fault = Some(b & HealthMin::FLAG_SERVO_POWER_FAULT != 0);
+            health.clock_unsynced = Some(b & HealthMin::FLAG_CLOCK_UNSYNCED != 0);
+            health.crash_loop = Some(b & HealthMin::FLAG_CRASH_LOOP != 0);
+            health.matter_unavailable = Some(b & HealthMin::FLAG_MATTER_UNAVAILABLE != 0);
+        }
+        Some(health)
+    }
+}
+
+/// Position and health a sleepy vent pushes to its coordinator after each
+/// poll, so the coordinator stays current without an Observe.
+///
+/// Encoded as 6 fixed bytes: `PROTOCOL_VERSION`, angle, the `HealthMin`
+/// bytes, then the position confidence. Version 0 reports lack the
+/// leading version byte, and earlier version 1 reports the confidence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollReport {
+    pub angle: u8,
+    pub health: HealthMin,
+    /// How far `angle` can be trusted; None from senders that predate it.
+    pub confidence: Option<Confidence>,
+}
+
+impl PollReport {
+    pub const ENCODED_LEN: usize = 3 + HealthMin::ENCODED_LEN;
+    /// Length of a version 0 report.
+    const V0_LEN: usize = 1 + HealthMin::ENCODED_LEN;
+    /// Length of a version 1 report without the confidence byte.
+    const V1_MIN_LEN: usize = 2 + HealthMin::ENCODED_LEN;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let health = self.health.to_bytes();
+        let confidence = self.confidence.map_or(0xff, |c| c.as_u8());
+        [PROTOCOL_VERSION, self.angle, health[0], health[1], health[2], confidence]
+    }
+
+    /// Schema version of an encoded report, without decoding the rest.
+    /// None if the length fits no known layout.
+    pub fn peek_version(raw: &[u8]) -> Option<u8> {
+        match raw.len() {
+            Self::V0_LEN => Some(0),
+            n if n >= Self::V1_MIN_LEN => Some(raw[0]),
+            _ => None,
+        }
+    }
+
+    /// Decode a report of any layout. None for a short buffer, a version
+    /// this build doesn't know or an invalid state byte; an unknown
+    /// confidence byte decodes as None.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let body = match Self::peek_version(raw)? {
+            0 => raw,
+            PROTOCOL_VERSION => &raw[1..],
+            _ => return None,
+        };
+        Some(Self {
+            angle: body[0],
+            health: HealthMin::from_bytes(&body[1..])?,
+            confidence: body.get(1 + HealthMin::ENCODED_LEN).copied().and_then(Confidence::from_u8),
+        })
+    }
+}
+
+/// Outcome of a target command, so a client can tell an accepted move
+/// from a clamped, no-op or refused one by the response alone.
+///
+/// Encoded as 5 fixed bytes: `PROTOCOL_VERSION`, target, previous target,
+/// current angle, accepted flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetResponse {
+    /// Target after clamping; the requested angle when refused or queued.
+    pub target: u8,
+    /// Servo angle before the command.
+    pub previous: u8,
+    /// Servo angle when the command was handled.
+    pub current: u8,
+    /// Whether the target was taken, or queued for after quiet hours.
+    pub accepted: bool,
+}
+
+impl TargetResponse {
+    pub const ENCODED_LEN: usize = 5;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        [PROTOCOL_VERSION, self.target, self.previous, self.current, self.accepted as u8]
+    }
+
+    /// Decode a response. None for a buffer of the wrong length or an
+    /// unknown version.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != Self::ENCODED_LEN || raw[0] != PROTOCOL_VERSION {
+            return None;
+        }
+        Some(Self {
+            target: raw[1],
+            previous: raw[2],
+            current: raw[3],
+            accepted: raw[4] != 0,
+        })
+    }
+}
+
+/// A target command. `min_fw_required`, when set, asks the device to
+/// refuse the command unless its firmware is at least that version.
+///
+/// Encoded as `PROTOCOL_VERSION`, the angle, then a length byte and the
+/// UTF-8 version string (length 0 = no requirement), then optionally an
+/// 8-byte little-endian idempotency token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetRequest<'a> {
+    pub angle: u8,
+    pub min_fw_required: Option<&'a str>,
+    /// Set by a client that may retry; a repeat of a processed token gets
+    /// the original result instead of a second move.
+    pub idempotency_token: Option<u64>,
+}
+
+impl<'a> TargetRequest<'a> {
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let fw = self.min_fw_required.unwrap_or("").as_bytes();
+        let fw = &fw[..fw.len().min(u8::MAX as usize)];
+        let mut out = Vec::with_capacity(3 + fw.len() + 8);
+        out.extend_from_slice(&[PROTOCOL_VERSION, self.angle, fw.len() as u8]);
+        out.extend_from_slice(fw);
+        if let Some(token) = self.idempotency_token {
+            out.extend_from_slice(&token.to_le_bytes());
+        }
+        out
+    }
+
+    /// Decode a request. A bare version and angle (no length byte) has no
+    /// requirement or token. None for a short buffer, an unknown version, a
+    /// length that overruns the buffer, a non-UTF-8 version string or a
+    /// token that isn't 8 bytes.
+    pub fn from_bytes(raw: &'a [u8]) -> Option<Self> {
+        let (&version, rest) = raw.split_first()?;
+        if version != PROTOCOL_VERSION {
+            return None;
+        }
+        let (&angle, rest) = rest.split_first()?;
+        let (min_fw_required, rest) = match rest.split_first() {
+            None => (None, rest),
+            Some((&len, tail)) => {
+                let fw = tail.get(..len as usize)?;
+                let fw = if len == 0 { None } else { Some(core::str::from_utf8(fw).ok()?) };
+                (fw, &tail[len as usize..])
+            }
+        };
+        let idempotency_token = match rest.len() {
+            0 => None,
+            8 => Some(u64::from_le_bytes(rest.try_into().ok()?)),
+            _ => return None,
+        };
+        Some(Self {
+            angle,
+            min_fw_required,
+            idempotency_token,
+        })
+    }
+}
+
+/// Split a "1.2.3", "v1.2" or "1.2.3-rc1+build" version into its numeric
+/// core (missing parts are 0) and pre-release tag. Build metadata is ignored.
+fn parse_version(v: &str) -> Option<([u32; 3], Option<&str>)> {
+    let v = v.trim();
+    let v = v.strip_prefix(['v', 'V']).unwrap_or(v);
+    let v = v.split('+').next().unwrap_or(v);
+    let (core, pre) = match v.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (v, None),
+    };
+    let mut nums = [0u32; 3];
+    let mut parts = core.split('.');
+    for (i, part) in parts.by_ref().enumerate() {
+        if i >= nums.len() {
+            return None;
+        }
+        nums[i] = part.parse().ok()?;
+    }
+    Some((nums, pre))
+}
+
+/// Order two dot-separated pre-release identifiers: numbers numerically
+/// and below words, words by their letters and then any trailing number,
+/// so `rc9` sorts before `rc10`.
+fn cmp_identifier(a: &str, b: &str) -> core::cmp::Ordering {
+    fn split(id: &str) -> (&str, Option<u64>) {
+        let word = id.trim_end_matches(|c: char| c.is_ascii_digit());
+        (word, id[word.len()..].parse().ok())
+    }
+    let (a_word, a_num) = split(a);
+    let (b_word, b_num) = split(b);
+    b_word
+        .is_empty()
+        .cmp(&a_word.is_empty())
+        .then_with(|| a_word.cmp(b_word))
+        .then_with(|| a_num.cmp(&b_num))
+}
+
+/// Order two pre-release tags identifier by identifier; a tag that runs
+/// out first sorts first.
+fn cmp_prerelease(a: &str, b: &str) -> core::cmp::Ordering {
+    let mut a_ids = a.split('.');
+    let mut b_ids = b.split('.');
+    loop {
+        match (a_ids.next(), b_ids.next()) {
+            (None, None) => return core::cmp::Ordering::Equal,
+            (None, Some(_)) => return core::cmp::Ordering::Less,
+            (Some(_), None) => return core::cmp::Ordering::Greater,
+            (Some(x), Some(y)) => match cmp_identifier(x, y) {
+                core::cmp::Ordering::Equal => {}
+                ord => return ord,
+            },
+        }
+    }
+}
+
+/// Whether firmware `running` is at least `required`, comparing
+/// major.minor.patch numerically. A pre-release sorts before its release.
+/// Either version failing to parse counts as not satisfied, so a device
+/// can't be talked past a check it doesn't understand.
+pub fn fw_satisfies(running: &str, required: &str) -> bool {
+    let (Some((run, run_pre)), Some((req, req_pre))) = (parse_version(running), parse_version(required)) else {
+        return false;
+    };
+    match run.cmp(&req) {
+        core::cmp::Ordering::Greater => true,
+        core::cmp::Ordering::Less => false,
+        core::cmp::Ordering::Equal => match (run_pre, req_pre) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(r), Some(q)) => cmp_prerelease(r, q).is_ge(),
+        },
+    }
+}
+
+/// How a neighbor relates to this node in the Thread mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborRole {
+    Router,
+    /// Rx-on-when-idle child.
+    Child,
+    /// Sleepy end device child.
+    SleepyChild,
+}
+
+impl NeighborRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NeighborRole::Router => "router",
+            NeighborRole::Child => "child",
+            NeighborRole::SleepyChild => "sleepy_child",
+        }
+    }
+}
+
+/// One entry of a router's neighbor table, for mesh topology mapping.
+///
+/// Encoded as 5 fixed bytes: RLOC16 (big-endian), average RSSI, incoming
+/// link quality, role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborInfo {
+    pub rloc16: u16,
+    /// Average RSSI in dBm.
+    pub rssi: i8,
+    /// Incoming link quality, 0–3.
+    pub lqi: u8,
+    pub role: NeighborRole,
+}
+
+impl NeighborInfo {
+    pub const ENCODED_LEN: usize = 5;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let rloc = self.rloc16.to_be_bytes();
+        let role = match self.role {
+            NeighborRole::Router => 0,
+            NeighborRole::Child => 1,
+            NeighborRole::SleepyChild => 2,
+        };
+        [rloc[0], rloc[1], self.rssi as u8, self.lqi, role]
+    }
+
+    /// Decode one entry. None for a short buffer or an unknown role.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() < Self::ENCODED_LEN {
+            return None;
+        }
+        let role = match raw[4] {
+            0 => NeighborRole::Router,
+            1 => NeighborRole::Child,
+            2 => NeighborRole::SleepyChild,
+            _ => return None,
+        };
+        Some(Self {
+            rloc16: u16::from_be_bytes([raw[0], raw[1]]),
+            rssi: raw[2] as i8,
+            lqi: raw[3],
+            role,
+        })
+    }
+}
+
+/// Encode a neighbor table as `PROTOCOL_VERSION`, a count byte, then the
+/// entries. Tables longer than 255 entries are truncated.
+#[cfg(feature = "std")]
+pub fn encode_neighbors(neighbors: &[NeighborInfo]) -> Vec<u8> {
+    let count = neighbors.len().min(u8::MAX as usize);
+    let mut out = Vec::with_capacity(2 + count * NeighborInfo::ENCODED_LEN);
+    out.push(PROTOCOL_VERSION);
+    out.push(count as u8);
+    for n in &neighbors[..count] {
+        out.extend_from_slice(&n.to_bytes());
+    }
+    out
+}
+
+/// Schema version of an encoded neighbor table, without decoding it.
+/// Entries are a fixed size, so a version 0 table (count byte first) is
+/// told apart by its length. None if the length fits no known layout.
+pub fn neighbors_version(raw: &[u8]) -> Option<u8> {
+    match raw.len() % NeighborInfo::ENCODED_LEN {
+        1 => Some(0),
+        2 => Some(raw[0]),
+        _ => None,
+    }
+}
+
+/// Decode a table written by `encode_neighbors`, of either version. None
+/// for an unknown version, a length that doesn't match the count or an
+/// invalid entry.
+#[cfg(feature = "std")]
+pub fn decode_neighbors(raw: &[u8]) -> Option<Vec<NeighborInfo>> {
+    let body = match neighbors_version(raw)? {
+        0 => raw,
+        PROTOCOL_VERSION => &raw[1..],
+        _ => return None,
+    };
+    let (&count, entries) = body.split_first()?;
+    if entries.len() != count as usize * NeighborInfo::ENCODED_LEN {
+        return None;
+    }
+    entries
+        .chunks_exact(NeighborInfo::ENCODED_LEN)
+        .map(NeighborInfo::from_bytes)
+        .collect()
+}
+
+/// A Thread network heard during an active scan, for diagnosing a vent
+/// that can't join (e.g. its dataset names the wrong channel).
+///
+/// Encoded as 20 fixed bytes: channel, PAN ID (big-endian), RSSI, then the
+/// network name zero-padded to 16 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanResult {
+    pub channel: u8,
+    pub panid: u16,
+    /// Beacon RSSI in dBm.
+    pub rssi: i8,
+    network_name: [u8; ScanResult::NAME_LEN],
+}
+
+impl ScanResult {
+    pub const ENCODED_LEN: usize = 4 + Self::NAME_LEN;
+    /// Longest Thread network name, in bytes.
+    pub const NAME_LEN: usize = 16;
+
+    /// A result for `name`, truncated to `NAME_LEN` bytes.
+    pub fn new(channel: u8, panid: u16, rssi: i8, name: &[u8]) -> Self {
+        let mut network_name = [0u8; Self::NAME_LEN];
+        let len = name.len().min(Self::NAME_LEN);
+        network_name[..len].copy_from_slice(&name[..len]);
+        Self {
+            channel,
+            panid,
+            rssi,
+            network_name,
+        }
+    }
+
+    /// Network name, or "" if it isn't valid UTF-8.
+    pub fn name(&self) -> &str {
+        let len = self.network_name.iter().position(|&b| b == 0).unwrap_or(Self::NAME_LEN);
+        core::str::from_utf8(&self.network_name[..len]).unwrap_or("")
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut out = [0u8; Self::ENCODED_LEN];
+        out[0] = self.channel;
+        out[1..3].copy_from_slice(&self.panid.to_be_bytes());
+        out[3] = self.rssi as u8;
+        out[4..].copy_from_slice(&self.network_name);
+        out
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != Self::ENCODED_LEN {
+            return None;
+        }
+        let mut network_name = [0u8; Self::NAME_LEN];
+        network_name.copy_from_slice(&raw[4..]);
+        Some(Self {
+            channel: raw[0],
+            panid: u16::from_be_bytes([raw[1], raw[2]]),
+            rssi: raw[3] as i8,
+            network_name,
+        })
+    }
+}
+
+/// Encode scan results as `PROTOCOL_VERSION`, a count byte, then the
+/// entries. More than 255 results are truncated.
+#[cfg(feature = "std")]
+pub fn encode_scan_results(results: &[ScanResult]) -> Vec<u8> {
+    let count = results.len().min(u8::MAX as usize);
+    let mut out = Vec::with_capacity(2 + count * ScanResult::ENCODED_LEN);
+    out.push(PROTOCOL_VERSION);
+    out.push(count as u8);
+    for r in &results[..count] {
+        out.extend_from_slice(&r.to_bytes());
+    }
+    out
+}
+
+/// Decode results written by `encode_scan_results`. None for an unknown
+/// version or a length that doesn't match the count.
+#[cfg(feature = "std")]
+pub fn decode_scan_results(raw: &[u8]) -> Option<Vec<ScanResult>> {
+    let (&version, body) = raw.split_first()?;
+    if version != PROTOCOL_VERSION {
+        return None;
+    }
+    let (&count, entries) = body.split_first()?;
+    if entries.len() != count as usize * ScanResult::ENCODED_LEN {
+        return None;
+    }
+    entries
+        .chunks_exact(ScanResult::ENCODED_LEN)
+        .map(ScanResult::from_bytes)
+        .collect()
+}
+
+/// Peak energy heard on one channel during an energy scan, for spotting
+/// Wi-Fi or other interference when choosing a Thread channel.
+///
+/// Encoded as 2 fixed bytes: channel, max RSSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelEnergy {
+    pub channel: u8,
+    /// Strongest RSSI sampled on the channel, in dBm.
+    pub max_rssi: i8,
 }
 
-impl VentState {
+impl ChannelEnergy {
+    pub const ENCODED_LEN: usize = 2;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        [self.channel, self.max_rssi as u8]
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != Self::ENCODED_LEN {
+            return None;
+        }
+        Some(Self {
+            channel: raw[0],
+            max_rssi: raw[1] as i8,
+        })
+    }
+}
+
+/// Encode energy scan results like `encode_scan_results`:
+/// `PROTOCOL_VERSION`, a count byte, then the entries.
+#[cfg(feature = "std")]
+pub fn encode_channel_energy(channels: &[ChannelEnergy]) -> Vec<u8> {
+    let count = channels.len().min(u8::MAX as usize);
+    let mut out = Vec::with_capacity(2 + count * ChannelEnergy::ENCODED_LEN);
+    out.push(PROTOCOL_VERSION);
+    out.push(count as u8);
+    for c in &channels[..count] {
+        out.extend_from_slice(&c.to_bytes());
+    }
+    out
+}
+
+/// Decode results written by `encode_channel_energy`. None for an unknown
+/// version or a length that doesn't match the count.
+#[cfg(feature = "std")]
+pub fn decode_channel_energy(raw: &[u8]) -> Option<Vec<ChannelEnergy>> {
+    let (&version, body) = raw.split_first()?;
+    if version != PROTOCOL_VERSION {
+        return None;
+    }
+    let (&count, entries) = body.split_first()?;
+    if entries.len() != count as usize * ChannelEnergy::ENCODED_LEN {
+        return None;
+    }
+    entries
+        .chunks_exact(ChannelEnergy::ENCODED_LEN)
+        .map(ChannelEnergy::from_bytes)
+        .collect()
+}
+
+/// Coarse link quality bucket for deciding where a mesh needs another router.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkQuality {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl LinkQuality {
     pub fn as_str(&self) -> &'static str {
         match self {
-            VentState::Open => "open",
-            VentState::Closed => "closed",
-            VentState::Partial => "partial",
-            VentState::Moving => "moving",
+            LinkQuality::Good => "good",
+            LinkQuality::Fair => "fair",
+            LinkQuality::Poor => "poor",
         }
     }
+}
 
-    pub fn from_angle(angle: u8) -> Self {
-        match angle {
-            ANGLE_CLOSED => VentState::Closed,
-            ANGLE_OPEN => VentState::Open,
-            _ => VentState::Partial,
+/// Parent link metrics as reported by OpenThread.
+///
+/// Encoded as `PROTOCOL_VERSION`, RSSI (i8), LQI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkMetrics {
+    /// Average RSSI of frames from the parent, in dBm (-128 = unknown).
+    pub rssi: i8,
+    /// Incoming link quality indicator, 0 (none) to 3 (best).
+    pub lqi: u8,
+}
+
+impl LinkMetrics {
+    pub const ENCODED_LEN: usize = 3;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_LEN] {
+        [PROTOCOL_VERSION, self.rssi as u8, self.lqi]
+    }
+
+    /// Decode metrics. None for a buffer of the wrong length or an unknown
+    /// version.
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != Self::ENCODED_LEN || raw[0] != PROTOCOL_VERSION {
+            return None;
+        }
+        Some(Self {
+            rssi: raw[1] as i8,
+            lqi: raw[2],
+        })
+    }
+
+    /// Good needs both a strong signal and LQI 3; either a weak signal or
+    /// LQI of 1 or less is poor.
+    pub fn classify(&self) -> LinkQuality {
+        if self.rssi < -85 || self.lqi <= 1 {
+            LinkQuality::Poor
+        } else if self.rssi >= -70 && self.lqi >= 3 {
+            LinkQuality::Good
+        } else {
+            LinkQuality::Fair
         }
     }
 }
 
+impl core::fmt::Display for LinkMetrics {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "rssi={}dBm lqi={} quality={}",
+            self.rssi,
+            self.lqi,
+            self.classify().as_str()
+        )
+    }
+}
+
 /// Power source variants.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PowerSource {
     Usb,
     Battery,
+    /// Supercapacitor charged by an energy harvester, e.g. a small solar cell.
+    Harvested,
 }
 
 impl PowerSource {
@@ -44,6 +1328,7 @@ impl PowerSource {
         match self {
             PowerSource::Usb => "usb",
             PowerSource::Battery => "battery",
+            PowerSource::Harvested => "harvested",
         }
     }
 }
@@ -53,6 +1338,80 @@ pub fn clamp_angle(angle: u8) -> u8 {
     angle.clamp(ANGLE_CLOSED, ANGLE_OPEN)
 }
 
+/// Round a position in millidegrees to the nearest `round_deg` step for
+/// reporting. Steps count from closed so closed always reports as closed;
+/// open is always a candidate too, so it reports as open even when the
+/// range isn't a whole number of steps. A step of 0 or 1 rounds to the
+/// nearest whole degree.
+pub fn round_to(angle_mdeg: u32, round_deg: u8) -> u8 {
+    let step_deg = round_deg.max(1) as u32;
+    let closed_mdeg = ANGLE_CLOSED as u32 * 1000;
+    let open_mdeg = ANGLE_OPEN as u32 * 1000;
+    let mdeg = angle_mdeg.clamp(closed_mdeg, open_mdeg);
+    let steps = (mdeg - closed_mdeg + step_deg * 500) / (step_deg * 1000);
+    let grid_mdeg = (closed_mdeg + steps * step_deg * 1000).min(open_mdeg);
+    if open_mdeg - mdeg <= mdeg.abs_diff(grid_mdeg) {
+        ANGLE_OPEN
+    } else {
+        (grid_mdeg / 1000) as u8
+    }
+}
+
+/// Convert a servo angle to an open fraction in thousandths.
+/// 0 = fully closed, 1000 = fully open. Out-of-range angles are clamped.
+pub fn angle_to_permille(angle: u8) -> u16 {
+    let from_closed = (clamp_angle(angle) - ANGLE_CLOSED) as u32;
+    let range = (ANGLE_OPEN - ANGLE_CLOSED) as u32;
+    ((from_closed * 1000) / range) as u16
+}
+
+/// Keep a configured midpoint strictly inside the vent range so neither
+/// half of the piecewise mapping is empty.
+fn usable_mid(mid: u8) -> u8 {
+    mid.clamp(ANGLE_CLOSED + 1, ANGLE_OPEN - 1)
+}
+
+/// Map an open fraction `part / full` to an angle, piecewise-linear
+/// through `mid` at the halfway point. With a non-linear louver this puts
+/// the perceived 50% where the airflow actually halves.
+pub fn fraction_to_angle_with_mid(part: u32, full: u32, mid: u8) -> u8 {
+    let mid = usable_mid(mid) as u32;
+    let part = part.min(full);
+    let half = full / 2;
+    // Round to the nearest degree so angle -> fraction -> angle is stable
+    let angle = if part <= half {
+        let den = half.max(1);
+        ANGLE_CLOSED as u32 + (part * (mid - ANGLE_CLOSED as u32) + den / 2) / den
+    } else {
+        let den = full - half;
+        mid + ((part - half) * (ANGLE_OPEN as u32 - mid) + den / 2) / den
+    };
+    angle as u8
+}
+
+/// Inverse of `fraction_to_angle_with_mid`: the open fraction (out of
+/// `full`) for an angle. Out-of-range angles are clamped.
+pub fn angle_to_fraction_with_mid(angle: u8, full: u32, mid: u8) -> u32 {
+    let mid = usable_mid(mid) as u32;
+    let angle = clamp_angle(angle) as u32;
+    let half = full / 2;
+    if angle <= mid {
+        (angle - ANGLE_CLOSED as u32) * half / (mid - ANGLE_CLOSED as u32)
+    } else {
+        half + (angle - mid) * (full - half) / (ANGLE_OPEN as u32 - mid)
+    }
+}
+
+/// Percent open (0–100) to an angle, with 50% at `mid`.
+pub fn percent_to_angle_with_mid(pct: u8, mid: u8) -> u8 {
+    fraction_to_angle_with_mid(pct as u32, 100, mid)
+}
+
+/// As `angle_to_permille`, but with the midpoint remapped to 500.
+pub fn angle_to_permille_with_mid(angle: u8, mid: u8) -> u16 {
+    angle_to_fraction_with_mid(angle, 1000, mid) as u16
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +1431,839 @@ mod tests {
         assert_eq!(clamp_angle(180), 180);
         assert_eq!(clamp_angle(255), ANGLE_OPEN);
     }
+
+    #[test]
+    fn test_round_to_granularities() {
+        assert_eq!(round_to(134_400, 1), 134);
+        assert_eq!(round_to(134_500, 1), 135);
+        assert_eq!(round_to(134_500, 0), 135);
+        assert_eq!(round_to(136_000, 5), 135);
+        assert_eq!(round_to(137_500, 5), 140);
+        assert_eq!(round_to(104_000, 10), 100);
+        assert_eq!(round_to(106_000, 10), 110);
+    }
+
+    #[test]
+    fn test_round_to_endpoints() {
+        // Closed stays closed even when 90 isn't a multiple of the step
+        assert_eq!(round_to(90_000, 7), ANGLE_CLOSED);
+        assert_eq!(round_to(0, 5), ANGLE_CLOSED);
+        // Rounding past open is clamped back to open
+        assert_eq!(round_to(180_000, 7), ANGLE_OPEN);
+        // Halfway between the 178 grid point and open rounds up to open
+        assert_eq!(round_to(179_000, 4), ANGLE_OPEN);
+        assert_eq!(round_to(176_000, 4), 178);
+        assert_eq!(round_to(255_000, 1), ANGLE_OPEN);
+    }
+
+    #[test]
+    fn test_build_info_display() {
+        let info = BuildInfo {
+            firmware_version: "0.1.0",
+            git_hash: "abc1234",
+            build_timestamp: 1_700_000_000,
+            target_chip: "esp32c6",
+        };
+        assert_eq!(
+            format!("{}", info),
+            "v0.1.0 (abc1234, esp32c6, built 1700000000)"
+        );
+    }
+
+    #[test]
+    fn test_build_info_roundtrip() {
+        let info = BuildInfo {
+            firmware_version: "0.1.0",
+            git_hash: "abc1234",
+            build_timestamp: 1_700_000_000,
+            target_chip: "esp32c6",
+        };
+        let bytes = info.to_bytes();
+        assert_eq!(bytes.len(), 9 + 6 + 8 + 8);
+        assert_eq!(bytes[9..15], [5, b'0', b'.', b'1', b'.', b'0']);
+        assert_eq!(BuildInfo::from_bytes(&bytes), Some(info));
+        // Truncated, trailing data, unknown version
+        assert_eq!(BuildInfo::from_bytes(&bytes[..bytes.len() - 1]), None);
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(BuildInfo::from_bytes(&longer), None);
+        let mut other = bytes;
+        other[0] = 9;
+        assert_eq!(BuildInfo::from_bytes(&other), None);
+    }
+
+    #[test]
+    fn test_angle_to_permille() {
+        assert_eq!(angle_to_permille(ANGLE_CLOSED), 0);
+        assert_eq!(angle_to_permille(ANGLE_OPEN), 1000);
+        assert_eq!(angle_to_permille(135), 500);
+    }
+
+    #[test]
+    fn test_angle_to_permille_clamps() {
+        assert_eq!(angle_to_permille(0), 0);
+        assert_eq!(angle_to_permille(255), 1000);
+    }
+
+    #[test]
+    fn test_angle_to_permille_monotonic() {
+        for angle in ANGLE_CLOSED..ANGLE_OPEN {
+            assert!(angle_to_permille(angle) < angle_to_permille(angle + 1));
+        }
+    }
+
+    #[test]
+    fn test_percent_with_mid_hits_endpoints_and_mid() {
+        for mid in [100, 120, 135, 160] {
+            assert_eq!(percent_to_angle_with_mid(0, mid), ANGLE_CLOSED);
+            assert_eq!(percent_to_angle_with_mid(100, mid), ANGLE_OPEN);
+            assert_eq!(percent_to_angle_with_mid(50, mid), mid);
+        }
+        // The linear center reproduces the plain mapping
+        assert_eq!(percent_to_angle_with_mid(20, 135), 108);
+        assert_eq!(percent_to_angle_with_mid(200, 120), ANGLE_OPEN);
+    }
+
+    #[test]
+    fn test_percent_with_mid_piecewise() {
+        // 50% at 120°: the first half spans 30°, the second 60°
+        assert_eq!(percent_to_angle_with_mid(25, 120), 105);
+        assert_eq!(percent_to_angle_with_mid(75, 120), 150);
+        for pct in 0..100 {
+            assert!(percent_to_angle_with_mid(pct, 120) <= percent_to_angle_with_mid(pct + 1, 120));
+        }
+    }
+
+    #[test]
+    fn test_mid_at_range_edge_is_pulled_inside() {
+        assert_eq!(percent_to_angle_with_mid(50, ANGLE_CLOSED), ANGLE_CLOSED + 1);
+        assert_eq!(percent_to_angle_with_mid(50, 255), ANGLE_OPEN - 1);
+        assert_eq!(angle_to_permille_with_mid(ANGLE_CLOSED, ANGLE_CLOSED), 0);
+    }
+
+    #[test]
+    fn test_permille_with_mid_inverts() {
+        assert_eq!(angle_to_permille_with_mid(ANGLE_CLOSED, 120), 0);
+        assert_eq!(angle_to_permille_with_mid(120, 120), 500);
+        assert_eq!(angle_to_permille_with_mid(ANGLE_OPEN, 120), 1000);
+        assert_eq!(angle_to_permille_with_mid(135, 135), angle_to_permille(135));
+        for angle in ANGLE_CLOSED..=ANGLE_OPEN {
+            let permille = angle_to_permille_with_mid(angle, 120) as u32;
+            assert_eq!(fraction_to_angle_with_mid(permille, 1000, 120), angle);
+        }
+    }
+
+    fn sample_status() -> VentStatus {
+        VentStatus {
+            angle: 120,
+            target_angle: 150,
+            state: VentState::Partial,
+            moving: true,
+            open_fraction_permille: 333,
+            eta_ms: 450,
+        }
+    }
+
+    #[test]
+    fn test_field_mask_parse_query() {
+        let mask = FieldMask::parse_query("fields=0,2,5").unwrap();
+        assert_eq!(mask.bits(), 0b10_0101);
+        assert!(mask.includes(2) && !mask.includes(1));
+        assert_eq!(FieldMask::parse_query("fields= 1 , 1").unwrap().bits(), 0b10);
+        assert_eq!(FieldMask::parse_query("fields="), None);
+        assert_eq!(FieldMask::parse_query("fields=1,x"), None);
+        assert_eq!(FieldMask::parse_query("fields=8"), None);
+        assert_eq!(FieldMask::parse_query("field=1"), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_thin_status_decodes_partial() {
+        let status = sample_status();
+        let raw = status.to_bytes_selected(FieldMask::parse_query("fields=0,5").unwrap());
+        assert_eq!(raw, vec![0b10_0001, 120, 0xc2, 0x01, 0, 0]);
+        let partial = PartialVentStatus::from_bytes(&raw).unwrap();
+        assert_eq!(
+            partial,
+            PartialVentStatus {
+                angle: Some(120),
+                eta_ms: Some(450),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_thin_status_all_fields() {
+        let status = sample_status();
+        let raw = status.to_bytes_selected(FieldMask::ALL);
+        assert_eq!(raw[0], VentStatus::FIELD_BITS);
+        let partial = PartialVentStatus::from_bytes(&raw).unwrap();
+        assert_eq!(partial.angle, Some(status.angle));
+        assert_eq!(partial.target_angle, Some(status.target_angle));
+        assert_eq!(partial.state, Some(status.state));
+        assert_eq!(partial.moving, Some(true));
+        assert_eq!(partial.open_fraction_permille, Some(333));
+        assert_eq!(partial.eta_ms, Some(450));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_thin_health_decodes_partial() {
+        let health = HealthMin {
+            battery_pct: None,
+            rssi: -70,
+            state: VentState::Open,
+            servo_power_fault: true,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let raw = health.to_bytes_selected(FieldMask::parse_query("fields=0,2").unwrap());
+        assert_eq!(raw, vec![0b101, 0xff, VentState::Open.as_u8() | 0x80]);
+        assert_eq!(
+            PartialHealthMin::from_bytes(&raw),
+            Some(PartialHealthMin {
+                battery_pct: Some(None),
+                state: Some(VentState::Open),
+                servo_power_fault: Some(true),
+                clock_unsynced: Some(false),
+                crash_loop: Some(false),
+                matter_unavailable: Some(false),
+                ..Default::default()
+            })
+        );
+        let raw = health.to_bytes_selected(FieldMask::ALL);
+        assert_eq!(raw[0], HealthMin::FIELD_BITS);
+        assert_eq!(PartialHealthMin::from_bytes(&raw).unwrap().rssi, Some(-70));
+        assert_eq!(PartialHealthMin::from_bytes(&raw[..2]), None);
+    }
+
+    #[test]
+    fn test_thin_status_skips_unknown_fields() {
+        // A newer sender adds field 6 (two bytes) after the known ones
+        let raw = [0b100_0011, 120, 90, 0xaa, 0xbb];
+        let partial = PartialVentStatus::from_bytes(&raw).unwrap();
+        assert_eq!(
+            partial,
+            PartialVentStatus {
+                angle: Some(120),
+                target_angle: Some(90),
+                ..Default::default()
+            }
+        );
+        assert_eq!(PartialVentStatus::from_bytes(&[0b1000_0000]), Some(PartialVentStatus::default()));
+    }
+
+    #[test]
+    fn test_thin_status_rejects_bad_input() {
+        assert_eq!(PartialVentStatus::from_bytes(&[]), None);
+        // Mask promises the eta but the bytes stop short
+        assert_eq!(PartialVentStatus::from_bytes(&[0b10_0000, 1, 2]), None);
+        assert_eq!(PartialVentStatus::from_bytes(&[0b100, 9]), None);
+        assert_eq!(PartialVentStatus::from_bytes(&[0]), Some(PartialVentStatus::default()));
+    }
+
+    #[test]
+    fn test_units_roundtrip() {
+        for units in [Units::Angle, Units::PercentOpen, Units::Permille] {
+            assert_eq!(Units::from_u8(units.as_u8()), Some(units));
+        }
+        assert_eq!(Units::from_u8(3), None);
+        assert_eq!(Units::default(), Units::Angle);
+        assert_eq!(Units::PercentOpen.as_str(), "percent_open");
+    }
+
+    #[test]
+    fn test_device_type_roundtrip() {
+        for kind in [DeviceType::Cover, DeviceType::Damper] {
+            assert_eq!(DeviceType::from_u8(kind.as_u8()), Some(kind));
+            assert_eq!(DeviceType::from_name(kind.as_str()), Some(kind));
+        }
+        assert_eq!(DeviceType::from_u8(2), None);
+        assert_eq!(DeviceType::from_name("fan"), None);
+        assert_eq!(DeviceType::default(), DeviceType::Cover);
+    }
+
+    #[test]
+    fn test_confidence_roundtrip() {
+        for c in [Confidence::Low, Confidence::Medium, Confidence::High] {
+            assert_eq!(Confidence::from_u8(c.as_u8()), Some(c));
+        }
+        assert_eq!(Confidence::from_u8(3), None);
+        let report = PositionReport::from_status(&sample_status(), Units::Angle);
+        assert_eq!(report.confidence, Confidence::Medium);
+        assert_eq!(report.with_confidence(Confidence::High).confidence, Confidence::High);
+    }
+
+    #[test]
+    fn test_position_report_matches_units() {
+        let status = sample_status();
+        let report = PositionReport::from_status(&status, Units::Angle);
+        assert_eq!(report.units, Units::Angle);
+        assert_eq!(report.value, 120);
+        let report = PositionReport::from_status(&status, Units::PercentOpen);
+        assert_eq!(report.units, Units::PercentOpen);
+        assert_eq!(report.value, 33);
+        let report = PositionReport::from_status(&status, Units::Permille);
+        assert_eq!(report.units, Units::Permille);
+        assert_eq!(report.value, 333);
+    }
+
+    #[test]
+    fn test_device_addresses_roundtrip() {
+        let addrs = DeviceAddresses {
+            efuse_mac: [0x40, 0x4c, 0xca, 1, 2, 3],
+            eui64: [0x40, 0x4c, 0xca, 0xff, 0xfe, 1, 2, 3],
+            thread_ext_addr: Some([9; 8]),
+        };
+        let bytes = addrs.to_bytes();
+        assert_eq!(bytes[14], 1);
+        assert_eq!(DeviceAddresses::from_bytes(&bytes), Some(addrs));
+        let down = DeviceAddresses {
+            thread_ext_addr: None,
+            ..addrs
+        };
+        let bytes = down.to_bytes();
+        assert_eq!(&bytes[14..], &[0; 9]);
+        assert_eq!(DeviceAddresses::from_bytes(&bytes), Some(down));
+        assert_eq!(DeviceAddresses::from_bytes(&bytes[..22]), None);
+    }
+
+    #[test]
+    fn test_position_report_roundtrip() {
+        let report = PositionReport::from_status(&sample_status(), Units::Permille)
+            .with_confidence(Confidence::High);
+        let bytes = report.to_bytes();
+        assert_eq!(bytes, [2, 0x4d, 0x01, 2]);
+        assert_eq!(PositionReport::from_bytes(&bytes), Some(report));
+        assert_eq!(PositionReport::from_bytes(&bytes[..3]), None);
+        assert_eq!(PositionReport::from_bytes(&[3, 0, 0, 1]), None);
+        assert_eq!(PositionReport::from_bytes(&[0, 0, 0, 3]), None);
+    }
+
+    #[test]
+    fn test_position_report_percent_rounds() {
+        let status = VentStatus {
+            open_fraction_permille: 1000,
+            ..sample_status()
+        };
+        assert_eq!(PositionReport::from_status(&status, Units::PercentOpen).value, 100);
+        let status = VentStatus {
+            open_fraction_permille: 995,
+            ..sample_status()
+        };
+        assert_eq!(PositionReport::from_status(&status, Units::PercentOpen).value, 100);
+        let status = VentStatus {
+            open_fraction_permille: 4,
+            ..sample_status()
+        };
+        assert_eq!(PositionReport::from_status(&status, Units::PercentOpen).value, 0);
+    }
+
+    #[test]
+    fn test_link_metrics_display() {
+        let m = LinkMetrics { rssi: -72, lqi: 2 };
+        assert_eq!(format!("{}", m), "rssi=-72dBm lqi=2 quality=fair");
+    }
+
+    #[test]
+    fn test_pressure_data_roundtrip() {
+        let held = PressureData {
+            pascals: -42,
+            age_ms: 1_500,
+            target_pa: Some(25),
+        };
+        let free = PressureData { target_pa: None, ..held };
+        assert_eq!(PressureData::from_bytes(&held.to_bytes()), Some(held));
+        assert_eq!(PressureData::from_bytes(&free.to_bytes()), Some(free));
+        assert_eq!(free.to_bytes()[9..], [0, 0, 0, 0, 0]);
+        assert_eq!(PressureData::from_bytes(&held.to_bytes()[..13]), None);
+    }
+
+    #[test]
+    fn test_maintenance_info_roundtrip() {
+        let known = MaintenanceInfo {
+            install_unix_s: Some(1_700_000_000),
+            runtime_hours: 8_760,
+        };
+        let unknown = MaintenanceInfo {
+            install_unix_s: None,
+            ..known
+        };
+        assert_eq!(MaintenanceInfo::from_bytes(&known.to_bytes()), Some(known));
+        assert_eq!(MaintenanceInfo::from_bytes(&unknown.to_bytes()), Some(unknown));
+        assert_eq!(unknown.to_bytes()[1], 0);
+        assert_eq!(MaintenanceInfo::from_bytes(&known.to_bytes()[..13]), None);
+    }
+
+    #[test]
+    fn test_link_metrics_roundtrip() {
+        for m in [LinkMetrics { rssi: -72, lqi: 2 }, LinkMetrics { rssi: -128, lqi: 0 }] {
+            assert_eq!(LinkMetrics::from_bytes(&m.to_bytes()), Some(m));
+        }
+        assert_eq!(LinkMetrics { rssi: -72, lqi: 2 }.to_bytes(), [PROTOCOL_VERSION, 0xb8, 2]);
+        assert_eq!(LinkMetrics::from_bytes(&[PROTOCOL_VERSION, 0xb8]), None);
+        assert_eq!(LinkMetrics::from_bytes(&[9, 0xb8, 2]), None);
+    }
+
+    #[test]
+    fn test_link_quality_classification() {
+        assert_eq!(LinkMetrics { rssi: -60, lqi: 3 }.classify(), LinkQuality::Good);
+        assert_eq!(LinkMetrics { rssi: -70, lqi: 3 }.classify(), LinkQuality::Good);
+        // Strong signal but degraded LQI
+        assert_eq!(LinkMetrics { rssi: -60, lqi: 2 }.classify(), LinkQuality::Fair);
+        assert_eq!(LinkMetrics { rssi: -80, lqi: 3 }.classify(), LinkQuality::Fair);
+        assert_eq!(LinkMetrics { rssi: -86, lqi: 3 }.classify(), LinkQuality::Poor);
+        assert_eq!(LinkMetrics { rssi: -60, lqi: 1 }.classify(), LinkQuality::Poor);
+        // Unknown RSSI is never good
+        assert_eq!(LinkMetrics { rssi: -128, lqi: 0 }.classify(), LinkQuality::Poor);
+    }
+
+    #[test]
+    fn test_status_beacon_roundtrip() {
+        let beacon = StatusBeacon {
+            angle: 135,
+            state: VentState::Partial,
+            moving: false,
+            battery_pct: Some(87),
+            rssi: -71,
+        };
+        let bytes = beacon.to_bytes();
+        assert_eq!(bytes.len(), StatusBeacon::ENCODED_LEN);
+        assert_eq!(StatusBeacon::from_bytes(&bytes), Some(beacon));
+
+        let usb = StatusBeacon { battery_pct: None, ..beacon };
+        assert_eq!(StatusBeacon::from_bytes(&usb.to_bytes()), Some(usb));
+    }
+
+    #[test]
+    fn test_status_beacon_rejects_bad_input() {
+        let mut bytes = StatusBeacon {
+            angle: 90,
+            state: VentState::Closed,
+            moving: false,
+            battery_pct: None,
+            rssi: -60,
+        }
+        .to_bytes();
+        assert_eq!(StatusBeacon::from_bytes(&bytes[..5]), None);
+        bytes[0] = 9;
+        assert_eq!(StatusBeacon::from_bytes(&bytes), None);
+        bytes[0] = StatusBeacon::VERSION;
+        bytes[2] = 7;
+        assert_eq!(StatusBeacon::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn test_neighbor_table_roundtrip() {
+        let table = vec![
+            NeighborInfo {
+                rloc16: 0x4800,
+                rssi: -62,
+                lqi: 3,
+                role: NeighborRole::Router,
+            },
+            NeighborInfo {
+                rloc16: 0x4801,
+                rssi: -88,
+                lqi: 1,
+                role: NeighborRole::SleepyChild,
+            },
+            NeighborInfo {
+                rloc16: 0x4802,
+                rssi: -75,
+                lqi: 2,
+                role: NeighborRole::Child,
+            },
+        ];
+        let raw = encode_neighbors(&table);
+        assert_eq!(raw.len(), 2 + 3 * NeighborInfo::ENCODED_LEN);
+        assert_eq!(decode_neighbors(&raw), Some(table.clone()));
+        // Tables from firmware without the version byte still decode
+        assert_eq!(neighbors_version(&raw[1..]), Some(0));
+        assert_eq!(decode_neighbors(&raw[1..]), Some(table));
+    }
+
+    #[test]
+    fn test_empty_neighbor_table() {
+        let raw = encode_neighbors(&[]);
+        assert_eq!(raw, vec![PROTOCOL_VERSION, 0]);
+        assert_eq!(decode_neighbors(&raw), Some(vec![]));
+        // Truncated or missing count
+        assert_eq!(decode_neighbors(&[]), None);
+        assert_eq!(decode_neighbors(&[1, 0x48, 0x00]), None);
+    }
+
+    #[test]
+    fn test_health_min_roundtrip() {
+        let health = HealthMin {
+            battery_pct: Some(64),
+            rssi: -80,
+            state: VentState::Closed,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        assert_eq!(HealthMin::from_bytes(&health.to_bytes()), Some(health));
+        let usb = HealthMin { battery_pct: None, ..health };
+        assert_eq!(HealthMin::from_bytes(&usb.to_bytes()), Some(usb));
+        assert_eq!(HealthMin::from_bytes(&[64, 0xb0]), None);
+        assert_eq!(HealthMin::from_bytes(&[64, 0xb0, 9]), None);
+    }
+
+    #[test]
+    fn test_health_min_fault_flag_keeps_state() {
+        let health = HealthMin {
+            battery_pct: None,
+            rssi: -70,
+            state: VentState::Partial,
+            servo_power_fault: true,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let bytes = health.to_bytes();
+        assert_eq!(bytes.len(), HealthMin::ENCODED_LEN);
+        assert_eq!(HealthMin::from_bytes(&bytes), Some(health));
+        // Older firmware never sets the flag
+        let legacy = [0xff, (-70i8) as u8, VentState::Partial.as_u8()];
+        assert!(!HealthMin::from_bytes(&legacy).unwrap().servo_power_fault);
+    }
+
+    #[test]
+    fn test_health_min_clock_flag_independent() {
+        let health = HealthMin {
+            battery_pct: Some(90),
+            rssi: -65,
+            state: VentState::Open,
+            servo_power_fault: false,
+            clock_unsynced: true,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let decoded = HealthMin::from_bytes(&health.to_bytes()).unwrap();
+        assert_eq!(decoded, health);
+        assert!(!decoded.servo_power_fault);
+        let both = HealthMin { servo_power_fault: true, ..health };
+        assert_eq!(HealthMin::from_bytes(&both.to_bytes()), Some(both));
+    }
+
+    #[test]
+    fn test_health_min_degraded_mode_flags() {
+        let health = HealthMin {
+            battery_pct: None,
+            rssi: -60,
+            state: VentState::Closed,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: true,
+            matter_unavailable: false,
+        };
+        let bytes = health.to_bytes();
+        assert_eq!(bytes[2], VentState::Closed.as_u8() | 0x20);
+        assert_eq!(HealthMin::from_bytes(&bytes), Some(health));
+        let coap_only = HealthMin { crash_loop: false, matter_unavailable: true, ..health };
+        assert_eq!(coap_only.to_bytes()[2], VentState::Closed.as_u8() | 0x10);
+        assert_eq!(HealthMin::from_bytes(&coap_only.to_bytes()), Some(coap_only));
+    }
+
+    #[test]
+    fn test_poll_report_roundtrip() {
+        let report = PollReport {
+            angle: 135,
+            health: HealthMin {
+                battery_pct: None,
+                rssi: -72,
+                state: VentState::Partial,
+                servo_power_fault: false,
+                clock_unsynced: false,
+                crash_loop: false,
+                matter_unavailable: false,
+            },
+            confidence: Some(Confidence::Low),
+        };
+        let bytes = report.to_bytes();
+        assert_eq!(bytes[5], Confidence::Low.as_u8());
+        assert_eq!(PollReport::from_bytes(&bytes), Some(report));
+        // A version 1 report from before the confidence byte
+        let short = PollReport {
+            confidence: None,
+            ..report
+        };
+        assert_eq!(PollReport::from_bytes(&bytes[..5]), Some(short));
+        assert_eq!(PollReport::from_bytes(&[135, 0xff, 0xb8]), None);
+    }
+
+    #[test]
+    fn test_target_response_roundtrip_accepted() {
+        let resp = TargetResponse {
+            target: 150,
+            previous: 90,
+            current: 92,
+            accepted: true,
+        };
+        assert_eq!(TargetResponse::from_bytes(&resp.to_bytes()), Some(resp));
+    }
+
+    #[test]
+    fn test_target_response_roundtrip_rejected() {
+        let resp = TargetResponse {
+            target: 180,
+            previous: 120,
+            current: 120,
+            accepted: false,
+        };
+        let bytes = resp.to_bytes();
+        assert_eq!(bytes[4], 0);
+        assert_eq!(TargetResponse::from_bytes(&bytes), Some(resp));
+        assert_eq!(TargetResponse::from_bytes(&bytes[..4]), None);
+        assert_eq!(TargetResponse::from_bytes(&[PROTOCOL_VERSION, 180, 120, 120, 0, 0]), None);
+        assert_eq!(TargetResponse::from_bytes(&[9, 180, 120, 120, 0]), None);
+    }
+
+    #[test]
+    fn test_target_request_roundtrip() {
+        let req = TargetRequest {
+            angle: 150,
+            min_fw_required: Some("1.4.0"),
+            idempotency_token: None,
+        };
+        let bytes = req.to_bytes();
+        assert_eq!(&bytes[..3], &[PROTOCOL_VERSION, 150, 5]);
+        assert_eq!(TargetRequest::from_bytes(&bytes), Some(req));
+
+        let plain = TargetRequest {
+            angle: 120,
+            min_fw_required: None,
+            idempotency_token: None,
+        };
+        assert_eq!(plain.to_bytes(), vec![PROTOCOL_VERSION, 120, 0]);
+        assert_eq!(TargetRequest::from_bytes(&plain.to_bytes()), Some(plain));
+        assert_eq!(TargetRequest::from_bytes(&[PROTOCOL_VERSION, 120]), Some(plain));
+    }
+
+    #[test]
+    fn test_target_request_token_roundtrip() {
+        let req = TargetRequest {
+            angle: 135,
+            min_fw_required: Some("1.4.0"),
+            idempotency_token: Some(0x0123_4567_89ab_cdef),
+        };
+        let bytes = req.to_bytes();
+        assert_eq!(bytes.len(), 3 + 5 + 8);
+        assert_eq!(TargetRequest::from_bytes(&bytes), Some(req));
+        let bare = TargetRequest { min_fw_required: None, ..req };
+        assert_eq!(TargetRequest::from_bytes(&bare.to_bytes()), Some(bare));
+        // A token cut short is refused rather than guessed at
+        assert_eq!(TargetRequest::from_bytes(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_target_request_rejects_bad_input() {
+        assert_eq!(TargetRequest::from_bytes(&[]), None);
+        assert_eq!(TargetRequest::from_bytes(&[PROTOCOL_VERSION]), None);
+        assert_eq!(TargetRequest::from_bytes(&[9, 120, 0]), None);
+        // Length runs past the end
+        assert_eq!(TargetRequest::from_bytes(&[PROTOCOL_VERSION, 120, 5, b'1', b'.']), None);
+        assert_eq!(TargetRequest::from_bytes(&[PROTOCOL_VERSION, 120, 1, 0xff]), None);
+        // Trailing bytes that aren't a whole token
+        assert_eq!(TargetRequest::from_bytes(&[PROTOCOL_VERSION, 120, 0, 0xaa]), None);
+        assert_eq!(TargetRequest::from_bytes(&[PROTOCOL_VERSION, 120, 1, b'1', 0, 0]), None);
+    }
+
+    #[test]
+    fn test_fw_satisfies_numeric_order() {
+        assert!(fw_satisfies("1.4.0", "1.4.0"));
+        assert!(fw_satisfies("1.10.0", "1.9.3"));
+        assert!(fw_satisfies("2.0.0", "1.99.99"));
+        assert!(!fw_satisfies("1.3.9", "1.4.0"));
+        assert!(!fw_satisfies("0.9.0", "1.0.0"));
+        // Missing parts count as 0; a "v" prefix and build metadata are ignored
+        assert!(fw_satisfies("1.4", "1.4.0"));
+        assert!(fw_satisfies("v1.4.1", "1.4"));
+        assert!(fw_satisfies("1.4.0+abc123", "1.4.0"));
+    }
+
+    #[test]
+    fn test_fw_satisfies_prerelease_and_garbage() {
+        assert!(!fw_satisfies("1.4.0-rc1", "1.4.0"));
+        assert!(fw_satisfies("1.4.0", "1.4.0-rc1"));
+        assert!(fw_satisfies("1.4.0-rc2", "1.4.0-rc1"));
+        assert!(fw_satisfies("1.5.0-rc1", "1.4.0"));
+        assert!(!fw_satisfies("1.4.0", "latest"));
+        assert!(!fw_satisfies("dev", "1.0.0"));
+        assert!(!fw_satisfies("1.4.0", "1.4.0.1"));
+    }
+
+    #[test]
+    fn test_fw_satisfies_prerelease_numbers() {
+        assert!(fw_satisfies("1.4.0-rc10", "1.4.0-rc9"));
+        assert!(!fw_satisfies("1.4.0-rc9", "1.4.0-rc10"));
+        assert!(fw_satisfies("1.4.0-rc.10", "1.4.0-rc.9"));
+        assert!(fw_satisfies("1.4.0-beta", "1.4.0-alpha.3"));
+        // More identifiers sort after a matching prefix; numbers below words
+        assert!(fw_satisfies("1.4.0-rc.1", "1.4.0-rc"));
+        assert!(!fw_satisfies("1.4.0-2", "1.4.0-rc"));
+    }
+
+    #[test]
+    fn test_scan_result_roundtrip() {
+        let r = ScanResult::new(15, 0x1234, -72, b"SmartVent");
+        assert_eq!(r.name(), "SmartVent");
+        let bytes = r.to_bytes();
+        assert_eq!(&bytes[..4], &[15, 0x12, 0x34, (-72i8) as u8]);
+        assert_eq!(ScanResult::from_bytes(&bytes), Some(r));
+        assert_eq!(ScanResult::from_bytes(&bytes[..19]), None);
+
+        // Names are capped at 16 bytes
+        let long = ScanResult::new(11, 0xface, -40, b"A-very-long-network-name");
+        assert_eq!(long.name(), "A-very-long-netw");
+        assert_eq!(ScanResult::from_bytes(&long.to_bytes()), Some(long));
+    }
+
+    #[test]
+    fn test_scan_results_list_roundtrip() {
+        let results = vec![
+            ScanResult::new(15, 0x1234, -60, b"SmartVent"),
+            ScanResult::new(25, 0xabcd, -85, b"NeighborNet"),
+        ];
+        let bytes = encode_scan_results(&results);
+        assert_eq!(bytes.len(), 2 + 2 * ScanResult::ENCODED_LEN);
+        assert_eq!(&bytes[..2], &[PROTOCOL_VERSION, 2]);
+        assert_eq!(decode_scan_results(&bytes), Some(results));
+        assert_eq!(decode_scan_results(&encode_scan_results(&[])), Some(vec![]));
+        assert_eq!(decode_scan_results(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode_scan_results(&[9, 0]), None);
+    }
+
+    #[test]
+    fn test_channel_energy_roundtrip() {
+        let channels = vec![
+            ChannelEnergy {
+                channel: 11,
+                max_rssi: -95,
+            },
+            ChannelEnergy {
+                channel: 15,
+                max_rssi: -42,
+            },
+        ];
+        let bytes = encode_channel_energy(&channels);
+        assert_eq!(bytes, vec![PROTOCOL_VERSION, 2, 11, (-95i8) as u8, 15, (-42i8) as u8]);
+        assert_eq!(decode_channel_energy(&bytes), Some(channels));
+        assert_eq!(decode_channel_energy(&bytes[..5]), None);
+        assert_eq!(decode_channel_energy(&[9, 0]), None);
+    }
+
+    #[test]
+    fn test_payloads_lead_with_protocol_version() {
+        let health = HealthMin {
+            battery_pct: Some(80),
+            rssi: -70,
+            state: VentState::Open,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let report = PollReport {
+            angle: 180,
+            health,
+            confidence: None,
+        }
+        .to_bytes();
+        assert_eq!(report[0], PROTOCOL_VERSION);
+        assert_eq!(PollReport::peek_version(&report), Some(PROTOCOL_VERSION));
+        assert_eq!(encode_neighbors(&[])[0], PROTOCOL_VERSION);
+        let beacon = StatusBeacon {
+            angle: 180,
+            state: VentState::Open,
+            moving: false,
+            battery_pct: None,
+            rssi: -70,
+        };
+        assert_eq!(StatusBeacon::peek_version(&beacon.to_bytes()), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn test_version_mismatch_detected_before_decode() {
+        let health = HealthMin {
+            battery_pct: None,
+            rssi: -72,
+            state: VentState::Partial,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let mut report = PollReport {
+            angle: 135,
+            health,
+            confidence: None,
+        }
+        .to_bytes();
+        report[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(PollReport::peek_version(&report), Some(PROTOCOL_VERSION + 1));
+        assert_eq!(PollReport::from_bytes(&report), None);
+        let mut table = encode_neighbors(&[]);
+        table[0] = PROTOCOL_VERSION + 1;
+        assert_eq!(neighbors_version(&table), Some(PROTOCOL_VERSION + 1));
+        assert_eq!(decode_neighbors(&table), None);
+    }
+
+    #[test]
+    fn test_unversioned_poll_report_decodes_as_v0() {
+        let health = HealthMin {
+            battery_pct: Some(64),
+            rssi: -80,
+            state: VentState::Closed,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let legacy = [90, 64, (-80i8) as u8, VentState::Closed.as_u8()];
+        assert_eq!(PollReport::peek_version(&legacy), Some(0));
+        assert_eq!(
+            PollReport::from_bytes(&legacy),
+            Some(PollReport {
+                angle: 90,
+                health,
+                confidence: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_health_min_is_smaller_than_beacon() {
+        // The beacon already trims the status to its essentials
+        let beacon = StatusBeacon {
+            angle: 90,
+            state: VentState::Closed,
+            moving: false,
+            battery_pct: Some(64),
+            rssi: -80,
+        };
+        let health = HealthMin {
+            battery_pct: beacon.battery_pct,
+            rssi: beacon.rssi,
+            state: beacon.state,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        assert!(health.to_bytes().len() * 2 <= beacon.to_bytes().len());
+    }
+
+    #[test]
+    fn test_vent_state_code_roundtrip() {
+        for state in [VentState::Open, VentState::Closed, VentState::Partial, VentState::Moving] {
+            assert_eq!(VentState::from_u8(state.as_u8()), Some(state));
+        }
+        assert_eq!(VentState::from_u8(4), None);
+    }
 }
@@ -1,3 +1,27 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
     embuild::espidf::sysenv::output();
+
+    // Build metadata for vent_protocol::BuildInfo (see identity::build_info)
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".into());
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let chip = std::env::var("MCU").unwrap_or_else(|_| "esp32c6".into());
+
+    println!("cargo:rustc-env=VENT_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=VENT_BUILD_TIMESTAMP={}", timestamp);
+    println!("cargo:rustc-env=VENT_TARGET_CHIP={}", chip);
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+    println!("cargo:rerun-if-env-changed=MCU");
 }
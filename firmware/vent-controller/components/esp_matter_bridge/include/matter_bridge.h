@@ -22,17 +22,30 @@ typedef void (*matter_position_cb_t)(uint16_t percent100ths, void *ctx);
  */
 typedef void (*matter_identify_cb_t)(uint16_t duration_s, void *ctx);
 
+/**
+ * Callback invoked when a Matter controller selects an airflow mode.
+ * @param mode 0 = Closed, 1 = Low, 2 = Medium, 3 = High, 4 = Open
+ * @param ctx User context pointer passed to matter_bridge_init()
+ */
+typedef void (*matter_mode_cb_t)(uint8_t mode, void *ctx);
+
 /**
  * Initialize the Matter node with a Window Covering endpoint.
  * Must be called before matter_bridge_start().
  *
  * @param position_cb Called when controller changes target position
  * @param identify_cb Called when controller triggers identify
+ * @param mode_cb Called when controller selects an airflow mode
+ * @param discriminator 12-bit commissioning discriminator
+ * @param setup_passcode Setup passcode (1–99999998, not a reserved value)
  * @param ctx User context forwarded to callbacks
  * @return 0 on success, non-zero on failure
  */
 int matter_bridge_init(matter_position_cb_t position_cb,
                        matter_identify_cb_t identify_cb,
+                       matter_mode_cb_t mode_cb,
+                       uint16_t discriminator,
+                       uint32_t setup_passcode,
                        void *ctx);
 
 /**
@@ -54,6 +67,12 @@ void matter_bridge_update_position(uint16_t percent100ths);
  */
 void matter_bridge_update_operational_status(uint8_t status);
 
+/**
+ * Report the airflow mode nearest the current position.
+ * @param mode 0 = Closed … 4 = Open
+ */
+void matter_bridge_update_mode(uint8_t mode);
+
 /**
  * Check if the device has been commissioned into a Matter fabric.
  * @return true if commissioned
@@ -6,12 +6,14 @@
 #include <esp_openthread.h>
 #include <platform/ESP32/OpenthreadLauncher.h>
 #include <nvs_flash.h>
-#include <esp_mac.h>
 #include <app/server/Server.h>
 #include <app/server/OnboardingCodesUtil.h>
 #include <app/clusters/window-covering-server/window-covering-server.h>
 #include <app/clusters/window-covering-server/window-covering-delegate.h>
+#include <app/clusters/mode-select-server/supported-modes-manager.h>
 #include <app-common/zap-generated/attributes/Accessors.h>
+#include <platform/CommissionableDataProvider.h>
+#include <crypto/CHIPCryptoPAL.h>
 
 static const char *TAG = "matter_bridge";
 
@@ -23,9 +25,13 @@ using namespace chip::app::Clusters;
 
 static matter_position_cb_t s_position_cb = nullptr;
 static matter_identify_cb_t s_identify_cb = nullptr;
+static matter_mode_cb_t s_mode_cb = nullptr;
 static void *s_user_ctx = nullptr;
 static uint16_t s_endpoint_id = 0;
 static node_t *s_node = nullptr;
+// Set while we write CurrentMode ourselves so the update isn't taken as a
+// controller selecting that mode
+static bool s_reporting_mode = false;
 
 // --- Matter attribute update callback ---
 
@@ -56,6 +62,14 @@ static esp_err_t app_attribute_update_cb(
         }
     }
 
+    // ModeSelect cluster: ChangeToMode writes CurrentMode
+    if (cluster_id == ModeSelect::Id && attribute_id == ModeSelect::Attributes::CurrentMode::Id) {
+        if (!s_reporting_mode && s_mode_cb) {
+            ESP_LOGI(TAG, "Matter: airflow mode set to %u", val->val.u8);
+            s_mode_cb(val->val.u8, s_user_ctx);
+        }
+    }
+
     return ESP_OK;
 }
 
@@ -98,6 +112,108 @@ public:
 
 static VentCoveringDelegate s_wc_delegate;
 
+// --- Airflow modes ---
+// A fixed Closed/Low/Medium/High/Open list on the covering endpoint so
+// controllers can offer a dropdown. The mode values match the Rust
+// `AirflowMode`; their angles are configured on the Rust side.
+
+class AirflowModesManager : public ModeSelect::SupportedModesManager {
+public:
+    ModeOptionsProvider getModeOptionsProvider(chip::EndpointId endpointId) const override {
+        if (endpointId != s_endpoint_id) {
+            return ModeOptionsProvider();
+        }
+        return ModeOptionsProvider(kOptions, kOptions + kCount);
+    }
+
+    chip::Protocols::InteractionModel::Status getModeOptionByMode(
+        chip::EndpointId endpointId, uint8_t mode, const ModeOptionStructType **dataPtr) const override {
+        if (endpointId != s_endpoint_id) {
+            return chip::Protocols::InteractionModel::Status::UnsupportedCluster;
+        }
+        for (size_t i = 0; i < kCount; i++) {
+            if (kOptions[i].mode == mode) {
+                *dataPtr = &kOptions[i];
+                return chip::Protocols::InteractionModel::Status::Success;
+            }
+        }
+        return chip::Protocols::InteractionModel::Status::InvalidCommand;
+    }
+
+private:
+    static ModeOptionStructType Option(const char *label, uint8_t mode) {
+        ModeOptionStructType option;
+        option.label = chip::CharSpan::fromCharString(label);
+        option.mode = mode;
+        option.semanticTags = chip::app::DataModel::List<const SemanticTag>();
+        return option;
+    }
+
+    static constexpr size_t kCount = 5;
+    static inline const ModeOptionStructType kOptions[kCount] = {
+        Option("Closed", 0), Option("Low", 1), Option("Medium", 2), Option("High", 3), Option("Open", 4),
+    };
+};
+
+static AirflowModesManager s_modes_manager;
+
+// --- Commissionable data ---
+// Discriminator and passcode are derived per device on the Rust side. The
+// SPAKE2+ verifier is generated from the passcode at boot with a fresh
+// random salt, so nothing secret needs to be flashed.
+
+class DerivedCommissionableDataProvider : public chip::DeviceLayer::CommissionableDataProvider {
+public:
+    CHIP_ERROR Init(uint16_t discriminator, uint32_t passcode) {
+        mDiscriminator = discriminator;
+        mPasscode = passcode;
+        ReturnErrorOnFailure(chip::Crypto::DRBG_get_bytes(mSalt, sizeof(mSalt)));
+        chip::Crypto::Spake2pVerifier verifier;
+        ReturnErrorOnFailure(verifier.Generate(kIterations, chip::ByteSpan(mSalt), mPasscode));
+        chip::MutableByteSpan span(mVerifier);
+        ReturnErrorOnFailure(verifier.Serialize(span));
+        mVerifierLen = span.size();
+        return CHIP_NO_ERROR;
+    }
+
+    CHIP_ERROR GetSetupDiscriminator(uint16_t &discriminator) override {
+        discriminator = mDiscriminator;
+        return CHIP_NO_ERROR;
+    }
+    CHIP_ERROR SetSetupDiscriminator(uint16_t discriminator) override {
+        mDiscriminator = discriminator;
+        return CHIP_NO_ERROR;
+    }
+    CHIP_ERROR GetSpake2pIterationCount(uint32_t &iterations) override {
+        iterations = kIterations;
+        return CHIP_NO_ERROR;
+    }
+    CHIP_ERROR GetSpake2pSalt(chip::MutableByteSpan &salt) override {
+        return chip::CopySpanToMutableSpan(chip::ByteSpan(mSalt), salt);
+    }
+    CHIP_ERROR GetSpake2pVerifier(chip::MutableByteSpan &verifier, size_t &len) override {
+        len = mVerifierLen;
+        return chip::CopySpanToMutableSpan(chip::ByteSpan(mVerifier, mVerifierLen), verifier);
+    }
+    CHIP_ERROR GetSetupPasscode(uint32_t &passcode) override {
+        passcode = mPasscode;
+        return CHIP_NO_ERROR;
+    }
+    CHIP_ERROR SetSetupPasscode(uint32_t) override {
+        return CHIP_ERROR_NOT_IMPLEMENTED;
+    }
+
+private:
+    static constexpr uint32_t kIterations = chip::Crypto::kSpake2p_Min_PBKDF_Iterations;
+    uint16_t mDiscriminator = 0;
+    uint32_t mPasscode = 0;
+    uint8_t mSalt[chip::Crypto::kSpake2p_Max_PBKDF_Salt_Length] = {};
+    uint8_t mVerifier[chip::Crypto::kSpake2p_VerifierSerialized_Length] = {};
+    size_t mVerifierLen = 0;
+};
+
+static DerivedCommissionableDataProvider s_commissionable_data;
+
 // --- Matter identification callback ---
 
 static esp_err_t app_identification_cb(
@@ -125,14 +241,26 @@ static esp_err_t app_identification_cb(
 
 int matter_bridge_init(matter_position_cb_t position_cb,
                        matter_identify_cb_t identify_cb,
+                       matter_mode_cb_t mode_cb,
+                       uint16_t discriminator,
+                       uint32_t setup_passcode,
                        void *ctx)
 {
     ESP_LOGI(TAG, "Initializing Matter node...");
 
     s_position_cb = position_cb;
     s_identify_cb = identify_cb;
+    s_mode_cb = mode_cb;
     s_user_ctx = ctx;
 
+    // Install before esp_matter::start() so BLE advertising and the setup
+    // payload use the per-device values
+    if (s_commissionable_data.Init(discriminator, setup_passcode) != CHIP_NO_ERROR) {
+        ESP_LOGE(TAG, "Failed to set up commissionable data");
+        return -1;
+    }
+    chip::DeviceLayer::SetCommissionableDataProvider(&s_commissionable_data);
+
     // Create Matter node
     node::config_t node_config;
     s_node = node::create(&node_config, app_attribute_update_cb, app_identification_cb);
@@ -168,6 +296,18 @@ int matter_bridge_init(matter_position_cb_t position_cb,
         }
     }
 
+    // Airflow mode presets alongside the lift position
+    {
+        ModeSelect::setSupportedModesManager(&s_modes_manager);
+        cluster::mode_select::config_t ms_cfg;
+        ms_cfg.current_mode = 0;
+        if (cluster::mode_select::create(ep, &ms_cfg, CLUSTER_FLAG_SERVER)) {
+            ESP_LOGI(TAG, "ModeSelect cluster added (airflow presets)");
+        } else {
+            ESP_LOGE(TAG, "Could not add ModeSelect cluster");
+        }
+    }
+
     // Set Basic Information cluster attributes
     endpoint_t *root_ep = endpoint::get_first(s_node);
     if (root_ep) {
@@ -186,12 +326,6 @@ int matter_bridge_init(matter_position_cb_t position_cb,
         }
     }
 
-    // Derive discriminator from EUI-64 lower 12 bits for unique BLE advertising
-    uint8_t mac[8] = {};
-    esp_read_mac(mac, ESP_MAC_IEEE802154);
-    uint16_t discriminator = ((uint16_t)mac[6] << 4 | (mac[7] >> 4)) & 0x0FFF;
-    ESP_LOGI(TAG, "Discriminator derived from EUI-64: %u", discriminator);
-
     ESP_LOGI(TAG, "Matter node initialized (VID=0xFFF1, PID=0x8001, disc=%u)", discriminator);
     return 0;
 }
@@ -244,6 +378,17 @@ void matter_bridge_update_operational_status(uint8_t status)
                      WindowCovering::Attributes::OperationalStatus::Id, &val);
 }
 
+void matter_bridge_update_mode(uint8_t mode)
+{
+    ESP_LOGI(TAG, "Reporting airflow mode: %u", mode);
+
+    esp_matter_attr_val_t val = esp_matter_uint8(mode);
+    s_reporting_mode = true;
+    attribute::update(s_endpoint_id, ModeSelect::Id,
+                     ModeSelect::Attributes::CurrentMode::Id, &val);
+    s_reporting_mode = false;
+}
+
 bool matter_bridge_is_commissioned(void)
 {
     auto &server = chip::Server::GetInstance();
@@ -0,0 +1,258 @@
+use log::{info, warn};
+use std::net::{Ipv6Addr, SocketAddrV6, UdpSocket};
+use vent_protocol::{Confidence, HealthMin, PollReport, StatusBeacon};
+
+/// Realm-local all-nodes group, so beacons reach the whole Thread mesh.
+pub const BEACON_GROUP: Ipv6Addr = Ipv6Addr::new(0xff03, 0, 0, 0, 0, 0, 0, 1);
+pub const BEACON_PORT: u16 = 5683;
+/// CoAP resource the beacon is POSTed to.
+pub const BEACON_PATH: &str = "beacon";
+
+/// CoAP resource a sleepy vent POSTs its post-poll report to.
+pub const POLL_REPORT_PATH: &str = "report";
+
+/// Payload for the report a sleepy vent sends after each poll.
+pub fn build_poll_report(angle: u8, health: HealthMin, confidence: Confidence) -> Vec<u8> {
+    PollReport {
+        angle,
+        health,
+        confidence: Some(confidence),
+    }
+    .to_bytes()
+    .to_vec()
+}
+
+/// Decides when the next beacon is due. An interval of 0 disables beacons
+/// (the default, to save battery).
+#[derive(Debug)]
+pub struct BeaconGate {
+    interval_ms: u64,
+    last_sent_ms: Option<u64>,
+}
+
+impl BeaconGate {
+    pub fn new(interval_ms: u64) -> Self {
+        Self {
+            interval_ms,
+            last_sent_ms: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.interval_ms > 0
+    }
+
+    /// True if a beacon should go out at `now_ms`; records the send.
+    pub fn poll(&mut self, now_ms: u64) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        let due = match self.last_sent_ms {
+            None => true,
+            Some(last) => now_ms.saturating_sub(last) >= self.interval_ms,
+        };
+        if due {
+            self.last_sent_ms = Some(now_ms);
+        }
+        due
+    }
+}
+
+/// Build a non-confirmable CoAP POST with a single Uri-Path segment.
+pub fn coap_non_post(message_id: u16, path: &str, payload: &[u8]) -> Vec<u8> {
+    debug_assert!(path.len() < 13, "path needs extended option length");
+    let mut msg = Vec::with_capacity(4 + 1 + path.len() + 1 + payload.len());
+    // Version 1, type NON (1), token length 0
+    msg.push(0x50);
+    // Code 0.02 POST
+    msg.push(0x02);
+    msg.extend_from_slice(&message_id.to_be_bytes());
+    // Uri-Path is option 11; first option, so delta = 11
+    msg.push((11 << 4) | path.len() as u8);
+    msg.extend_from_slice(path.as_bytes());
+    if !payload.is_empty() {
+        msg.push(0xff);
+        msg.extend_from_slice(payload);
+    }
+    msg
+}
+
+/// CoAP Max-Age option number.
+pub const OPTION_MAX_AGE: u16 = 14;
+
+/// Max-Age (seconds) per resource. Build and capabilities only change with
+/// a firmware update, so coordinators may cache them and spare a SED the
+/// wake; presets change rarely; position and health must always be
+/// fetched fresh.
+const MAX_AGE_TABLE: &[(&str, u32)] = &[
+    ("device/build", 3600),
+    ("device/capabilities", 3600),
+    ("device/presets", 300),
+    ("vent/position", 0),
+    ("device/health", 0),
+];
+
+/// Max-Age for a resource path. Unlisted resources are treated as volatile.
+pub fn max_age_s(path: &str) -> u32 {
+    let path = path.trim_matches('/');
+    MAX_AGE_TABLE
+        .iter()
+        .find(|(p, _)| *p == path)
+        .map_or(0, |(_, age)| *age)
+}
+
+/// Encode a uint-valued CoAP option with minimal-length value, `delta`
+/// being the option number minus the previous option's (RFC 7252 §3.1).
+pub fn encode_uint_option(delta: u16, value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let value = &bytes[value.leading_zeros() as usize / 8..];
+    let mut out = Vec::with_capacity(3 + value.len());
+    match delta {
+        0..=12 => out.push(((delta as u8) << 4) | value.len() as u8),
+        13..=268 => {
+            out.push((13 << 4) | value.len() as u8);
+            out.push((delta - 13) as u8);
+        }
+        _ => {
+            out.push((14 << 4) | value.len() as u8);
+            out.extend_from_slice(&(delta - 269).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+/// Sends status beacons to the multicast group.
+pub struct BeaconSender {
+    socket: Option<UdpSocket>,
+    message_id: u16,
+}
+
+impl BeaconSender {
+    pub fn new() -> Self {
+        Self {
+            socket: None,
+            message_id: 0,
+        }
+    }
+
+    /// Send one beacon. The socket is opened on first use so a disabled
+    /// beacon costs nothing.
+    pub fn send(&mut self, beacon: &StatusBeacon) {
+        let dest = SocketAddrV6::new(BEACON_GROUP, BEACON_PORT, 0, 0);
+        self.post(dest, BEACON_PATH, &beacon.to_bytes());
+    }
+
+    /// Send a post-poll report to the registered coordinator.
+    pub fn send_poll_report(&mut self, coordinator: SocketAddrV6, payload: &[u8]) {
+        self.post(coordinator, POLL_REPORT_PATH, payload);
+    }
+
+    fn post(&mut self, dest: SocketAddrV6, path: &str, payload: &[u8]) {
+        if self.socket.is_none() {
+            match UdpSocket::bind("[::]:0") {
+                Ok(sock) => {
+                    info!("Status beacon socket bound");
+                    self.socket = Some(sock);
+                }
+                Err(e) => {
+                    warn!("Failed to open beacon socket: {}", e);
+                    return;
+                }
+            }
+        }
+        self.message_id = self.message_id.wrapping_add(1);
+        let msg = coap_non_post(self.message_id, path, payload);
+        if let Some(sock) = self.socket.as_ref() {
+            if let Err(e) = sock.send_to(&msg, dest) {
+                warn!("{} send failed: {}", path, e);
+            }
+        }
+    }
+}
+
+impl Default for BeaconSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate_disabled_by_default() {
+        let mut gate = BeaconGate::new(0);
+        assert!(!gate.enabled());
+        for now in [0, 1_000, 1_000_000] {
+            assert!(!gate.poll(now));
+        }
+    }
+
+    #[test]
+    fn test_gate_interval() {
+        let mut gate = BeaconGate::new(60_000);
+        assert!(gate.poll(5_000));
+        assert!(!gate.poll(30_000));
+        assert!(!gate.poll(64_999));
+        assert!(gate.poll(65_000));
+        assert!(!gate.poll(65_001));
+    }
+
+    #[test]
+    fn test_poll_report_roundtrip() {
+        let health = HealthMin {
+            battery_pct: Some(40),
+            rssi: -85,
+            state: vent_protocol::VentState::Open,
+            servo_power_fault: false,
+            clock_unsynced: false,
+            crash_loop: false,
+            matter_unavailable: false,
+        };
+        let payload = build_poll_report(180, health, Confidence::Medium);
+        assert_eq!(
+            PollReport::from_bytes(&payload),
+            Some(PollReport {
+                angle: 180,
+                health,
+                confidence: Some(Confidence::Medium)
+            })
+        );
+        // Fits in a single-segment NON POST alongside the path
+        let msg = coap_non_post(1, POLL_REPORT_PATH, &payload);
+        assert_eq!(&msg[msg.len() - payload.len()..], payload.as_slice());
+    }
+
+    #[test]
+    fn test_max_age_table() {
+        assert_eq!(max_age_s("device/build"), 3600);
+        assert_eq!(max_age_s("/device/capabilities"), 3600);
+        assert_eq!(max_age_s("device/presets"), 300);
+        assert_eq!(max_age_s("vent/position"), 0);
+        assert_eq!(max_age_s("device/health"), 0);
+        // Unknown resources are never cached
+        assert_eq!(max_age_s("vent/target"), 0);
+    }
+
+    #[test]
+    fn test_max_age_option_encoding() {
+        // First option in a response: delta 14 needs the 1-byte extension
+        assert_eq!(encode_uint_option(OPTION_MAX_AGE, 3600), vec![0xd2, 0x01, 0x0e, 0x10]);
+        // After Uri-Path (11) the delta fits the header nibble
+        assert_eq!(encode_uint_option(OPTION_MAX_AGE - 11, 300), vec![0x32, 0x01, 0x2c]);
+        // Zero is sent as an empty value
+        assert_eq!(encode_uint_option(3, 0), vec![0x30]);
+        assert_eq!(encode_uint_option(300, 1), vec![0xe1, 0x00, 0x1f, 0x01]);
+    }
+
+    #[test]
+    fn test_coap_non_post_layout() {
+        let msg = coap_non_post(0x1234, "beacon", &[1, 2]);
+        assert_eq!(&msg[..4], &[0x50, 0x02, 0x12, 0x34]);
+        assert_eq!(msg[4], 0xb6);
+        assert_eq!(&msg[5..11], b"beacon");
+        assert_eq!(&msg[11..], &[0xff, 1, 2]);
+    }
+}
@@ -0,0 +1,115 @@
+/// Full open/close cycles when the installer doesn't pick a count.
+pub const DEFAULT_BREAKIN_CYCLES: u8 = 3;
+/// Upper bound so a typo can't wear a new servo out.
+pub const MAX_BREAKIN_CYCLES: u8 = 20;
+/// Minimum step delay during break-in; the slow sweep is gentler on stiff
+/// louvers than normal speed.
+pub const BREAKIN_STEP_DELAY_MS: u32 = 40;
+
+/// Why a break-in run was started, which decides what is recorded when
+/// it finishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BreakInPurpose {
+    /// Requested break-in of a new install; marks `breakin_done`.
+    #[default]
+    Install,
+    /// One-cycle check on first boot; marks the first-boot cycle done.
+    FirstBootCheck,
+}
+
+/// Whether to run the one-cycle install check: a single slow open/close
+/// on a fresh device, so the installer sees the linkage move. It runs once,
+/// on first boot only, unless disabled.
+pub fn should_run_first_boot_cycle(enabled: bool, first_boot: bool, done: bool) -> bool {
+    enabled && first_boot && !done
+}
+
+/// Endpoints visited by a break-in: `open` then `closed`, once per cycle.
+/// The caller passes the ends of the travel commands are allowed to
+/// reach, e.g. `ANGLE_OPEN`/`ANGLE_CLOSED` narrowed by soft limits and the
+/// airflow floor.
+pub fn breakin_sequence(cycles: u8, closed: u8, open: u8) -> Vec<u8> {
+    (0..cycles.min(MAX_BREAKIN_CYCLES))
+        .flat_map(|_| [open, closed])
+        .collect()
+}
+
+/// A running break-in: the endpoint sequence followed by a return to the
+/// angle the vent held before it started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakIn {
+    targets: Vec<u8>,
+    next: usize,
+}
+
+impl BreakIn {
+    pub fn new(cycles: u8, closed: u8, open: u8, restore_angle: u8) -> Self {
+        let mut targets = breakin_sequence(cycles, closed, open);
+        targets.push(restore_angle);
+        Self { targets, next: 0 }
+    }
+
+    /// Next target to drive to, or None once the sequence is exhausted.
+    pub fn next_target(&mut self) -> Option<u8> {
+        let target = self.targets.get(self.next).copied()?;
+        self.next += 1;
+        Some(target)
+    }
+
+    /// Legs started so far and the total, for progress reporting.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.next, self.targets.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vent_protocol::{ANGLE_CLOSED, ANGLE_OPEN};
+
+    #[test]
+    fn test_first_boot_cycle_only_on_first_boot() {
+        assert!(should_run_first_boot_cycle(true, true, false));
+        assert!(!should_run_first_boot_cycle(true, false, false));
+    }
+
+    #[test]
+    fn test_first_boot_cycle_disabled_or_done() {
+        assert!(!should_run_first_boot_cycle(false, true, false));
+        // e.g. NVS init flag erased but the cycle already ran
+        assert!(!should_run_first_boot_cycle(true, true, true));
+    }
+
+    #[test]
+    fn test_sequence_visits_each_endpoint_per_cycle() {
+        let seq = breakin_sequence(3, ANGLE_CLOSED, ANGLE_OPEN);
+        assert_eq!(seq.len(), 6);
+        assert_eq!(seq.iter().filter(|&&a| a == ANGLE_OPEN).count(), 3);
+        assert_eq!(seq.iter().filter(|&&a| a == ANGLE_CLOSED).count(), 3);
+        // Alternates, so every leg is a full stroke
+        assert!(seq.windows(2).all(|w| w[0] != w[1]));
+        assert!(breakin_sequence(0, ANGLE_CLOSED, ANGLE_OPEN).is_empty());
+    }
+
+    #[test]
+    fn test_sequence_stays_within_given_travel() {
+        let seq = breakin_sequence(2, 110, 170);
+        assert_eq!(seq, vec![170, 110, 170, 110]);
+    }
+
+    #[test]
+    fn test_sequence_capped() {
+        assert_eq!(breakin_sequence(255, ANGLE_CLOSED, ANGLE_OPEN).len(), MAX_BREAKIN_CYCLES as usize * 2);
+    }
+
+    #[test]
+    fn test_breakin_ends_at_restore_angle() {
+        let mut run = BreakIn::new(1, ANGLE_CLOSED, ANGLE_OPEN, 135);
+        assert_eq!(run.progress(), (0, 3));
+        assert_eq!(run.next_target(), Some(ANGLE_OPEN));
+        assert_eq!(run.next_target(), Some(ANGLE_CLOSED));
+        assert_eq!(run.next_target(), Some(135));
+        assert_eq!(run.progress(), (3, 3));
+        assert_eq!(run.next_target(), None);
+    }
+}
@@ -0,0 +1,147 @@
+use esp_idf_hal::ledc::LedcDriver;
+use esp_idf_sys::EspError;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Audible feedback events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuzzerEvent {
+    MoveStart,
+    MoveEnd,
+    Identify,
+}
+
+/// One tone in a pattern. A frequency of 0 is a rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tone {
+    pub freq_hz: u32,
+    pub duration_ms: u32,
+}
+
+const fn tone(freq_hz: u32, duration_ms: u32) -> Tone {
+    Tone { freq_hz, duration_ms }
+}
+
+const MOVE_START: &[Tone] = &[tone(2000, 60)];
+const MOVE_END: &[Tone] = &[tone(2500, 60), tone(0, 40), tone(1500, 90)];
+const IDENTIFY: &[Tone] = &[
+    tone(3000, 100),
+    tone(0, 100),
+    tone(3000, 100),
+    tone(0, 100),
+    tone(3000, 100),
+];
+
+/// Map an event to its tone pattern. Start is a single chirp, end a
+/// falling two-note pair, identify a repeated beep — distinguishable by ear.
+pub fn tone_pattern(event: BuzzerEvent) -> &'static [Tone] {
+    match event {
+        BuzzerEvent::MoveStart => MOVE_START,
+        BuzzerEvent::MoveEnd => MOVE_END,
+        BuzzerEvent::Identify => IDENTIFY,
+    }
+}
+
+/// Buzzer settings persisted in NVS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuzzerConfig {
+    pub enabled: bool,
+    /// Loudness as PWM duty, 0–100 (100 = 50% duty, the loudest for a piezo).
+    pub volume_pct: u8,
+}
+
+impl Default for BuzzerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            volume_pct: 50,
+        }
+    }
+}
+
+/// LEDC timer driving the buzzer (the servo owns timer 0).
+const BUZZER_TIMER: esp_idf_sys::ledc_timer_t = esp_idf_sys::ledc_timer_t_LEDC_TIMER_1;
+
+/// Piezo buzzer on its own LEDC timer/channel.
+pub struct BuzzerDriver<'d> {
+    ledc: LedcDriver<'d>,
+    max_duty: u32,
+    config: BuzzerConfig,
+}
+
+impl<'d> BuzzerDriver<'d> {
+    pub fn new(ledc: LedcDriver<'d>, config: BuzzerConfig) -> Self {
+        let max_duty = ledc.get_max_duty();
+        Self {
+            ledc,
+            max_duty,
+            config,
+        }
+    }
+
+    /// Play the pattern for `event`. Blocks for the pattern duration.
+    pub fn play(&mut self, event: BuzzerEvent) -> Result<(), EspError> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let duty = self.max_duty / 2 * self.config.volume_pct.min(100) as u32 / 100;
+        for t in tone_pattern(event) {
+            if t.freq_hz == 0 {
+                self.ledc.set_duty(0)?;
+            } else {
+                esp_idf_sys::esp!(unsafe {
+                    esp_idf_sys::ledc_set_freq(
+                        esp_idf_sys::ledc_mode_t_LEDC_LOW_SPEED_MODE,
+                        BUZZER_TIMER,
+                        t.freq_hz,
+                    )
+                })?;
+                self.ledc.set_duty(duty)?;
+            }
+            sleep(Duration::from_millis(t.duration_ms as u64));
+        }
+        self.ledc.set_duty(0)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total_ms(pattern: &[Tone]) -> u32 {
+        pattern.iter().map(|t| t.duration_ms).sum()
+    }
+
+    #[test]
+    fn test_each_event_has_audible_tone() {
+        for event in [BuzzerEvent::MoveStart, BuzzerEvent::MoveEnd, BuzzerEvent::Identify] {
+            let pattern = tone_pattern(event);
+            assert!(pattern.iter().any(|t| t.freq_hz > 0), "{:?} is silent", event);
+        }
+    }
+
+    #[test]
+    fn test_start_and_end_are_distinct() {
+        assert_ne!(
+            tone_pattern(BuzzerEvent::MoveStart),
+            tone_pattern(BuzzerEvent::MoveEnd)
+        );
+    }
+
+    #[test]
+    fn test_move_patterns_are_short() {
+        // Move chirps must not noticeably delay the servo
+        assert!(total_ms(tone_pattern(BuzzerEvent::MoveStart)) <= 200);
+        assert!(total_ms(tone_pattern(BuzzerEvent::MoveEnd)) <= 200);
+    }
+
+    #[test]
+    fn test_tones_within_piezo_range() {
+        for event in [BuzzerEvent::MoveStart, BuzzerEvent::MoveEnd, BuzzerEvent::Identify] {
+            for t in tone_pattern(event) {
+                assert!(t.freq_hz == 0 || (500..=5000).contains(&t.freq_hz));
+            }
+        }
+    }
+}
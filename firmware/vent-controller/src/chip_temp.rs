@@ -0,0 +1,80 @@
+use crate::sensors::{Sensor, SensorKind};
+use esp_idf_sys::EspError;
+
+/// Die temperature (°C) at which servo moves are held. Well above normal
+/// running temperature; reaching it means a stalled servo or attic heat.
+pub const CHIP_TEMP_LIMIT_C: i32 = 90;
+/// How often the die temperature is sampled.
+pub const CHIP_TEMP_POLL_MS: u64 = 5_000;
+/// How long a held move waits before checking the temperature again.
+pub const THERMAL_RECHECK_MS: u64 = 1_000;
+
+/// Whether the chip is too hot to keep driving the servo.
+pub fn over_temp(temp_c: i32, limit_c: i32) -> bool {
+    temp_c >= limit_c
+}
+
+/// The ESP32-C6 on-die temperature sensor.
+pub struct ChipTemp {
+    handle: esp_idf_sys::temperature_sensor_handle_t,
+}
+
+// The handle is only ever used from the main loop through the sensor
+// scheduler, which sits behind the app state lock.
+unsafe impl Send for ChipTemp {}
+
+impl ChipTemp {
+    /// Install and enable the sensor, measuring over its 20–100 °C range.
+    pub fn new() -> Result<Self, EspError> {
+        let config = esp_idf_sys::temperature_sensor_config_t {
+            range_min: 20,
+            range_max: 100,
+            clk_src: esp_idf_sys::soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+            ..Default::default()
+        };
+        let mut handle = std::ptr::null_mut();
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::temperature_sensor_install(&config, &mut handle))?;
+            esp_idf_sys::esp!(esp_idf_sys::temperature_sensor_enable(handle))?;
+        }
+        Ok(Self { handle })
+    }
+
+    /// Current die temperature, rounded to whole degrees.
+    pub fn read_celsius(&mut self) -> Result<i32, EspError> {
+        let mut celsius = 0f32;
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::temperature_sensor_get_celsius(self.handle, &mut celsius))?;
+        }
+        Ok(celsius.round() as i32)
+    }
+}
+
+impl Sensor for ChipTemp {
+    fn kind(&self) -> SensorKind {
+        SensorKind::ChipTemperature
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        self.read_celsius().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_over_temp_at_limit() {
+        assert!(!over_temp(89, 90));
+        assert!(over_temp(90, 90));
+        assert!(over_temp(91, 90));
+    }
+
+    #[test]
+    fn test_over_temp_cold_and_negative() {
+        assert!(!over_temp(-20, 90));
+        assert!(!over_temp(0, 1));
+        assert!(over_temp(-5, -10));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::pressure::crc8;
+use crate::sensors::{Sensor, SensorKind, SensorScheduler};
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::i2c::I2cDriver;
+use esp_idf_sys::EspError;
+use log::warn;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often temperature and humidity are sampled. The heater only
+/// re-evaluates once a minute, so this just keeps its input fresh.
+pub const CLIMATE_POLL_MS: u64 = 30_000;
+
+/// I2C address of the Sensirion SHT4x temperature/humidity sensor.
+const SHT4X_ADDR: u8 = 0x44;
+/// Measure temperature and humidity at high repeatability.
+const CMD_MEASURE_HIGH: u8 = 0xfd;
+/// Worst-case high-repeatability measurement time.
+const MEASURE_TIME: Duration = Duration::from_millis(10);
+/// The temperature and humidity channels are read back to back; one
+/// measurement serves both.
+const REUSE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Parse a 6-byte SHT4x frame (temperature word, humidity word, each with
+/// a CRC) into milli-°C and hundredths of a percent RH, the latter clamped
+/// to 0–100 %. None on CRC mismatch.
+pub fn parse_measurement(frame: &[u8; 6]) -> Option<(i32, i32)> {
+    for word in frame.chunks_exact(3) {
+        if crc8(&word[..2]) != word[2] {
+            return None;
+        }
+    }
+    let raw_t = u16::from_be_bytes([frame[0], frame[1]]) as i64;
+    let raw_rh = u16::from_be_bytes([frame[3], frame[4]]) as i64;
+    let temp_mc = -45_000 + 175_000 * raw_t / 65_535;
+    let rh_centi = (-600 + 12_500 * raw_rh / 65_535).clamp(0, 10_000);
+    Some((temp_mc as i32, rh_centi as i32))
+}
+
+/// SHT4x on the I2C bus, for the anti-condensation heater.
+pub struct ClimateSensor<'d> {
+    i2c: I2cDriver<'d>,
+    last: Option<(Instant, i32, i32)>,
+}
+
+impl<'d> ClimateSensor<'d> {
+    pub fn new(i2c: I2cDriver<'d>) -> Self {
+        Self { i2c, last: None }
+    }
+
+    /// Temperature (milli-°C) and relative humidity (hundredths of a
+    /// percent), measuring afresh unless the last result is very recent.
+    pub fn sample(&mut self) -> Option<(i32, i32)> {
+        if let Some((at, temp_mc, rh_centi)) = self.last {
+            if at.elapsed() < REUSE_WINDOW {
+                return Some((temp_mc, rh_centi));
+            }
+        }
+        match self.measure() {
+            Ok(Some((temp_mc, rh_centi))) => {
+                self.last = Some((Instant::now(), temp_mc, rh_centi));
+                Some((temp_mc, rh_centi))
+            }
+            Ok(None) => {
+                warn!("Climate sensor frame failed its CRC");
+                None
+            }
+            Err(e) => {
+                warn!("Climate sensor read failed: {:?}", e);
+                None
+            }
+        }
+    }
+
+    fn measure(&mut self) -> Result<Option<(i32, i32)>, EspError> {
+        self.i2c.write(SHT4X_ADDR, &[CMD_MEASURE_HIGH], BLOCK)?;
+        std::thread::sleep(MEASURE_TIME);
+        let mut frame = [0u8; 6];
+        self.i2c.read(SHT4X_ADDR, &mut frame, BLOCK)?;
+        Ok(parse_measurement(&frame))
+    }
+}
+
+/// One quantity of a shared `ClimateSensor`, so the scheduler can cache
+/// temperature and humidity under their own kinds.
+struct ClimateChannel<'d> {
+    sensor: Arc<Mutex<ClimateSensor<'d>>>,
+    kind: SensorKind,
+}
+
+impl Sensor for ClimateChannel<'_> {
+    fn kind(&self) -> SensorKind {
+        self.kind
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        let (temp_mc, rh_centi) = self.sensor.lock().unwrap_or_else(|e| e.into_inner()).sample()?;
+        match self.kind {
+            SensorKind::Humidity => Some(rh_centi),
+            _ => Some(temp_mc),
+        }
+    }
+}
+
+/// Register the sensor's temperature and humidity with the scheduler.
+pub fn register(i2c: I2cDriver<'static>, sensors: &mut SensorScheduler) {
+    let sensor = Arc::new(Mutex::new(ClimateSensor::new(i2c)));
+    for kind in [SensorKind::Temperature, SensorKind::Humidity] {
+        let channel = ClimateChannel {
+            sensor: sensor.clone(),
+            kind,
+        };
+        sensors.register(Box::new(channel), CLIMATE_POLL_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_measurement() {
+        // Raw 0x6666 ≈ 25.0 °C, raw 0x8000 ≈ 56.5 % RH
+        let frame = [0x66, 0x66, 0x93, 0x80, 0x00, 0xa2];
+        assert_eq!(parse_measurement(&frame), Some((25_000, 5_650)));
+    }
+
+    #[test]
+    fn test_parse_measurement_rejects_bad_crc() {
+        let frame = [0x66, 0x66, 0x92, 0x80, 0x00, 0xa2];
+        assert_eq!(parse_measurement(&frame), None);
+    }
+
+    #[test]
+    fn test_parse_measurement_clamps_humidity() {
+        // Raw 0 reads as -6 % RH, raw 0xffff as 119 %
+        let (_, rh) = parse_measurement(&[0x00, 0x00, 0x81, 0x00, 0x00, 0x81]).unwrap();
+        assert_eq!(rh, 0);
+        let (_, rh) = parse_measurement(&[0x00, 0x00, 0x81, 0xff, 0xff, 0xac]).unwrap();
+        assert_eq!(rh, 10_000);
+    }
+}
@@ -0,0 +1,1383 @@
+use crate::beacon::{encode_uint_option, max_age_s, OPTION_MAX_AGE};
+use crate::breakin::DEFAULT_BREAKIN_CYCLES;
+use crate::command::{self, BreakInError, CommandSource, ConfigUpdateError, RebootError, RejectReason};
+use crate::config::{config_from_json, ConfigError};
+use crate::identity::build_info;
+use crate::logbuf;
+use crate::matter;
+use crate::motion::{decode_curve, encode_curve};
+use crate::presets::{decode_presets, encode_presets};
+use crate::pressure;
+use crate::state::AppState;
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use vent_protocol::{
+    encode_channel_energy, encode_neighbors, encode_scan_results, DeviceType, FieldMask, TargetRequest,
+    PROTOCOL_VERSION,
+};
+
+/// UDP port the CoAP server listens on.
+pub const COAP_PORT: u16 = 5683;
+/// Largest datagram read; anything longer is truncated and fails to parse.
+const MAX_MESSAGE: usize = 1152;
+/// Default cap on replies waiting for the socket (NVS `coap_inflight`).
+pub const DEFAULT_MAX_IN_FLIGHT: u8 = 4;
+/// Default cap on simultaneous observers (NVS `coap_observers`).
+pub const DEFAULT_MAX_OBSERVERS: u8 = 4;
+/// Notifications left unacknowledged in a row before an observer is
+/// dropped as stale.
+pub const OBSERVER_MAX_FAILURES: u8 = 3;
+/// Block size exponent for `device/logs`: 2^(5+4) = 512-byte blocks, well
+/// inside one Thread frame after fragmentation. Clients may ask for less.
+const LOG_BLOCK_SZX: u32 = 5;
+/// Resources a client can observe (RFC 7641).
+const OBSERVABLE: &[&str] = &["vent/position", "vent/status"];
+
+/// CoAP option numbers the server understands (RFC 7252 §5.10).
+const OPTION_URI_HOST: u16 = 3;
+const OPTION_OBSERVE: u16 = 6;
+const OPTION_URI_PORT: u16 = 7;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_URI_QUERY: u16 = 15;
+const OPTION_ACCEPT: u16 = 17;
+const OPTION_BLOCK2: u16 = 23;
+
+/// Content-Format for `text/plain; charset=utf-8`.
+const CONTENT_FORMAT_TEXT: u32 = 0;
+/// Content-Format for `application/json`.
+const CONTENT_FORMAT_JSON: u32 = 50;
+
+/// Request method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl Method {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0x01 => Some(Method::Get),
+            0x02 => Some(Method::Post),
+            0x03 => Some(Method::Put),
+            0x04 => Some(Method::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Response code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Changed,
+    Content,
+    BadRequest,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    PreconditionFailed,
+    RequestEntityTooLarge,
+    UnsupportedContentFormat,
+    InternalServerError,
+    ServiceUnavailable,
+}
+
+impl Code {
+    /// The code byte, class in the top 3 bits and detail below.
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Code::Changed => 0x44,
+            Code::Content => 0x45,
+            Code::BadRequest => 0x80,
+            Code::Forbidden => 0x83,
+            Code::NotFound => 0x84,
+            Code::MethodNotAllowed => 0x85,
+            Code::PreconditionFailed => 0x8c,
+            Code::RequestEntityTooLarge => 0x8d,
+            Code::UnsupportedContentFormat => 0x8f,
+            Code::InternalServerError => 0xa0,
+            Code::ServiceUnavailable => 0xa3,
+        }
+    }
+}
+
+/// A parsed request. Uri-Path segments are joined with `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub confirmable: bool,
+    pub method: Method,
+    pub message_id: u16,
+    pub token: Vec<u8>,
+    pub path: String,
+    pub query: Vec<String>,
+    /// Content-Format option, None when absent.
+    pub content_format: Option<u32>,
+    /// Observe option: 0 registers, 1 deregisters.
+    pub observe: Option<u32>,
+    /// Block2 option (RFC 7959) as its raw uint value.
+    pub block2: Option<u32>,
+    pub payload: Vec<u8>,
+}
+
+/// A response to send back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub code: Code,
+    pub payload: Vec<u8>,
+    /// Block2 option for one block of a larger body (`block2_value`).
+    pub block2: Option<u32>,
+}
+
+impl Response {
+    pub fn new(code: Code) -> Self {
+        Self {
+            code,
+            payload: Vec::new(),
+            block2: None,
+        }
+    }
+
+    pub fn with_payload(code: Code, payload: Vec<u8>) -> Self {
+        Self {
+            code,
+            payload,
+            block2: None,
+        }
+    }
+}
+
+/// Block2 option value: block number, more flag, size exponent (block
+/// size 2^(szx+4)).
+pub fn block2_value(num: u32, more: bool, szx: u32) -> u32 {
+    (num << 4) | ((more as u32) << 3) | (szx & 0x07)
+}
+
+/// Read an option's extended delta or length field (RFC 7252 §3.1).
+fn extended(nibble: u8, raw: &[u8], pos: &mut usize) -> Option<u16> {
+    match nibble {
+        0..=12 => Some(nibble as u16),
+        13 => {
+            let v = *raw.get(*pos)? as u16 + 13;
+            *pos += 1;
+            Some(v)
+        }
+        14 => {
+            let v = u16::from_be_bytes([*raw.get(*pos)?, *raw.get(*pos + 1)?]).checked_add(269)?;
+            *pos += 2;
+            Some(v)
+        }
+        _ => None,
+    }
+}
+
+/// Parse a CoAP request. None for anything that isn't a well-formed
+/// version-1 request, or one carrying a critical option we don't know.
+pub fn parse_request(raw: &[u8]) -> Option<Request> {
+    let (&first, _) = raw.split_first()?;
+    if raw.len() < 4 || first >> 6 != 1 {
+        return None;
+    }
+    let confirmable = match (first >> 4) & 0x03 {
+        0 => true,
+        1 => false,
+        _ => return None,
+    };
+    let token_len = (first & 0x0f) as usize;
+    if token_len > 8 {
+        return None;
+    }
+    let method = Method::from_code(raw[1])?;
+    let message_id = u16::from_be_bytes([raw[2], raw[3]]);
+    let token = raw.get(4..4 + token_len)?.to_vec();
+
+    let mut path = Vec::new();
+    let mut query = Vec::new();
+    let mut content_format = None;
+    let mut observe = None;
+    let mut block2 = None;
+    let mut payload = Vec::new();
+    let mut number = 0u16;
+    let mut pos = 4 + token_len;
+    while let Some(&byte) = raw.get(pos) {
+        pos += 1;
+        if byte == 0xff {
+            // A payload marker must be followed by a payload
+            payload = raw.get(pos..).filter(|p| !p.is_empty())?.to_vec();
+            break;
+        }
+        let delta = extended(byte >> 4, raw, &mut pos)?;
+        let len = extended(byte & 0x0f, raw, &mut pos)? as usize;
+        number = number.checked_add(delta)?;
+        let value = raw.get(pos..pos + len)?;
+        pos += len;
+        match number {
+            OPTION_URI_PATH => path.push(core::str::from_utf8(value).ok()?.to_string()),
+            OPTION_URI_QUERY => query.push(core::str::from_utf8(value).ok()?.to_string()),
+            OPTION_CONTENT_FORMAT if len <= 2 => {
+                content_format = Some(value.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32))
+            }
+            OPTION_CONTENT_FORMAT => return None,
+            OPTION_OBSERVE if len <= 3 => observe = Some(value.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32)),
+            OPTION_OBSERVE => return None,
+            OPTION_BLOCK2 if len <= 3 => block2 = Some(value.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32)),
+            OPTION_BLOCK2 => return None,
+            OPTION_URI_HOST | OPTION_URI_PORT | OPTION_ACCEPT => {}
+            // Unknown elective options (even numbers) may be ignored
+            n if n % 2 == 1 => return None,
+            _ => {}
+        }
+    }
+    Some(Request {
+        confirmable,
+        method,
+        message_id,
+        token,
+        path: path.join("/"),
+        query,
+        content_format,
+        observe,
+        block2,
+        payload,
+    })
+}
+
+/// An empty ACK or RST: the message ID it answers, and whether it is a
+/// RST. None for anything else.
+pub fn parse_empty(raw: &[u8]) -> Option<(u16, bool)> {
+    if raw.len() != 4 || raw[0] >> 6 != 1 || raw[1] != 0 {
+        return None;
+    }
+    let message_id = u16::from_be_bytes([raw[2], raw[3]]);
+    match (raw[0] >> 4) & 0x03 {
+        2 => Some((message_id, false)),
+        3 => Some((message_id, true)),
+        _ => None,
+    }
+}
+
+/// Encode the reply to `req`: a piggybacked ACK for a confirmable
+/// request, else a NON with `message_id`. Content carries the resource's
+/// Max-Age (`max_age_s`), explicitly 0 for volatile ones, since an absent
+/// option would let a cache keep them for 60 s.
+pub fn build_response(req: &Request, message_id: u16, resp: &Response) -> Vec<u8> {
+    build_message(req, message_id, None, resp)
+}
+
+/// As `build_response`, with an Observe option carrying `observe`.
+fn build_message(req: &Request, message_id: u16, observe: Option<u32>, resp: &Response) -> Vec<u8> {
+    let (msg_type, message_id) = if req.confirmable {
+        (2, req.message_id)
+    } else {
+        (1, message_id)
+    };
+    encode_message(msg_type, message_id, &req.token, &req.path, observe, resp)
+}
+
+fn encode_message(
+    msg_type: u8,
+    message_id: u16,
+    token: &[u8],
+    path: &str,
+    observe: Option<u32>,
+    resp: &Response,
+) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(4 + token.len() + 8 + 1 + resp.payload.len());
+    msg.push(0x40 | (msg_type << 4) | token.len() as u8);
+    msg.push(resp.code.as_u8());
+    msg.extend_from_slice(&message_id.to_be_bytes());
+    msg.extend_from_slice(token);
+    let mut last = 0;
+    if let Some(seq) = observe {
+        msg.extend_from_slice(&encode_uint_option(OPTION_OBSERVE, seq));
+        last = OPTION_OBSERVE;
+    }
+    if resp.code == Code::Content {
+        msg.extend_from_slice(&encode_uint_option(OPTION_MAX_AGE - last, max_age_s(path)));
+        last = OPTION_MAX_AGE;
+    }
+    if let Some(block) = resp.block2 {
+        msg.extend_from_slice(&encode_uint_option(OPTION_BLOCK2 - last, block));
+    }
+    if !resp.payload.is_empty() {
+        msg.push(0xff);
+        msg.extend_from_slice(&resp.payload);
+    }
+    msg
+}
+
+/// Route a request to its resource.
+pub fn handle(s: &mut AppState, req: &Request) -> Response {
+    match (req.path.as_str(), req.method) {
+        ("vent/target", Method::Post | Method::Put) => post_target(s, req),
+        ("vent/target", _) => Response::new(Code::MethodNotAllowed),
+        ("vent/position", Method::Get) => {
+            Response::with_payload(Code::Content, s.position_report().to_bytes().to_vec())
+        }
+        ("vent/position", _) => Response::new(Code::MethodNotAllowed),
+        ("vent/status", Method::Get) => match field_mask(req) {
+            Some(mask) => Response::with_payload(
+                Code::Content,
+                s.vent_status().to_bytes_selected(mask.unwrap_or(FieldMask::ALL)),
+            ),
+            None => Response::new(Code::BadRequest),
+        },
+        ("vent/status", _) => Response::new(Code::MethodNotAllowed),
+        ("vent/preset", Method::Put | Method::Post) => put_preset(s, req),
+        ("vent/preset", _) => Response::new(Code::MethodNotAllowed),
+        ("vent/pressure", Method::Get) => {
+            let now_ms = s.start_time.elapsed().as_millis() as u64;
+            match pressure::pressure_data(&s.sensors, now_ms, s.pressure_control.as_ref()) {
+                Some(data) => Response::with_payload(Code::Content, data.to_bytes().to_vec()),
+                None => Response::new(Code::NotFound),
+            }
+        }
+        ("vent/pressure", _) => Response::new(Code::MethodNotAllowed),
+        ("vent/detach", Method::Post) => write_op(s, command::detach),
+        ("vent/detach", _) => Response::new(Code::MethodNotAllowed),
+        ("vent/engage", Method::Post) => write_op(s, command::engage),
+        ("vent/engage", _) => Response::new(Code::MethodNotAllowed),
+        ("device/health", Method::Get) => get_health(s, req),
+        ("device/health", _) => Response::new(Code::MethodNotAllowed),
+        ("device/addresses", Method::Get) => {
+            Response::with_payload(Code::Content, s.device_addresses().to_bytes().to_vec())
+        }
+        ("device/addresses", _) => Response::new(Code::MethodNotAllowed),
+        ("device/link", Method::Get) => {
+            Response::with_payload(Code::Content, s.thread.link_metrics().to_bytes().to_vec())
+        }
+        ("device/link", _) => Response::new(Code::MethodNotAllowed),
+        ("device/neighbors", Method::Get) => {
+            Response::with_payload(Code::Content, encode_neighbors(&s.thread.neighbors()))
+        }
+        ("device/neighbors", _) => Response::new(Code::MethodNotAllowed),
+        ("device/build", Method::Get) => Response::with_payload(Code::Content, build_info().to_bytes()),
+        ("device/build", _) => Response::new(Code::MethodNotAllowed),
+        ("device/capabilities", Method::Get) => {
+            let kind = s.identity.get_device_type().unwrap_or_default();
+            Response::with_payload(Code::Content, encode_capabilities(kind))
+        }
+        ("device/capabilities", _) => Response::new(Code::MethodNotAllowed),
+        ("device/maintenance", Method::Get) => {
+            Response::with_payload(Code::Content, s.maintenance_info().to_bytes().to_vec())
+        }
+        ("device/maintenance", _) => Response::new(Code::MethodNotAllowed),
+        ("device/logs", Method::Get) => get_logs(req),
+        ("device/logs", _) => Response::new(Code::MethodNotAllowed),
+        ("device/breakin", Method::Post) => post_breakin(s, req),
+        ("device/breakin", _) => Response::new(Code::MethodNotAllowed),
+        ("device/label", Method::Get) => Response::with_payload(Code::Content, s.identity.label().into_bytes()),
+        ("device/label", Method::Put) => put_label(s, req),
+        ("device/label", _) => Response::new(Code::MethodNotAllowed),
+        ("device/zone", Method::Put) => put_zone(s, req),
+        ("device/zone", _) => Response::new(Code::MethodNotAllowed),
+        ("device/relay", Method::Get) => Response::with_payload(
+            Code::Content,
+            vec![s.relay_open as u8, s.relay_override as u8, s.zone_all_closed as u8],
+        ),
+        ("device/relay", Method::Put) => put_relay(s, req),
+        ("device/relay", _) => Response::new(Code::MethodNotAllowed),
+        ("device/posttest", Method::Get) => match s.last_selftest {
+            Some(report) => Response::with_payload(Code::Content, report.to_bytes().to_vec()),
+            None => Response::new(Code::NotFound),
+        },
+        ("device/posttest", Method::Post) => {
+            if !command::is_write_allowed(s.access_mode) {
+                Response::new(Code::Forbidden)
+            } else if command::request_self_test(s) {
+                Response::new(Code::Changed)
+            } else {
+                Response::new(Code::ServiceUnavailable)
+            }
+        }
+        ("device/posttest", _) => Response::new(Code::MethodNotAllowed),
+        ("device/motion", Method::Get) => Response::with_payload(Code::Content, vec![s.trim.get() as u8]),
+        ("device/motion", Method::Put) => put_motion(s, req),
+        ("device/motion", _) => Response::new(Code::MethodNotAllowed),
+        ("device/motion/curve", Method::Get) => {
+            Response::with_payload(Code::Content, encode_curve(&s.identity.get_motion_curve().unwrap_or_default()))
+        }
+        ("device/motion/curve", Method::Put) => put_motion_curve(s, req),
+        ("device/motion/curve", _) => Response::new(Code::MethodNotAllowed),
+        ("device/config", Method::Put) => put_config(s, req),
+        ("device/config", _) => Response::new(Code::MethodNotAllowed),
+        ("device/presets", Method::Get) => {
+            Response::with_payload(Code::Content, encode_presets(&s.identity.get_presets().unwrap_or_default()))
+        }
+        ("device/presets", Method::Put) => put_presets(s, req),
+        ("device/presets", _) => Response::new(Code::MethodNotAllowed),
+        ("device/counters", Method::Get) => match s.identity.boot_counters() {
+            Ok(counters) => Response::with_payload(Code::Content, counters.to_bytes().to_vec()),
+            Err(_) => Response::new(Code::InternalServerError),
+        },
+        ("device/counters", _) => Response::new(Code::MethodNotAllowed),
+        ("device/counters/reset", Method::Put) => {
+            if !command::is_write_allowed(s.access_mode) {
+                return Response::new(Code::Forbidden);
+            }
+            stored(s.identity.reset_counters(), "boot counters")
+        }
+        ("device/counters/reset", _) => Response::new(Code::MethodNotAllowed),
+        ("device/travel", Method::Get) => {
+            Response::with_payload(Code::Content, s.servo_travel.total_deg().to_le_bytes().to_vec())
+        }
+        ("device/travel", Method::Put) => {
+            if !command::is_write_allowed(s.access_mode) {
+                return Response::new(Code::Forbidden);
+            }
+            stored(s.reset_servo_travel(), "servo travel")
+        }
+        ("device/travel", _) => Response::new(Code::MethodNotAllowed),
+        #[cfg(feature = "wal-debug")]
+        ("device/wal/simulate", Method::Post) => post_wal_simulate(s, req),
+        #[cfg(feature = "wal-debug")]
+        ("device/wal/simulate", _) => Response::new(Code::MethodNotAllowed),
+        ("device/dataset/secondary", Method::Put) => put_secondary_dataset(s, req),
+        ("device/dataset/secondary", _) => Response::new(Code::MethodNotAllowed),
+        ("device/dataset", Method::Post) => post_dataset(s, req),
+        ("device/dataset", _) => Response::new(Code::MethodNotAllowed),
+        ("device/dataset/confirm", Method::Post) => {
+            if s.confirm_dataset_push() {
+                Response::new(Code::Changed)
+            } else {
+                Response::new(Code::PreconditionFailed)
+            }
+        }
+        ("device/dataset/confirm", _) => Response::new(Code::MethodNotAllowed),
+        ("device/reboot", Method::Post) => post_reboot(s, req),
+        ("device/reboot", _) => Response::new(Code::MethodNotAllowed),
+        ("device/calibration/freq", Method::Post) => {
+            if command::request_freq_detect(s) {
+                Response::new(Code::Changed)
+            } else {
+                Response::new(Code::ServiceUnavailable)
+            }
+        }
+        ("device/calibration/freq", _) => Response::new(Code::MethodNotAllowed),
+        ("device/calibration/learn", Method::Post) => {
+            if command::request_learn_limits(s) {
+                Response::new(Code::Changed)
+            } else {
+                Response::new(Code::ServiceUnavailable)
+            }
+        }
+        ("device/calibration/learn", _) => Response::new(Code::MethodNotAllowed),
+        ("device/calibration/auto", Method::Post) => {
+            if command::request_inversion_detect(s) {
+                Response::new(Code::Changed)
+            } else {
+                Response::new(Code::ServiceUnavailable)
+            }
+        }
+        ("device/calibration/auto", _) => Response::new(Code::MethodNotAllowed),
+        ("device/scan", Method::Get) => get_scan(s, req),
+        ("device/scan", Method::Post) => post_scan(s, req),
+        ("device/scan", _) => Response::new(Code::MethodNotAllowed),
+        _ => Response::new(Code::NotFound),
+    }
+}
+
+/// Debug: record the one-byte payload as an interrupted move and reboot
+/// (`simulate_wal_interruption`). The device restarts before replying, so
+/// only a failure gets an answer.
+#[cfg(feature = "wal-debug")]
+fn post_wal_simulate(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    let [target] = req.payload[..] else {
+        return Response::new(Code::BadRequest);
+    };
+    match command::simulate_wal_interruption(s, target) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(e) => {
+            warn!("coap: WAL simulation failed: {:?}", e);
+            Response::new(Code::InternalServerError)
+        }
+    }
+}
+
+/// 2.04 once an NVS write has gone through, else 5.00.
+fn stored(result: Result<(), EspError>, what: &str) -> Response {
+    match result {
+        Ok(()) => {
+            info!("coap: {} reset", what);
+            Response::new(Code::Changed)
+        }
+        Err(e) => {
+            warn!("coap: {} reset failed: {:?}", what, e);
+            Response::new(Code::InternalServerError)
+        }
+    }
+}
+
+/// Run a write that can't fail, refused in read-only mode.
+fn write_op(s: &mut AppState, op: impl FnOnce(&mut AppState)) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    op(s);
+    Response::new(Code::Changed)
+}
+
+/// `PROTOCOL_VERSION`, the device-type hint (`DeviceType::as_u8`), then
+/// the Matter device type it advertises (u32 LE).
+fn encode_capabilities(kind: DeviceType) -> Vec<u8> {
+    let mut out = vec![PROTOCOL_VERSION, kind.as_u8()];
+    out.extend_from_slice(&matter::matter_device_type(kind).to_le_bytes());
+    out
+}
+
+/// The `fields=` selector among the Uri-Query options: `Some(None)` when
+/// the client sent none, None when it is malformed.
+fn field_mask(req: &Request) -> Option<Option<FieldMask>> {
+    match req.query.iter().find(|q| q.starts_with("fields=")) {
+        Some(q) => FieldMask::parse_query(q).map(Some),
+        None => Some(None),
+    }
+}
+
+/// `HealthMin`, thinned to the selected bytes when the client sends
+/// `fields=`; the plain 3-byte form otherwise. A `nudge` query also asks
+/// for a prove-alive nudge; a refused one still gets the health.
+fn get_health(s: &mut AppState, req: &Request) -> Response {
+    if req.query.iter().any(|q| q == "nudge") && !command::request_nudge(s) {
+        info!("coap: nudge refused");
+    }
+    let health = s.health_min();
+    match field_mask(req) {
+        Some(Some(mask)) => Response::with_payload(Code::Content, health.to_bytes_selected(mask)),
+        Some(None) => Response::with_payload(Code::Content, health.to_bytes().to_vec()),
+        None => Response::new(Code::BadRequest),
+    }
+}
+
+/// Recent log lines as plain text, one Block2 block at a time. Blocks are
+/// cut from the ring as it is when each is asked for, so a line logged
+/// mid-transfer can shift the later ones.
+fn get_logs(req: &Request) -> Response {
+    let (num, szx) = match req.block2 {
+        Some(v) => (v >> 4, (v & 0x07).min(LOG_BLOCK_SZX)),
+        None => (0, LOG_BLOCK_SZX),
+    };
+    let text = logbuf::recent_text();
+    match logbuf::block(text.as_bytes(), num as usize, 16 << szx) {
+        Some((chunk, more)) => Response {
+            block2: Some(block2_value(num, more, szx)),
+            ..Response::with_payload(Code::Content, chunk.to_vec())
+        },
+        None => Response::new(Code::BadRequest),
+    }
+}
+
+/// Set the device name from the UTF-8 payload. An empty one clears it, so
+/// the label falls back to the EUI-64.
+fn put_label(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    let Ok(name) = core::str::from_utf8(&req.payload) else {
+        return Response::new(Code::BadRequest);
+    };
+    match s.identity.set_name(name.trim()) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(e) => {
+            warn!("coap: name write failed: {:?}", e);
+            Response::new(Code::InternalServerError)
+        }
+    }
+}
+
+/// Record the hub's zone report from a one-byte payload (1 = every vent in
+/// the zone is closed). Accepted in read-only mode too: it is a status
+/// input, and the only thing it can do is open the bypass relay.
+fn put_zone(s: &mut AppState, req: &Request) -> Response {
+    match req.payload[..] {
+        [v @ (0 | 1)] => {
+            s.zone_all_closed = v == 1;
+            Response::new(Code::Changed)
+        }
+        _ => Response::new(Code::BadRequest),
+    }
+}
+
+/// Hold the bypass relay open (1) or release the manual hold (0).
+fn put_relay(s: &mut AppState, req: &Request) -> Response {
+    let open = match req.payload[..] {
+        [v @ (0 | 1)] => v == 1,
+        _ => return Response::new(Code::BadRequest),
+    };
+    match command::set_relay_override(s, open) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(_) => Response::new(Code::Forbidden),
+    }
+}
+
+/// Set the servo trim from a one-byte payload (i8 degrees). It applies at
+/// once and is saved to NVS once it stops changing.
+fn put_motion(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    let [trim] = req.payload[..] else {
+        return Response::new(Code::BadRequest);
+    };
+    let now_ms = s.start_time.elapsed().as_millis() as u64;
+    s.trim.set(trim as i8, now_ms);
+    Response::new(Code::Changed)
+}
+
+/// Store a step curve in its NVS encoding (`encode_curve`): per point,
+/// fraction (permille) and speed (%), both u16 LE. An empty payload
+/// restores linear motion. It takes effect at the next boot.
+fn put_motion_curve(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    let points = match decode_curve(&req.payload) {
+        Ok(points) => points,
+        Err(e) => {
+            info!("coap: motion curve rejected: {:?}", e);
+            return Response::new(Code::BadRequest);
+        }
+    };
+    match s.identity.set_motion_curve(&points) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(e) => {
+            warn!("coap: motion curve write failed: {:?}", e);
+            Response::new(Code::InternalServerError)
+        }
+    }
+}
+
+/// Start a break-in (`start_breakin`). An optional one-byte payload sets
+/// the cycle count; a `force` query repeats one that already ran.
+fn post_breakin(s: &mut AppState, req: &Request) -> Response {
+    let cycles = match req.payload[..] {
+        [] => DEFAULT_BREAKIN_CYCLES,
+        [n] if n > 0 => n,
+        _ => return Response::new(Code::BadRequest),
+    };
+    let force = req.query.iter().any(|q| q == "force");
+    match command::start_breakin(s, cycles, force) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(BreakInError::ReadOnly) => Response::new(Code::Forbidden),
+        Err(BreakInError::AlreadyDone) => Response::new(Code::PreconditionFailed),
+        Err(BreakInError::Busy) => Response::new(Code::ServiceUnavailable),
+        Err(BreakInError::WalFailed) => Response::new(Code::InternalServerError),
+    }
+}
+
+/// Set the target from a `TargetRequest`. A retry carrying an
+/// idempotency token already processed gets the original outcome
+/// (`DedupCache`) instead of moving again; a request needing newer
+/// firmware gets 4.12. A `text/plain` payload is a bare angle instead
+/// (`apply_text_target`).
+fn post_target(s: &mut AppState, req: &Request) -> Response {
+    if req.content_format == Some(CONTENT_FORMAT_TEXT) {
+        return match command::apply_text_target(s, CommandSource::Coap, &req.payload) {
+            Ok(angle) => Response::with_payload(Code::Changed, angle.to_string().into_bytes()),
+            Err(RejectReason::QuietHours) => Response::new(Code::Changed),
+            Err(RejectReason::Malformed) => Response::new(Code::BadRequest),
+            Err(reason) => Response::with_payload(target_code(Err(reason)), reason.as_str().as_bytes().to_vec()),
+        };
+    }
+    let Some(target) = TargetRequest::from_bytes(&req.payload) else {
+        s.decode_failures.record();
+        return Response::new(Code::BadRequest);
+    };
+    match command::apply_target_request(s, CommandSource::Coap, &target) {
+        Ok((resp, outcome)) => Response::with_payload(target_code(outcome), resp.to_bytes().to_vec()),
+        Err(reason) => Response::new(target_code(Err(reason))),
+    }
+}
+
+/// Move to the preset named by the UTF-8 payload (`apply_preset`). An
+/// unknown name gets 4.04.
+fn put_preset(s: &mut AppState, req: &Request) -> Response {
+    let Ok(name) = core::str::from_utf8(&req.payload) else {
+        return Response::new(Code::BadRequest);
+    };
+    match command::apply_preset(s, CommandSource::Coap, name) {
+        Ok(angle) => Response::with_payload(Code::Changed, angle.to_string().into_bytes()),
+        Err(reason) => Response::with_payload(target_code(Err(reason)), reason.as_str().as_bytes().to_vec()),
+    }
+}
+
+/// Replace the preset table. The payload uses the NVS encoding
+/// (`encode_presets`): per entry, name length, name, angle.
+fn put_presets(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    let Ok(table) = decode_presets(&req.payload) else {
+        return Response::new(Code::BadRequest);
+    };
+    match s.identity.set_presets(&table) {
+        Ok(()) => {
+            info!("coap: {} presets stored", table.len());
+            Response::new(Code::Changed)
+        }
+        Err(e) => {
+            warn!("coap: preset write failed: {:?}", e);
+            Response::new(Code::InternalServerError)
+        }
+    }
+}
+
+/// Response code for a target's outcome. Only an accepted target, or one
+/// queued for the end of quiet hours, gets 2.04.
+fn target_code(outcome: command::CachedResponse) -> Code {
+    match outcome {
+        Ok(_) | Err(RejectReason::QuietHours) => Code::Changed,
+        Err(RejectReason::Malformed | RejectReason::AngleOutOfRange) => Code::BadRequest,
+        Err(RejectReason::UnknownPreset) => Code::NotFound,
+        Err(RejectReason::FirmwareTooOld) => Code::PreconditionFailed,
+        Err(RejectReason::WalFailed | RejectReason::NvsVerifyFailed) => Code::InternalServerError,
+        Err(_) => Code::Forbidden,
+    }
+}
+
+/// Update settings from a JSON object (`config_from_json`). Other
+/// content formats get 4.15; a bad key's name rides in the 4.00 payload.
+fn put_config(s: &mut AppState, req: &Request) -> Response {
+    if req.content_format != Some(CONTENT_FORMAT_JSON) {
+        return Response::new(Code::UnsupportedContentFormat);
+    }
+    let config = match core::str::from_utf8(&req.payload).map(config_from_json) {
+        Ok(Ok(config)) => config,
+        Ok(Err(ConfigError::BadValue(key))) => {
+            return Response::with_payload(Code::BadRequest, key.as_bytes().to_vec())
+        }
+        Ok(Err(ConfigError::Malformed)) | Err(_) => return Response::new(Code::BadRequest),
+    };
+    match command::apply_config(s, &config) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(ConfigUpdateError::ReadOnly) => Response::new(Code::Forbidden),
+        Err(ConfigUpdateError::Storage) => Response::new(Code::InternalServerError),
+    }
+}
+
+/// Reboot once the payload names this device's EUI-64 (`request_reboot`).
+/// The restart waits `REBOOT_DELAY_MS`, long enough for this reply to go.
+fn post_reboot(s: &mut AppState, req: &Request) -> Response {
+    let Ok(token) = core::str::from_utf8(&req.payload) else {
+        return Response::new(Code::BadRequest);
+    };
+    match command::request_reboot(s, token) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(RebootError::ReadOnly) => Response::new(Code::Forbidden),
+        Err(RebootError::BadToken) => Response::new(Code::BadRequest),
+    }
+}
+
+/// Results of the last finished Thread scan: networks heard
+/// (`encode_scan_results`), or per-channel energy with an `energy` query.
+fn get_scan(s: &mut AppState, req: &Request) -> Response {
+    let payload = if req.query.iter().any(|q| q == "energy") {
+        encode_channel_energy(&s.channel_energy)
+    } else {
+        encode_scan_results(&s.thread_scan)
+    };
+    Response::with_payload(Code::Content, payload)
+}
+
+/// Start an active scan, or an energy scan with an `energy` query. The
+/// results replace the last ones when it finishes; fetch them with GET.
+fn post_scan(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    let started = if req.query.iter().any(|q| q == "energy") {
+        s.thread.energy_scan()
+    } else {
+        s.thread.active_scan()
+    };
+    match started {
+        Ok(()) => Response::new(Code::Changed),
+        Err(_) => Response::new(Code::ServiceUnavailable),
+    }
+}
+
+/// Store the payload's raw TLVs as the fallback Thread dataset.
+fn put_secondary_dataset(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    if req.payload.is_empty() {
+        return Response::new(Code::BadRequest);
+    }
+    match s.set_secondary_dataset(&req.payload) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(e) => dataset_error(e),
+    }
+}
+
+/// Apply a pushed dataset (raw TLVs) on probation: it is reverted unless
+/// the coordinator confirms it over the new mesh (`device/dataset/confirm`).
+fn post_dataset(s: &mut AppState, req: &Request) -> Response {
+    if !command::is_write_allowed(s.access_mode) {
+        return Response::new(Code::Forbidden);
+    }
+    if req.payload.is_empty() {
+        return Response::new(Code::BadRequest);
+    }
+    if s.dataset_push_since.is_some() {
+        return Response::new(Code::PreconditionFailed);
+    }
+    match s.begin_dataset_push(&req.payload) {
+        Ok(()) => Response::new(Code::Changed),
+        Err(e) => dataset_error(e),
+    }
+}
+
+/// The response for a refused dataset write.
+fn dataset_error(e: EspError) -> Response {
+    match e.code() {
+        esp_idf_sys::ESP_ERR_INVALID_SIZE => Response::new(Code::RequestEntityTooLarge),
+        esp_idf_sys::ESP_ERR_INVALID_STATE => Response::new(Code::PreconditionFailed),
+        _ => {
+            warn!("coap: dataset write failed: {:?}", e);
+            Response::new(Code::InternalServerError)
+        }
+    }
+}
+
+/// A client observing a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Observer {
+    peer: SocketAddr,
+    token: Vec<u8>,
+    path: String,
+    /// Observe value of the last notification (or the registration).
+    seq: u32,
+    /// Message ID of the last notification while it awaits its ACK.
+    awaiting: Option<u16>,
+    /// Notifications left unacknowledged in a row.
+    failures: u8,
+    /// Representation last sent, so only changes are notified.
+    last_payload: Vec<u8>,
+}
+
+/// A notification to send: a CON carrying the observer's token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub peer: SocketAddr,
+    pub token: Vec<u8>,
+    pub message_id: u16,
+    pub seq: u32,
+}
+
+/// Clients observing resources, at most `max` at once. An observer that
+/// leaves `max_failures` notifications in a row unacknowledged is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObserverRegistry {
+    observers: Vec<Observer>,
+    max: usize,
+    max_failures: u8,
+}
+
+impl ObserverRegistry {
+    pub fn new(max: u8, max_failures: u8) -> Self {
+        Self {
+            observers: Vec::new(),
+            max: max as usize,
+            max_failures: max_failures.max(1),
+        }
+    }
+
+    /// Register `peer` as observing `path` under `token`, currently at
+    /// `payload`. Returns the Observe value for the reply, or None when
+    /// full. Re-registering an existing observation never counts against
+    /// the cap.
+    pub fn register(&mut self, peer: SocketAddr, token: &[u8], path: &str, payload: &[u8]) -> Option<u32> {
+        if let Some(o) = self.observers.iter_mut().find(|o| o.peer == peer && o.token == token) {
+            o.path = path.to_string();
+            o.failures = 0;
+            o.awaiting = None;
+            o.last_payload = payload.to_vec();
+            return Some(o.seq);
+        }
+        if self.observers.len() >= self.max {
+            return None;
+        }
+        self.observers.push(Observer {
+            peer,
+            token: token.to_vec(),
+            path: path.to_string(),
+            seq: 0,
+            awaiting: None,
+            failures: 0,
+            last_payload: payload.to_vec(),
+        });
+        Some(0)
+    }
+
+    pub fn deregister(&mut self, peer: SocketAddr, token: &[u8]) {
+        self.observers.retain(|o| !(o.peer == peer && o.token == token));
+    }
+
+    /// An ACK from `peer`: the observer it answers is live again.
+    pub fn acked(&mut self, peer: SocketAddr, message_id: u16) {
+        for o in self.observers.iter_mut() {
+            if o.peer == peer && o.awaiting == Some(message_id) {
+                o.awaiting = None;
+                o.failures = 0;
+            }
+        }
+    }
+
+    /// A RST from `peer`: the client has forgotten the observation.
+    pub fn reset(&mut self, peer: SocketAddr, message_id: u16) {
+        self.observers.retain(|o| !(o.peer == peer && o.awaiting == Some(message_id)));
+    }
+
+    /// Resources with at least one observer.
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.observers.iter().map(|o| o.path.clone()).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Notifications for the observers of `path` now that it reads as
+    /// `payload`; unchanged ones get none. Each takes a message ID from
+    /// `next_id`. Observers still owing an ACK for their last one count a
+    /// failure, and are dropped at `max_failures`.
+    pub fn notify(&mut self, path: &str, payload: &[u8], mut next_id: impl FnMut() -> u16) -> Vec<Notification> {
+        let max_failures = self.max_failures;
+        self.observers.retain_mut(|o| {
+            if o.path != path || o.last_payload == payload {
+                return true;
+            }
+            if o.awaiting.is_some() {
+                o.failures += 1;
+            }
+            if o.failures >= max_failures {
+                warn!("CoAP observer {} dropped after {} unacknowledged notifications", o.peer, o.failures);
+                return false;
+            }
+            true
+        });
+        let mut out = Vec::new();
+        for o in self.observers.iter_mut().filter(|o| o.path == path && o.last_payload != payload) {
+            let message_id = next_id();
+            // 24-bit sequence space (RFC 7641 §4.4)
+            o.seq = (o.seq + 1) & 0x00ff_ffff;
+            o.awaiting = Some(message_id);
+            o.last_payload = payload.to_vec();
+            out.push(Notification {
+                peer: o.peer,
+                token: o.token.clone(),
+                message_id,
+                seq: o.seq,
+            });
+        }
+        out
+    }
+}
+
+/// Counts replies built but not yet handed to the network stack. Once
+/// `cap` are waiting, new requests are refused early instead of piling
+/// more buffers behind a full socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InFlightGuard {
+    in_flight: u8,
+    cap: u8,
+}
+
+impl InFlightGuard {
+    /// A cap of 0 is treated as 1, or nothing could ever be answered.
+    pub fn new(cap: u8) -> Self {
+        Self {
+            in_flight: 0,
+            cap: cap.max(1),
+        }
+    }
+
+    /// Take a slot for a reply. False, taking nothing, when all are in use.
+    pub fn try_acquire(&mut self) -> bool {
+        if self.in_flight >= self.cap {
+            return false;
+        }
+        self.in_flight += 1;
+        true
+    }
+
+    /// Give a slot back once its reply has been sent or dropped.
+    pub fn release(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+}
+
+/// CoAP server on `COAP_PORT`, polled from the main loop.
+pub struct CoapServer {
+    socket: UdpSocket,
+    message_id: u16,
+    guard: InFlightGuard,
+    /// Replies the socket would not take yet, oldest first.
+    backlog: VecDeque<(Vec<u8>, SocketAddr)>,
+    observers: ObserverRegistry,
+}
+
+impl CoapServer {
+    pub fn bind(max_in_flight: u8, max_observers: u8) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("::", COAP_PORT))?;
+        socket.set_nonblocking(true)?;
+        info!("CoAP server listening on port {}", COAP_PORT);
+        Ok(Self {
+            socket,
+            message_id: 0,
+            guard: InFlightGuard::new(max_in_flight),
+            backlog: VecDeque::new(),
+            observers: ObserverRegistry::new(max_observers, OBSERVER_MAX_FAILURES),
+        })
+    }
+
+    /// Answer every request waiting on the socket, without blocking, then
+    /// notify observers of resources that changed. While the in-flight cap
+    /// is reached, requests get a bare 5.03 and the handler isn't run.
+    pub fn poll(&mut self, mut handler: impl FnMut(&Request) -> Response) {
+        self.serve(&mut handler);
+        self.notify(&mut handler);
+    }
+
+    fn serve(&mut self, handler: &mut impl FnMut(&Request) -> Response) {
+        self.flush_backlog();
+        let mut buf = [0u8; MAX_MESSAGE];
+        loop {
+            let (len, peer) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("CoAP receive failed: {}", e);
+                    return;
+                }
+            };
+            let Some(req) = parse_request(&buf[..len]) else {
+                match parse_empty(&buf[..len]) {
+                    Some((message_id, true)) => self.observers.reset(peer, message_id),
+                    Some((message_id, false)) => self.observers.acked(peer, message_id),
+                    None => {}
+                }
+                continue;
+            };
+            self.message_id = self.message_id.wrapping_add(1);
+            if !self.guard.try_acquire() {
+                // Best effort: if even this won't go, the client retries
+                let msg = build_response(&req, self.message_id, &Response::new(Code::ServiceUnavailable));
+                let _ = self.socket.send_to(&msg, peer);
+                continue;
+            }
+            let mut resp = handler(&req);
+            let mut observe = None;
+            if req.method == Method::Get && resp.code == Code::Content && OBSERVABLE.contains(&req.path.as_str()) {
+                match req.observe {
+                    Some(0) => match self.observers.register(peer, &req.token, &req.path, &resp.payload) {
+                        Some(seq) => observe = Some(seq),
+                        None => resp = Response::new(Code::ServiceUnavailable),
+                    },
+                    Some(1) => self.observers.deregister(peer, &req.token),
+                    _ => {}
+                }
+            }
+            let msg = build_message(&req, self.message_id, observe, &resp);
+            match self.socket.send_to(&msg, peer) {
+                Ok(_) => self.guard.release(),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => self.backlog.push_back((msg, peer)),
+                Err(e) => {
+                    warn!("CoAP reply to {} failed: {}", peer, e);
+                    self.guard.release();
+                }
+            }
+        }
+    }
+
+    /// Send a CON notification to each observer of a resource whose
+    /// representation changed since the last one. Unsent or unanswered
+    /// ones count toward the observer's failures.
+    fn notify(&mut self, handler: &mut impl FnMut(&Request) -> Response) {
+        for path in self.observers.paths() {
+            let req = Request {
+                confirmable: false,
+                method: Method::Get,
+                message_id: 0,
+                token: Vec::new(),
+                path,
+                query: Vec::new(),
+                content_format: None,
+                observe: None,
+                block2: None,
+                payload: Vec::new(),
+            };
+            let resp = handler(&req);
+            if resp.code != Code::Content {
+                continue;
+            }
+            let ids = &mut self.message_id;
+            let due = self.observers.notify(&req.path, &resp.payload, || {
+                *ids = ids.wrapping_add(1);
+                *ids
+            });
+            for n in due {
+                let msg = encode_message(0, n.message_id, &n.token, &req.path, Some(n.seq), &resp);
+                if let Err(e) = self.socket.send_to(&msg, n.peer) {
+                    warn!("CoAP notification to {} failed: {}", n.peer, e);
+                }
+            }
+        }
+    }
+
+    /// Send queued replies until the socket pushes back again.
+    fn flush_backlog(&mut self) {
+        while let Some((msg, peer)) = self.backlog.front() {
+            match self.socket.send_to(msg, *peer) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => warn!("CoAP reply to {} failed: {}", peer, e),
+            }
+            self.backlog.pop_front();
+            self.guard.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CON POST /vent/target, token 0xa1b2, payload [1, 135].
+    const TARGET_POST: &[u8] = &[
+        0x42, 0x02, 0x12, 0x34, 0xa1, 0xb2, // header + token
+        0xb4, b'v', b'e', b'n', b't', // Uri-Path "vent"
+        0x06, b't', b'a', b'r', b'g', b'e', b't', // Uri-Path "target"
+        0xff, 1, 135,
+    ];
+
+    fn peer(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, n], 5683))
+    }
+
+    #[test]
+    fn test_observer_registration_cap() {
+        let mut reg = ObserverRegistry::new(2, OBSERVER_MAX_FAILURES);
+        assert_eq!(reg.register(peer(1), &[1], "vent/position", &[90]), Some(0));
+        assert!(reg.register(peer(2), &[2], "vent/status", &[90]).is_some());
+        // Full: a new observer is refused, a re-registration is not
+        assert_eq!(reg.register(peer(3), &[3], "vent/position", &[90]), None);
+        assert!(reg.register(peer(1), &[1], "vent/position", &[90]).is_some());
+        assert_eq!(reg.paths(), vec!["vent/position".to_string(), "vent/status".to_string()]);
+        // Existing observers are still served
+        let mut id = 0;
+        let due = reg.notify("vent/position", &[100], || {
+            id += 1;
+            id
+        });
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].peer, due[0].message_id, due[0].seq), (peer(1), 1, 1));
+        // Deregistering frees a slot
+        reg.deregister(peer(2), &[2]);
+        assert!(reg.register(peer(3), &[3], "vent/position", &[90]).is_some());
+    }
+
+    #[test]
+    fn test_observer_evicted_after_unacked_notifications() {
+        let mut reg = ObserverRegistry::new(4, 3);
+        reg.register(peer(1), &[1], "vent/position", &[0]);
+        reg.register(peer(2), &[2], "vent/position", &[0]);
+        let mut id = 0u16;
+        for angle in 1..=3u8 {
+            let due = reg.notify("vent/position", &[angle], || {
+                id += 1;
+                id
+            });
+            assert_eq!(due.len(), 2);
+            // Only peer 2 acknowledges
+            let acked = due.iter().find(|n| n.peer == peer(2)).unwrap().message_id;
+            reg.acked(peer(2), acked);
+        }
+        // Peer 1 owes ACKs for three notifications in a row
+        let due = reg.notify("vent/position", &[4], || {
+            id += 1;
+            id
+        });
+        assert_eq!(due.iter().map(|n| n.peer).collect::<Vec<_>>(), vec![peer(2)]);
+        // No change, no notification
+        assert!(reg.notify("vent/position", &[4], || 0).is_empty());
+        // A RST drops the observer at once
+        reg.reset(peer(2), due[0].message_id);
+        assert!(reg.paths().is_empty());
+    }
+
+    #[test]
+    fn test_block2_option() {
+        // CON GET, no token: Uri-Path "x", Block2 num 2, szx 2 (delta 12)
+        let raw = [0x40, 0x01, 0x00, 0x01, 0xb1, b'x', 0xc1, 0x22];
+        let req = parse_request(&raw).unwrap();
+        assert_eq!(req.block2, Some(block2_value(2, false, 2)));
+        // A middle block: Max-Age 0 then Block2 num 2, more, szx 2
+        let resp = Response {
+            block2: Some(block2_value(2, true, 2)),
+            ..Response::with_payload(Code::Content, vec![7])
+        };
+        let msg = build_response(&req, 0, &resp);
+        assert_eq!(msg, vec![0x60, 0x45, 0x00, 0x01, 0xd0, 0x01, 0x91, 0x2a, 0xff, 7]);
+    }
+
+    #[test]
+    fn test_parse_observe_and_empty_messages() {
+        // CON GET, no token: Observe 0 (empty value), Uri-Path "x"
+        let raw = [0x40, 0x01, 0x00, 0x01, 0x60, 0x51, b'x'];
+        let req = parse_request(&raw).unwrap();
+        assert_eq!(req.observe, Some(0));
+        assert_eq!(req.path, "x");
+        assert_eq!(parse_empty(&[0x60, 0x00, 0x12, 0x34]), Some((0x1234, false)));
+        assert_eq!(parse_empty(&[0x70, 0x00, 0x12, 0x34]), Some((0x1234, true)));
+        assert_eq!(parse_empty(&[0x40, 0x00, 0x12, 0x34]), None);
+        assert_eq!(parse_empty(&[0x60, 0x45, 0x12, 0x34]), None);
+    }
+
+    #[test]
+    fn test_in_flight_guard_caps_and_releases() {
+        let mut guard = InFlightGuard::new(2);
+        assert!(guard.try_acquire());
+        assert!(guard.try_acquire());
+        assert!(!guard.try_acquire());
+        guard.release();
+        assert!(guard.try_acquire());
+        guard.release();
+        guard.release();
+        // An extra release doesn't open more slots than the cap
+        guard.release();
+        assert!(guard.try_acquire());
+        assert!(guard.try_acquire());
+        assert!(!guard.try_acquire());
+        // A zero cap still lets one reply through
+        let mut guard = InFlightGuard::new(0);
+        assert!(guard.try_acquire());
+        assert!(!guard.try_acquire());
+    }
+
+    #[test]
+    fn test_capabilities_carry_device_type() {
+        assert_eq!(encode_capabilities(DeviceType::Cover), vec![PROTOCOL_VERSION, 0, 0x02, 0x02, 0, 0]);
+        assert_eq!(encode_capabilities(DeviceType::Damper), vec![PROTOCOL_VERSION, 1, 0x2b, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_target_code() {
+        assert_eq!(target_code(Ok(90)), Code::Changed);
+        assert_eq!(target_code(Err(RejectReason::QuietHours)), Code::Changed);
+        assert_eq!(target_code(Err(RejectReason::ReadOnly)), Code::Forbidden);
+        assert_eq!(target_code(Err(RejectReason::Superseded)), Code::Forbidden);
+        assert_eq!(target_code(Err(RejectReason::FirmwareTooOld)), Code::PreconditionFailed);
+        assert_eq!(target_code(Err(RejectReason::WalFailed)), Code::InternalServerError);
+        assert_eq!(target_code(Err(RejectReason::UnknownPreset)), Code::NotFound);
+    }
+
+    #[test]
+    fn test_out_of_range_target_code_per_policy() {
+        use crate::command::{check_range, RangePolicy};
+        assert_eq!(target_code(check_range(45, RangePolicy::Clamp)), Code::Changed);
+        assert_eq!(target_code(check_range(45, RangePolicy::Reject)), Code::BadRequest);
+        assert_eq!(target_code(check_range(135, RangePolicy::Reject)), Code::Changed);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let req = parse_request(TARGET_POST).unwrap();
+        assert!(req.confirmable);
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.message_id, 0x1234);
+        assert_eq!(req.token, vec![0xa1, 0xb2]);
+        assert_eq!(req.path, "vent/target");
+        assert!(req.query.is_empty());
+        assert_eq!(req.content_format, None);
+        assert_eq!(req.payload, vec![1, 135]);
+    }
+
+    #[test]
+    fn test_parse_content_format() {
+        // NON PUT, no token: Uri-Path "x", Content-Format 0 (empty value)
+        let raw = [0x50, 0x03, 0x00, 0x01, 0xb1, b'x', 0x10, 0xff, b'9', b'0'];
+        let req = parse_request(&raw).unwrap();
+        assert_eq!(req.content_format, Some(CONTENT_FORMAT_TEXT));
+        assert_eq!(req.payload, b"90".to_vec());
+        // application/octet-stream (42)
+        let raw = [0x50, 0x03, 0x00, 0x01, 0xb1, b'x', 0x11, 42];
+        assert_eq!(parse_request(&raw).unwrap().content_format, Some(42));
+    }
+
+    #[test]
+    fn test_parse_extended_delta_and_query() {
+        // NON GET, no token: Uri-Path "x", Uri-Query "fields=0" (delta 4),
+        // then elective option 60 (Size1) via a 1-byte extended delta
+        let raw = [
+            0x50, 0x01, 0x00, 0x07, 0xb1, b'x', 0x48, b'f', b'i', b'e', b'l', b'd', b's', b'=', b'0', 0xd1, 32, 9,
+        ];
+        let req = parse_request(&raw).unwrap();
+        assert!(!req.confirmable);
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.path, "x");
+        assert_eq!(req.query, vec!["fields=0".to_string()]);
+        assert!(req.payload.is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert_eq!(parse_request(&[]), None);
+        assert_eq!(parse_request(&[0x40, 0x01, 0x00]), None);
+        // Version 2
+        assert_eq!(parse_request(&[0x80, 0x01, 0x00, 0x01]), None);
+        // A response code, not a request
+        assert_eq!(parse_request(&[0x40, 0x45, 0x00, 0x01]), None);
+        // Payload marker with nothing after it
+        assert_eq!(parse_request(&[0x40, 0x02, 0x00, 0x01, 0xff]), None);
+        // Unknown critical option 9
+        assert_eq!(parse_request(&[0x40, 0x01, 0x00, 0x01, 0x91, 0x00]), None);
+        // Option value runs past the end
+        assert_eq!(parse_request(&[0x40, 0x01, 0x00, 0x01, 0xb4, b'v']), None);
+    }
+
+    #[test]
+    fn test_response_piggybacks_on_con() {
+        let req = parse_request(TARGET_POST).unwrap();
+        let resp = Response::with_payload(Code::Changed, vec![7]);
+        let msg = build_response(&req, 99, &resp);
+        // ACK echoing the message ID and token
+        assert_eq!(msg, vec![0x62, 0x44, 0x12, 0x34, 0xa1, 0xb2, 0xff, 7]);
+
+        let non = Request { confirmable: false, ..req };
+        let msg = build_response(&non, 99, &Response::new(Code::NotFound));
+        assert_eq!(msg, vec![0x52, 0x84, 0x00, 99, 0xa1, 0xb2]);
+    }
+
+    #[test]
+    fn test_field_mask_from_query() {
+        let mut req = parse_request(TARGET_POST).unwrap();
+        assert_eq!(field_mask(&req), Some(None));
+        req.query = vec!["nudge".to_string(), "fields=0,2".to_string()];
+        assert_eq!(field_mask(&req), Some(Some(FieldMask::from_bits(0b101))));
+        req.query = vec!["fields=9".to_string()];
+        assert_eq!(field_mask(&req), None);
+    }
+
+    #[test]
+    fn test_content_carries_max_age() {
+        let mut req = parse_request(TARGET_POST).unwrap();
+        req.token.clear();
+        req.path = "device/build".to_string();
+        let msg = build_response(&req, 0, &Response::with_payload(Code::Content, vec![1]));
+        // Max-Age 3600 (option 14, 2-byte value)
+        assert_eq!(msg, vec![0x60, 0x45, 0x12, 0x34, 0xd2, 0x01, 0x0e, 0x10, 0xff, 1]);
+        req.path = "vent/position".to_string();
+        let msg = build_response(&req, 0, &Response::with_payload(Code::Content, vec![1]));
+        // Volatile: an explicit zero-length Max-Age
+        assert_eq!(msg, vec![0x60, 0x45, 0x12, 0x34, 0xd0, 0x01, 0xff, 1]);
+    }
+}
@@ -0,0 +1,1461 @@
+use crate::breakin::{BreakIn, BreakInPurpose, MAX_BREAKIN_CYCLES};
+use crate::config::DeviceConfig;
+use crate::identity::{build_info, Metric, WalError};
+use crate::maintenance;
+use crate::motion::{approach_sequence, APPROACH_OVERSHOOT_DEG};
+use crate::presets::resolve_preset;
+use crate::sensors::SensorKind;
+use crate::servo::{nudge_allowed, SoftLimits, NUDGE_MIN_INTERVAL_MS};
+use crate::state::AppState;
+use log::{info, warn};
+use std::collections::VecDeque;
+use vent_protocol::{
+    clamp_angle, fw_satisfies, PowerSource, TargetRequest, TargetResponse, ANGLE_CLOSED, ANGLE_OPEN,
+};
+
+/// Where a target command came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandSource {
+    /// WindowCovering GoToLiftPercentage / UpOrOpen / DownOrClose.
+    Matter,
+    /// POST to the local CoAP server's `vent/target`.
+    Coap,
+    /// On-device pressure-hold automation.
+    PressureControl,
+    /// On-device fail-safe, e.g. opening an uncommissioned vent.
+    Failsafe,
+    /// Scheduled exercise sweep leg.
+    Maintenance,
+}
+
+impl CommandSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandSource::Matter => "matter",
+            CommandSource::Coap => "coap",
+            CommandSource::PressureControl => "pressure",
+            CommandSource::Failsafe => "failsafe",
+            CommandSource::Maintenance => "maintenance",
+        }
+    }
+
+    /// Whether the command is a safety move that must run even during
+    /// quiet hours (e.g. enforcing an airflow floor or antifreeze).
+    pub fn is_critical(&self) -> bool {
+        match self {
+            CommandSource::Matter
+            | CommandSource::Coap
+            | CommandSource::PressureControl
+            | CommandSource::Maintenance => false,
+            CommandSource::Failsafe => true,
+        }
+    }
+
+    /// Whether the command came in over the network. Read-only mode only
+    /// refuses these; on-device automations keep running.
+    pub fn is_remote(&self) -> bool {
+        matches!(self, CommandSource::Matter | CommandSource::Coap)
+    }
+
+    /// Resource the command addressed, for the audit log.
+    pub fn path(&self) -> &'static str {
+        match self {
+            CommandSource::Matter => "window_covering/target",
+            CommandSource::Coap => "vent/target",
+            CommandSource::PressureControl => "pressure/hold",
+            CommandSource::Failsafe => "device/failsafe",
+            CommandSource::Maintenance => "device/sweep",
+        }
+    }
+}
+
+/// API permission mode. Unlike a maintenance lock (physical safety), this
+/// only controls whether remote clients may change anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessMode {
+    #[default]
+    ReadWrite,
+    ReadOnly,
+}
+
+impl AccessMode {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => AccessMode::ReadOnly,
+            _ => AccessMode::ReadWrite,
+        }
+    }
+}
+
+/// Whether a remote write is permitted under `mode`. Reads are always
+/// allowed.
+pub fn is_write_allowed(mode: AccessMode) -> bool {
+    mode == AccessMode::ReadWrite
+}
+
+/// Whether a target from `source` may be written under `mode`.
+pub fn source_write_allowed(source: CommandSource, mode: AccessMode) -> bool {
+    !source.is_remote() || is_write_allowed(mode)
+}
+
+/// How a target outside the vent range is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RangePolicy {
+    /// Silently pull it into range.
+    #[default]
+    Clamp,
+    /// Refuse it, so strict clients learn their request was invalid.
+    Reject,
+}
+
+impl RangePolicy {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => RangePolicy::Reject,
+            _ => RangePolicy::Clamp,
+        }
+    }
+}
+
+/// Apply the range policy to a requested angle.
+pub fn check_range(requested: u8, policy: RangePolicy) -> Result<u8, RejectReason> {
+    let clamped = clamp_angle(requested);
+    match policy {
+        RangePolicy::Reject if clamped != requested => Err(RejectReason::AngleOutOfRange),
+        _ => Ok(clamped),
+    }
+}
+
+/// Two remote targets this close together (ms) count as simultaneous.
+pub const CONFLICT_WINDOW_MS: u64 = 250;
+
+/// Which of two near-simultaneous remote targets is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// The later arrival wins; an exact tie goes to Matter, the controller
+    /// of record.
+    #[default]
+    LatestWins,
+    /// A direct CoAP target beats a Matter one arriving within
+    /// `CONFLICT_WINDOW_MS` of it, e.g. a manual tool fighting an automation.
+    CoapOverridesMatter,
+}
+
+impl ConflictPolicy {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ConflictPolicy::CoapOverridesMatter,
+            _ => ConflictPolicy::LatestWins,
+        }
+    }
+}
+
+/// The target to keep when a CoAP and a Matter target (with arrival
+/// times in ms) race.
+pub fn resolve_conflict(
+    coap_target: u8,
+    coap_ts: u64,
+    matter_target: u8,
+    matter_ts: u64,
+    policy: ConflictPolicy,
+) -> u8 {
+    let coap_is_later = coap_ts > matter_ts;
+    match policy {
+        ConflictPolicy::LatestWins => {
+            if coap_is_later {
+                coap_target
+            } else {
+                matter_target
+            }
+        }
+        ConflictPolicy::CoapOverridesMatter => {
+            if coap_is_later || matter_ts - coap_ts <= CONFLICT_WINDOW_MS {
+                coap_target
+            } else {
+                matter_target
+            }
+        }
+    }
+}
+
+/// Whether a CoAP or Matter target arriving at `now_ms` loses to the other
+/// source's latest target (angle, arrival ms) under `policy`. Targets from
+/// other sources never race.
+pub fn superseded(
+    source: CommandSource,
+    requested: u8,
+    now_ms: u64,
+    coap: Option<(u8, u64)>,
+    matter: Option<(u8, u64)>,
+    policy: ConflictPolicy,
+) -> bool {
+    let ((coap_target, coap_ts), (matter_target, matter_ts)) = match (source, coap, matter) {
+        (CommandSource::Coap, _, Some(matter)) => ((requested, now_ms), matter),
+        (CommandSource::Matter, Some(coap), _) => (coap, (requested, now_ms)),
+        _ => return false,
+    };
+    resolve_conflict(coap_target, coap_ts, matter_target, matter_ts, policy) != requested
+}
+
+/// Parse a plain-text angle such as `135` or `135\n`, for debugging with
+/// tools that can't build CBOR. Surrounding whitespace is ignored; signs,
+/// fractions and values that don't fit a u8 are refused. The range policy
+/// still applies to the result.
+pub fn parse_text_angle(bytes: &[u8]) -> Option<u8> {
+    let text = core::str::from_utf8(bytes).ok()?.trim();
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// How often a changed decode-failure count is written to NVS.
+pub const DECODE_FAILURE_PERSIST_INTERVAL_S: u64 = 300;
+
+/// Command payloads that failed to decode, across reboots. A count that
+/// keeps rising on one vent points at a firmware mismatch or a bad link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeFailures {
+    total: u32,
+    dirty: bool,
+}
+
+impl DecodeFailures {
+    /// Resume from the count persisted in NVS.
+    pub fn new(persisted: u32) -> Self {
+        Self {
+            total: persisted,
+            dirty: false,
+        }
+    }
+
+    pub fn record(&mut self) {
+        self.total = self.total.saturating_add(1);
+        self.dirty = true;
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// The count to write to NVS, if it changed since the last write.
+    pub fn take_unsaved(&mut self) -> Option<u32> {
+        core::mem::take(&mut self.dirty).then_some(self.total)
+    }
+}
+
+/// Decode a plain-text target, counting it in `failures` if malformed.
+pub fn decode_text_target(bytes: &[u8], failures: &mut DecodeFailures) -> Option<u8> {
+    let angle = parse_text_angle(bytes);
+    if angle.is_none() {
+        failures.record();
+    }
+    angle
+}
+
+/// What to do with a target that arrives while the servo is detached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetachedAction {
+    /// Not detached — apply normally.
+    Proceed,
+    /// Re-engage PWM, then apply.
+    Reengage,
+    /// Refuse until explicitly re-engaged.
+    Reject,
+}
+
+/// Decide how a target is handled given the detach state and the
+/// `detach_honors_commands` setting.
+pub fn detached_action(detached: bool, honors_commands: bool) -> DetachedAction {
+    match (detached, honors_commands) {
+        (false, _) => DetachedAction::Proceed,
+        (true, true) => DetachedAction::Reengage,
+        (true, false) => DetachedAction::Reject,
+    }
+}
+
+/// Raise `requested` to the airflow floor so no single automation can seal
+/// a critical register and spike static pressure. An explicit override
+/// bypasses the floor.
+pub fn enforce_min_airflow(requested: u8, floor: u8, override_floor: bool) -> u8 {
+    if override_floor {
+        requested
+    } else {
+        requested.max(floor)
+    }
+}
+
+/// Keep `angle` inside the learned travel, then raise it to the airflow
+/// floor. The floor is applied last so a soft limit below it can't pull a
+/// move back under the floor.
+pub fn limit_target(angle: u8, soft_limits: Option<SoftLimits>, floor: u8, override_floor: bool) -> u8 {
+    let limited = soft_limits.map_or(angle, |limits| limits.clamp(angle));
+    enforce_min_airflow(limited, floor, override_floor)
+}
+
+/// What to do with a target further than the per-command travel cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaxMoveMode {
+    /// Move the capped distance; a follow-up command continues the move.
+    #[default]
+    Clamp,
+    /// Refuse the whole command.
+    Reject,
+}
+
+impl MaxMoveMode {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => MaxMoveMode::Reject,
+            _ => MaxMoveMode::Clamp,
+        }
+    }
+}
+
+/// Per-command travel cap, persisted in NVS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxMove {
+    pub max_deg: u8,
+    pub mode: MaxMoveMode,
+}
+
+/// Outcome of the per-command travel cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveDecision {
+    /// Within the cap; go to the target.
+    Proceed(u8),
+    /// Too far; go this far toward the target instead.
+    Clamped(u8),
+    /// Too far and the mode refuses it.
+    Reject,
+}
+
+/// Cap how far one command may move the vent from `current`, so a buggy
+/// automation can't slam the louver across its range. A cap of 0 is off.
+pub fn apply_max_move(current: u8, target: u8, max_deg: u8, mode: MaxMoveMode) -> MoveDecision {
+    if max_deg == 0 || current.abs_diff(target) <= max_deg {
+        return MoveDecision::Proceed(target);
+    }
+    match mode {
+        MaxMoveMode::Reject => MoveDecision::Reject,
+        MaxMoveMode::Clamp if target > current => MoveDecision::Clamped(current.saturating_add(max_deg)),
+        MaxMoveMode::Clamp => MoveDecision::Clamped(current.saturating_sub(max_deg)),
+    }
+}
+
+/// Why a target command was not applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The device is in read-only access mode.
+    ReadOnly,
+    /// The write-ahead record could not be persisted.
+    WalFailed,
+    /// The write-ahead record was written but did not read back intact.
+    NvsVerifyFailed,
+    /// No preset with the requested name.
+    UnknownPreset,
+    /// The servo is detached and commands don't re-engage it.
+    Detached,
+    /// The angle is outside the vent range and the policy rejects it.
+    AngleOutOfRange,
+    /// A break-in routine owns the servo until it finishes.
+    BreakIn,
+    /// Quiet hours are in effect; the target is queued until they end.
+    QuietHours,
+    /// The payload could not be decoded.
+    Malformed,
+    /// The move exceeds the per-command travel cap.
+    MoveTooLarge,
+    /// The battery is too low to finish a move.
+    LowBattery,
+    /// The command requires newer firmware than this device runs.
+    FirmwareTooOld,
+    /// A racing target from the other remote source took priority.
+    Superseded,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::ReadOnly => "read_only",
+            RejectReason::WalFailed => "wal_failed",
+            RejectReason::NvsVerifyFailed => "nvs_verify_failed",
+            RejectReason::UnknownPreset => "unknown_preset",
+            RejectReason::Detached => "detached",
+            RejectReason::AngleOutOfRange => "angle_out_of_range",
+            RejectReason::BreakIn => "break_in",
+            RejectReason::QuietHours => "quiet_hours",
+            RejectReason::Malformed => "malformed",
+            RejectReason::MoveTooLarge => "move_too_large",
+            RejectReason::LowBattery => "low_battery",
+            RejectReason::FirmwareTooOld => "firmware_too_old",
+            RejectReason::Superseded => "superseded",
+        }
+    }
+}
+
+/// One accepted or rejected target command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandEvent {
+    /// Milliseconds since boot.
+    pub uptime_ms: u64,
+    pub source: CommandSource,
+    pub old_angle: u8,
+    pub new_angle: u8,
+    pub outcome: Result<(), RejectReason>,
+}
+
+/// Format a command event as a single greppable log line.
+pub fn format_command(event: &CommandEvent) -> String {
+    let result = match event.outcome {
+        Ok(()) => "result=accepted".to_string(),
+        Err(reason) => format!("result=rejected reason={}", reason.as_str()),
+    };
+    format!(
+        "cmd t={}ms src={} path={} old={} new={} {}",
+        event.uptime_ms,
+        event.source.as_str(),
+        event.source.path(),
+        event.old_angle,
+        event.new_angle,
+        result
+    )
+}
+
+/// Log a command decision. Every accept/reject path goes through here.
+pub fn log_command(event: &CommandEvent) {
+    let line = format_command(event);
+    match event.outcome {
+        Ok(()) => info!("{}", line),
+        Err(_) => warn!("{}", line),
+    }
+}
+
+/// Apply a target command: persist the WAL intent, then hand the target to
+/// the state machine. Returns the previous angle on success.
+pub fn apply_target(s: &mut AppState, source: CommandSource, angle: u8) -> Result<u8, RejectReason> {
+    apply_target_with_override(s, source, angle, false)
+}
+
+/// Describe a command's outcome. A target held back for quiet hours is
+/// queued rather than refused, so it counts as accepted and reports the
+/// queued angle.
+pub fn target_response(requested: u8, previous: u8, current: u8, new_target: u8, outcome: CachedResponse) -> TargetResponse {
+    TargetResponse {
+        target: if outcome.is_ok() { new_target } else { requested },
+        previous,
+        current,
+        accepted: matches!(outcome, Ok(_) | Err(RejectReason::QuietHours)),
+    }
+}
+
+/// As `apply_target`, describing the outcome in a `TargetResponse` so a
+/// refused command looks different from an accepted one.
+pub fn apply_target_response(s: &mut AppState, source: CommandSource, angle: u8) -> TargetResponse {
+    let previous = s.vent.current_angle();
+    let outcome = apply_target(s, source, angle);
+    // The overshoot leg is a waypoint; report the real target
+    let new_target = s.approach_final.unwrap_or(s.vent.target_angle());
+    target_response(angle, previous, s.vent.current_angle(), new_target, outcome)
+}
+
+/// Whether a command's firmware requirement lets it run. Commands without
+/// one always do, as does everything when the check is turned off.
+pub fn min_fw_ok(enforce: bool, running: &str, required: Option<&str>) -> bool {
+    match required {
+        Some(required) if enforce => fw_satisfies(running, required),
+        _ => true,
+    }
+}
+
+/// Apply a decoded `TargetRequest`. A request that needs newer firmware is
+/// refused with `FirmwareTooOld` before it touches the state machine, so
+/// a coordinator can tell it apart from an ordinary rejection. Otherwise
+/// it goes through `apply_target_idempotent` with the request's token.
+pub fn apply_target_request(
+    s: &mut AppState,
+    source: CommandSource,
+    req: &TargetRequest<'_>,
+) -> Result<(TargetResponse, CachedResponse), RejectReason> {
+    let running = build_info().firmware_version;
+    if !min_fw_ok(s.enforce_min_fw, running, req.min_fw_required) {
+        warn!(
+            "{}: command needs firmware {}, running {}",
+            source.as_str(),
+            req.min_fw_required.unwrap_or_default(),
+            running
+        );
+        let current = s.vent.current_angle();
+        let event = CommandEvent {
+            uptime_ms: s.start_time.elapsed().as_millis() as u64,
+            source,
+            old_angle: current,
+            new_angle: req.angle,
+            outcome: Err(RejectReason::FirmwareTooOld),
+        };
+        log_command(&event);
+        return Err(RejectReason::FirmwareTooOld);
+    }
+    let previous = s.vent.current_angle();
+    let outcome = apply_target_idempotent(s, source, req.angle, req.idempotency_token);
+    let new_target = s.approach_final.unwrap_or(s.vent.target_angle());
+    let response = target_response(req.angle, previous, s.vent.current_angle(), new_target, outcome);
+    Ok((response, outcome))
+}
+
+/// As `apply_target`, but `override_floor` lets an authenticated caller
+/// go below the minimum-airflow floor.
+pub fn apply_target_with_override(
+    s: &mut AppState,
+    source: CommandSource,
+    requested: u8,
+    override_floor: bool,
+) -> Result<u8, RejectReason> {
+    let now_ms = s.start_time.elapsed().as_millis() as u64;
+    let outcome = try_target(s, source, requested, override_floor);
+    note_race_target(source, requested, now_ms, &outcome, &mut s.coap_target_at, &mut s.matter_target_at);
+    outcome
+}
+
+/// Remember a CoAP or Matter target (angle, arrival ms) for `superseded`
+/// once it has reached the WAL. A refused target never wins a race.
+pub fn note_race_target(
+    source: CommandSource,
+    requested: u8,
+    now_ms: u64,
+    outcome: &Result<u8, RejectReason>,
+    coap: &mut Option<(u8, u64)>,
+    matter: &mut Option<(u8, u64)>,
+) {
+    if outcome.is_err() {
+        return;
+    }
+    match source {
+        CommandSource::Coap => *coap = Some((requested, now_ms)),
+        CommandSource::Matter => *matter = Some((requested, now_ms)),
+        _ => {}
+    }
+}
+
+fn try_target(
+    s: &mut AppState,
+    source: CommandSource,
+    requested: u8,
+    override_floor: bool,
+) -> Result<u8, RejectReason> {
+    let old_angle = s.vent.current_angle();
+    let in_range = match check_range(requested, s.range_policy) {
+        Ok(angle) => angle,
+        Err(reason) => {
+            let event = CommandEvent {
+                uptime_ms: s.start_time.elapsed().as_millis() as u64,
+                source,
+                old_angle,
+                new_angle: requested,
+                outcome: Ok(()),
+            };
+            return reject(event, reason);
+        }
+    };
+    let limited = s.soft_limits.map_or(in_range, |limits| limits.clamp(in_range));
+    if limited != in_range {
+        info!(
+            "{}: {}° is past the learned travel limit, holding at {}°",
+            source.as_str(),
+            in_range,
+            limited
+        );
+    }
+    let mut angle = limit_target(in_range, s.soft_limits, s.min_airflow_angle, override_floor);
+    if angle != limited {
+        info!(
+            "{}: {}° is below the airflow floor, holding at {}°",
+            source.as_str(),
+            limited,
+            angle
+        );
+    }
+    // Fail-safe moves must reach their target in one go
+    let cap = s.max_move.filter(|_| !source.is_critical());
+    let mut too_far = false;
+    if let Some(cap) = cap {
+        match apply_max_move(old_angle, angle, cap.max_deg, cap.mode) {
+            MoveDecision::Proceed(_) => {}
+            MoveDecision::Clamped(capped) => {
+                info!(
+                    "{}: {}° is more than {}° away, moving to {}° for now",
+                    source.as_str(),
+                    angle,
+                    cap.max_deg,
+                    capped
+                );
+                angle = capped;
+            }
+            MoveDecision::Reject => too_far = true,
+        }
+    }
+    let event = CommandEvent {
+        uptime_ms: s.start_time.elapsed().as_millis() as u64,
+        source,
+        old_angle,
+        new_angle: angle,
+        outcome: Ok(()),
+    };
+    if too_far {
+        return reject(event, RejectReason::MoveTooLarge);
+    }
+    // Only the winner of a CoAP/Matter race reaches the WAL
+    let lost_race = superseded(
+        source,
+        requested,
+        event.uptime_ms,
+        s.coap_target_at,
+        s.matter_target_at,
+        s.conflict_policy,
+    );
+    if lost_race {
+        info!("{}: {}° superseded by a racing target", source.as_str(), requested);
+        return reject(event, RejectReason::Superseded);
+    }
+
+    if !source_write_allowed(source, s.access_mode) {
+        return reject(event, RejectReason::ReadOnly);
+    }
+    if s.breakin.is_some() {
+        return reject(event, RejectReason::BreakIn);
+    }
+    if !source.is_critical() && s.quiet_hours.is_some_and(|q| q.active_now()) {
+        // Only the latest target matters once the window ends. A sweep leg
+        // isn't worth replaying; the sweep just stops.
+        if source != CommandSource::Maintenance {
+            s.deferred_target = Some((source, requested));
+        }
+        return reject(event, RejectReason::QuietHours);
+    }
+
+    // A move that can't finish leaves the louver stuck mid-travel; fail-safe
+    // parking still goes ahead
+    if !source.is_critical() && s.power_source == PowerSource::Battery && s.min_move_mv > 0 {
+        let battery_mv = s.sensors.latest_raw(SensorKind::SupplyVoltage).map(|r| r.value.max(0) as u32);
+        if let Some(mv) = battery_mv {
+            if !s.battery_gate.can_move_on_battery(mv, s.min_move_mv) {
+                warn!("{}: battery at {} mV, below {} mV — not moving", source.as_str(), mv, s.min_move_mv);
+                return reject(event, RejectReason::LowBattery);
+            }
+        }
+    }
+
+    match detached_action(s.detached, s.detach_honors_commands) {
+        DetachedAction::Proceed => {}
+        DetachedAction::Reengage => {
+            info!("{}: target while detached — re-engaging servo", source.as_str());
+            s.detached = false;
+        }
+        DetachedAction::Reject => return reject(event, RejectReason::Detached),
+    }
+
+    // Anything else the vent is told to do takes over from a sweep
+    if source != CommandSource::Maintenance && s.sweep.take().is_some() {
+        info!("{}: maintenance sweep cancelled", source.as_str());
+    }
+
+    // WAL: persist intent before moving
+    match s.identity.write_ahead(angle) {
+        Ok(()) => {}
+        Err(WalError::VerifyFailed) => {
+            warn!("{}: WAL read-back did not match {}°", source.as_str(), angle);
+            return reject(event, RejectReason::NvsVerifyFailed);
+        }
+        Err(WalError::Nvs(e)) => {
+            warn!("{}: WAL write-ahead failed: {:?}", source.as_str(), e);
+            return reject(event, RejectReason::WalFailed);
+        }
+    }
+
+    // The WAL holds the real target; an overshoot leg is only a waypoint
+    let legs = approach_sequence(s.vent.current_angle(), angle, s.approach, APPROACH_OVERSHOOT_DEG);
+    let first = limit_target(legs[0], s.soft_limits, s.min_airflow_angle, override_floor);
+    s.approach_final = (first != angle).then_some(angle);
+    let prev = s.vent.set_target(first);
+    s.command_since_boot = true;
+    s.metrics.incr(Metric::Commands);
+    log_command(&event);
+    Ok(prev)
+}
+
+/// Manually hold the HVAC bypass relay open, or release it. Releasing
+/// never closes the relay while the zone is all closed; the main loop's
+/// interlock decides.
+pub fn set_relay_override(s: &mut AppState, open: bool) -> Result<(), RejectReason> {
+    if !is_write_allowed(s.access_mode) {
+        warn!("relay: override rejected (read_only)");
+        return Err(RejectReason::ReadOnly);
+    }
+    info!("relay: manual override {}", if open { "on" } else { "off" });
+    s.relay_override = open;
+    Ok(())
+}
+
+/// Queue a prove-alive nudge for the main loop, e.g. from a health query.
+/// Returns false without queuing if nudges are off, the servo is detached,
+/// or one ran within `NUDGE_MIN_INTERVAL_MS`.
+pub fn request_nudge(s: &mut AppState) -> bool {
+    let now_ms = s.start_time.elapsed().as_millis() as u64;
+    if !s.nudge_enabled || s.detached || !nudge_allowed(s.last_nudge_ms, now_ms, NUDGE_MIN_INTERVAL_MS) {
+        return false;
+    }
+    s.last_nudge_ms = Some(now_ms);
+    s.nudge_pending = true;
+    true
+}
+
+/// Queue a servo PWM frequency auto-detect for the main loop. Returns
+/// false without queuing in read-only mode, while detached, or while a
+/// move or break-in is under way.
+pub fn request_freq_detect(s: &mut AppState) -> bool {
+    if !is_write_allowed(s.access_mode) || s.detached || s.vent.is_moving() || s.breakin.is_some() {
+        return false;
+    }
+    s.freq_detect_pending = true;
+    true
+}
+
+/// Queue soft-limit learning for the main loop. Returns false without
+/// queuing in read-only mode, while detached, or while a move or break-in
+/// is under way.
+pub fn request_learn_limits(s: &mut AppState) -> bool {
+    if !is_write_allowed(s.access_mode) || s.detached || s.vent.is_moving() || s.breakin.is_some() {
+        return false;
+    }
+    s.learn_limits_pending = true;
+    true
+}
+
+/// Queue a servo inversion auto-detect for the main loop. Returns false
+/// without queuing in read-only mode, while detached, or while a move or
+/// break-in is under way.
+pub fn request_inversion_detect(s: &mut AppState) -> bool {
+    if !is_write_allowed(s.access_mode) || s.detached || s.vent.is_moving() || s.breakin.is_some() {
+        return false;
+    }
+    s.inversion_detect_pending = true;
+    true
+}
+
+/// Queue a self-test for the main loop. Returns false without queuing in
+/// read-only mode or while a move or break-in is under way. A detached
+/// servo doesn't block it; the servo check is skipped instead.
+pub fn request_self_test(s: &mut AppState) -> bool {
+    if !is_write_allowed(s.access_mode) || s.vent.is_moving() || s.breakin.is_some() {
+        return false;
+    }
+    s.selftest_pending = true;
+    true
+}
+
+/// Apply the target queued during quiet hours, once they have ended.
+/// Returns the angle applied, if any.
+pub fn apply_deferred_target(s: &mut AppState) -> Option<u8> {
+    if s.quiet_hours.is_some_and(|q| q.active_now()) {
+        return None;
+    }
+    let (source, angle) = s.deferred_target.take()?;
+    info!("{}: quiet hours over, applying queued {}°", source.as_str(), angle);
+    apply_target(s, source, angle).ok().map(|_| s.vent.target_angle())
+}
+
+/// Outcome returned for a target command, cached for retries.
+pub type CachedResponse = Result<u8, RejectReason>;
+
+/// Number of recent idempotency tokens remembered.
+pub const DEDUP_CAPACITY: usize = 8;
+
+/// Recent idempotency tokens and their responses, so a retransmitted
+/// command returns the original result instead of moving again. The oldest
+/// entry is evicted when full.
+#[derive(Debug, Default)]
+pub struct DedupCache {
+    entries: VecDeque<(u64, CachedResponse)>,
+}
+
+impl DedupCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cached response if `token` was already processed.
+    pub fn check(&self, token: u64) -> Option<CachedResponse> {
+        self.entries
+            .iter()
+            .find(|(t, _)| *t == token)
+            .map(|(_, response)| *response)
+    }
+
+    pub fn record(&mut self, token: u64, response: CachedResponse) {
+        if self.check(token).is_some() {
+            return;
+        }
+        if self.entries.len() == DEDUP_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((token, response));
+    }
+}
+
+/// As `apply_target`, but a command carrying an already-seen idempotency
+/// token returns the cached response without moving again.
+pub fn apply_target_idempotent(
+    s: &mut AppState,
+    source: CommandSource,
+    angle: u8,
+    token: Option<u64>,
+) -> CachedResponse {
+    let Some(token) = token else {
+        return apply_target(s, source, angle);
+    };
+    if let Some(cached) = s.command_dedup.check(token) {
+        info!("{}: repeated token {:#x}, returning cached result", source.as_str(), token);
+        return cached;
+    }
+    let response = apply_target(s, source, angle);
+    s.command_dedup.record(token, response);
+    response
+}
+
+/// Stop driving the servo so the louver can be adjusted by hand. The main
+/// loop cuts PWM on the next pass.
+pub fn detach(s: &mut AppState) {
+    if !s.detached {
+        info!("Servo detached");
+        s.detached = true;
+    }
+}
+
+/// Resume driving the servo at its current logical angle.
+pub fn engage(s: &mut AppState) {
+    if s.detached {
+        info!("Servo re-engaged at {}°", s.vent.current_angle());
+        s.detached = false;
+    }
+}
+
+/// Move to a named preset. The name is resolved against the NVS preset
+/// table and the angle then goes through the normal target path.
+pub fn apply_preset(s: &mut AppState, source: CommandSource, name: &str) -> Result<u8, RejectReason> {
+    let table = s.identity.get_presets().unwrap_or_default();
+    match resolve_preset(name, &table) {
+        Some(angle) => {
+            info!("{}: preset '{}' -> {}°", source.as_str(), name, angle);
+            apply_target(s, source, angle)
+        }
+        None => {
+            let current = s.vent.current_angle();
+            let event = CommandEvent {
+                uptime_ms: s.start_time.elapsed().as_millis() as u64,
+                source,
+                old_angle: current,
+                new_angle: current,
+                outcome: Ok(()),
+            };
+            warn!("{}: unknown preset '{}'", source.as_str(), name);
+            reject(event, RejectReason::UnknownPreset)
+        }
+    }
+}
+
+/// Apply a plain-text target payload (see `parse_text_angle`). Undecodable
+/// payloads are counted toward the persistent decode-failure total.
+pub fn apply_text_target(s: &mut AppState, source: CommandSource, bytes: &[u8]) -> Result<u8, RejectReason> {
+    match decode_text_target(bytes, &mut s.decode_failures) {
+        Some(angle) => apply_target(s, source, angle),
+        None => {
+            let current = s.vent.current_angle();
+            let event = CommandEvent {
+                uptime_ms: s.start_time.elapsed().as_millis() as u64,
+                source,
+                old_angle: current,
+                new_angle: current,
+                outcome: Ok(()),
+            };
+            warn!(
+                "{}: undecodable target payload ({} bytes, {} failures total)",
+                source.as_str(),
+                bytes.len(),
+                s.decode_failures.total()
+            );
+            reject(event, RejectReason::Malformed)
+        }
+    }
+}
+
+/// Delay between accepting a reboot and restarting, so the reply can flush.
+pub const REBOOT_DELAY_MS: u64 = 500;
+
+/// Why a break-in request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakInError {
+    ReadOnly,
+    /// Break-in already ran on this device; pass `force` to repeat it.
+    AlreadyDone,
+    /// The servo is detached, identifying or already breaking in.
+    Busy,
+    /// The first leg could not be written ahead.
+    WalFailed,
+}
+
+/// Start a break-in of `cycles` slow full strokes. Normal target commands
+/// are rejected until it finishes and the vent returns to where it was.
+pub fn start_breakin(s: &mut AppState, cycles: u8, force: bool) -> Result<(), BreakInError> {
+    if !is_write_allowed(s.access_mode) {
+        return Err(BreakInError::ReadOnly);
+    }
+    if s.breakin.is_some() || s.detached || s.identify_mode {
+        return Err(BreakInError::Busy);
+    }
+    if !force && s.identity.get_breakin_done().unwrap_or(false) {
+        info!("Break-in already completed — not repeating without force");
+        return Err(BreakInError::AlreadyDone);
+    }
+    // Strokes stay inside the travel a command could reach
+    let closed = limit_target(ANGLE_CLOSED, s.soft_limits, s.min_airflow_angle, false);
+    let open = limit_target(ANGLE_OPEN, s.soft_limits, s.min_airflow_angle, false);
+    let mut run = BreakIn::new(cycles, closed, open, s.vent.target_angle());
+    let Some(first) = run.next_target() else {
+        return Ok(());
+    };
+    if let Err(e) = s.identity.write_ahead(first) {
+        warn!("Break-in WAL write-ahead failed: {:?}", e);
+        return Err(BreakInError::WalFailed);
+    }
+    info!(
+        "Break-in: {} cycles starting ({} legs)",
+        cycles.min(MAX_BREAKIN_CYCLES),
+        run.progress().1
+    );
+    s.vent.set_target(first);
+    // The break-in's own legs replace any approach still in flight
+    s.approach_final = None;
+    s.breakin = Some(run);
+    s.breakin_purpose = BreakInPurpose::Install;
+    Ok(())
+}
+
+/// Start a scheduled exercise sweep: one slow full cycle, then back to the
+/// commanded angle. Each leg is an ordinary `Maintenance` target, so it
+/// gets the same range, soft-limit, floor, battery and quiet-hours checks
+/// as any command, and any other accepted command cancels the rest.
+/// Deferred as `Busy` while the vent is moving, breaking in, detached,
+/// identifying or in quiet hours. The start is stamped in NVS up front so
+/// a sweep that can't finish isn't retried on every check.
+pub fn start_maintenance_sweep(s: &mut AppState, now_unix_s: u64) -> Result<(), BreakInError> {
+    if s.vent.is_moving()
+        || s.breakin.is_some()
+        || s.sweep.is_some()
+        || s.detached
+        || s.identify_mode
+        || s.quiet_hours.is_some_and(|q| q.active_now())
+    {
+        return Err(BreakInError::Busy);
+    }
+    if let Err(e) = s.identity.set_last_sweep(now_unix_s) {
+        warn!("Failed to record maintenance sweep: {:?}", e);
+    }
+    info!("Maintenance sweep started");
+    s.sweep = Some(BreakIn::new(
+        maintenance::SWEEP_CYCLES,
+        ANGLE_CLOSED,
+        ANGLE_OPEN,
+        s.vent.target_angle(),
+    ));
+    advance_sweep(s);
+    Ok(())
+}
+
+/// Send the sweep's next leg through the command path, called when the
+/// previous leg completes. A rejected leg ends the sweep where it is; a
+/// leg the limits leave with nowhere to go is skipped.
+pub fn advance_sweep(s: &mut AppState) {
+    while let Some(next) = s.sweep.as_mut().and_then(BreakIn::next_target) {
+        if let Err(reason) = apply_target(s, CommandSource::Maintenance, next) {
+            warn!("Maintenance sweep stopped: leg to {}° rejected ({})", next, reason.as_str());
+            s.sweep = None;
+            return;
+        }
+        if s.vent.is_moving() {
+            return;
+        }
+    }
+    if s.sweep.take().is_some() {
+        info!("Maintenance sweep complete");
+    }
+}
+
+/// Why a reboot request was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootError {
+    ReadOnly,
+    /// The confirmation token did not name this device.
+    BadToken,
+}
+
+/// A reboot must be confirmed with this device's EUI-64, so a request
+/// aimed at the wrong vent (or sent by accident) is refused. Separators
+/// and case are ignored.
+pub fn reboot_token_valid(token: &str, eui64: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| *c != ':' && *c != '-')
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    };
+    let token = normalize(token.trim());
+    !token.is_empty() && token == normalize(eui64)
+}
+
+/// Run `commit`, then `restart` regardless of the commit result: a failed
+/// commit only means the WAL replays the last move on the next boot.
+pub fn commit_then_restart<E: core::fmt::Debug>(
+    commit: impl FnOnce() -> Result<(), E>,
+    restart: impl FnOnce(),
+) {
+    if let Err(e) = commit() {
+        warn!("Pre-reboot commit failed: {:?}", e);
+    }
+    restart();
+}
+
+/// Soft reboot on request: commit the current angle and bank runtime, then restart after
+/// `REBOOT_DELAY_MS` from a background thread so the caller can reply.
+pub fn request_reboot(s: &mut AppState, token: &str) -> Result<(), RebootError> {
+    if !is_write_allowed(s.access_mode) {
+        warn!("Reboot refused: read-only mode");
+        return Err(RebootError::ReadOnly);
+    }
+    if !reboot_token_valid(token, s.identity.eui64()) {
+        warn!("Reboot refused: confirmation token does not match this device");
+        return Err(RebootError::BadToken);
+    }
+    let angle = s.vent.current_angle();
+    warn!(
+        "Reboot requested — committing {}° and restarting in {}ms",
+        angle, REBOOT_DELAY_MS
+    );
+    commit_then_restart(
+        || {
+            s.identity.commit(angle)?;
+            s.flush_metrics();
+            s.persist_runtime()
+        },
+        || {
+            std::thread::spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(REBOOT_DELAY_MS));
+                unsafe { esp_idf_sys::esp_restart() };
+            });
+        },
+    );
+    Ok(())
+}
+
+/// Why a config update was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigUpdateError {
+    ReadOnly,
+    /// A setting could not be written to NVS; earlier ones may have been.
+    Storage,
+}
+
+/// Write a partial config to NVS and pick up the settings the main loop
+/// reads from `AppState`. The rest take effect at the next boot.
+pub fn apply_config(s: &mut AppState, config: &DeviceConfig) -> Result<(), ConfigUpdateError> {
+    if !is_write_allowed(s.access_mode) {
+        warn!("config: update rejected (read_only)");
+        return Err(ConfigUpdateError::ReadOnly);
+    }
+    if let Err(e) = config.apply(&mut s.identity) {
+        warn!("config: NVS write failed: {:?}", e);
+        return Err(ConfigUpdateError::Storage);
+    }
+    if let Some(ms) = config.poll_ms {
+        s.poll_period_ms = ms;
+    }
+    if let Some(deg) = config.report_round_deg {
+        s.report_round_deg = deg;
+    }
+    if let Some(angle) = config.min_airflow_deg {
+        s.min_airflow_angle = angle;
+    }
+    info!("config: update applied");
+    Ok(())
+}
+
+/// Debug handler: record `target` as an interrupted move and reboot, so the
+/// next boot must replay it from the WAL.
+#[cfg(feature = "wal-debug")]
+#[allow(unreachable_code)] // esp_restart never returns on target
+pub fn simulate_wal_interruption(s: &mut AppState, target: u8) -> Result<(), WalError> {
+    s.identity.simulate_interrupted_move(target)?;
+    warn!("WAL simulate: pending target {}° recorded without commit — rebooting", target);
+    unsafe { esp_idf_sys::esp_restart() };
+    Ok(())
+}
+
+fn reject(mut event: CommandEvent, reason: RejectReason) -> Result<u8, RejectReason> {
+    event.outcome = Err(reason);
+    log_command(&event);
+    Err(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_response_outcomes() {
+        let moved = target_response(150, 90, 90, 150, Ok(90));
+        assert!(moved.accepted);
+        assert_eq!((moved.target, moved.previous, moved.current), (150, 90, 90));
+        // Queued for the end of quiet hours
+        let queued = target_response(150, 90, 90, 90, Err(RejectReason::QuietHours));
+        assert!(queued.accepted);
+        assert_eq!(queued.target, 150);
+        let refused = target_response(150, 90, 90, 90, Err(RejectReason::ReadOnly));
+        assert!(!refused.accepted);
+        assert_eq!(refused.target, 150);
+    }
+
+    #[test]
+    fn test_latest_wins() {
+        let p = ConflictPolicy::LatestWins;
+        assert_eq!(resolve_conflict(90, 1_000, 180, 2_000, p), 180);
+        assert_eq!(resolve_conflict(90, 2_000, 180, 1_000, p), 90);
+        // Near-simultaneous: still strictly by arrival
+        assert_eq!(resolve_conflict(90, 1_001, 180, 1_000, p), 90);
+        assert_eq!(resolve_conflict(90, 1_000, 180, 1_001, p), 180);
+        // Exact tie goes to Matter
+        assert_eq!(resolve_conflict(90, 1_000, 180, 1_000, p), 180);
+    }
+
+    #[test]
+    fn test_coap_overrides_matter_within_window() {
+        let p = ConflictPolicy::CoapOverridesMatter;
+        assert_eq!(resolve_conflict(90, 1_000, 180, 1_000, p), 90);
+        assert_eq!(resolve_conflict(90, 1_000, 180, 1_000 + CONFLICT_WINDOW_MS, p), 90);
+        assert_eq!(resolve_conflict(90, 2_000, 180, 1_000, p), 90);
+    }
+
+    #[test]
+    fn test_coap_override_expires_after_window() {
+        let p = ConflictPolicy::CoapOverridesMatter;
+        assert_eq!(resolve_conflict(90, 1_000, 180, 1_001 + CONFLICT_WINDOW_MS, p), 180);
+    }
+
+    #[test]
+    fn test_superseded_by_racing_source() {
+        let p = ConflictPolicy::CoapOverridesMatter;
+        // Matter 100 ms after a CoAP target loses; after the window it wins
+        assert!(superseded(CommandSource::Matter, 180, 1_100, Some((90, 1_000)), None, p));
+        assert!(!superseded(CommandSource::Matter, 180, 1_000 + CONFLICT_WINDOW_MS + 1, Some((90, 1_000)), None, p));
+        // The same angle from both sources is no conflict
+        assert!(!superseded(CommandSource::Matter, 90, 1_100, Some((90, 1_000)), None, p));
+        // Latest wins: the arrival being judged is always the later one
+        let p = ConflictPolicy::LatestWins;
+        assert!(!superseded(CommandSource::Matter, 180, 1_100, Some((90, 1_000)), None, p));
+        assert!(!superseded(CommandSource::Coap, 180, 1_100, None, Some((90, 1_000)), p));
+        // A tie goes to Matter
+        assert!(superseded(CommandSource::Coap, 180, 1_000, None, Some((90, 1_000)), p));
+        // Nothing to race against, or a local source
+        assert!(!superseded(CommandSource::Coap, 180, 1_000, Some((90, 999)), None, p));
+        assert!(!superseded(CommandSource::Failsafe, 180, 1_000, Some((90, 1_000)), Some((90, 1_000)), p));
+    }
+
+    #[test]
+    fn test_refused_coap_target_does_not_supersede_matter() {
+        let p = ConflictPolicy::CoapOverridesMatter;
+        let (mut coap, mut matter) = (None, None);
+        note_race_target(CommandSource::Matter, 180, 1_000, &Ok(90), &mut coap, &mut matter);
+        // Read-only mode refuses the CoAP target, so it never counts
+        note_race_target(CommandSource::Coap, 90, 1_050, &Err(RejectReason::ReadOnly), &mut coap, &mut matter);
+        assert_eq!(coap, None);
+        assert!(!superseded(CommandSource::Matter, 180, 1_100, coap, matter, p));
+        // An accepted one does
+        note_race_target(CommandSource::Coap, 90, 1_050, &Ok(180), &mut coap, &mut matter);
+        assert!(superseded(CommandSource::Matter, 180, 1_100, coap, matter, p));
+    }
+
+    #[test]
+    fn test_conflict_policy_from_u8() {
+        assert_eq!(ConflictPolicy::from_u8(0), ConflictPolicy::LatestWins);
+        assert_eq!(ConflictPolicy::from_u8(1), ConflictPolicy::CoapOverridesMatter);
+        assert_eq!(ConflictPolicy::from_u8(7), ConflictPolicy::LatestWins);
+    }
+
+    #[test]
+    fn test_format_rejected_command_has_all_fields() {
+        let event = CommandEvent {
+            uptime_ms: 12_345,
+            source: CommandSource::Matter,
+            old_angle: 90,
+            new_angle: 180,
+            outcome: Err(RejectReason::WalFailed),
+        };
+        let line = format_command(&event);
+        assert!(line.contains("t=12345ms"));
+        assert!(line.contains("src=matter"));
+        assert!(line.contains("path=window_covering/target"));
+        assert!(line.contains("old=90"));
+        assert!(line.contains("new=180"));
+        assert!(line.contains("result=rejected"));
+        assert!(line.contains("reason=wal_failed"));
+    }
+
+    #[test]
+    fn test_min_airflow_clamps_up_to_floor() {
+        assert_eq!(enforce_min_airflow(90, 110, false), 110);
+        assert_eq!(enforce_min_airflow(105, 110, false), 110);
+        assert_eq!(enforce_min_airflow(110, 110, false), 110);
+        assert_eq!(enforce_min_airflow(150, 110, false), 150);
+        // Default floor of fully closed never changes a target
+        assert_eq!(enforce_min_airflow(90, 90, false), 90);
+    }
+
+    #[test]
+    fn test_min_airflow_override_bypasses_floor() {
+        assert_eq!(enforce_min_airflow(90, 110, true), 90);
+        assert_eq!(enforce_min_airflow(150, 110, true), 150);
+    }
+
+    #[test]
+    fn test_floor_applies_after_soft_limits() {
+        let limits = Some(SoftLimits { closed: 95, open: 170 });
+        assert_eq!(limit_target(180, limits, 110, false), 170);
+        assert_eq!(limit_target(90, limits, 90, false), 95);
+        // A limit below the floor doesn't pull the move under it
+        let low = Some(SoftLimits { closed: 90, open: 100 });
+        assert_eq!(limit_target(150, low, 110, false), 110);
+        assert_eq!(limit_target(150, low, 110, true), 100);
+        assert_eq!(limit_target(120, None, 110, false), 120);
+    }
+
+    #[test]
+    fn test_reboot_token_validation() {
+        let eui = "40:4c:ca:ff:fe:41:2a:10";
+        assert!(reboot_token_valid(eui, eui));
+        assert!(reboot_token_valid("404CCAFFFE412A10", eui));
+        assert!(reboot_token_valid(" 40-4c-ca-ff-fe-41-2a-10 ", eui));
+        assert!(!reboot_token_valid("40:4c:ca:ff:fe:41:2a:11", eui));
+        assert!(!reboot_token_valid("", eui));
+        assert!(!reboot_token_valid("yes", eui));
+    }
+
+    #[test]
+    fn test_commit_attempted_before_restart() {
+        let steps = std::cell::RefCell::new(Vec::new());
+        commit_then_restart(
+            || -> Result<(), ()> {
+                steps.borrow_mut().push("commit");
+                Ok(())
+            },
+            || steps.borrow_mut().push("restart"),
+        );
+        assert_eq!(*steps.borrow(), ["commit", "restart"]);
+
+        // A failed commit still restarts
+        steps.borrow_mut().clear();
+        commit_then_restart(
+            || -> Result<(), ()> {
+                steps.borrow_mut().push("commit");
+                Err(())
+            },
+            || steps.borrow_mut().push("restart"),
+        );
+        assert_eq!(*steps.borrow(), ["commit", "restart"]);
+    }
+
+    #[test]
+    fn test_range_clamp_mode() {
+        assert_eq!(check_range(200, RangePolicy::Clamp), Ok(180));
+        assert_eq!(check_range(10, RangePolicy::Clamp), Ok(90));
+        assert_eq!(check_range(135, RangePolicy::Clamp), Ok(135));
+    }
+
+    #[test]
+    fn test_range_reject_mode() {
+        assert_eq!(
+            check_range(200, RangePolicy::Reject),
+            Err(RejectReason::AngleOutOfRange)
+        );
+        assert_eq!(
+            check_range(10, RangePolicy::Reject),
+            Err(RejectReason::AngleOutOfRange)
+        );
+        assert_eq!(check_range(90, RangePolicy::Reject), Ok(90));
+        assert_eq!(check_range(180, RangePolicy::Reject), Ok(180));
+    }
+
+    #[test]
+    fn test_range_policy_from_u8() {
+        assert_eq!(RangePolicy::from_u8(0), RangePolicy::Clamp);
+        assert_eq!(RangePolicy::from_u8(1), RangePolicy::Reject);
+        assert_eq!(RangePolicy::from_u8(9), RangePolicy::Clamp);
+    }
+
+    #[test]
+    fn test_max_move_within_cap_or_off() {
+        assert_eq!(apply_max_move(90, 120, 30, MaxMoveMode::Reject), MoveDecision::Proceed(120));
+        assert_eq!(apply_max_move(180, 150, 30, MaxMoveMode::Clamp), MoveDecision::Proceed(150));
+        assert_eq!(apply_max_move(90, 180, 0, MaxMoveMode::Reject), MoveDecision::Proceed(180));
+    }
+
+    #[test]
+    fn test_max_move_clamp_mode() {
+        assert_eq!(apply_max_move(90, 180, 30, MaxMoveMode::Clamp), MoveDecision::Clamped(120));
+        assert_eq!(apply_max_move(180, 90, 30, MaxMoveMode::Clamp), MoveDecision::Clamped(150));
+        // A follow-up command continues from where the capped move ended
+        assert_eq!(apply_max_move(120, 180, 30, MaxMoveMode::Clamp), MoveDecision::Clamped(150));
+        assert_eq!(apply_max_move(150, 180, 30, MaxMoveMode::Clamp), MoveDecision::Proceed(180));
+    }
+
+    #[test]
+    fn test_max_move_reject_mode() {
+        assert_eq!(apply_max_move(90, 121, 30, MaxMoveMode::Reject), MoveDecision::Reject);
+        assert_eq!(apply_max_move(180, 90, 30, MaxMoveMode::Reject), MoveDecision::Reject);
+    }
+
+    #[test]
+    fn test_max_move_mode_from_u8() {
+        assert_eq!(MaxMoveMode::from_u8(0), MaxMoveMode::Clamp);
+        assert_eq!(MaxMoveMode::from_u8(1), MaxMoveMode::Reject);
+        assert_eq!(MaxMoveMode::from_u8(5), MaxMoveMode::Clamp);
+    }
+
+    #[test]
+    fn test_decode_failures_count_only_bad_payloads() {
+        let mut failures = DecodeFailures::new(3);
+        assert_eq!(decode_text_target(b"135", &mut failures), Some(135));
+        assert_eq!(decode_text_target(b"200", &mut failures), Some(200));
+        assert_eq!(failures.total(), 3);
+        assert_eq!(failures.take_unsaved(), None);
+
+        assert_eq!(decode_text_target(b"\xa1\x00", &mut failures), None);
+        assert_eq!(decode_text_target(b"-5", &mut failures), None);
+        assert_eq!(failures.total(), 5);
+        assert_eq!(failures.take_unsaved(), Some(5));
+        assert_eq!(failures.take_unsaved(), None);
+    }
+
+    #[test]
+    fn test_parse_text_angle_valid() {
+        assert_eq!(parse_text_angle(b"135"), Some(135));
+        assert_eq!(parse_text_angle(b"90"), Some(90));
+        assert_eq!(parse_text_angle(b"0"), Some(0));
+        // Out of the vent range but a valid number: the policy decides
+        assert_eq!(parse_text_angle(b"200"), Some(200));
+    }
+
+    #[test]
+    fn test_parse_text_angle_whitespace() {
+        assert_eq!(parse_text_angle(b" 135 "), Some(135));
+        assert_eq!(parse_text_angle(b"135\n"), Some(135));
+        assert_eq!(parse_text_angle(b"\t180\r\n"), Some(180));
+        assert_eq!(parse_text_angle(b"1 35"), None);
+        assert_eq!(parse_text_angle(b"   "), None);
+    }
+
+    #[test]
+    fn test_parse_text_angle_rejects_negatives_and_garbage() {
+        assert_eq!(parse_text_angle(b"-10"), None);
+        assert_eq!(parse_text_angle(b"+90"), None);
+        assert_eq!(parse_text_angle(b"135.5"), None);
+        assert_eq!(parse_text_angle(b"open"), None);
+        assert_eq!(parse_text_angle(b"300"), None);
+        assert_eq!(parse_text_angle(b""), None);
+        assert_eq!(parse_text_angle(&[0xff, 0x31]), None);
+    }
+
+    #[test]
+    fn test_dedup_suppresses_repeat() {
+        let mut cache = DedupCache::new();
+        assert_eq!(cache.check(0xabc), None);
+        cache.record(0xabc, Ok(90));
+        assert_eq!(cache.check(0xabc), Some(Ok(90)));
+        // A rejection is replayed too, not retried
+        cache.record(0xdef, Err(RejectReason::ReadOnly));
+        assert_eq!(cache.check(0xdef), Some(Err(RejectReason::ReadOnly)));
+        // The first response for a token wins
+        cache.record(0xabc, Ok(180));
+        assert_eq!(cache.check(0xabc), Some(Ok(90)));
+    }
+
+    #[test]
+    fn test_dedup_evicts_oldest() {
+        let mut cache = DedupCache::new();
+        for token in 0..DEDUP_CAPACITY as u64 {
+            cache.record(token, Ok(90));
+        }
+        assert!(cache.check(0).is_some());
+        cache.record(100, Ok(120));
+        assert_eq!(cache.check(0), None);
+        assert!(cache.check(1).is_some());
+        assert_eq!(cache.check(100), Some(Ok(120)));
+    }
+
+    #[test]
+    fn test_min_fw_requirement() {
+        assert!(min_fw_ok(true, "1.4.0", None));
+        assert!(min_fw_ok(true, "1.4.0", Some("1.3.0")));
+        assert!(!min_fw_ok(true, "1.4.0", Some("1.5.0")));
+        // Check turned off: requirement ignored
+        assert!(min_fw_ok(false, "1.4.0", Some("1.5.0")));
+        assert_eq!(RejectReason::FirmwareTooOld.as_str(), "firmware_too_old");
+    }
+
+    #[test]
+    fn test_detached_target_honored() {
+        assert_eq!(detached_action(true, true), DetachedAction::Reengage);
+        assert_eq!(detached_action(false, true), DetachedAction::Proceed);
+    }
+
+    #[test]
+    fn test_detached_target_rejected() {
+        assert_eq!(detached_action(true, false), DetachedAction::Reject);
+        assert_eq!(detached_action(false, false), DetachedAction::Proceed);
+        assert_eq!(RejectReason::Detached.as_str(), "detached");
+    }
+
+    #[test]
+    fn test_is_write_allowed() {
+        assert!(is_write_allowed(AccessMode::ReadWrite));
+        assert!(!is_write_allowed(AccessMode::ReadOnly));
+    }
+
+    #[test]
+    fn test_read_only_refuses_only_remote_sources() {
+        assert!(!source_write_allowed(CommandSource::Matter, AccessMode::ReadOnly));
+        assert!(!source_write_allowed(CommandSource::Coap, AccessMode::ReadOnly));
+        assert!(source_write_allowed(CommandSource::Failsafe, AccessMode::ReadOnly));
+        assert!(source_write_allowed(CommandSource::PressureControl, AccessMode::ReadOnly));
+        assert!(source_write_allowed(CommandSource::Maintenance, AccessMode::ReadOnly));
+        assert!(source_write_allowed(CommandSource::Coap, AccessMode::ReadWrite));
+    }
+
+    #[test]
+    fn test_access_mode_from_nvs() {
+        assert_eq!(AccessMode::from_u8(0), AccessMode::ReadWrite);
+        assert_eq!(AccessMode::from_u8(1), AccessMode::ReadOnly);
+        assert_eq!(AccessMode::from_u8(0xff), AccessMode::ReadWrite);
+    }
+
+    #[test]
+    fn test_format_accepted_command() {
+        let event = CommandEvent {
+            uptime_ms: 0,
+            source: CommandSource::Matter,
+            old_angle: 135,
+            new_angle: 90,
+            outcome: Ok(()),
+        };
+        let line = format_command(&event);
+        assert!(line.ends_with("result=accepted"));
+        assert!(!line.contains("reason="));
+    }
+}
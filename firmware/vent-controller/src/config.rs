@@ -0,0 +1,350 @@
+use crate::identity::DeviceIdentity;
+use esp_idf_sys::EspError;
+use vent_protocol::DeviceType;
+
+/// Settings writable as a JSON object, for web tools that can't build
+/// CBOR. Every field is optional: a key that's absent leaves the stored
+/// setting untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceConfig {
+    pub name: Option<String>,
+    pub room: Option<String>,
+    pub floor: Option<String>,
+    pub poll_ms: Option<u32>,
+    pub fabric_timeout_s: Option<u32>,
+    pub report_tolerance_deg: Option<u8>,
+    pub report_round_deg: Option<u8>,
+    pub min_airflow_deg: Option<u8>,
+    /// Entity class hubs should present the vent as.
+    pub device_type: Option<DeviceType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// Not a JSON object.
+    Malformed,
+    /// A known key held the wrong type or an out-of-range value.
+    BadValue(&'static str),
+}
+
+impl DeviceConfig {
+    /// Write each present field to NVS.
+    pub fn apply(&self, identity: &mut DeviceIdentity) -> Result<(), EspError> {
+        if let Some(name) = &self.name {
+            identity.set_name(name)?;
+        }
+        if let Some(room) = &self.room {
+            identity.set_room(room)?;
+        }
+        if let Some(floor) = &self.floor {
+            identity.set_floor(floor)?;
+        }
+        if let Some(ms) = self.poll_ms {
+            identity.set_poll_period(ms)?;
+        }
+        if let Some(secs) = self.fabric_timeout_s {
+            identity.set_fabric_timeout(secs)?;
+        }
+        if let Some(deg) = self.report_tolerance_deg {
+            identity.set_report_tolerance(deg)?;
+        }
+        if let Some(deg) = self.report_round_deg {
+            identity.set_report_round(deg)?;
+        }
+        if let Some(angle) = self.min_airflow_deg {
+            identity.set_min_airflow_angle(angle)?;
+        }
+        if let Some(kind) = self.device_type {
+            identity.set_device_type(kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a JSON config update. Unknown keys are skipped so newer tools can
+/// talk to older firmware; a known key with the wrong type is refused
+/// rather than half-applied.
+pub fn config_from_json(text: &str) -> Result<DeviceConfig, ConfigError> {
+    let mut p = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let mut config = DeviceConfig::default();
+    p.expect(b'{')?;
+    if !p.eat(b'}') {
+        loop {
+            let key = p.string()?;
+            p.expect(b':')?;
+            let value = p.value()?;
+            match key.as_str() {
+                "name" => config.name = Some(value.into_string("name")?),
+                "room" => config.room = Some(value.into_string("room")?),
+                "floor" => config.floor = Some(value.into_string("floor")?),
+                "poll_ms" => config.poll_ms = Some(value.into_int("poll_ms")?),
+                "fabric_timeout_s" => {
+                    config.fabric_timeout_s = Some(value.into_int("fabric_timeout_s")?)
+                }
+                "report_tolerance_deg" => {
+                    config.report_tolerance_deg = Some(value.into_int("report_tolerance_deg")?)
+                }
+                "report_round_deg" => {
+                    config.report_round_deg = Some(value.into_int("report_round_deg")?)
+                }
+                "min_airflow_deg" => {
+                    config.min_airflow_deg = Some(value.into_int("min_airflow_deg")?)
+                }
+                "device_type" => {
+                    let name = value.into_string("device_type")?;
+                    config.device_type =
+                        Some(DeviceType::from_name(&name).ok_or(ConfigError::BadValue("device_type"))?)
+                }
+                _ => {}
+            }
+            if p.eat(b'}') {
+                break;
+            }
+            p.expect(b',')?;
+        }
+    }
+    p.skip_ws();
+    if p.pos != p.bytes.len() {
+        return Err(ConfigError::Malformed);
+    }
+    Ok(config)
+}
+
+/// A parsed JSON value. Only what config fields need is kept; arrays and
+/// objects are consumed but not retained.
+enum Value {
+    Str(String),
+    Int(i64),
+    Other,
+}
+
+impl Value {
+    fn into_string(self, key: &'static str) -> Result<String, ConfigError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(ConfigError::BadValue(key)),
+        }
+    }
+
+    fn into_int<T: TryFrom<i64>>(self, key: &'static str) -> Result<T, ConfigError> {
+        match self {
+            Value::Int(n) => T::try_from(n).map_err(|_| ConfigError::BadValue(key)),
+            _ => Err(ConfigError::BadValue(key)),
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while matches!(self.bytes.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), ConfigError> {
+        if self.eat(b) {
+            Ok(())
+        } else {
+            Err(ConfigError::Malformed)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ConfigError> {
+        match self.peek().ok_or(ConfigError::Malformed)? {
+            b'"' => Ok(Value::Str(self.string()?)),
+            b'{' => {
+                self.pos += 1;
+                if !self.eat(b'}') {
+                    loop {
+                        self.string()?;
+                        self.expect(b':')?;
+                        self.value()?;
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Other)
+            }
+            b'[' => {
+                self.pos += 1;
+                if !self.eat(b']') {
+                    loop {
+                        self.value()?;
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Other)
+            }
+            b't' => self.literal("true"),
+            b'f' => self.literal("false"),
+            b'n' => self.literal("null"),
+            _ => self.number(),
+        }
+    }
+
+    fn literal(&mut self, word: &str) -> Result<Value, ConfigError> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(Value::Other)
+        } else {
+            Err(ConfigError::Malformed)
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ConfigError> {
+        let start = self.pos;
+        while matches!(
+            self.bytes.get(self.pos),
+            Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')
+        ) {
+            self.pos += 1;
+        }
+        let token = core::str::from_utf8(&self.bytes[start..self.pos])
+            .map_err(|_| ConfigError::Malformed)?;
+        if let Ok(n) = token.parse::<i64>() {
+            Ok(Value::Int(n))
+        } else if token.parse::<f64>().is_ok() {
+            Ok(Value::Other)
+        } else {
+            Err(ConfigError::Malformed)
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ConfigError> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let rest = core::str::from_utf8(&self.bytes[self.pos..])
+                .map_err(|_| ConfigError::Malformed)?;
+            let mut chars = rest.chars();
+            let c = chars.next().ok_or(ConfigError::Malformed)?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let esc = *self.bytes.get(self.pos).ok_or(ConfigError::Malformed)?;
+                    self.pos += 1;
+                    out.push(match esc {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let hex = self
+                                .bytes
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|h| core::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .ok_or(ConfigError::Malformed)?;
+                            self.pos += 4;
+                            // Surrogate pairs don't occur in names and rooms
+                            char::from_u32(hex).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err(ConfigError::Malformed),
+                    });
+                }
+                c if (c as u32) < 0x20 => return Err(ConfigError::Malformed),
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_update_sets_only_present_keys() {
+        let config = config_from_json(r#"{"room": "Office", "poll_ms": 2000}"#).unwrap();
+        assert_eq!(config.room.as_deref(), Some("Office"));
+        assert_eq!(config.poll_ms, Some(2000));
+        assert_eq!(config.name, None);
+        assert_eq!(config.min_airflow_deg, None);
+        let config = config_from_json(r#"{"device_type": "damper"}"#).unwrap();
+        assert_eq!(config.device_type, Some(DeviceType::Damper));
+        assert_eq!(config_from_json("{}").unwrap(), DeviceConfig::default());
+    }
+
+    #[test]
+    fn test_unknown_keys_ignored() {
+        let config = config_from_json(
+            r#"{"future": {"a": [1, 2.5, true, null]}, "name": "Vent \"A\"", "x": -1e3}"#,
+        )
+        .unwrap();
+        assert_eq!(config.name.as_deref(), Some("Vent \"A\""));
+        assert_eq!(
+            config,
+            DeviceConfig {
+                name: Some("Vent \"A\"".into()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_malformed_json_rejected() {
+        for text in [
+            "",
+            "[]",
+            r#"{"room": "Office""#,
+            r#"{"room" "Office"}"#,
+            r#"{"room": "Office",}"#,
+            r#"{"room": "Office"} trailing"#,
+            r#"{"a": tru}"#,
+        ] {
+            assert_eq!(config_from_json(text), Err(ConfigError::Malformed), "{text}");
+        }
+    }
+
+    #[test]
+    fn test_wrong_type_or_range_rejected() {
+        assert_eq!(
+            config_from_json(r#"{"poll_ms": "2000"}"#),
+            Err(ConfigError::BadValue("poll_ms"))
+        );
+        assert_eq!(
+            config_from_json(r#"{"min_airflow_deg": 300}"#),
+            Err(ConfigError::BadValue("min_airflow_deg"))
+        );
+        assert_eq!(
+            config_from_json(r#"{"room": 3}"#),
+            Err(ConfigError::BadValue("room"))
+        );
+        assert_eq!(
+            config_from_json(r#"{"device_type": "fan"}"#),
+            Err(ConfigError::BadValue("device_type"))
+        );
+    }
+}
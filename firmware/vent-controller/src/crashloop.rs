@@ -0,0 +1,99 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// More boots than this inside the window means the vent is crash-looping.
+pub const CRASH_LOOP_BOOTS: usize = 4;
+/// Window for counting recent boots.
+pub const CRASH_LOOP_WINDOW_S: u32 = 600;
+/// Boot timestamps kept in NVS; one more than the threshold is enough to
+/// tell whether it was exceeded.
+pub const BOOT_HISTORY_LEN: usize = CRASH_LOOP_BOOTS + 1;
+
+/// Whether more than `n` of `boot_times` fall within `window_s` before
+/// `now` (inclusive). Times after `now` are ignored: the clock restarted
+/// with a power cycle, so they can't be compared.
+pub fn is_crash_looping(boot_times: &[u32], now: u32, n: usize, window_s: u32) -> bool {
+    boot_times
+        .iter()
+        .filter(|&&t| t <= now && now - t <= window_s)
+        .count()
+        > n
+}
+
+/// Append this boot to the history, dropping stale future timestamps and
+/// keeping the newest `cap` entries.
+pub fn record_boot(history: &mut Vec<u32>, now: u32, cap: usize) {
+    history.retain(|&t| t <= now);
+    history.push(now);
+    if history.len() > cap {
+        history.drain(..history.len() - cap);
+    }
+}
+
+/// Encode boot timestamps for NVS as little-endian u32s.
+pub fn encode_boot_times(times: &[u32]) -> Vec<u8> {
+    times.iter().flat_map(|t| t.to_le_bytes()).collect()
+}
+
+/// Decode boot timestamps from NVS. A trailing partial entry is ignored.
+pub fn decode_boot_times(raw: &[u8]) -> Vec<u32> {
+    raw.chunks_exact(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Seconds on the system clock. The RTC keeps counting across panic and
+/// watchdog resets, which is what a crash loop is made of; a power cycle
+/// starts it over, which `is_crash_looping` tolerates.
+pub fn boot_clock_s() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rapid_boots_are_a_loop() {
+        let boots = [100, 130, 160, 190, 220];
+        assert!(is_crash_looping(&boots, 220, 4, 600));
+        // Exactly n boots is not more than n
+        assert!(!is_crash_looping(&boots[1..], 220, 4, 600));
+    }
+
+    #[test]
+    fn test_spread_out_boots_are_not_a_loop() {
+        let boots = [0, 1_000, 2_000, 3_000, 4_000];
+        assert!(!is_crash_looping(&boots, 4_000, 4, 600));
+        // Old boots age out of the window
+        let boots = [100, 130, 160, 190, 220];
+        assert!(!is_crash_looping(&boots, 800, 4, 600));
+        assert!(!is_crash_looping(&[], 100, 0, 600));
+    }
+
+    #[test]
+    fn test_future_timestamps_ignored() {
+        // Clock restarted after a power cycle; old entries are "ahead"
+        let boots = [50_000, 50_030, 50_060, 50_090, 5];
+        assert!(!is_crash_looping(&boots, 5, 4, 600));
+    }
+
+    #[test]
+    fn test_record_boot_caps_history() {
+        let mut history = vec![10, 20, 30];
+        record_boot(&mut history, 40, 3);
+        assert_eq!(history, vec![20, 30, 40]);
+        // A clock restart drops the stale entries
+        record_boot(&mut history, 5, 3);
+        assert_eq!(history, vec![5]);
+    }
+
+    #[test]
+    fn test_boot_times_roundtrip() {
+        let times = vec![0, 1, 600, u32::MAX];
+        assert_eq!(decode_boot_times(&encode_boot_times(&times)), times);
+        assert_eq!(decode_boot_times(&[1, 0, 0, 0, 9]), vec![1]);
+    }
+}
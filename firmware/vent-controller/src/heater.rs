@@ -0,0 +1,102 @@
+use esp_idf_hal::gpio::{AnyOutputPin, Output, PinDriver};
+use esp_idf_sys::EspError;
+
+/// How often the main loop re-evaluates the heater.
+pub const HEATER_CHECK_INTERVAL_S: u64 = 60;
+
+// Magnus formula coefficients (Sonntag 1990), valid from -45 to 60 °C.
+const MAGNUS_B: f32 = 17.62;
+const MAGNUS_C: f32 = 243.12;
+
+/// Dew point in milli-°C for a temperature (milli-°C) and relative
+/// humidity (hundredths of a percent). None for a non-positive humidity.
+pub fn dew_point_mc(temp_mc: i32, rh_centi: u16) -> Option<i32> {
+    if rh_centi == 0 {
+        return None;
+    }
+    let t = temp_mc as f32 / 1000.0;
+    let rh = (rh_centi.min(10_000) as f32 / 10_000.0).ln();
+    let gamma = rh + MAGNUS_B * t / (MAGNUS_C + t);
+    let dew = MAGNUS_C * gamma / (MAGNUS_B - gamma);
+    Some((dew * 1000.0).round() as i32)
+}
+
+/// Whether the heater should run: true when the temperature is within
+/// `margin_mc` of the dew point (condensation risk).
+pub fn heater_should_run(temp_mc: i32, rh_centi: u16, margin_mc: i32) -> bool {
+    match dew_point_mc(temp_mc, rh_centi) {
+        Some(dew) => temp_mc - dew <= margin_mc,
+        None => false,
+    }
+}
+
+/// Anti-condensation heater on a GPIO (XIAO D9), driven through a MOSFET.
+pub struct Heater<'d> {
+    enable: PinDriver<'d, AnyOutputPin, Output>,
+    on: bool,
+}
+
+impl<'d> Heater<'d> {
+    /// Take the heater pin and start with it off.
+    pub fn new(pin: AnyOutputPin) -> Result<Self, EspError> {
+        let mut enable = PinDriver::output(pin)?;
+        enable.set_low()?;
+        Ok(Self { enable, on: false })
+    }
+
+    /// Switch the heater on or off. No-op if already in that state.
+    pub fn set(&mut self, on: bool) -> Result<(), EspError> {
+        if on != self.on {
+            if on {
+                self.enable.set_high()?;
+            } else {
+                self.enable.set_low()?;
+            }
+            self.on = on;
+        }
+        Ok(())
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARGIN: i32 = 2_000;
+
+    #[test]
+    fn test_dew_point_reference_values() {
+        // 20 °C at 50% RH has a dew point of about 9.3 °C
+        let dew = dew_point_mc(20_000, 5_000).unwrap();
+        assert!((9_200..=9_400).contains(&dew), "dew = {}", dew);
+        // Saturated air: dew point equals temperature
+        let dew = dew_point_mc(5_000, 10_000).unwrap();
+        assert!((dew - 5_000).abs() <= 10, "dew = {}", dew);
+        assert_eq!(dew_point_mc(20_000, 0), None);
+    }
+
+    #[test]
+    fn test_dry_air_does_not_heat() {
+        assert!(!heater_should_run(20_000, 3_000, MARGIN));
+        assert!(!heater_should_run(-5_000, 4_000, MARGIN));
+        assert!(!heater_should_run(20_000, 0, MARGIN));
+    }
+
+    #[test]
+    fn test_humid_air_heats() {
+        assert!(heater_should_run(8_000, 9_500, MARGIN));
+        assert!(heater_should_run(2_000, 10_000, MARGIN));
+    }
+
+    #[test]
+    fn test_near_dew_point_uses_margin() {
+        // 10 °C at 85% RH: dew point ~7.6 °C, a 2.4 °C gap
+        assert!(!heater_should_run(10_000, 8_500, 2_000));
+        assert!(heater_should_run(10_000, 8_500, 2_500));
+        assert!(!heater_should_run(10_000, 8_500, 0));
+    }
+}
@@ -1,6 +1,19 @@
+use crate::buzzer::BuzzerConfig;
+use crate::command::{AccessMode, ConflictPolicy, MaxMove, MaxMoveMode, RangePolicy};
+use crate::crashloop;
+use crate::motion::{self, ApproachDirection, CurvePoint, Stepping};
+use crate::position_sensor::EncoderCalibration;
+use crate::presets::{self, ModeTable, Preset};
+use crate::pressure::PressureControl;
+use crate::quiet::{QuietHours, MINUTES_PER_DAY};
+use crate::servo::{KickConfig, SeatConfig, SoftLimits};
+use crate::state::{BootBehavior, PushMode};
+use crate::thread::{DatasetChoice, MAX_DATASET_LEN};
 use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
 use esp_idf_sys::EspError;
-use log::info;
+use log::{info, warn};
+use std::net::SocketAddrV6;
+use vent_protocol::{clamp_angle, BuildInfo, DeviceType, Units, ANGLE_CLOSED, ANGLE_OPEN};
 
 const NVS_NAMESPACE: &str = "vent_cfg";
 const KEY_ROOM: &str = "room";
@@ -9,33 +22,171 @@ const KEY_NAME: &str = "name";
 const KEY_INITIALIZED: &str = "init";
 const KEY_POWER_MODE: &str = "pwr_mode";
 const KEY_POLL_PERIOD: &str = "poll_ms";
+const KEY_FABRIC_TIMEOUT: &str = "fabric_tmo_s";
+const KEY_COMMISSION_TIMEOUT: &str = "comm_tmo_s";
+const KEY_BUZZER: &str = "buzzer";
+const KEY_MOVE_GRACE: &str = "move_grace_ms";
+const KEY_TRIM: &str = "trim_deg";
+const KEY_SERVO_FREQ: &str = "servo_freq";
+const KEY_SAVE_DEBOUNCE: &str = "save_debounce";
+const KEY_ACCESS_MODE: &str = "access_mode";
+const KEY_BOOT_COUNT: &str = "boot_count";
+const KEY_RESET_REASON: &str = "reset_reason";
+const KEY_DATASET_A: &str = "ds_a";
+const KEY_DATASET_B: &str = "ds_b";
+const KEY_DATASET_ACTIVE: &str = "ds_active";
+const KEY_DATASET_PENDING: &str = "ds_pending";
+const KEY_DATASET_CONFIRM: &str = "ds_confirm_s";
+const KEY_SERVO_RAIL: &str = "servo_rail";
+const KEY_RAIL_SETTLE: &str = "rail_settle_ms";
+const KEY_RAIL_PGOOD: &str = "rail_pgood";
+const KEY_SEAT: &str = "seat_close";
+const KEY_KICK: &str = "start_kick";
+const KEY_MOTION_CURVE: &str = "motion_curve";
+const KEY_REPORT_TOL: &str = "report_tol";
+const KEY_REPORT_ROUND: &str = "report_round";
+const KEY_REPORT_STAGGER: &str = "report_stagger";
+const KEY_ECHO_WINDOW: &str = "echo_win_ms";
+const KEY_SCAN_AFTER: &str = "scan_after_s";
+const KEY_SERVO_TRAVEL: &str = "travel_deg";
+const KEY_DECODE_FAILURES: &str = "decode_fail";
+const KEY_PRESETS: &str = "presets";
+const KEY_MODE_TABLE: &str = "mode_angles";
+const KEY_CHILD_TIMEOUT: &str = "child_tmo_s";
+const KEY_PRESSURE: &str = "pressure";
+const KEY_BYPASS_RELAY: &str = "bypass_relay";
+const KEY_SERVO_INVERTED: &str = "servo_inv";
+const KEY_DETACH_HONORS: &str = "detach_cmds";
+const KEY_DETACH_UNCOMMISSIONED: &str = "detach_ship";
+const KEY_GENTLE_ENGAGE: &str = "gentle_engage";
+const KEY_MIN_FW_CHECK: &str = "min_fw_check";
+const KEY_PRESSURE_CONTROL: &str = "p_control";
+const KEY_BEACON_INTERVAL: &str = "beacon_s";
+const KEY_HEATER_MARGIN: &str = "heater_mc";
+const KEY_DAMPING: &str = "damping_deg";
+const KEY_MIN_AIRFLOW: &str = "min_airflow";
+const KEY_MOVE_YIELD: &str = "move_yield_ms";
+const KEY_BOOT_BEHAVIOR: &str = "boot_mode";
+const KEY_JOIN_HOLD: &str = "join_hold_s";
+const KEY_BROWNOUT_MV: &str = "brownout_mv";
+const KEY_MIN_MOVE_MV: &str = "min_move_mv";
+const KEY_SUPPLY_EMA: &str = "supply_ema";
+const KEY_SUPPLY_DIV: &str = "supply_div";
+const KEY_SOFT_LIMITS: &str = "soft_limits";
+const KEY_RANGE_POLICY: &str = "range_policy";
+const KEY_RUNTIME: &str = "runtime_s";
+const KEY_INSTALL_DATE: &str = "install_s";
+const KEY_MIDPOINT: &str = "midpoint_deg";
+const KEY_PCT_INVERTED: &str = "pct_inverted";
+const KEY_BOOT_TIMES: &str = "boot_times";
+const KEY_BREAKIN_DONE: &str = "breakin_done";
+const KEY_FIRST_CYCLE: &str = "first_cycle";
+const KEY_FIRST_CYCLE_DONE: &str = "first_cyc_done";
+const KEY_UNITS: &str = "units";
+const KEY_DEVICE_TYPE: &str = "device_type";
+const KEY_STEPPING: &str = "stepping";
+const KEY_APPROACH: &str = "approach_dir";
+const KEY_PROGRESS_LOG: &str = "move_log_deg";
+const KEY_QUIET_HOURS: &str = "quiet_hours";
+const KEY_CREEP: &str = "creep_s";
+const KEY_SWEEP_INTERVAL: &str = "sweep_s";
+const KEY_LAST_SWEEP: &str = "last_sweep";
+const KEY_CLOCK_MAX_AGE: &str = "clock_max_age";
+const KEY_METRICS_FLUSH: &str = "metrics_s";
+const KEY_COMMAND_COUNT: &str = "command_count";
+const KEY_MOVE_COUNT: &str = "move_count";
+const KEY_CONFLICT: &str = "conflict_pol";
+const KEY_PUSH_MODE: &str = "push_mode";
+const KEY_COORDINATOR: &str = "coord_addr";
+const KEY_NUDGE: &str = "nudge_ok";
+const KEY_SLEEP_HOLD: &str = "sleep_hold";
+const KEY_MAX_MOVE: &str = "max_move";
+const KEY_ENCODER: &str = "encoder";
+const KEY_PRUNE_FULL: &str = "prune_full";
+const KEY_SELFTEST_BOOT: &str = "selftest_boot";
+const KEY_SCRATCH: &str = "scratch";
+const KEY_COAP: &str = "coap";
+const KEY_COAP_IN_FLIGHT: &str = "coap_inflight";
+const KEY_COAP_OBSERVERS: &str = "coap_observers";
+
+/// Metadata for the running firmware build. The values come from
+/// build.rs; they fall back to "unknown"/0 when built without it.
+pub fn build_info() -> BuildInfo<'static> {
+    BuildInfo {
+        firmware_version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("VENT_GIT_HASH").unwrap_or("unknown"),
+        build_timestamp: option_env!("VENT_BUILD_TIMESTAMP")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        target_chip: option_env!("VENT_TARGET_CHIP").unwrap_or("unknown"),
+    }
+}
+
+/// Boot counter and last reset reason, persisted across reboots so a
+/// fleet-wide power event (or a crashing unit) is visible afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootCounters {
+    pub boot_count: u32,
+    /// Raw `esp_reset_reason_t` of the most recent boot (0 = cleared/unknown).
+    pub last_reset_reason: u8,
+}
+
+impl BootCounters {
+    /// Account for a new boot with the given reset reason.
+    pub fn on_boot(&mut self, reset_reason: u8) {
+        self.boot_count = self.boot_count.saturating_add(1);
+        self.last_reset_reason = reset_reason;
+    }
+
+    /// Zero the counters, establishing a new baseline.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Wire form: boot count (u32 LE), then the last reset reason.
+    pub fn to_bytes(self) -> [u8; 5] {
+        let c = self.boot_count.to_le_bytes();
+        [c[0], c[1], c[2], c[3], self.last_reset_reason]
+    }
+}
 
 /// Device identity manager using NVS for persistent config.
 pub struct DeviceIdentity {
     nvs: EspNvs<NvsDefault>,
     eui64: String,
+    eui64_bytes: [u8; 8],
+    /// Prune non-critical keys when a write finds the partition full.
+    prune_when_full: bool,
 }
 
 impl DeviceIdentity {
     /// Initialize identity manager. Reads EUI-64 from eFuse.
     pub fn new(nvs_partition: EspNvsPartition<NvsDefault>) -> Result<Self, EspError> {
         let nvs = EspNvs::new(nvs_partition, NVS_NAMESPACE, true)?;
-        let eui64 = Self::read_eui64();
+        let eui64_bytes = Self::read_eui64();
+        let eui64 = format_eui64(&eui64_bytes);
         info!("Device EUI-64: {}", eui64);
+        let mut buf = [0u8; 1];
+        let prune_when_full = match nvs.get_raw(KEY_PRUNE_FULL, &mut buf)? {
+            Some(val) => val[0] != 0,
+            None => true,
+        };
 
-        Ok(Self { nvs, eui64 })
+        Ok(Self {
+            nvs,
+            eui64,
+            eui64_bytes,
+            prune_when_full,
+        })
     }
 
     /// Read the EUI-64 MAC address from ESP32-C6 eFuse.
-    fn read_eui64() -> String {
+    fn read_eui64() -> [u8; 8] {
         let mut mac = [0u8; 8];
         unsafe {
             esp_idf_sys::esp_efuse_mac_get_default(mac.as_mut_ptr());
         }
-        mac.iter()
-            .map(|b| format!("{:02x}", b))
-            .collect::<Vec<_>>()
-            .join(":")
+        mac
     }
 
     /// Get the device's permanent EUI-64 identifier.
@@ -43,6 +194,18 @@ impl DeviceIdentity {
         &self.eui64
     }
 
+    /// The base MAC as read from eFuse (the first six EUI-64 bytes).
+    pub fn efuse_mac(&self) -> [u8; 6] {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&self.eui64_bytes[..6]);
+        mac
+    }
+
+    /// The EUI-64 as raw bytes, e.g. for deriving Matter setup credentials.
+    pub fn eui64_bytes(&self) -> &[u8; 8] {
+        &self.eui64_bytes
+    }
+
     /// Check if this is the first boot (no config in NVS).
     pub fn is_first_boot(&self) -> Result<bool, EspError> {
         let mut buf = [0u8; 1];
@@ -55,7 +218,7 @@ impl DeviceIdentity {
 
     /// Mark device as initialized in NVS.
     pub fn mark_initialized(&mut self) -> Result<(), EspError> {
-        self.nvs.set_raw(KEY_INITIALIZED, &[1])?;
+        self.put_raw(KEY_INITIALIZED, &[1])?;
         Ok(())
     }
 
@@ -69,6 +232,12 @@ impl DeviceIdentity {
         self.set_string(KEY_ROOM, room)
     }
 
+    /// Configured name, falling back to the EUI-64 (see `display_label`).
+    pub fn label(&self) -> String {
+        let name = self.get_name().ok().flatten();
+        display_label(name.as_deref(), &self.eui64)
+    }
+
     /// Get floor assignment from NVS.
     pub fn get_floor(&self) -> Result<Option<String>, EspError> {
         self.get_string(KEY_FLOOR)
@@ -94,11 +263,6 @@ impl DeviceIdentity {
         self.get_string(KEY_POWER_MODE)
     }
 
-    /// Set power mode in NVS ("always_on" or "sed").
-    pub fn set_power_mode(&mut self, mode: &str) -> Result<(), EspError> {
-        self.set_string(KEY_POWER_MODE, mode)
-    }
-
     /// Get SED poll period from NVS (milliseconds). Returns None if unset.
     pub fn get_poll_period(&self) -> Result<Option<u32>, EspError> {
         let mut buf = [0u8; 4];
@@ -114,98 +278,1528 @@ impl DeviceIdentity {
 
     /// Set SED poll period in NVS (milliseconds).
     pub fn set_poll_period(&mut self, ms: u32) -> Result<(), EspError> {
-        self.nvs.set_raw(KEY_POLL_PERIOD, &ms.to_le_bytes())?;
+        self.put_raw(KEY_POLL_PERIOD, &ms.to_le_bytes())?;
         Ok(())
     }
 
-    fn get_string(&self, key: &str) -> Result<Option<String>, EspError> {
-        let mut buf = [0u8; 64];
-        match self.nvs.get_raw(key, &mut buf) {
-            Ok(Some(val)) => {
-                let len = val.len();
-                let s = core::str::from_utf8(&buf[..len])
-                    .unwrap_or_default()
-                    .to_string();
-                Ok(Some(s))
-            }
-            Ok(None) => Ok(None),
+    /// Get the Thread child timeout (seconds) for SED mode. Returns None if unset.
+    pub fn get_child_timeout(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_CHILD_TIMEOUT)
+    }
+
+    /// Get the coordinator a sleepy vent reports to after each poll, as
+    /// `[addr]:port`. Returns None if unset or unparseable.
+    pub fn get_coordinator(&self) -> Result<Option<SocketAddrV6>, EspError> {
+        Ok(self.get_string(KEY_COORDINATOR)?.and_then(|s| s.parse().ok()))
+    }
+
+    /// Get the status beacon interval in seconds. Returns None if unset;
+    /// unset or 0 means beacons are off.
+    pub fn get_beacon_interval(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_BEACON_INTERVAL)
+    }
+
+    /// Get the heater dew-point margin in milli-°C. Returns None if unset,
+    /// which means no heater is fitted.
+    pub fn get_heater_margin(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_HEATER_MARGIN)
+    }
+
+    /// Get the longest uninterrupted sleep (ms) during a move. Returns None
+    /// if unset.
+    pub fn get_move_yield(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_MOVE_YIELD)
+    }
+
+    /// Get the supercap brownout threshold (mV) for harvested power.
+    /// Returns None if unset.
+    pub fn get_brownout_threshold(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_BROWNOUT_MV)
+    }
+
+    /// Get the battery level (mV) below which moves are refused. Returns
+    /// None if unset.
+    pub fn get_min_move_mv(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_MIN_MOVE_MV)
+    }
+
+    /// Get the supply-voltage EMA weight in thousandths. Returns None if
+    /// unset.
+    pub fn get_supply_ema(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_SUPPLY_EMA)
+    }
+
+    /// Get the ratio (thousandths) of the supply divider on D0. Returns
+    /// None if unset, meaning no divider is fitted.
+    pub fn get_supply_divider(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_SUPPLY_DIV)
+    }
+
+    /// Get how long (seconds) a commissioned device may be off the fabric
+    /// before it attempts a rejoin. Returns None if unset.
+    pub fn get_fabric_timeout(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_FABRIC_TIMEOUT)
+    }
+
+    /// Set the fabric-loss timeout in seconds (0 disables the monitor).
+    pub fn set_fabric_timeout(&mut self, secs: u32) -> Result<(), EspError> {
+        self.set_u32(KEY_FABRIC_TIMEOUT, secs)
+    }
+
+    /// Get how long (seconds) an uncommissioned device waits for pairing
+    /// before opening fail-safe. Returns None if unset.
+    pub fn get_commission_timeout(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_COMMISSION_TIMEOUT)
+    }
+
+    /// Get the move-timeout grace period (ms) from NVS. Returns None if unset.
+    pub fn get_move_grace(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_MOVE_GRACE)
+    }
+
+    /// Get the API access mode from NVS (default: read-write).
+    pub fn get_access_mode(&self) -> Result<AccessMode, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_ACCESS_MODE, &mut buf) {
+            Ok(Some(val)) => Ok(AccessMode::from_u8(val[0])),
+            Ok(None) => Ok(AccessMode::default()),
             Err(e) => Err(e),
         }
     }
 
-    fn set_string(&mut self, key: &str, value: &str) -> Result<(), EspError> {
-        self.nvs.set_raw(key, value.as_bytes())?;
+    /// Get how out-of-range targets are handled (default: clamp).
+    pub fn get_range_policy(&self) -> Result<RangePolicy, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_RANGE_POLICY, &mut buf) {
+            Ok(Some(val)) => Ok(RangePolicy::from_u8(val[0])),
+            Ok(None) => Ok(RangePolicy::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get how racing CoAP and Matter targets are resolved (default:
+    /// latest wins).
+    pub fn get_conflict_policy(&self) -> Result<ConflictPolicy, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_CONFLICT, &mut buf) {
+            Ok(Some(val)) => Ok(ConflictPolicy::from_u8(val[0])),
+            Ok(None) => Ok(ConflictPolicy::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get when move-complete reports are pushed to the coordinator
+    /// (default: after every move).
+    pub fn get_push_mode(&self) -> Result<PushMode, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_PUSH_MODE, &mut buf) {
+            Ok(Some(val)) => Ok(PushMode::from_u8(val[0])),
+            Ok(None) => Ok(PushMode::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether health queries may wiggle the servo to prove it responds.
+    /// Defaults to false.
+    pub fn get_nudge_enabled(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_NUDGE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the self-test runs automatically shortly after boot.
+    pub fn get_selftest_on_boot(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_SELFTEST_BOOT, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a scratch key, read it back and erase it. True if the value
+    /// survived the round trip.
+    pub fn nvs_scratch_check(&mut self) -> Result<bool, EspError> {
+        const PATTERN: [u8; 4] = [0xa5, 0x5a, 0x0f, 0xf0];
+        self.put_raw(KEY_SCRATCH, &PATTERN)?;
+        let mut buf = [0u8; 4];
+        let ok = self.nvs.get_raw(KEY_SCRATCH, &mut buf)? == Some(&PATTERN[..]);
+        self.nvs.remove(KEY_SCRATCH)?;
+        Ok(ok)
+    }
+
+    /// Whether switched outputs are latched off through deep sleep.
+    /// Defaults to true.
+    pub fn get_sleep_hold(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_SLEEP_HOLD, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the per-command travel cap. None if unset or 0 (no cap).
+    pub fn get_max_move(&self) -> Result<Option<MaxMove>, EspError> {
+        let mut buf = [0u8; 2];
+        match self.nvs.get_raw(KEY_MAX_MOVE, &mut buf) {
+            Ok(Some(val)) if val.len() == 2 && val[0] > 0 => Ok(Some(MaxMove {
+                max_deg: val[0],
+                mode: MaxMoveMode::from_u8(val[1]),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the servo power rail is switched by the enable GPIO.
+    /// Defaults to false (rail always on) for boards without the MOSFET.
+    pub fn get_servo_rail_switched(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_SERVO_RAIL, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the delay (ms) after switching the servo rail on before the
+    /// first step. Returns None if unset.
+    pub fn get_rail_settle(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_RAIL_SETTLE)
+    }
+
+    /// Whether the switched servo rail has a power-good sense on D1.
+    pub fn get_rail_power_good(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_RAIL_PGOOD, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get seat-on-close settings from NVS. Returns None if unset (disabled).
+    pub fn get_seat_config(&self) -> Result<Option<SeatConfig>, EspError> {
+        let mut buf = [0u8; 3];
+        match self.nvs.get_raw(KEY_SEAT, &mut buf) {
+            Ok(Some(val)) if val.len() == 3 => Ok(Some(SeatConfig {
+                overshoot_deg: val[0],
+                hold_ms: u16::from_le_bytes([val[1], val[2]]),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get start-kick settings. Returns None if unset.
+    pub fn get_kick_config(&self) -> Result<Option<KickConfig>, EspError> {
+        let mut buf = [0u8; 3];
+        match self.nvs.get_raw(KEY_KICK, &mut buf) {
+            Ok(Some(val)) if val.len() == 3 => Ok(Some(KickConfig {
+                kick_deg: val[0],
+                hold_ms: u16::from_le_bytes([val[1], val[2]]),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the boot behavior and its fixed angle. Defaults to restoring the
+    /// checkpoint.
+    pub fn get_boot_behavior(&self) -> Result<(BootBehavior, u8), EspError> {
+        let mut buf = [0u8; 2];
+        match self.nvs.get_raw(KEY_BOOT_BEHAVIOR, &mut buf) {
+            Ok(Some(val)) if val.len() == 2 => Ok((BootBehavior::from_u8(val[0]), clamp_angle(val[1]))),
+            Ok(_) => Ok((BootBehavior::RestoreCheckpoint, ANGLE_CLOSED)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get how long (seconds) after joining the mesh a boot move waits for a
+    /// coordinator command. Returns None if unset.
+    pub fn get_join_hold(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_JOIN_HOLD)
+    }
+
+    /// Get the step curve from NVS. Empty (linear) if unset or invalid.
+    pub fn get_motion_curve(&self) -> Result<Vec<CurvePoint>, EspError> {
+        let mut buf = [0u8; 4 * motion::MAX_CURVE_POINTS];
+        match self.nvs.get_raw(KEY_MOTION_CURVE, &mut buf) {
+            Ok(Some(val)) => Ok(motion::decode_curve(val).unwrap_or_else(|e| {
+                warn!("Ignoring invalid motion curve in NVS: {:?}", e);
+                Vec::new()
+            })),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store a step curve in NVS. Rejects curves that fail validation; an
+    /// empty curve restores linear motion.
+    pub fn set_motion_curve(&mut self, points: &[CurvePoint]) -> Result<(), EspError> {
+        if motion::validate_curve(points).is_err() {
+            return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_INVALID_ARG }>());
+        }
+        self.put_raw(KEY_MOTION_CURVE, &motion::encode_curve(points))?;
         Ok(())
     }
 
-    // -- Write-Ahead Checkpoint for servo position recovery --
-    //
-    // Inspired by write-ahead logging (WAL), but NOT an append-only log.
-    // Three fixed NVS keys are overwritten in place — total storage is
-    // constant at 3 bytes regardless of how many commands are processed.
-    //
-    // NVS keys (each 1 byte, overwritten on every cycle):
-    //   "angle"  — checkpoint: last committed (known-good) angle
-    //   "target" — intent: target recorded before the move starts
-    //   "wal"    — commit flag: 1 = committed, 0 = pending
-    //
-    // Protocol:
-    //   1. write_ahead(target)  — persist intent + clear commit flag  (2 NVS writes)
-    //   2. servo moves          — RAM only, no NVS writes
-    //   3. commit(angle)        — persist final angle + set flag      (2 NVS writes)
-    //
-    // Recovery (boot with wal=0):
-    //   restore checkpoint, replay pending target
-    //
-    // Flash wear: 4 NVS writes per command cycle. ESP-IDF NVS is
-    // internally log-structured and wear-leveled across pages.
-    // With a 24KB NVS partition (~600K effective writes) and 100
-    // commands/day, flash wear is not a concern for ~16 years.
+    /// Get the named preset table. Empty if unset or invalid.
+    pub fn get_presets(&self) -> Result<Vec<Preset>, EspError> {
+        let mut buf = [0u8; presets::MAX_PRESETS * (presets::MAX_NAME_LEN + 2)];
+        match self.nvs.get_raw(KEY_PRESETS, &mut buf) {
+            Ok(Some(val)) => Ok(presets::decode_presets(val).unwrap_or_else(|e| {
+                warn!("Ignoring invalid preset table in NVS: {:?}", e);
+                Vec::new()
+            })),
+            Ok(None) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
 
-    /// Get the last committed (checkpoint) vent angle from NVS.
-    pub fn checkpoint_angle(&self) -> Result<Option<u8>, EspError> {
+    /// Replace the named preset table in NVS. Rejects tables that fail
+    /// validation.
+    pub fn set_presets(&mut self, table: &[Preset]) -> Result<(), EspError> {
+        if presets::validate_presets(table).is_err() {
+            return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_INVALID_ARG }>());
+        }
+        self.put_raw(KEY_PRESETS, &presets::encode_presets(table))?;
+        Ok(())
+    }
+
+    /// Get the airflow mode angles. Defaults if unset or invalid.
+    pub fn get_mode_table(&self) -> Result<ModeTable, EspError> {
+        let mut buf = [0u8; 5];
+        match self.nvs.get_raw(KEY_MODE_TABLE, &mut buf) {
+            Ok(Some(val)) => Ok(presets::decode_mode_table(val).unwrap_or_else(|e| {
+                warn!("Ignoring invalid mode table in NVS: {:?}", e);
+                ModeTable::default()
+            })),
+            Ok(None) => Ok(ModeTable::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the mid-move Matter report interval (ms) that reports are
+    /// staggered within. Returns None if unset.
+    pub fn get_report_stagger(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_REPORT_STAGGER)
+    }
+
+    /// Get the window (ms) after a report in which a Matter command for
+    /// the same position is ignored as an echo. Returns None if unset.
+    pub fn get_echo_window(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_ECHO_WINDOW)
+    }
+
+    /// Get how long (s) the vent may go without joining the mesh before it
+    /// scans for Thread networks. Returns None if unset (no scan).
+    pub fn get_scan_after(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_SCAN_AFTER)
+    }
+
+    /// Get the Matter position-report tolerance (degrees). Returns None if unset.
+    pub fn get_report_tolerance(&self) -> Result<Option<u8>, EspError> {
         let mut buf = [0u8; 1];
-        match self.nvs.get_raw("angle", &mut buf) {
+        match self.nvs.get_raw(KEY_REPORT_TOL, &mut buf) {
             Ok(Some(val)) => Ok(Some(val[0])),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
     }
 
-    /// Write-ahead: record target intent and clear the commit flag.
-    /// Must be called BEFORE the servo starts moving.
-    pub fn write_ahead(&mut self, target: u8) -> Result<(), EspError> {
-        self.nvs.set_raw("target", &[target])?;
-        self.nvs.set_raw("wal", &[0u8])?;
+    /// Set the Matter position-report tolerance (degrees) in NVS.
+    pub fn set_report_tolerance(&mut self, deg: u8) -> Result<(), EspError> {
+        self.put_raw(KEY_REPORT_TOL, &[deg])?;
         Ok(())
     }
 
-    /// Get the pending (write-ahead) target from the last uncommitted move.
-    pub fn get_pending(&self) -> Result<Option<u8>, EspError> {
+    /// Get the reported-position rounding step (degrees). Returns None if
+    /// unset.
+    pub fn get_report_round(&self) -> Result<Option<u8>, EspError> {
         let mut buf = [0u8; 1];
-        match self.nvs.get_raw("target", &mut buf) {
+        match self.nvs.get_raw(KEY_REPORT_ROUND, &mut buf) {
             Ok(Some(val)) => Ok(Some(val[0])),
             Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
     }
 
-    /// Commit: save the final angle as the new checkpoint and set the
-    /// commit flag. Called after the servo reaches its target.
-    pub fn commit(&mut self, angle: u8) -> Result<(), EspError> {
-        self.nvs.set_raw("angle", &[angle])?;
-        self.nvs.set_raw("wal", &[1u8])?;
+    /// Set the reported-position rounding step in degrees (0 or 1 = exact).
+    pub fn set_report_round(&mut self, deg: u8) -> Result<(), EspError> {
+        self.put_raw(KEY_REPORT_ROUND, &[deg])?;
         Ok(())
     }
 
-    /// Check whether the last move was committed.
-    /// Returns false if power was lost between write_ahead and commit.
-    pub fn is_committed(&self) -> Result<bool, EspError> {
+    /// Get the minimum-airflow floor angle. Returns None if unset (no floor).
+    pub fn get_min_airflow_angle(&self) -> Result<Option<u8>, EspError> {
         let mut buf = [0u8; 1];
-        match self.nvs.get_raw("wal", &mut buf) {
-            Ok(Some(val)) => Ok(val[0] == 1),
-            Ok(None) => Ok(true), // no WAL entry = no pending move = committed
+        match self.nvs.get_raw(KEY_MIN_AIRFLOW, &mut buf) {
+            Ok(Some(val)) => Ok(Some(clamp_angle(val[0]))),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set the minimum-airflow floor angle (clamped to the vent range).
+    pub fn set_min_airflow_angle(&mut self, angle: u8) -> Result<(), EspError> {
+        self.put_raw(KEY_MIN_AIRFLOW, &[clamp_angle(angle)])?;
+        Ok(())
+    }
+
+    /// Get the learned travel limits. None until a learning run has stored
+    /// a valid pair.
+    pub fn get_soft_limits(&self) -> Result<Option<SoftLimits>, EspError> {
+        let mut buf = [0u8; 2];
+        match self.nvs.get_raw(KEY_SOFT_LIMITS, &mut buf) {
+            Ok(Some(val)) if val.len() == 2 && val[0] < val[1] => Ok(Some(SoftLimits {
+                closed: clamp_angle(val[0]),
+                open: clamp_angle(val[1]),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Persist learned travel limits. Takes effect on next boot.
+    pub fn set_soft_limits(&mut self, limits: &SoftLimits) -> Result<(), EspError> {
+        self.put_raw(KEY_SOFT_LIMITS, &[limits.closed, limits.open])?;
+        Ok(())
+    }
+
+    /// Get the louver midpoint angle (reported as 50%). None if unset or
+    /// not strictly inside the vent range, meaning the linear center.
+    pub fn get_midpoint_angle(&self) -> Result<Option<u8>, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_MIDPOINT, &mut buf) {
+            Ok(Some(val)) if val[0] > ANGLE_CLOSED && val[0] < ANGLE_OPEN => Ok(Some(val[0])),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether Matter percentages count openness instead of closure.
+    /// Defaults to false (the spec's convention).
+    pub fn get_percent_inverted(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_PCT_INVERTED, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether a differential-pressure sensor is fitted on the I2C bus.
+    pub fn get_pressure_sensor_enabled(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_PRESSURE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the AS5600 encoder calibration. None (encoder not fitted) if
+    /// unset or malformed.
+    pub fn get_encoder_calibration(&self) -> Result<Option<EncoderCalibration>, EspError> {
+        let mut buf = [0u8; 6];
+        match self.nvs.get_raw(KEY_ENCODER, &mut buf) {
+            Ok(Some(val)) if val.len() == 6 => Ok(Some(EncoderCalibration {
+                zero_offset: u16::from_le_bytes([val[0], val[1]]),
+                cal_min: u16::from_le_bytes([val[2], val[3]]),
+                cal_max: u16::from_le_bytes([val[4], val[5]]),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether an HVAC bypass relay is wired to this node.
+    pub fn get_bypass_relay_enabled(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_BYPASS_RELAY, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the local CoAP server runs. Off unless set; Matter is the
+    /// supported control path.
+    pub fn get_coap_enabled(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_COAP, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the cap on CoAP replies waiting for the socket. Returns None if
+    /// unset.
+    pub fn get_coap_max_in_flight(&self) -> Result<Option<u8>, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_COAP_IN_FLIGHT, &mut buf) {
+            Ok(Some(val)) => Ok(Some(val[0])),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the cap on simultaneous CoAP observers. Returns None if unset.
+    pub fn get_coap_max_observers(&self) -> Result<Option<u8>, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_COAP_OBSERVERS, &mut buf) {
+            Ok(Some(val)) => Ok(Some(val[0])),
+            Ok(None) => Ok(None),
             Err(e) => Err(e),
         }
     }
+
+    /// Get pressure-hold settings. Returns None if unset (automation off).
+    pub fn get_pressure_control(&self) -> Result<Option<PressureControl>, EspError> {
+        let mut buf = [0u8; 6];
+        match self.nvs.get_raw(KEY_PRESSURE_CONTROL, &mut buf) {
+            Ok(Some(val)) if val.len() == 6 => Ok(Some(PressureControl {
+                target_pa: i32::from_le_bytes([val[0], val[1], val[2], val[3]]),
+                kp_milli: u16::from_le_bytes([val[4], val[5]]),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the servo is mounted inverted. Defaults to false.
+    pub fn get_servo_inverted(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_SERVO_INVERTED, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Persist the servo inversion flag.
+    pub fn set_servo_inverted(&mut self, inverted: bool) -> Result<(), EspError> {
+        self.put_raw(KEY_SERVO_INVERTED, &[inverted as u8])?;
+        Ok(())
+    }
+
+    /// Get the approach damping zone in degrees (0 = off, the default).
+    pub fn get_damping_zone(&self) -> Result<u8, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_DAMPING, &mut buf) {
+            Ok(Some(val)) => Ok(val[0].min(motion::MAX_DAMPING_ZONE_DEG)),
+            Ok(None) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether a target received while detached re-engages the servo.
+    /// Defaults to true.
+    pub fn get_detach_honors_commands(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_DETACH_HONORS, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the servo stays detached until the device is commissioned.
+    /// Defaults to false.
+    pub fn get_detach_until_commissioned(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_DETACH_UNCOMMISSIONED, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether re-engaging after a detach starts from the louver's actual
+    /// position rather than the logical one. Defaults to true.
+    pub fn get_gentle_engage(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_GENTLE_ENGAGE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether commands carrying a minimum firmware version newer than
+    /// this build are refused. Defaults to true.
+    pub fn get_min_fw_check(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_MIN_FW_CHECK, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the quiet-hours window. None if unset, malformed, or empty
+    /// (start equals end).
+    pub fn get_quiet_hours(&self) -> Result<Option<QuietHours>, EspError> {
+        let mut buf = [0u8; 6];
+        match self.nvs.get_raw(KEY_QUIET_HOURS, &mut buf) {
+            Ok(Some(val)) if val.len() == 6 => {
+                let quiet = QuietHours {
+                    start_min: u16::from_le_bytes([val[0], val[1]]),
+                    end_min: u16::from_le_bytes([val[2], val[3]]),
+                    utc_offset_min: i16::from_le_bytes([val[4], val[5]]),
+                };
+                let valid = quiet.start_min < MINUTES_PER_DAY
+                    && quiet.end_min < MINUTES_PER_DAY
+                    && quiet.start_min != quiet.end_min;
+                Ok(valid.then_some(quiet))
+            }
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the servo update strategy (default: stepped).
+    pub fn get_stepping(&self) -> Result<Stepping, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_STEPPING, &mut buf) {
+            Ok(Some(val)) => Ok(Stepping::from_u8(val[0])),
+            Ok(None) => Ok(Stepping::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the final-approach direction (default: any).
+    pub fn get_approach_direction(&self) -> Result<ApproachDirection, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_APPROACH, &mut buf) {
+            Ok(Some(val)) => Ok(ApproachDirection::from_u8(val[0])),
+            Ok(None) => Ok(ApproachDirection::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the degrees of travel between move-progress log lines. Returns
+    /// None if unset.
+    pub fn get_progress_log_deg(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_PROGRESS_LOG)
+    }
+
+    /// Get how often (seconds) a stationary servo's position is re-driven
+    /// to correct analog creep. Returns None if unset.
+    pub fn get_creep_interval(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_CREEP)
+    }
+
+    /// Whether hubs should present the vent as a cover or a damper.
+    pub fn get_device_type(&self) -> Result<DeviceType, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_DEVICE_TYPE, &mut buf) {
+            Ok(Some(val)) => Ok(DeviceType::from_u8(val[0]).unwrap_or_default()),
+            Ok(None) => Ok(DeviceType::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_device_type(&mut self, kind: DeviceType) -> Result<(), EspError> {
+        self.put_raw(KEY_DEVICE_TYPE, &[kind.as_u8()])?;
+        Ok(())
+    }
+
+    /// Get the preferred position units (default: angle).
+    pub fn get_preferred_units(&self) -> Result<Units, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_UNITS, &mut buf) {
+            Ok(Some(val)) => Ok(Units::from_u8(val[0]).unwrap_or_default()),
+            Ok(None) => Ok(Units::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the break-in routine has completed on this device.
+    pub fn get_breakin_done(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_BREAKIN_DONE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record whether break-in has completed.
+    pub fn set_breakin_done(&mut self, done: bool) -> Result<(), EspError> {
+        self.put_raw(KEY_BREAKIN_DONE, &[done as u8])?;
+        Ok(())
+    }
+
+    /// Whether a fresh device does one slow open/close cycle on first boot.
+    /// Defaults to true.
+    pub fn get_first_boot_cycle(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_FIRST_CYCLE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the first-boot cycle has completed on this device.
+    pub fn get_first_boot_cycle_done(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_FIRST_CYCLE_DONE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_first_boot_cycle_done(&mut self, done: bool) -> Result<(), EspError> {
+        self.put_raw(KEY_FIRST_CYCLE_DONE, &[done as u8])?;
+        Ok(())
+    }
+
+    /// Get the servo angle trim (degrees) from NVS. Returns None if unset.
+    pub fn get_trim(&self) -> Result<Option<i8>, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_TRIM, &mut buf) {
+            Ok(Some(val)) => Ok(Some(val[0] as i8)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set the servo angle trim (degrees) in NVS.
+    pub fn set_trim(&mut self, trim_deg: i8) -> Result<(), EspError> {
+        self.put_raw(KEY_TRIM, &[trim_deg as u8])?;
+        Ok(())
+    }
+
+    /// Get the servo PWM frequency (Hz). Returns None if unset.
+    pub fn get_servo_frequency(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_SERVO_FREQ)
+    }
+
+    /// Set the servo PWM frequency (Hz). Takes effect on next boot.
+    pub fn set_servo_frequency(&mut self, hz: u32) -> Result<(), EspError> {
+        self.set_u32(KEY_SERVO_FREQ, hz)
+    }
+
+    /// Quiet period (seconds) before a live trim change is written to NVS.
+    pub fn get_save_debounce(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_SAVE_DEBOUNCE)
+    }
+
+    /// Read the persisted boot counters (zero if never recorded).
+    pub fn boot_counters(&self) -> Result<BootCounters, EspError> {
+        let mut buf = [0u8; 1];
+        let last_reset_reason = match self.nvs.get_raw(KEY_RESET_REASON, &mut buf)? {
+            Some(val) => val[0],
+            None => 0,
+        };
+        Ok(BootCounters {
+            boot_count: self.get_u32(KEY_BOOT_COUNT)?.unwrap_or(0),
+            last_reset_reason,
+        })
+    }
+
+    /// Increment the boot counter and record this boot's reset reason.
+    /// Call once, early in boot. Returns the updated counters.
+    pub fn record_boot(&mut self, reset_reason: u8) -> Result<BootCounters, EspError> {
+        let mut counters = self.boot_counters()?;
+        counters.on_boot(reset_reason);
+        self.store_boot_counters(&counters)?;
+        Ok(counters)
+    }
+
+    /// Zero the boot counter and clear the last reset reason.
+    pub fn reset_counters(&mut self) -> Result<(), EspError> {
+        let mut counters = self.boot_counters()?;
+        counters.reset();
+        self.store_boot_counters(&counters)
+    }
+
+    /// Add this boot's clock reading to the recent-boot history used for
+    /// crash-loop detection. Returns the updated history, oldest first.
+    pub fn record_boot_time(&mut self, now: u32) -> Result<Vec<u32>, EspError> {
+        let mut buf = [0u8; crashloop::BOOT_HISTORY_LEN * 4];
+        let mut history = match self.nvs.get_raw(KEY_BOOT_TIMES, &mut buf)? {
+            Some(val) => crashloop::decode_boot_times(val),
+            None => Vec::new(),
+        };
+        crashloop::record_boot(&mut history, now, crashloop::BOOT_HISTORY_LEN);
+        self.put_raw(KEY_BOOT_TIMES, &crashloop::encode_boot_times(&history))?;
+        Ok(history)
+    }
+
+    fn store_boot_counters(&mut self, counters: &BootCounters) -> Result<(), EspError> {
+        self.set_u32(KEY_BOOT_COUNT, counters.boot_count)?;
+        self.put_raw(KEY_RESET_REASON, &[counters.last_reset_reason])?;
+        Ok(())
+    }
+
+    /// Get the banked powered-on runtime (seconds). Zero if never recorded.
+    pub fn get_runtime(&self) -> Result<u32, EspError> {
+        Ok(self.get_u32(KEY_RUNTIME)?.unwrap_or(0))
+    }
+
+    /// Bank the total powered-on runtime (seconds).
+    pub fn set_runtime(&mut self, secs: u32) -> Result<(), EspError> {
+        self.set_u32(KEY_RUNTIME, secs)
+    }
+
+    /// Get the install (first-commission) date as Unix seconds.
+    pub fn get_install_date(&self) -> Result<Option<u64>, EspError> {
+        let mut buf = [0u8; 8];
+        match self.nvs.get_raw(KEY_INSTALL_DATE, &mut buf) {
+            Ok(Some(val)) if val.len() == 8 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(val);
+                Ok(Some(u64::from_le_bytes(bytes)))
+            }
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Record the install date. The caller only writes it once.
+    pub fn set_install_date(&mut self, unix_s: u64) -> Result<(), EspError> {
+        self.put_raw(KEY_INSTALL_DATE, &unix_s.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Get the exercise-sweep interval in seconds. Returns None if unset.
+    pub fn get_sweep_interval(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_SWEEP_INTERVAL)
+    }
+
+    /// Get how long (seconds) the wall clock is trusted after it was last
+    /// set. Returns None if unset.
+    pub fn get_clock_max_age(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_CLOCK_MAX_AGE)
+    }
+
+    /// Get the interval (seconds) between metric flushes to NVS. Returns
+    /// None if unset.
+    pub fn get_metrics_flush(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_METRICS_FLUSH)
+    }
+
+    /// Get a cached counter's persisted total. 0 if never written.
+    pub fn get_metric(&self, field: Metric) -> Result<u32, EspError> {
+        Ok(self.get_u32(field.nvs_key())?.unwrap_or(0))
+    }
+
+    pub fn set_metric(&mut self, field: Metric, total: u32) -> Result<(), EspError> {
+        self.set_u32(field.nvs_key(), total)
+    }
+
+    /// Get when the last exercise sweep started (Unix seconds).
+    pub fn get_last_sweep(&self) -> Result<Option<u64>, EspError> {
+        let mut buf = [0u8; 8];
+        match self.nvs.get_raw(KEY_LAST_SWEEP, &mut buf) {
+            Ok(Some(val)) if val.len() == 8 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(val);
+                Ok(Some(u64::from_le_bytes(bytes)))
+            }
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_last_sweep(&mut self, unix_s: u64) -> Result<(), EspError> {
+        self.put_raw(KEY_LAST_SWEEP, &unix_s.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Get the cumulative servo travel (degrees). Zero if never recorded.
+    pub fn get_servo_travel(&self) -> Result<u32, EspError> {
+        Ok(self.get_u32(KEY_SERVO_TRAVEL)?.unwrap_or(0))
+    }
+
+    /// Persist the cumulative servo travel (degrees).
+    pub fn set_servo_travel(&mut self, deg: u32) -> Result<(), EspError> {
+        self.set_u32(KEY_SERVO_TRAVEL, deg)
+    }
+
+    /// Get the persisted count of undecodable command payloads.
+    pub fn get_decode_failures(&self) -> Result<u32, EspError> {
+        Ok(self.get_u32(KEY_DECODE_FAILURES)?.unwrap_or(0))
+    }
+
+    /// Persist the count of undecodable command payloads.
+    pub fn set_decode_failures(&mut self, count: u32) -> Result<(), EspError> {
+        self.set_u32(KEY_DECODE_FAILURES, count)
+    }
+
+    /// Zero the cumulative servo travel, e.g. after replacing the servo.
+    pub fn reset_servo_travel(&mut self) -> Result<(), EspError> {
+        self.set_servo_travel(0)
+    }
+
+    /// Get buzzer settings from NVS. Returns None if unset (buzzer off).
+    pub fn get_buzzer_config(&self) -> Result<Option<BuzzerConfig>, EspError> {
+        let mut buf = [0u8; 2];
+        match self.nvs.get_raw(KEY_BUZZER, &mut buf) {
+            Ok(Some(val)) if val.len() == 2 => Ok(Some(BuzzerConfig {
+                enabled: val[0] != 0,
+                volume_pct: val[1],
+            })),
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // -- Primary/secondary Thread datasets --
+    //
+    // Two slots ("ds_a", "ds_b") hold raw operational-dataset TLVs, and
+    // "ds_active" selects which slot is the primary (0 = a, 1 = b).
+    // Promoting the secondary just flips the selector, so the old primary
+    // becomes the fallback.
+
+    /// Get a stored Thread dataset (raw TLVs). Returns None if unset.
+    pub fn get_dataset(&self, choice: DatasetChoice) -> Result<Option<Vec<u8>>, EspError> {
+        let key = self.dataset_key(choice)?;
+        let mut buf = [0u8; MAX_DATASET_LEN];
+        match self.nvs.get_raw(key, &mut buf) {
+            Ok(Some(val)) => Ok(Some(val.to_vec())),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store a Thread dataset (raw TLVs) in the primary or secondary slot.
+    /// One longer than OpenThread accepts is refused.
+    pub fn set_dataset(&mut self, choice: DatasetChoice, tlvs: &[u8]) -> Result<(), EspError> {
+        if tlvs.len() > MAX_DATASET_LEN {
+            return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_INVALID_SIZE }>());
+        }
+        let key = self.dataset_key(choice)?;
+        self.put_raw(key, tlvs)?;
+        Ok(())
+    }
+
+    /// Erase the primary or secondary dataset slot.
+    pub fn clear_dataset(&mut self, choice: DatasetChoice) -> Result<(), EspError> {
+        let key = self.dataset_key(choice)?;
+        self.nvs.remove(key)?;
+        Ok(())
+    }
+
+    /// Make the secondary dataset the primary (and vice versa).
+    pub fn promote_secondary_dataset(&mut self) -> Result<(), EspError> {
+        let active = self.active_dataset_slot()?;
+        self.put_raw(KEY_DATASET_ACTIVE, &[active ^ 1])?;
+        Ok(())
+    }
+
+    /// Whether a pushed dataset (in the secondary slot) is still awaiting
+    /// confirmation, e.g. across a reboot mid-window.
+    pub fn get_dataset_pending(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_DATASET_PENDING, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] != 0),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_dataset_pending(&mut self, pending: bool) -> Result<(), EspError> {
+        self.put_raw(KEY_DATASET_PENDING, &[pending as u8])?;
+        Ok(())
+    }
+
+    /// Get how long (seconds) a pushed dataset has to be confirmed before
+    /// it is reverted. Returns None if unset.
+    pub fn get_dataset_confirm_timeout(&self) -> Result<Option<u32>, EspError> {
+        self.get_u32(KEY_DATASET_CONFIRM)
+    }
+
+    fn active_dataset_slot(&self) -> Result<u8, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw(KEY_DATASET_ACTIVE, &mut buf) {
+            Ok(Some(val)) => Ok(val[0] & 1),
+            Ok(None) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn dataset_key(&self, choice: DatasetChoice) -> Result<&'static str, EspError> {
+        let primary_is_a = self.active_dataset_slot()? == 0;
+        let use_a = match choice {
+            DatasetChoice::Primary => primary_is_a,
+            DatasetChoice::Secondary => !primary_is_a,
+        };
+        Ok(if use_a { KEY_DATASET_A } else { KEY_DATASET_B })
+    }
+
+    fn get_u32(&self, key: &str) -> Result<Option<u32>, EspError> {
+        let mut buf = [0u8; 4];
+        match self.nvs.get_raw(key, &mut buf) {
+            Ok(Some(val)) if val.len() == 4 => {
+                Ok(Some(u32::from_le_bytes([val[0], val[1], val[2], val[3]])))
+            }
+            Ok(_) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write a raw value. If the partition is full and pruning is enabled,
+    /// drop non-critical keys in `prune_plan` order, retrying after each,
+    /// until the write fits or nothing is left to drop.
+    fn put_raw(&mut self, key: &str, value: &[u8]) -> Result<(), EspError> {
+        let err = match self.nvs.set_raw(key, value) {
+            Ok(_) => return Ok(()),
+            Err(e) if e.code() == esp_idf_sys::ESP_ERR_NVS_NOT_ENOUGH_SPACE => e,
+            Err(e) => return Err(e),
+        };
+        if !self.prune_when_full {
+            return Err(err);
+        }
+        for victim in prune_plan(&KEY_RETENTION) {
+            if victim == key || !self.nvs.remove(victim)? {
+                continue;
+            }
+            warn!("NVS full writing {}: pruned {}", key, victim);
+            match self.nvs.set_raw(key, value) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.code() == esp_idf_sys::ESP_ERR_NVS_NOT_ENOUGH_SPACE => {}
+                Err(e) => return Err(e),
+            }
+        }
+        warn!("NVS full writing {}: nothing left to prune", key);
+        Err(err)
+    }
+
+    fn set_u32(&mut self, key: &str, value: u32) -> Result<(), EspError> {
+        self.put_raw(key, &value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn get_string(&self, key: &str) -> Result<Option<String>, EspError> {
+        let mut buf = [0u8; 64];
+        match self.nvs.get_raw(key, &mut buf) {
+            Ok(Some(val)) => {
+                let len = val.len();
+                let s = core::str::from_utf8(&buf[..len])
+                    .unwrap_or_default()
+                    .to_string();
+                Ok(Some(s))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn set_string(&mut self, key: &str, value: &str) -> Result<(), EspError> {
+        self.put_raw(key, value.as_bytes())?;
+        Ok(())
+    }
+
+    // -- Write-Ahead Checkpoint for servo position recovery --
+    //
+    // Inspired by write-ahead logging (WAL), but NOT an append-only log.
+    // Three fixed NVS keys are overwritten in place — total storage is
+    // constant at 3 bytes regardless of how many commands are processed.
+    //
+    // NVS keys (each 1 byte, overwritten on every cycle):
+    //   "angle"  — checkpoint: last committed (known-good) angle
+    //   "target" — intent: target recorded before the move starts
+    //   "wal"    — commit flag: 1 = committed, 0 = pending
+    //
+    // Protocol:
+    //   1. write_ahead(target)  — persist intent + clear commit flag  (2 NVS writes)
+    //   2. servo moves          — RAM only, no NVS writes
+    //   3. commit(angle)        — persist final angle + set flag      (2 NVS writes)
+    //
+    // Recovery (boot with wal=0):
+    //   restore checkpoint, replay pending target
+    //
+    // Flash wear: 4 NVS writes per command cycle. ESP-IDF NVS is
+    // internally log-structured and wear-leveled across pages.
+    // With a 24KB NVS partition (~600K effective writes) and 100
+    // commands/day, flash wear is not a concern for ~16 years.
+
+    /// Get the last committed (checkpoint) vent angle from NVS.
+    pub fn checkpoint_angle(&self) -> Result<Option<u8>, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw("angle", &mut buf) {
+            Ok(Some(val)) => Ok(Some(val[0])),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write-ahead: record target intent and clear the commit flag.
+    /// Must be called BEFORE the servo starts moving.
+    /// Each entry is read back after writing; a mismatch returns
+    /// `WalError::VerifyFailed` so flaky flash is caught before a reboot
+    /// needs the record.
+    pub fn write_ahead(&mut self, target: u8) -> Result<(), WalError> {
+        write_pending(self, target)
+    }
+
+    /// Leave NVS exactly as a move to `target` interrupted by power loss
+    /// would: pending target set, commit flag cleared, checkpoint untouched.
+    /// Used to exercise boot-time recovery on real hardware.
+    #[cfg(feature = "wal-debug")]
+    pub fn simulate_interrupted_move(&mut self, target: u8) -> Result<(), WalError> {
+        self.write_ahead(target)
+    }
+
+    /// Get the pending (write-ahead) target from the last uncommitted move.
+    pub fn get_pending(&self) -> Result<Option<u8>, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw("target", &mut buf) {
+            Ok(Some(val)) => Ok(Some(val[0])),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Commit: save the final angle as the new checkpoint and set the
+    /// commit flag. Called after the servo reaches its target.
+    pub fn commit(&mut self, angle: u8) -> Result<(), EspError> {
+        self.put_raw("angle", &[angle])?;
+        self.put_raw("wal", &[1u8])?;
+        Ok(())
+    }
+
+    /// Check whether the last move was committed.
+    /// Returns false if power was lost between write_ahead and commit.
+    pub fn is_committed(&self) -> Result<bool, EspError> {
+        let mut buf = [0u8; 1];
+        match self.nvs.get_raw("wal", &mut buf) {
+            Ok(Some(val)) => Ok(val[0] == 1),
+            Ok(None) => Ok(true), // no WAL entry = no pending move = committed
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Failure to persist a write-ahead record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalError {
+    /// The NVS write itself failed.
+    Nvs(EspError),
+    /// The write reported success but reading it back gave a different value.
+    VerifyFailed,
+}
+
+impl From<EspError> for WalError {
+    fn from(e: EspError) -> Self {
+        WalError::Nvs(e)
+    }
+}
+
+/// How much losing a stored key costs when NVS runs out of space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Retention {
+    /// Diagnostic history; losing it only blurs the record.
+    Diagnostic,
+    /// Lifetime counters; losing them restarts the count.
+    Metric,
+    /// Config and the move WAL; never pruned.
+    Critical,
+}
+
+/// Keys considered for pruning when NVS is full. Anything not listed is
+/// treated as critical.
+const KEY_RETENTION: [(&str, Retention); 12] = [
+    (KEY_RUNTIME, Retention::Metric),
+    (KEY_COMMAND_COUNT, Retention::Metric),
+    (KEY_MOVE_COUNT, Retention::Metric),
+    (KEY_SERVO_TRAVEL, Retention::Metric),
+    (KEY_BOOT_COUNT, Retention::Metric),
+    (KEY_DECODE_FAILURES, Retention::Diagnostic),
+    (KEY_RESET_REASON, Retention::Diagnostic),
+    (KEY_LAST_SWEEP, Retention::Diagnostic),
+    ("angle", Retention::Critical),
+    ("target", Retention::Critical),
+    ("wal", Retention::Critical),
+    // Crash-loop detection depends on it; pruning it mid-loop would
+    // reset the count and let a bad boot move run again
+    (KEY_BOOT_TIMES, Retention::Critical),
+];
+
+/// Order in which to drop keys to free NVS space: least valuable first,
+/// keeping the listed order within a class. Critical keys are never
+/// included.
+pub fn prune_plan<'a>(keys_by_priority: &[(&'a str, Retention)]) -> Vec<&'a str> {
+    let mut plan: Vec<_> = keys_by_priority
+        .iter()
+        .filter(|(_, r)| *r != Retention::Critical)
+        .collect();
+    plan.sort_by_key(|(_, r)| *r);
+    plan.into_iter().map(|(k, _)| *k).collect()
+}
+
+/// Single-byte key/value storage holding the WAL entries.
+trait WalStore {
+    fn write(&mut self, key: &str, value: u8) -> Result<(), EspError>;
+    fn read(&self, key: &str) -> Result<Option<u8>, EspError>;
+}
+
+impl WalStore for DeviceIdentity {
+    fn write(&mut self, key: &str, value: u8) -> Result<(), EspError> {
+        self.put_raw(key, &[value])
+    }
+
+    fn read(&self, key: &str) -> Result<Option<u8>, EspError> {
+        let mut buf = [0u8; 1];
+        Ok(self.nvs.get_raw(key, &mut buf)?.and_then(|v| v.first().copied()))
+    }
+}
+
+/// Write the pending entries for `target`, then read each one back.
+fn write_pending<S: WalStore>(store: &mut S, target: u8) -> Result<(), WalError> {
+    for (key, value) in wal_pending_entries(target) {
+        store.write(key, value)?;
+    }
+    for (key, value) in wal_pending_entries(target) {
+        if store.read(key)? != Some(value) {
+            return Err(WalError::VerifyFailed);
+        }
+    }
+    Ok(())
+}
+
+/// Default quiet period before a live-adjusted setting is persisted.
+pub const DEFAULT_SAVE_DEBOUNCE_S: u32 = 5;
+
+/// A setting adjusted live in RAM and written to NVS only after it has
+/// stopped changing for `quiet_ms`, so an installer nudging trim a degree
+/// at a time costs one flash write instead of one per step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebouncedNvsValue<T> {
+    value: T,
+    /// Uptime of the latest unsaved change.
+    changed_ms: Option<u64>,
+    quiet_ms: u64,
+}
+
+impl<T: Copy + PartialEq> DebouncedNvsValue<T> {
+    /// Start from the value already in NVS.
+    pub fn new(persisted: T, quiet_ms: u64) -> Self {
+        Self {
+            value: persisted,
+            changed_ms: None,
+            quiet_ms,
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.value
+    }
+
+    /// Take a new value now and restart the quiet period.
+    pub fn set(&mut self, value: T, now_ms: u64) {
+        if value != self.value || self.changed_ms.is_some() {
+            self.value = value;
+            self.changed_ms = Some(now_ms);
+        }
+    }
+
+    /// The value to write, once it has been stable for the quiet period.
+    pub fn take_due(&mut self, now_ms: u64) -> Option<T> {
+        let changed = self.changed_ms?;
+        if now_ms.saturating_sub(changed) < self.quiet_ms {
+            return None;
+        }
+        self.changed_ms = None;
+        Some(self.value)
+    }
+
+    /// The value to write regardless of the quiet period, e.g. before a
+    /// restart.
+    pub fn take_unsaved(&mut self) -> Option<T> {
+        self.changed_ms.take().map(|_| self.value)
+    }
+}
+
+/// Default interval between metric flushes to NVS.
+pub const DEFAULT_METRICS_FLUSH_S: u32 = 300;
+
+/// Counters kept in a `MetricsCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Target commands accepted.
+    Commands,
+    /// Moves that ran to their target.
+    Moves,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 2] = [Metric::Commands, Metric::Moves];
+
+    fn index(self) -> usize {
+        match self {
+            Metric::Commands => 0,
+            Metric::Moves => 1,
+        }
+    }
+
+    fn nvs_key(self) -> &'static str {
+        match self {
+            Metric::Commands => KEY_COMMAND_COUNT,
+            Metric::Moves => KEY_MOVE_COUNT,
+        }
+    }
+}
+
+/// Counters incremented in RAM and written to NVS at most once per
+/// interval, and before a clean restart, instead of on every event. A
+/// crash loses at most one interval of counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsCache {
+    totals: [u32; Metric::ALL.len()],
+    dirty: [bool; Metric::ALL.len()],
+    interval_ms: u64,
+    last_flush_ms: u64,
+}
+
+impl MetricsCache {
+    /// Resume from the totals already in NVS, in `Metric::ALL` order.
+    pub fn new(persisted: [u32; Metric::ALL.len()], interval_ms: u64) -> Self {
+        Self {
+            totals: persisted,
+            dirty: [false; Metric::ALL.len()],
+            interval_ms,
+            last_flush_ms: 0,
+        }
+    }
+
+    pub fn incr(&mut self, field: Metric) {
+        let i = field.index();
+        self.totals[i] = self.totals[i].saturating_add(1);
+        self.dirty[i] = true;
+    }
+
+    /// Totals to write, once the interval has passed since the last flush.
+    pub fn flush_if_due(&mut self, now_ms: u64) -> Vec<(Metric, u32)> {
+        if now_ms.saturating_sub(self.last_flush_ms) < self.interval_ms {
+            return Vec::new();
+        }
+        self.last_flush_ms = now_ms;
+        self.flush()
+    }
+
+    /// Totals changed since the last flush, regardless of the interval,
+    /// e.g. before a restart.
+    pub fn flush(&mut self) -> Vec<(Metric, u32)> {
+        let totals = self.totals;
+        Metric::ALL
+            .into_iter()
+            .filter(|m| core::mem::take(&mut self.dirty[m.index()]))
+            .map(|m| (m, totals[m.index()]))
+            .collect()
+    }
+}
+
+/// Name a device goes by in responses and logs: the configured name, or
+/// the EUI-64 when none is set, so every response identifies its sender.
+pub fn display_label(name: Option<&str>, eui64: &str) -> String {
+    match name.map(str::trim) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => eui64.to_string(),
+    }
+}
+
+/// Format address bytes as lowercase colon-separated hex.
+pub fn format_eui64(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// NVS writes that mark a move to `target` as in flight. Shared by the real
+/// write-ahead and the debug simulation so both leave identical state.
+fn wal_pending_entries(target: u8) -> [(&'static str, u8); 2] {
+    [("target", target), ("wal", 0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_debounce_coalesces_rapid_updates() {
+        let mut trim = DebouncedNvsValue::new(0i8, 5000);
+        let mut writes = Vec::new();
+        for (i, step) in [1i8, 2, 3, 2].into_iter().enumerate() {
+            let now = i as u64 * 400;
+            trim.set(step, now);
+            writes.extend(trim.take_due(now));
+        }
+        assert_eq!(trim.get(), 2);
+        assert!(writes.is_empty());
+        assert_eq!(trim.take_due(1200 + 4999), None);
+        writes.extend(trim.take_due(1200 + 5000));
+        writes.extend(trim.take_due(60_000));
+        assert_eq!(writes, vec![2]);
+    }
+
+    #[test]
+    fn test_debounce_unchanged_value_not_written() {
+        let mut trim = DebouncedNvsValue::new(-3i8, 5000);
+        trim.set(-3, 0);
+        assert_eq!(trim.take_due(10_000), None);
+        trim.set(4, 0);
+        assert_eq!(trim.take_unsaved(), Some(4));
+        assert_eq!(trim.take_unsaved(), None);
+    }
+
+    /// In-memory store; `corrupt` makes reads of that key return a
+    /// different value than was written, like a silently truncated write.
+    #[derive(Default)]
+    struct MockStore {
+        map: HashMap<String, u8>,
+        corrupt: Option<&'static str>,
+    }
+
+    impl WalStore for MockStore {
+        fn write(&mut self, key: &str, value: u8) -> Result<(), EspError> {
+            self.map.insert(key.to_string(), value);
+            Ok(())
+        }
+
+        fn read(&self, key: &str) -> Result<Option<u8>, EspError> {
+            let value = self.map.get(key).copied();
+            if self.corrupt == Some(key) {
+                return Ok(value.map(|v| v ^ 0xff));
+            }
+            Ok(value)
+        }
+    }
+
+    #[test]
+    fn test_format_eui64() {
+        let bytes = [0x40, 0x4c, 0xca, 0xff, 0xfe, 0x0a, 0x1b, 0x02];
+        assert_eq!(format_eui64(&bytes), "40:4c:ca:ff:fe:0a:1b:02");
+        assert_eq!(format_eui64(&bytes[..6]), "40:4c:ca:ff:fe:0a");
+        assert_eq!(format_eui64(&[]), "");
+    }
+
+    #[test]
+    fn test_display_label_prefers_name() {
+        assert_eq!(display_label(Some("Office"), "40:4c:ca:ff:fe:0a:1b:02"), "Office");
+        assert_eq!(display_label(Some("  Den  "), "40:4c:ca:ff:fe:0a:1b:02"), "Den");
+    }
+
+    #[test]
+    fn test_display_label_falls_back_to_eui64() {
+        let eui = "40:4c:ca:ff:fe:0a:1b:02";
+        assert_eq!(display_label(None, eui), eui);
+        assert_eq!(display_label(Some(""), eui), eui);
+        assert_eq!(display_label(Some(" \t"), eui), eui);
+    }
+
+    #[test]
+    fn test_write_pending_verifies() {
+        let mut store = MockStore::default();
+        assert_eq!(write_pending(&mut store, 150), Ok(()));
+        assert_eq!(store.map.get("target"), Some(&150));
+        assert_eq!(store.map.get("wal"), Some(&0));
+    }
+
+    #[test]
+    fn test_write_pending_readback_mismatch() {
+        for key in ["target", "wal"] {
+            let mut store = MockStore {
+                corrupt: Some(key),
+                ..Default::default()
+            };
+            assert_eq!(write_pending(&mut store, 150), Err(WalError::VerifyFailed));
+        }
+    }
+
+    #[test]
+    fn test_metrics_accumulate_until_due() {
+        let mut cache = MetricsCache::new([10, 4], 300_000);
+        cache.incr(Metric::Commands);
+        cache.incr(Metric::Commands);
+        cache.incr(Metric::Moves);
+        assert!(cache.flush_if_due(299_999).is_empty());
+        assert_eq!(
+            cache.flush_if_due(300_000),
+            [(Metric::Commands, 12), (Metric::Moves, 5)]
+        );
+        // Nothing new since: nothing to write, and the interval restarts
+        assert!(cache.flush_if_due(600_000).is_empty());
+        cache.incr(Metric::Moves);
+        assert!(cache.flush_if_due(700_000).is_empty());
+        assert_eq!(cache.flush_if_due(900_000), [(Metric::Moves, 6)]);
+    }
+
+    #[test]
+    fn test_metrics_forced_flush() {
+        let mut cache = MetricsCache::new([0, 0], 300_000);
+        assert!(cache.flush().is_empty());
+        cache.incr(Metric::Moves);
+        assert_eq!(cache.flush(), [(Metric::Moves, 1)]);
+        assert!(cache.flush().is_empty());
+    }
+
+    #[test]
+    fn test_prune_plan_drops_diagnostics_before_metrics() {
+        let keys = [
+            ("runtime_s", Retention::Metric),
+            ("wal", Retention::Critical),
+            ("decode_fail", Retention::Diagnostic),
+            ("travel_deg", Retention::Metric),
+            ("boot_times", Retention::Diagnostic),
+        ];
+        assert_eq!(
+            prune_plan(&keys),
+            ["decode_fail", "boot_times", "runtime_s", "travel_deg"]
+        );
+    }
+
+    #[test]
+    fn test_prune_plan_keeps_wal_and_config() {
+        let plan = prune_plan(&KEY_RETENTION);
+        for key in ["angle", "target", "wal", KEY_BOOT_TIMES, KEY_TRIM, KEY_ROOM] {
+            assert!(!plan.contains(&key), "{} must not be pruned", key);
+        }
+        assert_eq!(plan.first(), Some(&KEY_DECODE_FAILURES));
+        assert!(prune_plan(&[("wal", Retention::Critical)]).is_empty());
+    }
+
+    #[test]
+    fn test_wal_pending_entries_mark_uncommitted() {
+        assert_eq!(wal_pending_entries(120), [("target", 120), ("wal", 0)]);
+    }
+
+    #[test]
+    fn test_boot_counters_increment() {
+        let mut c = BootCounters::default();
+        c.on_boot(1);
+        c.on_boot(4);
+        assert_eq!(c.boot_count, 2);
+        assert_eq!(c.last_reset_reason, 4);
+        assert_eq!(c.to_bytes(), [2, 0, 0, 0, 4]);
+    }
+
+    #[test]
+    fn test_boot_counters_reset_then_increment_from_zero() {
+        let mut c = BootCounters {
+            boot_count: 57,
+            last_reset_reason: 9,
+        };
+        c.reset();
+        assert_eq!(c, BootCounters::default());
+
+        c.on_boot(1);
+        assert_eq!(c.boot_count, 1);
+        assert_eq!(c.last_reset_reason, 1);
+    }
 }
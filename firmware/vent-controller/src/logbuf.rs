@@ -0,0 +1,179 @@
+use esp_idf_svc::log::EspLogger;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Lines kept in RAM. At the line cap below this bounds the buffer to ~10 KB.
+pub const LOG_RING_LINES: usize = 64;
+/// Longer lines are truncated before being buffered.
+pub const MAX_LINE_LEN: usize = 160;
+
+/// Fixed-size buffer of the most recent log lines; the oldest line is
+/// dropped when a new one arrives at capacity.
+pub struct LogRing {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl LogRing {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, mut line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if line.len() > MAX_LINE_LEN {
+            let mut cut = MAX_LINE_LEN;
+            while !line.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            line.truncate(cut);
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Buffered lines, oldest first, joined with newlines.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Slice `text` into blocks of `block_size` bytes for block-wise transfer.
+/// Returns block `num` and whether more blocks follow, or None past the end.
+pub fn block(text: &[u8], num: usize, block_size: usize) -> Option<(&[u8], bool)> {
+    if block_size == 0 {
+        return None;
+    }
+    let start = num.checked_mul(block_size)?;
+    if start >= text.len() && !(num == 0 && text.is_empty()) {
+        return None;
+    }
+    let end = (start + block_size).min(text.len());
+    Some((&text[start..end], end < text.len()))
+}
+
+static RING: Mutex<LogRing> = Mutex::new(LogRing::new(LOG_RING_LINES));
+
+/// Logger that writes through to the ESP-IDF console and keeps a copy of
+/// each line in the RAM ring for remote retrieval.
+struct CapturingLogger {
+    console: EspLogger,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        if record.level() <= log::Level::Info {
+            let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+            if let Ok(mut ring) = RING.lock() {
+                ring.push(line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    console: EspLogger::new(),
+};
+
+/// Install the capturing logger. Call once, first thing in `main`.
+pub fn init() -> Result<(), log::SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(LevelFilter::Info);
+    Ok(())
+}
+
+/// Recent log lines as one newline-separated string, oldest first.
+pub fn recent_text() -> String {
+    RING.lock().map(|r| r.text()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_captures_in_order() {
+        let mut ring = LogRing::new(4);
+        ring.push("a".into());
+        ring.push("b".into());
+        assert_eq!(ring.text(), "a\nb\n");
+    }
+
+    #[test]
+    fn test_ring_overwrites_oldest() {
+        let mut ring = LogRing::new(3);
+        for i in 0..5 {
+            ring.push(format!("line {}", i));
+        }
+        assert_eq!(ring.text().lines().count(), 3);
+        assert_eq!(ring.text(), "line 2\nline 3\nline 4\n");
+    }
+
+    #[test]
+    fn test_ring_truncates_long_lines() {
+        let mut ring = LogRing::new(2);
+        ring.push("x".repeat(MAX_LINE_LEN + 50));
+        assert_eq!(ring.text().len(), MAX_LINE_LEN + 1);
+        // Never splits a multi-byte character
+        ring.push("°".repeat(MAX_LINE_LEN));
+        assert!(ring.text().lines().last().unwrap().len() <= MAX_LINE_LEN);
+    }
+
+    #[test]
+    fn test_block_chunking() {
+        let text = b"0123456789";
+        assert_eq!(block(text, 0, 4), Some((&b"0123"[..], true)));
+        assert_eq!(block(text, 1, 4), Some((&b"4567"[..], true)));
+        assert_eq!(block(text, 2, 4), Some((&b"89"[..], false)));
+        assert_eq!(block(text, 3, 4), None);
+    }
+
+    #[test]
+    fn test_block_exact_multiple_and_empty() {
+        assert_eq!(block(b"abcd", 0, 4), Some((&b"abcd"[..], false)));
+        assert_eq!(block(b"abcd", 1, 4), None);
+        assert_eq!(block(b"", 0, 16), Some((&b""[..], false)));
+        assert_eq!(block(b"abc", 0, 0), None);
+    }
+
+    #[test]
+    fn test_blocks_reassemble_ring_text() {
+        let mut ring = LogRing::new(8);
+        for i in 0..8 {
+            ring.push(format!("I vent_controller: message number {}", i));
+        }
+        let text = ring.text();
+        let mut joined = Vec::new();
+        let mut num = 0;
+        while let Some((chunk, more)) = block(text.as_bytes(), num, 64) {
+            joined.extend_from_slice(chunk);
+            num += 1;
+            if !more {
+                break;
+            }
+        }
+        assert_eq!(joined, text.as_bytes());
+    }
+}
@@ -1,37 +1,104 @@
-#[allow(dead_code)]
+mod beacon;
+mod breakin;
+mod buzzer;
+mod chip_temp;
+mod climate;
+mod coap;
+mod command;
+mod config;
+mod crashloop;
+mod heater;
 mod identity;
-#[allow(dead_code)]
+mod logbuf;
+mod maintenance;
 mod matter;
-#[allow(dead_code)]
+mod motion;
+mod position_sensor;
 mod power;
-#[allow(dead_code)]
+mod presets;
+mod pressure;
+mod quiet;
+mod relay;
+mod selftest;
+mod sensors;
 mod servo;
-#[allow(dead_code)]
 mod state;
-#[allow(dead_code)]
+mod supply;
 mod thread;
 
+use buzzer::{BuzzerDriver, BuzzerEvent};
+use command::{AccessMode, CommandSource};
 use identity::DeviceIdentity;
-use power::{PowerManager, PowerMode};
-use servo::ServoDriver;
-use state::{AppState, VentStateMachine};
-use thread::ThreadManager;
-use vent_protocol::{PowerSource, ANGLE_CLOSED};
+use power::{PowerManager, PowerMode, RailSequencer};
+use sensors::SensorScheduler;
+use selftest::{SelfTestReport, TestResult};
+use servo::{PositionFeedback, ServoDriver, ServoPower};
+use state::{AppState, BootBehavior, HeldBootMove, MoveOutcome, MoveTimeout, PushFilter, VentStateMachine};
+use thread::{DatasetApply, DatasetChoice, DatasetFailover, ThreadManager};
+use vent_protocol::{PowerSource, StatusBeacon, ANGLE_CLOSED};
 
+use esp_idf_hal::gpio::{InputPin, OutputPin};
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
 use esp_idf_hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolution};
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_hal::prelude::*;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
 use log::{error, info, warn};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
+/// Default time a commissioned vent may stay off the fabric before rejoining.
+const DEFAULT_FABRIC_TIMEOUT_S: u32 = 600;
+/// Default time an uncommissioned vent waits before opening fail-safe.
+const DEFAULT_COMMISSION_TIMEOUT_S: u32 = 1800;
+/// How often the main loop probes fabric connectivity.
+const FABRIC_CHECK_INTERVAL_S: u64 = 10;
+/// Default slack on top of the nominal move duration before a move is aborted.
+const DEFAULT_MOVE_GRACE_MS: u32 = 2000;
+/// How often the duct pressure sensor is sampled.
+const PRESSURE_POLL_MS: u64 = 1000;
+/// How often pressure-hold automation may adjust the vent.
+const PRESSURE_CONTROL_INTERVAL_S: u64 = 30;
+/// Default minimum angle change between mid-move Matter position reports.
+const DEFAULT_REPORT_TOLERANCE_DEG: u8 = 5;
+/// Uptime before the automatic boot self-test runs.
+const SELFTEST_BOOT_DELAY_S: u64 = 60;
+/// How long to retry joining with one dataset before trying the other.
+const DATASET_RETRY_WINDOW_S: u64 = 120;
+/// Wait before retrying a Thread scan that failed to start
+const SCAN_RETRY_S: u64 = 60;
+
+/// Power a switched-off servo rail for a one-off servo action outside the
+/// rail sequencer (nudge, self-test, frequency detect). Returns whether the
+/// rail was switched on here, so the caller switches it off again after.
+/// None when the rail didn't come up power-good: the fault is flagged for
+/// health reports and the action should be skipped.
+fn rail_up_for(servo_power: Option<&mut ServoPower<'_>>, settle_ms: u32, what: &str) -> Option<bool> {
+    let p = match servo_power {
+        Some(p) if !p.is_powered() => p,
+        _ => return Some(false),
+    };
+    match p.power_up_blocking(settle_ms) {
+        Ok(true) => Some(true),
+        Ok(false) => {
+            error!("{}: servo rail not power-good after {} ms — skipping", what, settle_ms);
+            state::with_app_state(|s| s.servo_power_fault = true);
+            None
+        }
+        Err(e) => {
+            warn!("{}: servo rail switch failed: {:?}", what, e);
+            Some(true)
+        }
+    }
+}
+
 fn main() {
     // Initialize ESP-IDF logging and system
     esp_idf_svc::sys::link_patches();
-    esp_idf_logger::init().expect("Failed to init logger");
+    logbuf::init().expect("Failed to init logger");
 
-    info!("Vent Controller v{}", env!("CARGO_PKG_VERSION"));
+    info!("Vent Controller {}", identity::build_info());
     info!("Wakeup cause: {}", PowerManager::wakeup_cause_str());
 
     // Initialize peripherals
@@ -41,18 +108,63 @@ fn main() {
     // Initialize device identity
     let mut device_id = DeviceIdentity::new(nvs_partition).expect("Failed to init identity");
     info!("EUI-64: {}", device_id.eui64());
+    info!("Device label: {}", device_id.label());
+    let room = device_id.get_room().ok().flatten();
+    let floor = device_id.get_floor().ok().flatten();
+    info!("Room: {}, floor: {}", room.as_deref().unwrap_or("-"), floor.as_deref().unwrap_or("-"));
+    let setup_creds = matter::SetupCredentials::from_eui64(device_id.eui64_bytes());
+
+    let reset_reason = PowerManager::reset_reason();
+    match device_id.record_boot(reset_reason) {
+        Ok(c) => info!(
+            "Boot #{} (reset reason: {})",
+            c.boot_count,
+            power::reset_reason_str(c.last_reset_reason)
+        ),
+        Err(e) => warn!("Failed to record boot counters: {:?}", e),
+    }
+
+    // Crash-loop guard: too many boots in a short window starts the vent
+    // in minimal mode so it stays reachable for diagnosis
+    let boot_clock = crashloop::boot_clock_s();
+    let crash_loop = match device_id.record_boot_time(boot_clock) {
+        Ok(history) => crashloop::is_crash_looping(
+            &history,
+            boot_clock,
+            crashloop::CRASH_LOOP_BOOTS,
+            crashloop::CRASH_LOOP_WINDOW_S,
+        ),
+        Err(e) => {
+            warn!("Failed to record boot time: {:?}", e);
+            false
+        }
+    };
+    if crash_loop {
+        error!(
+            "Crash loop: more than {} boots in {}s — starting in minimal mode",
+            crashloop::CRASH_LOOP_BOOTS,
+            crashloop::CRASH_LOOP_WINDOW_S
+        );
+    }
 
     // Check first boot
-    match device_id.is_first_boot() {
+    let first_boot = match device_id.is_first_boot() {
         Ok(true) => {
             info!("First boot detected — initializing defaults");
             if let Err(e) = device_id.mark_initialized() {
                 warn!("Failed to mark initialized: {:?}", e);
             }
+            true
         }
-        Ok(false) => info!("Device previously initialized"),
-        Err(e) => warn!("Could not check boot status: {:?}", e),
-    }
+        Ok(false) => {
+            info!("Device previously initialized");
+            false
+        }
+        Err(e) => {
+            warn!("Could not check boot status: {:?}", e);
+            false
+        }
+    };
 
     // WAL recovery — check if previous move was committed
     let committed = device_id.is_committed().unwrap_or(true);
@@ -82,9 +194,49 @@ fn main() {
         (checkpoint, pending)
     };
 
+    // Minimal mode holds the louver still: a boot-time move may be what
+    // keeps crashing. An uncommitted move stays in the WAL for later, and
+    // no fixed boot move is written ahead over it.
+    let pending_target = if crash_loop {
+        if let Some(target) = pending_target {
+            warn!("Minimal mode: not replaying move to {}°", target);
+        }
+        None
+    } else {
+        // Fixed boot position: start from the checkpoint (where the louver
+        // physically is) and make a normal WAL-protected move to the fixed angle
+        match device_id.get_boot_behavior().unwrap_or_default() {
+            (BootBehavior::RestoreCheckpoint, _) => pending_target,
+            (BootBehavior::FixedAngle, fixed) => {
+                let target = state::boot_target(BootBehavior::FixedAngle, initial_angle, fixed);
+                info!("Boot behavior: fixed angle {}°", target);
+                if target == initial_angle {
+                    pending_target
+                } else {
+                    if let Err(e) = device_id.write_ahead(target) {
+                        warn!("Boot move WAL write-ahead failed: {:?}", e);
+                    }
+                    Some(target)
+                }
+            }
+        }
+    };
+
     // Initialize servo via LEDC PWM
+    let servo_freq_hz = match device_id.get_servo_frequency().ok().flatten() {
+        Some(hz) if servo::CANDIDATE_FREQS_HZ.contains(&hz) => hz,
+        Some(hz) => {
+            warn!(
+                "Stored servo frequency {} Hz is not a supported rate — using {} Hz",
+                hz,
+                servo::PWM_FREQ_HZ
+            );
+            servo::PWM_FREQ_HZ
+        }
+        None => servo::PWM_FREQ_HZ,
+    };
     let timer_config = TimerConfig::default()
-        .frequency(50.Hz().into())
+        .frequency(servo_freq_hz.Hz().into())
         .resolution(Resolution::Bits14);
     let timer = LedcTimerDriver::new(
         peripherals.ledc.timer0,
@@ -100,17 +252,194 @@ fn main() {
     .expect("Failed to init LEDC channel");
 
     let mut servo = ServoDriver::new(ledc_driver).expect("Failed to init servo");
-    if let Err(e) = servo.set_angle(initial_angle) {
-        error!("Failed to set initial servo angle: {:?}", e);
+    if servo_freq_hz != servo::PWM_FREQ_HZ {
+        info!("Servo PWM at {} Hz", servo_freq_hz);
+        if let Err(e) = servo.set_frequency(servo_freq_hz) {
+            error!("Failed to set servo frequency: {:?}", e);
+        }
+    }
+    if let Ok(Some(trim)) = device_id.get_trim() {
+        info!("Servo trim: {:+}°", trim);
+        servo.set_trim(trim);
+    }
+    let save_debounce_s = device_id
+        .get_save_debounce()
+        .ok()
+        .flatten()
+        .unwrap_or(identity::DEFAULT_SAVE_DEBOUNCE_S);
+    let trim = identity::DebouncedNvsValue::new(servo.trim(), save_debounce_s as u64 * 1000);
+    if device_id.get_servo_inverted().unwrap_or(false) {
+        info!("Servo mounted inverted");
+        servo.set_inverted(true);
+    }
+    let motion_curve = device_id.get_motion_curve().unwrap_or_default();
+    if !motion_curve.is_empty() {
+        info!("Motion curve: {} points", motion_curve.len());
+    }
+    let damping_zone = device_id.get_damping_zone().unwrap_or(0);
+    if damping_zone > 0 {
+        info!("Approach damping within {}° of target", damping_zone);
+    }
+    let stepping = device_id.get_stepping().unwrap_or_default();
+    if stepping == motion::Stepping::Snap {
+        info!("Servo stepping: snap to target");
+    }
+    let progress_log_deg = device_id
+        .get_progress_log_deg()
+        .ok()
+        .flatten()
+        .map(|d| d.min(u8::MAX as u32) as u8)
+        .unwrap_or(motion::DEFAULT_PROGRESS_LOG_DEG);
+    let approach = device_id.get_approach_direction().unwrap_or_default();
+    if approach != motion::ApproachDirection::Any {
+        info!("Final approach: {:?}", approach);
+    }
+    let creep_interval_s = device_id.get_creep_interval().ok().flatten().unwrap_or(0);
+    if creep_interval_s > 0 {
+        info!("Creep correction every {}s", creep_interval_s);
+    }
+    let sweep_interval_s = device_id.get_sweep_interval().ok().flatten().unwrap_or(0);
+    if sweep_interval_s > 0 {
+        info!("Maintenance sweep every {}s", sweep_interval_s);
+    }
+    let clock_max_age_s = device_id.get_clock_max_age().ok().flatten().unwrap_or(0);
+    if clock_max_age_s > 0 {
+        info!("Wall clock trusted for {}s after each sync", clock_max_age_s);
+    }
+    maintenance::set_clock_max_age_s(clock_max_age_s);
+    // Give the mesh time to form so the radio check means something
+    let mut selftest_on_boot = device_id.get_selftest_on_boot().unwrap_or(false) && !crash_loop;
+    let move_yield_ms = device_id.get_move_yield().ok().flatten().unwrap_or(motion::DEFAULT_MOVE_YIELD_MS);
+    let seat_config = device_id.get_seat_config().ok().flatten().unwrap_or_default();
+    if seat_config.enabled() {
+        info!(
+            "Seat on close: {}° overshoot, {} ms hold",
+            seat_config.overshoot_deg, seat_config.hold_ms
+        );
+    }
+    let kick_config = device_id.get_kick_config().ok().flatten().unwrap_or_default();
+    if kick_config.enabled() {
+        info!(
+            "Start kick: {}° for {} ms after {}s still",
+            kick_config.kick_deg,
+            kick_config.capped_hold_ms(),
+            servo::KICK_MIN_IDLE_MS / 1000
+        );
+    }
+
+    // Optional piezo buzzer for audible move feedback (XIAO D3), on its own
+    // LEDC timer so tone changes don't disturb the servo's 50 Hz PWM.
+    let buzzer_config = device_id.get_buzzer_config().ok().flatten().unwrap_or_default();
+    let mut buzzer = if buzzer_config.enabled && !crash_loop {
+        let buzzer_timer_config = TimerConfig::default()
+            .frequency(2000.Hz().into())
+            .resolution(Resolution::Bits10);
+        LedcTimerDriver::new(peripherals.ledc.timer1, &buzzer_timer_config)
+            .and_then(|timer| {
+                LedcDriver::new(peripherals.ledc.channel1, timer, peripherals.pins.gpio21)
+            })
+            .map(|ledc| BuzzerDriver::new(ledc, buzzer_config))
+            .map_err(|e| error!("Failed to init buzzer: {:?}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    // Optional MOSFET on the servo supply (XIAO D8). When fitted, the rail
+    // is only powered around moves; otherwise the servo is always powered.
+    let mut servo_power = if device_id.get_servo_rail_switched().unwrap_or(false) {
+        let power_good = device_id.get_rail_power_good().unwrap_or(false);
+        ServoPower::new(peripherals.pins.gpio19.downgrade_output())
+            .and_then(|p| {
+                // Optional power-good sense on D1
+                if power_good {
+                    p.with_power_good(peripherals.pins.gpio1.downgrade_input())
+                } else {
+                    Ok(p)
+                }
+            })
+            .map_err(|e| error!("Failed to init servo power enable: {:?}", e))
+            .ok()
+    } else {
+        None
+    };
+    // Servos differ in how long they take to stabilize after power-up
+    let rail_settle_ms = power::rail_settle_ms(device_id.get_rail_settle().ok().flatten());
+    // Optional anti-condensation heater (XIAO D9), fitted when a dew-point
+    // margin is configured
+    let heater_margin_mc = device_id
+        .get_heater_margin()
+        .ok()
+        .flatten()
+        .filter(|_| !crash_loop);
+    let mut heater = heater_margin_mc.and_then(|_| {
+        heater::Heater::new(peripherals.pins.gpio20.downgrade_output())
+            .map_err(|e| error!("Failed to init heater: {:?}", e))
+            .ok()
+    });
+    let mut last_heater_check = Instant::now();
+    // Optional HVAC bypass relay (XIAO D10) on a zone-master vent. Kept even
+    // in minimal mode: it protects the blower, not the vent.
+    let mut bypass_relay = if device_id.get_bypass_relay_enabled().unwrap_or(false) {
+        relay::BypassRelay::new(peripherals.pins.gpio18.downgrade_output())
+            .map_err(|e| error!("Failed to init bypass relay: {:?}", e))
+            .ok()
+    } else {
+        None
+    };
+    let mut last_relay_check = Instant::now();
+
+    // A unit shipped loose isn't driven until Matter says whether it has
+    // been commissioned
+    let detach_until_commissioned = device_id.get_detach_until_commissioned().unwrap_or(false);
+    let gentle_engage = device_id.get_gentle_engage().unwrap_or(true);
+    let mut servo_power_fault_at_boot = false;
+    if !detach_until_commissioned {
+        // Power up to seat at the initial angle; the rail sequencer cuts
+        // it again after the hold
+        let rail_ok = match servo_power.as_mut().map(|p| p.power_up_blocking(rail_settle_ms)) {
+            None | Some(Ok(true)) => true,
+            Some(Ok(false)) => {
+                error!("Servo rail not power-good after {} ms — not seating at boot", rail_settle_ms);
+                servo_power_fault_at_boot = true;
+                false
+            }
+            Some(Err(e)) => {
+                error!("Failed to enable servo rail: {:?}", e);
+                true
+            }
+        };
+        if rail_ok {
+            if let Err(e) = servo.set_angle(initial_angle) {
+                error!("Failed to set initial servo angle: {:?}", e);
+            }
+        }
     }
+    // Pick the rail sequence up from wherever boot left it
+    let rail_on_at_boot = servo_power.as_ref().is_none_or(|p| p.is_powered());
+    let mut rail = if rail_on_at_boot {
+        RailSequencer::already_powered(rail_settle_ms, power::SERVO_RAIL_HOLD_MS)
+    } else {
+        RailSequencer::new(rail_settle_ms, power::SERVO_RAIL_HOLD_MS)
+    };
 
     // Initialize state machine at last known position
     let mut vent_state = VentStateMachine::new(initial_angle);
 
+    // A coordinator often commands a vent as soon as it joins; holding the
+    // boot move until then avoids moving twice
+    let join_hold = Duration::from_secs(device_id.get_join_hold().ok().flatten().unwrap_or(0) as u64);
+    let mut held_boot_move = None;
+
     // If a pending target exists from an interrupted move, replay it
     if let Some(target) = pending_target {
-        info!("Replaying interrupted command: target {}°", target);
-        vent_state.set_target(target);
+        if join_hold.is_zero() {
+            info!("Replaying interrupted command: target {}°", target);
+            vent_state.set_target(target);
+        } else {
+            info!("Holding boot move to {}° for {}s after join", target, join_hold.as_secs());
+            held_boot_move = Some(target);
+        }
     }
 
     // Determine power mode from NVS (default: always-on)
@@ -126,24 +455,340 @@ fn main() {
             PowerMode::AlwaysOn
         }
     };
-    let power_mgr = PowerManager::new(power_mode);
+    // Drive the fitted switched outputs off before deep sleep so the servo
+    // rail or heater can't run while asleep
+    let sleep_hold = power::SleepHoldConfig {
+        enabled: device_id.get_sleep_hold().unwrap_or(true),
+        servo_rail: servo_power.is_some(),
+        heater: heater.is_some(),
+        relay: bypass_relay.is_some(),
+    };
+    let power_mgr = PowerManager::new(power_mode)
+        .with_child_timeout(device_id.get_child_timeout().ok().flatten())
+        .with_sleep_hold(power::sleep_hold_pins(&sleep_hold));
+    // The drivers above have set their idle levels; hand the pins back
+    power_mgr.release_sleep_holds();
+    if !matches!(power_mode, PowerMode::AlwaysOn) {
+        for gpio in power::sleep_hold_pins(&sleep_hold) {
+            if !power::holds_in_deep_sleep(gpio) {
+                info!("GPIO{} floats in deep sleep — its gate needs a pull-down", gpio);
+            }
+        }
+    }
 
     // Thread networking is managed by Matter SDK — only create a query handle
     let thread_mgr = ThreadManager::new_matter_managed();
 
     // Configure SED if battery-powered
-    if let Err(e) = power_mgr.configure_sed() {
+    if let Err(e) = power_mgr.configure_sed(&thread_mgr) {
         error!("Failed to configure SED mode: {:?}", e);
     }
 
+    // Non-linear louver: report the configured midpoint as 50%
+    let midpoint_angle = device_id.get_midpoint_angle().ok().flatten();
+    if let Some(mid) = midpoint_angle {
+        info!("Louver midpoint: 50% at {}°", mid);
+    }
+    matter::set_midpoint_angle(midpoint_angle);
+    // Coarser reported positions keep dashboards from chattering
+    let report_round_deg = device_id.get_report_round().ok().flatten().unwrap_or(0);
+    if report_round_deg > 1 {
+        info!("Reporting positions rounded to {}°", report_round_deg);
+    }
+    matter::set_report_round_deg(report_round_deg);
+    let percent_inverted = device_id.get_percent_inverted().unwrap_or(false);
+    if percent_inverted {
+        info!("Matter percentages inverted: 100% = open");
+    }
+    matter::set_percent_inverted(percent_inverted);
+    // Controllers that write back what we report would otherwise chase
+    // our own mid-move positions
+    let echo_window_ms = device_id
+        .get_echo_window()
+        .ok()
+        .flatten()
+        .unwrap_or(matter::DEFAULT_ECHO_WINDOW_MS);
+    matter::set_echo_window_ms(echo_window_ms);
+    matter::set_mode_table(device_id.get_mode_table().unwrap_or_default());
+
+    // The bridge only builds a Window Covering endpoint so far; a damper
+    // hint is for hubs that read it from the device
+    let device_type = device_id.get_device_type().unwrap_or_default();
+    info!(
+        "Device type hint: {} (Matter type 0x{:04x})",
+        device_type.as_str(),
+        matter::matter_device_type(device_type)
+    );
+
     // Initialize Matter (creates node + Window Covering endpoint).
     // Matter manages the OpenThread stack and its mainloop internally.
-    matter::init();
-    matter::start();
-    matter::log_pairing_info();
+    // If bring-up fails, keep running without it: every later Matter call
+    // is skipped rather than poking a half-initialized node.
+    let matter_unavailable = match matter::init_and_start(&setup_creds) {
+        Ok(()) => {
+            matter::log_pairing_info(&setup_creds);
+            false
+        }
+        Err(e) => {
+            error!("Matter unavailable ({:?}) — continuing without it", e);
+            true
+        }
+    };
+
+    // Quiet hours, sweeps and the install date need the wall clock. The
+    // sync callback is the only thing that marks it set; keep the client
+    // alive for the periodic resyncs.
+    let ntp_conf = SntpConf {
+        servers: [maintenance::NTP_SERVER],
+        ..Default::default()
+    };
+    let sntp = if matter_unavailable {
+        None
+    } else {
+        match EspSntp::new_with_callback(&ntp_conf, |since_epoch| {
+            maintenance::note_clock_sync(since_epoch.as_secs())
+        }) {
+            Ok(sntp) => Some(sntp),
+            Err(e) => {
+                warn!("SNTP unavailable ({:?}) — time-based automations stay paused", e);
+                None
+            }
+        }
+    };
+
+    // Without Matter nothing can ever commission the unit, so holding for
+    // it would leave the vent stuck detached; treat it as commissioned
+    let commissioned = matter_unavailable || matter::is_commissioned();
+    let shipping_hold = !servo::should_engage_servo(detach_until_commissioned, commissioned);
+    if shipping_hold {
+        info!("Not commissioned — servo left detached for mounting");
+        // Don't replay an interrupted move; it stays in the WAL for later
+        vent_state.set_target(vent_state.current_angle());
+        held_boot_move = None;
+    } else if detach_until_commissioned {
+        if let Err(e) = servo.set_angle(initial_angle) {
+            error!("Failed to set initial servo angle: {:?}", e);
+        }
+    }
+
+    // Publish the restored position right away so controllers don't show a
+    // stale value until the next move. Must come after start() or the
+    // attribute update is dropped.
+    let restored_angle = vent_state.current_angle();
+    info!(
+        "Syncing restored position to Matter: {}° ({} percent100ths)",
+        restored_angle,
+        matter::angle_to_matter_percent(restored_angle, midpoint_angle, percent_inverted)
+    );
+    // Vents moved together by a zone command spread their mid-move
+    // reports over the interval instead of hitting the fabric at once
+    let report_stagger = matter::ReportStagger::for_device(
+        device_id.eui64_bytes(),
+        device_id.get_report_stagger().ok().flatten().unwrap_or(0),
+    );
+    if let Some(st) = report_stagger {
+        info!("Matter reports staggered: every {} ms, offset {} ms", st.interval_ms, st.offset_ms);
+    }
+    let mut position_reporter = matter::PositionReporter::new(
+        device_id
+            .get_report_tolerance()
+            .ok()
+            .flatten()
+            .unwrap_or(DEFAULT_REPORT_TOLERANCE_DEG),
+    )
+    .with_stagger(report_stagger);
+    position_reporter.observe(restored_angle, true);
+    matter::report_position(restored_angle);
+    matter::report_operational_status(false);
 
     // Build and publish the shared AppState. The main loop and Matter
     // handlers both reach into it via state::with_app_state.
+    let access_mode = device_id.get_access_mode().unwrap_or_default();
+    if access_mode == AccessMode::ReadOnly {
+        info!("Access mode: read-only — remote target commands will be rejected");
+    }
+    let quiet_hours = device_id.get_quiet_hours().ok().flatten();
+    if let Some(q) = quiet_hours {
+        info!(
+            "Quiet hours: {:02}:{:02}–{:02}:{:02} (UTC{:+} min)",
+            q.start_min / 60,
+            q.start_min % 60,
+            q.end_min / 60,
+            q.end_min % 60,
+            q.utc_offset_min
+        );
+        // The window is in wall-clock time, which only SNTP provides
+        if sntp.is_none() {
+            warn!("Quiet hours set but no SNTP client — they won't take effect this boot");
+        }
+    }
+    let preferred_units = device_id.get_preferred_units().unwrap_or_default();
+    let range_policy = device_id.get_range_policy().unwrap_or_default();
+    let conflict_policy = device_id.get_conflict_policy().unwrap_or_default();
+    if range_policy == command::RangePolicy::Reject {
+        info!("Out-of-range targets will be rejected");
+    }
+    let enforce_min_fw = device_id.get_min_fw_check().unwrap_or(true);
+
+    // Optional differential-pressure sensor on I2C (XIAO D4 = SDA, D5 = SCL)
+    // for duct balancing, plus proportional pressure-hold automation.
+    // An AS5600 encoder on the horn can use the bus instead, and with
+    // neither, a fitted heater gets an SHT4x for its dew-point check.
+    let mut sensors = SensorScheduler::new();
+    let pressure_enabled = device_id.get_pressure_sensor_enabled().unwrap_or(false);
+    let encoder_cal = device_id.get_encoder_calibration().ok().flatten();
+    if pressure_enabled && encoder_cal.is_some() {
+        warn!("Pressure sensor and encoder both configured — I2C goes to the pressure sensor");
+    }
+    let climate_wanted = heater.is_some();
+    if climate_wanted && (pressure_enabled || encoder_cal.is_some()) {
+        warn!("Heater fitted but I2C is taken — no temperature/humidity sensor, the heater will stay off");
+    }
+    let mut encoder = None;
+    if pressure_enabled || encoder_cal.is_some() || climate_wanted {
+        let i2c_config = I2cConfig::new().baudrate(100.kHz().into());
+        let i2c = I2cDriver::new(
+            peripherals.i2c0,
+            peripherals.pins.gpio22,
+            peripherals.pins.gpio23,
+            &i2c_config,
+        );
+        match (i2c, encoder_cal) {
+            (Err(e), _) => error!("Failed to init I2C: {:?}", e),
+            (Ok(bus), Some(cal)) if !pressure_enabled => {
+                info!("AS5600 encoder: zero {}, travel {}–{}", cal.zero_offset, cal.cal_min, cal.cal_max);
+                encoder = Some(position_sensor::As5600::new(bus, cal));
+            }
+            (Ok(bus), _) if pressure_enabled => match pressure::PressureSensor::new(bus) {
+                Ok(sensor) => sensors.register(Box::new(sensor), PRESSURE_POLL_MS),
+                Err(e) => error!("Failed to init pressure sensor: {:?}", e),
+            },
+            (Ok(bus), _) => {
+                info!("SHT4x climate sensor for the heater");
+                climate::register(bus, &mut sensors);
+            }
+        }
+    }
+    // On-die temperature: no external part needed, and it catches a
+    // stalled servo cooking the board
+    match chip_temp::ChipTemp::new() {
+        Ok(sensor) => sensors.register(Box::new(sensor), chip_temp::CHIP_TEMP_POLL_MS),
+        Err(e) => warn!("Chip temperature sensor unavailable: {:?}", e),
+    }
+    // Battery or supercap voltage through a divider on D0, for the
+    // low-battery and harvested-energy move gates
+    if let Some(divider) = device_id.get_supply_divider().ok().flatten().filter(|&d| d > 0) {
+        match supply::SupplyVoltage::new(divider) {
+            Ok(sensor) => sensors.register(Box::new(sensor), supply::SUPPLY_POLL_MS),
+            Err(e) => error!("Failed to init supply voltage ADC: {:?}", e),
+        }
+    }
+    // Report the resting supply level rather than the sag under servo load
+    let supply_ema = device_id
+        .get_supply_ema()
+        .ok()
+        .flatten()
+        .map(|a| a.min(1000) as u16)
+        .unwrap_or(sensors::DEFAULT_SUPPLY_EMA_PERMILLE);
+    sensors.set_smoothing(sensors::SensorKind::SupplyVoltage, supply_ema);
+    let pressure_control = device_id
+        .get_pressure_control()
+        .ok()
+        .flatten()
+        .filter(|_| !crash_loop);
+    if let Some(c) = pressure_control {
+        info!("Pressure hold: target {} Pa, kp {:.3}°/Pa", c.target_pa, c.kp());
+    }
+    let mut last_pressure_control = Instant::now();
+
+    let servo_travel = servo::TravelCounter::new(device_id.get_servo_travel().unwrap_or(0));
+    info!("Servo travel: {}°", servo_travel.total_deg());
+    let decode_failures = command::DecodeFailures::new(device_id.get_decode_failures().unwrap_or(0));
+    // Counters that change on every command are batched to spare the flash
+    let metrics_flush_s = device_id
+        .get_metrics_flush()
+        .ok()
+        .flatten()
+        .unwrap_or(identity::DEFAULT_METRICS_FLUSH_S);
+    let metrics = identity::MetricsCache::new(
+        identity::Metric::ALL.map(|m| device_id.get_metric(m).unwrap_or(0)),
+        metrics_flush_s as u64 * 1000,
+    );
+    if decode_failures.total() > 0 {
+        warn!("Undecodable command payloads so far: {}", decode_failures.total());
+    }
+
+    let detach_honors_commands = device_id.get_detach_honors_commands().unwrap_or(true);
+    let runtime_base_s = device_id.get_runtime().unwrap_or(0);
+    let install_unix_s = device_id.get_install_date().ok().flatten();
+    info!(
+        "Runtime: {}h, installed: {}",
+        maintenance::runtime_hours(runtime_base_s),
+        install_unix_s.map_or("not yet".to_string(), |t| t.to_string())
+    );
+    let mut soft_limits = device_id.get_soft_limits().ok().flatten();
+    if let Some(limits) = soft_limits {
+        info!("Learned travel limits: {}°–{}°", limits.closed, limits.open);
+    }
+    let min_airflow_angle = device_id.get_min_airflow_angle().ok().flatten().unwrap_or(ANGLE_CLOSED);
+    if min_airflow_angle > ANGLE_CLOSED {
+        info!("Minimum airflow floor: {}°", min_airflow_angle);
+    }
+    let max_move = device_id.get_max_move().ok().flatten();
+    if let Some(cap) = max_move {
+        info!("Per-command travel cap: {}° ({:?})", cap.max_deg, cap.mode);
+    }
+    let min_move_mv = device_id.get_min_move_mv().ok().flatten().unwrap_or(power::DEFAULT_MIN_MOVE_MV);
+    if matches!(power_mode, PowerMode::Sed { .. }) && min_move_mv > 0 {
+        info!("Moves refused below {} mV battery", min_move_mv);
+    }
+
+    // Status beacon: off unless an interval is configured
+    let beacon_interval_s = if crash_loop {
+        0
+    } else {
+        device_id.get_beacon_interval().ok().flatten().unwrap_or(0)
+    };
+    if beacon_interval_s > 0 {
+        info!("Status beacon every {}s", beacon_interval_s);
+    }
+    let mut beacon_gate = beacon::BeaconGate::new(beacon_interval_s as u64 * 1000);
+    let mut beacon_sender = beacon::BeaconSender::new();
+    // Local CoAP resources for coordinators and diagnostics; needs the
+    // network stack Matter brings up
+    let mut coap_server = if !matter_unavailable && device_id.get_coap_enabled().unwrap_or(false) {
+        let max_in_flight = device_id.get_coap_max_in_flight().ok().flatten();
+        let max_observers = device_id.get_coap_max_observers().ok().flatten();
+        coap::CoapServer::bind(
+            max_in_flight.unwrap_or(coap::DEFAULT_MAX_IN_FLIGHT),
+            max_observers.unwrap_or(coap::DEFAULT_MAX_OBSERVERS),
+        )
+            .map_err(|e| warn!("CoAP server unavailable: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    let nudge_enabled = device_id.get_nudge_enabled().unwrap_or(false);
+    let dataset_pending = device_id.get_dataset_pending().unwrap_or(false);
+    if dataset_pending {
+        warn!("Pushed Thread dataset still unconfirmed");
+    }
+    // Sleepy vents push position and health to a registered coordinator
+    // once per poll period, riding the radio wake the poll already costs
+    let coordinator = match power_mode {
+        PowerMode::AlwaysOn => None,
+        _ => device_id.get_coordinator().ok().flatten(),
+    };
+    if let Some(addr) = coordinator {
+        info!("Reporting to {} after each poll", addr);
+    }
+    let push_mode = device_id.get_push_mode().unwrap_or_default();
+    let mut poll_report_gate = beacon::BeaconGate::new(if coordinator.is_some() {
+        power_mode.poll_period_ms() as u64
+    } else {
+        0
+    });
+
     let app_state = AppState {
         vent: vent_state,
         identity: device_id,
@@ -152,32 +797,1170 @@ fn main() {
         power_source: match power_mode {
             PowerMode::AlwaysOn => PowerSource::Usb,
             PowerMode::Sed { .. } => PowerSource::Battery,
+            PowerMode::Harvested { .. } => PowerSource::Harvested,
         },
         poll_period_ms: power_mode.poll_period_ms(),
         identify_mode: false,
         identify_restore_angle: None,
+        access_mode,
+        range_policy,
+        push_filter: PushFilter::new(push_mode),
+        conflict_policy,
+        coap_target_at: None,
+        matter_target_at: None,
+        enforce_min_fw,
+        sensors,
+        pressure_control,
+        servo_rail_powered: rail_on_at_boot,
+        servo_power_fault: servo_power_fault_at_boot,
+        servo_travel,
+        decode_failures,
+        detached: shipping_hold,
+        detach_honors_commands,
+        detached_until_commissioned: shipping_hold,
+        heater_on: false,
+        zone_all_closed: false,
+        relay_override: false,
+        relay_open: false,
+        nudge_enabled,
+        last_nudge_ms: None,
+        nudge_pending: false,
+        freq_detect_pending: false,
+        learn_limits_pending: false,
+        inversion_detect_pending: false,
+        selftest_pending: false,
+        last_selftest: None,
+        min_airflow_angle,
+        max_move,
+        min_move_mv,
+        battery_gate: power::BatteryMoveGate::default(),
+        command_dedup: command::DedupCache::new(),
+        soft_limits,
+        runtime_base_s,
+        install_unix_s,
+        midpoint_angle,
+        report_round_deg,
+        crash_loop,
+        breakin: None,
+        breakin_purpose: breakin::BreakInPurpose::Install,
+        sweep: None,
+        trim,
+        preferred_units,
+        matter_unavailable,
+        awaiting_commission: false,
+        quiet_hours,
+        deferred_target: None,
+        approach,
+        approach_final: None,
+        // A push interrupted by a reboot gets a fresh confirmation window
+        dataset_push_since: dataset_pending.then(Instant::now),
+        thread_scan: Vec::new(),
+        channel_energy: Vec::new(),
+        dataset_push_confirmed: false,
+        held_boot_move,
+        command_since_boot: false,
+        // Until the interrupted move is redone, the louver could be anywhere
+        last_move_outcome: if committed {
+            MoveOutcome::Completed
+        } else {
+            MoveOutcome::Recovered
+        },
+        has_position_feedback: encoder.is_some(),
+        metrics,
+        clock_unsynced: true,
     };
     state::init_app_state(app_state);
 
+    // Fresh install: one slow open/close so the installer sees the
+    // linkage work, then back home
+    state::with_app_state(|s| {
+        let enabled = s.identity.get_first_boot_cycle().unwrap_or(true) && !crash_loop;
+        let done = s.identity.get_first_boot_cycle_done().unwrap_or(false);
+        if !breakin::should_run_first_boot_cycle(enabled, first_boot, done) {
+            return;
+        }
+        match command::start_breakin(s, 1, true) {
+            Ok(()) => s.breakin_purpose = breakin::BreakInPurpose::FirstBootCheck,
+            Err(e) => info!("First-boot cycle skipped: {:?}", e),
+        }
+    });
+
+    // Fabric monitor: a commissioned vent that stays off the mesh for
+    // `fabric_timeout` first tries a Thread rejoin, then reboots.
+    let fabric_timeout = Duration::from_secs(
+        state::with_app_state(|s| s.identity.get_fabric_timeout().ok().flatten())
+            .flatten()
+            .unwrap_or(DEFAULT_FABRIC_TIMEOUT_S) as u64,
+    );
+    let mut last_fabric_check = Instant::now();
+
+    // Commissioning fail-safe: a vent nobody finishes setting up opens
+    // rather than leaving the room stuck closed.
+    let commission_timeout = Duration::from_secs(
+        state::with_app_state(|s| s.identity.get_commission_timeout().ok().flatten())
+            .flatten()
+            .unwrap_or(DEFAULT_COMMISSION_TIMEOUT_S) as u64,
+    );
+    let dataset_confirm_ms = state::with_app_state(|s| s.identity.get_dataset_confirm_timeout().ok().flatten())
+        .flatten()
+        .unwrap_or(thread::DEFAULT_DATASET_CONFIRM_S) as u64
+        * 1000;
+    let mut fabric_down_since: Option<Instant> = None;
+    let mut rejoin_attempted = false;
+
+    // Dataset failover: if the vent cannot attach within the retry window,
+    // alternate to the other stored dataset; promote it once attached.
+    let mut dataset_failover = DatasetFailover::new();
+    let mut detached_since: Option<Instant> = None;
+    let mut primary_dataset_saved = false;
+
+    // Optional channel scan once the vent has failed to join for a while,
+    // to show which networks (and channels) are actually in range
+    let scan_after = state::with_app_state(|s| s.identity.get_scan_after().ok().flatten())
+        .flatten()
+        .filter(|&s| s > 0)
+        .map(|s| Duration::from_secs(s as u64));
+    let mut unjoined_since: Option<Instant> = None;
+    let mut scan_started = false;
+    let mut scan_retry_at: Option<Instant> = None;
+
+    let move_grace_ms = state::with_app_state(|s| s.identity.get_move_grace().ok().flatten())
+        .flatten()
+        .unwrap_or(DEFAULT_MOVE_GRACE_MS);
+    // A slow step curve stretches moves beyond the linear estimate
+    let curve_slack_ms = motion::extra_travel_ms(&motion_curve, servo::STEP_DELAY_MS);
+    // Damping adds under one extra step delay per degree of the zone
+    let damping_slack_ms = damping_zone as u32 * servo::STEP_DELAY_MS;
+    // A slow break-in stroke takes longer than the nominal step delay
+    // allows; only break-in legs get this
+    let breakin_slack_ms = breakin::BREAKIN_STEP_DELAY_MS.saturating_sub(servo::STEP_DELAY_MS)
+        * (vent_protocol::ANGLE_OPEN - ANGLE_CLOSED) as u32;
+    // The start kick's hold comes before the first step, as does the rail
+    // settle when the rail was off
+    let kick_slack_ms = kick_config.capped_hold_ms();
+    let settle_slack_ms = if servo_power.is_some() { rail_settle_ms } else { 0 };
+    let mut move_timeout = MoveTimeout::new(
+        move_grace_ms + curve_slack_ms + damping_slack_ms + kick_slack_ms + settle_slack_ms,
+    );
+
+    // Harvested power: a move only starts once the supercap can finish it
+    let harvested = matches!(power_mode, PowerMode::Harvested { .. });
+    let brownout_mv = state::with_app_state(|s| s.identity.get_brownout_threshold().ok().flatten())
+        .flatten()
+        .unwrap_or(power::DEFAULT_BROWNOUT_MV);
+    let mut move_energy_cleared = false;
+    let mut energy_deferred = false;
+    let mut thermal_hold = false;
+
+    let mut last_runtime_persist = Instant::now();
+    let mut last_quiet_check = Instant::now();
+    let mut last_decode_persist = Instant::now();
+    let mut last_sweep_check = Instant::now();
+    let mut last_supply_check = Instant::now();
+    let mut last_clock_check = Instant::now();
+    let mut servo_power_fault = servo_power_fault_at_boot;
+
+    let mut progress_log = motion::ProgressLog::new(progress_log_deg);
+    let mut was_moving = false;
+    let mut was_detached = false;
+    let mut was_identifying = false;
+    let mut last_reassert_ms = 0u64;
+    let mut last_moving_ms = 0u64;
+    let mut pending_kick = None;
+
+    let mut boot_move_held = held_boot_move.is_some();
+    let mut joined_at: Option<Instant> = None;
+
     info!("Vent controller running. Waiting for Matter commands...");
 
     // Main loop: process servo steps and Thread events
     loop {
-        let is_moving = state::with_app_state(|s| s.vent.is_moving()).unwrap_or(false);
+        if let Some(server) = coap_server.as_mut() {
+            server.poll(|req| {
+                state::with_app_state(|s| coap::handle(s, req))
+                    .unwrap_or_else(|| coap::Response::new(coap::Code::ServiceUnavailable))
+            });
+        }
 
+        if last_fabric_check.elapsed() >= Duration::from_secs(FABRIC_CHECK_INTERVAL_S) {
+            last_fabric_check = Instant::now();
+            let commissioned = matter::is_commissioned();
+            let connected = state::with_app_state(|s| s.thread.is_connected()).unwrap_or(false);
+            state::with_app_state(|s| {
+                let boot_elapsed = s.start_time.elapsed();
+                // A unit held detached for shipping stays loose; the
+                // fail-safe mustn't drive it while it's being mounted
+                if matter::should_failsafe_open(connected, commissioned, boot_elapsed, commission_timeout)
+                    && !s.detached_until_commissioned
+                {
+                    if !s.awaiting_commission {
+                        warn!("Not commissioned after {}s — opening fail-safe", boot_elapsed.as_secs());
+                        // Only latch once the move is accepted, so a
+                        // rejected one is retried on the next check
+                        match command::apply_target(s, CommandSource::Failsafe, vent_protocol::ANGLE_OPEN) {
+                            Ok(_) => s.awaiting_commission = true,
+                            Err(reason) => warn!("Fail-safe open rejected ({}) — retrying", reason.as_str()),
+                        }
+                    }
+                } else if (connected || commissioned) && s.awaiting_commission {
+                    info!("Commissioning resumed — fail-safe cleared");
+                    s.awaiting_commission = false;
+                }
+            });
+            if commissioned {
+                state::with_app_state(|s| {
+                    if std::mem::take(&mut s.detached_until_commissioned) {
+                        info!("Commissioned — engaging servo at {}°", s.vent.current_angle());
+                        s.detached = false;
+                    }
+                    if let Err(e) = s.record_install_date() {
+                        warn!("Failed to record install date: {:?}", e);
+                    }
+                });
+            }
+
+            if connected {
+                if fabric_down_since.take().is_some() {
+                    let link = state::with_app_state(|s| s.thread.link_metrics());
+                    if let Some(link) = link {
+                        info!("Fabric connectivity restored ({})", link);
+                    }
+                }
+                rejoin_attempted = false;
+                detached_since = None;
+                if unjoined_since.take().is_some() {
+                    state::with_app_state(|s| {
+                        let addr = s.thread.get_ipv6_address().unwrap_or_default();
+                        info!("Joined Thread mesh as {} ({})", s.thread.role_str(), addr);
+                    });
+                }
+                scan_started = false;
+                scan_retry_at = None;
+                if dataset_failover.on_attached() {
+                    info!("Attached with secondary dataset — promoting to primary");
+                    state::with_app_state(|s| {
+                        if let Err(e) = s.identity.promote_secondary_dataset() {
+                            error!("Failed to promote secondary dataset: {:?}", e);
+                        }
+                    });
+                } else if !primary_dataset_saved {
+                    // Keep a copy of the known-good dataset to fall back to
+                    state::with_app_state(|s| {
+                        if let Ok(None) = s.identity.get_dataset(DatasetChoice::Primary) {
+                            if let Some(tlvs) = s.thread.active_dataset_tlvs() {
+                                let _ = s.identity.set_dataset(DatasetChoice::Primary, &tlvs);
+                            }
+                        }
+                    });
+                    primary_dataset_saved = true;
+                }
+            } else {
+                if commissioned && fabric_down_since.is_none() {
+                    warn!("Commissioned but disconnected from the mesh");
+                    fabric_down_since = Some(Instant::now());
+                }
+
+                let unjoined = unjoined_since.get_or_insert_with(Instant::now).elapsed();
+                if scan_after.is_some_and(|after| unjoined >= after)
+                    && !scan_started
+                    && scan_retry_at.is_none_or(|at| Instant::now() >= at)
+                {
+                    warn!("Not joined after {}s — scanning for Thread networks", unjoined.as_secs());
+                    // One scan per outage; a failed start (already logged)
+                    // is retried after a pause
+                    if state::with_app_state(|s| s.thread.active_scan().is_ok()).unwrap_or(false) {
+                        scan_started = true;
+                    } else {
+                        scan_retry_at = Some(Instant::now() + Duration::from_secs(SCAN_RETRY_S));
+                    }
+                }
+
+                let since = *detached_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= Duration::from_secs(DATASET_RETRY_WINDOW_S) {
+                    detached_since = Some(Instant::now());
+                    state::with_app_state(|s| {
+                        // A pushed dataset has its own revert path
+                        if s.dataset_push_since.is_some() {
+                            return;
+                        }
+                        let has_secondary = matches!(
+                            s.identity.get_dataset(DatasetChoice::Secondary),
+                            Ok(Some(_))
+                        );
+                        if let Some(choice) = dataset_failover.on_join_timeout(has_secondary) {
+                            warn!("Join retry window elapsed — switching to {:?} dataset", choice);
+                            if let Ok(Some(tlvs)) = s.identity.get_dataset(choice) {
+                                if let Err(e) = s.thread.apply_dataset_tlvs(&tlvs) {
+                                    error!("Failed to apply {:?} dataset: {:?}", choice, e);
+                                }
+                            }
+                        }
+                    });
+                }
+            }
+
+            if let Some(found) = state::with_app_state(|s| s.thread.scan_results()).flatten() {
+                info!("Thread scan found {} network(s)", found.len());
+                for n in &found {
+                    info!("  '{}' PAN 0x{:04x} channel {} RSSI {} dBm", n.name(), n.panid, n.channel, n.rssi);
+                }
+                state::with_app_state(|s| s.thread_scan = found);
+            }
+            if let Some(energy) = state::with_app_state(|s| s.thread.energy_results()).flatten() {
+                info!("Thread energy scan sampled {} channel(s)", energy.len());
+                for c in &energy {
+                    info!("  channel {} peak {} dBm", c.channel, c.max_rssi);
+                }
+                state::with_app_state(|s| s.channel_energy = energy);
+            }
+
+            // Keep a pushed dataset only once the coordinator has reached
+            // us over it; otherwise go back to the one known to work
+            let push = state::with_app_state(|s| {
+                let since = s.dataset_push_since?;
+                let elapsed_ms = since.elapsed().as_millis() as u64;
+                Some(DatasetApply::Pending.next(s.dataset_push_confirmed, elapsed_ms, dataset_confirm_ms))
+            })
+            .flatten();
+            match push {
+                Some(DatasetApply::Confirmed) => {
+                    info!("Pushed dataset confirmed — promoting to primary");
+                    state::with_app_state(|s| {
+                        if let Err(e) = s.identity.promote_secondary_dataset() {
+                            error!("Failed to promote pushed dataset: {:?}", e);
+                        }
+                        let _ = s.identity.set_dataset_pending(false);
+                        s.dataset_push_since = None;
+                        s.dataset_push_confirmed = false;
+                    });
+                }
+                Some(DatasetApply::RevertNeeded) => {
+                    error!("Pushed dataset not confirmed in time — reverting and rebooting");
+                    state::with_app_state(|s| {
+                        if let Ok(Some(tlvs)) = s.identity.get_dataset(DatasetChoice::Primary) {
+                            if let Err(e) = s.thread.apply_dataset_tlvs(&tlvs) {
+                                error!("Failed to restore primary dataset: {:?}", e);
+                            }
+                        }
+                        let _ = s.identity.set_dataset_pending(false);
+                        let _ = s.persist_runtime();
+                        let _ = s.flush_trim();
+                        s.flush_metrics();
+                    });
+                    unsafe { esp_idf_sys::esp_restart() };
+                }
+                _ => {}
+            }
+
+            let down_for = fabric_down_since.map(|t| t.elapsed()).unwrap_or_default();
+            if matter::should_rejoin(commissioned, connected, down_for, fabric_timeout) {
+                if !rejoin_attempted {
+                    warn!("Off the fabric for {}s — attempting rejoin", down_for.as_secs());
+                    state::with_app_state(|s| s.thread.rejoin());
+                    rejoin_attempted = true;
+                    // Give the rejoin a full window before escalating
+                    fabric_down_since = Some(Instant::now());
+                } else {
+                    error!("Rejoin did not restore connectivity — rebooting");
+                    state::with_app_state(|s| {
+                        let _ = s.persist_runtime();
+                        let _ = s.flush_trim();
+                        s.flush_metrics();
+                    });
+                    unsafe { esp_idf_sys::esp_restart() };
+                }
+            }
+        }
+
+        // Bank runtime hours so a power cut loses at most one interval
+        if last_runtime_persist.elapsed() >= Duration::from_secs(maintenance::RUNTIME_PERSIST_INTERVAL_S) {
+            last_runtime_persist = Instant::now();
+            state::with_app_state(|s| {
+                if let Err(e) = s.persist_runtime() {
+                    warn!("Failed to persist runtime: {:?}", e);
+                }
+            });
+        }
+
+        // Bank the decode-failure count if it moved
+        if last_decode_persist.elapsed() >= Duration::from_secs(command::DECODE_FAILURE_PERSIST_INTERVAL_S) {
+            last_decode_persist = Instant::now();
+            state::with_app_state(|s| {
+                if let Some(count) = s.decode_failures.take_unsaved() {
+                    if let Err(e) = s.identity.set_decode_failures(count) {
+                        warn!("Failed to persist decode failures: {:?}", e);
+                    }
+                }
+            });
+        }
+
+        // Let the coordinator's first command replace the boot move
+        if boot_move_held {
+            boot_move_held = state::with_app_state(|s| {
+                if joined_at.is_none() && s.thread.is_connected() {
+                    joined_at = Some(Instant::now());
+                }
+                let Some(target) = s.held_boot_move else {
+                    return false;
+                };
+                if s.breakin.is_some() {
+                    return true;
+                }
+                let since_join = joined_at.map(|t| t.elapsed());
+                match state::held_boot_move(s.command_since_boot, since_join, s.start_time.elapsed(), join_hold) {
+                    HeldBootMove::Wait => return true,
+                    HeldBootMove::Proceed => {
+                        info!("No command after join — completing boot move to {}°", target);
+                        s.vent.set_target(target);
+                    }
+                    HeldBootMove::Drop => info!("Boot move to {}° superseded by a command", target),
+                }
+                s.held_boot_move = None;
+                false
+            })
+            .unwrap_or(false);
+        }
+
+        // Release a target held back during quiet hours once they end
+        if quiet_hours.is_some()
+            && last_quiet_check.elapsed() >= Duration::from_secs(quiet::QUIET_CHECK_INTERVAL_S)
+        {
+            last_quiet_check = Instant::now();
+            state::with_app_state(command::apply_deferred_target);
+        }
+
+        // Time-based automations wait for a usable clock; say when that changes
+        if last_clock_check.elapsed() >= Duration::from_secs(maintenance::CLOCK_CHECK_INTERVAL_S) {
+            last_clock_check = Instant::now();
+            let unsynced = maintenance::usable_now().is_none();
+            state::with_app_state(|s| {
+                if unsynced != s.clock_unsynced {
+                    if unsynced {
+                        warn!("Wall clock unsynced or stale — pausing time-based automations");
+                    } else {
+                        info!("Wall clock synced — time-based automations resumed");
+                    }
+                    s.clock_unsynced = unsynced;
+                }
+            });
+        }
+
+        // A battery or harvested vent whose supply has sagged below the
+        // brownout level sleeps it off rather than browning out mid-move
+        if !matches!(power_mode, PowerMode::AlwaysOn)
+            && last_supply_check.elapsed() >= Duration::from_millis(supply::SUPPLY_POLL_MS)
+        {
+            last_supply_check = Instant::now();
+            let low_mv = state::with_app_state(|s| {
+                if s.vent.is_moving() {
+                    return None;
+                }
+                let mv = s.sensors.latest_raw(sensors::SensorKind::SupplyVoltage)?.value.max(0) as u32;
+                if !power::should_sleep_for_supply(mv, brownout_mv) {
+                    return None;
+                }
+                // Nothing survives deep sleep but NVS; wake is a fresh boot
+                if let Err(e) = s.identity.commit(s.vent.current_angle()) {
+                    error!("WAL commit failed: {:?}", e);
+                }
+                s.flush_metrics();
+                if let Err(e) = s.persist_runtime() {
+                    warn!("Failed to bank runtime: {:?}", e);
+                }
+                Some(mv)
+            })
+            .flatten();
+            if let Some(mv) = low_mv {
+                warn!(
+                    "Supply at {} mV, below {} mV — sleeping {}s",
+                    mv,
+                    brownout_mv,
+                    power::LOW_SUPPLY_SLEEP_S
+                );
+                power_mgr.enter_deep_sleep(Duration::from_secs(power::LOW_SUPPLY_SLEEP_S));
+            }
+        }
+
+        // Exercise a rarely-moved vent so the louver doesn't seize. Needs
+        // the wall clock, since the interval spans reboots.
+        if sweep_interval_s > 0
+            && !crash_loop
+            && last_sweep_check.elapsed() >= Duration::from_secs(maintenance::SWEEP_CHECK_INTERVAL_S)
+        {
+            last_sweep_check = Instant::now();
+            state::with_app_state(|s| {
+                let Some(now) = maintenance::usable_now() else {
+                    return;
+                };
+                let last = s.identity.get_last_sweep().ok().flatten();
+                if maintenance::maintenance_due(last, now, sweep_interval_s) {
+                    // Deferred sweeps are retried at the next check
+                    let _ = command::start_maintenance_sweep(s, now);
+                }
+            });
+        }
+
+        // Abort a move that overruns its deadline and checkpoint wherever
+        // the vent stopped, so the WAL doesn't replay it on the next boot.
+        state::with_app_state(|s| {
+            let now_ms = s.start_time.elapsed().as_millis() as u64;
+            // A move waiting on energy or heat hasn't started, so it isn't timed yet
+            let moving = s.vent.is_moving() && !energy_deferred && !thermal_hold;
+            let slack_ms = if s.breakin.is_some() || s.sweep.is_some() { breakin_slack_ms } else { 0 };
+            move_timeout.observe(now_ms, s.vent.current_angle(), s.vent.target_angle(), moving, slack_ms);
+            if move_timeout.expired(now_ms) {
+                let stuck_at = s.vent.current_angle();
+                error!("Move timed out at {}° (target {}°) — stopping", stuck_at, s.vent.target_angle());
+                s.vent.set_target(stuck_at);
+                s.approach_final = None;
+                if s.breakin.take().is_some() {
+                    warn!("Break-in aborted by move timeout");
+                }
+                if s.sweep.take().is_some() {
+                    warn!("Maintenance sweep aborted by move timeout");
+                }
+                if let Err(e) = s.identity.commit(stuck_at) {
+                    error!("WAL commit failed: {:?}", e);
+                }
+                s.last_move_outcome = MoveOutcome::TimedOut;
+                position_reporter.observe(stuck_at, true);
+                matter::report_position(stuck_at);
+                matter::report_operational_status(false);
+            }
+        });
+
+        // Sample any sensors that are due (no-op when none are registered)
+        state::with_app_state(|s| {
+            let now_ms = s.start_time.elapsed().as_millis() as u64;
+            s.sensors.poll(now_ms);
+        });
+
+        // Write batched counters once their flush interval is up
+        state::with_app_state(|s| {
+            let now_ms = s.start_time.elapsed().as_millis() as u64;
+            s.flush_metrics_if_due(now_ms);
+        });
+
+        // Bypass relay: open whenever the whole zone is closed
+        if let Some(r) = bypass_relay.as_mut() {
+            if last_relay_check.elapsed() >= Duration::from_secs(relay::RELAY_CHECK_INTERVAL_S) {
+                last_relay_check = Instant::now();
+                let open = state::with_app_state(|s| relay::relay_should_open(s.zone_all_closed, s.relay_override))
+                    .unwrap_or(true);
+                if open != r.is_open() {
+                    match r.set(open) {
+                        Ok(()) => {
+                            info!("Bypass relay {}", if open { "open" } else { "closed" });
+                            state::with_app_state(|s| s.relay_open = open);
+                        }
+                        Err(e) => error!("Failed to switch bypass relay: {:?}", e),
+                    }
+                }
+            }
+        }
+
+        // Heater: run while the board is close to the dew point
+        if let (Some(h), Some(margin_mc)) = (heater.as_mut(), heater_margin_mc) {
+            if last_heater_check.elapsed() >= Duration::from_secs(heater::HEATER_CHECK_INTERVAL_S) {
+                last_heater_check = Instant::now();
+                let climate = state::with_app_state(|s| {
+                    let temp = s.sensors.latest(sensors::SensorKind::Temperature)?;
+                    let rh = s.sensors.latest(sensors::SensorKind::Humidity)?;
+                    Some((temp.value, rh.value.clamp(0, 10_000) as u16))
+                })
+                .flatten();
+                // Without a climate reading there is no basis to heat
+                let run = climate
+                    .map(|(temp_mc, rh)| heater::heater_should_run(temp_mc, rh, margin_mc as i32))
+                    .unwrap_or(false);
+                if run != h.is_on() {
+                    match h.set(run) {
+                        Ok(()) => {
+                            info!("Heater {}", if run { "on" } else { "off" });
+                            state::with_app_state(|s| s.heater_on = run);
+                        }
+                        Err(e) => error!("Failed to switch heater: {:?}", e),
+                    }
+                }
+            }
+        }
+
+        // Pressure hold: nudge the vent toward the target static pressure
+        if let Some(control) = pressure_control.as_ref() {
+            if last_pressure_control.elapsed() >= Duration::from_secs(PRESSURE_CONTROL_INTERVAL_S) {
+                last_pressure_control = Instant::now();
+                state::with_app_state(|s| {
+                    if s.vent.is_moving() || s.identify_mode || s.detached {
+                        return;
+                    }
+                    if let Some(reading) = s.sensors.latest(sensors::SensorKind::Pressure) {
+                        let current = s.vent.current_angle();
+                        let error_pa = reading.value - control.target_pa;
+                        let next = pressure::p_control(error_pa, control.kp(), current);
+                        if next != current {
+                            let _ = command::apply_target(s, CommandSource::PressureControl, next);
+                        }
+                    }
+                });
+            }
+        }
+
+        // Periodic status beacon for passive monitoring
+        if beacon_gate.enabled() {
+            let beacon = state::with_app_state(|s| {
+                let now_ms = s.start_time.elapsed().as_millis() as u64;
+                if !beacon_gate.poll(now_ms) || !s.thread.is_connected() {
+                    return None;
+                }
+                let status = s.vent_status();
+                Some(StatusBeacon {
+                    angle: status.angle,
+                    state: status.state,
+                    moving: status.moving,
+                    battery_pct: None,
+                    rssi: s.thread.get_rssi(),
+                })
+            })
+            .flatten();
+            if let Some(beacon) = beacon {
+                beacon_sender.send(&beacon);
+            }
+        }
+        if let Some(coordinator) = coordinator {
+            let payload = state::with_app_state(|s| {
+                let now_ms = s.start_time.elapsed().as_millis() as u64;
+                if !poll_report_gate.poll(now_ms) || !s.thread.is_connected() {
+                    return None;
+                }
+                Some(beacon::build_poll_report(s.vent_status().angle, s.health_min(), s.position_report().confidence))
+            })
+            .flatten();
+            if let Some(payload) = payload {
+                beacon_sender.send_poll_report(coordinator, &payload);
+            }
+        }
+
+        let (is_moving, identifying) =
+            state::with_app_state(|s| (s.vent.is_moving(), s.identify_mode)).unwrap_or_default();
+
+        // Cut PWM on detach; re-assert the current angle on re-engage
+        let (detached, hold_angle) =
+            state::with_app_state(|s| (s.detached, s.vent.current_angle())).unwrap_or((false, ANGLE_CLOSED));
+        if detached != was_detached {
+            let result = if detached {
+                servo.disable()
+            } else if gentle_engage {
+                // The louver may have been set by hand while loose; pick
+                // up from there instead of snapping back
+                let has_feedback = encoder.is_some();
+                let sensed = encoder.as_mut().and_then(PositionFeedback::read_angle);
+                let engage_at = state::with_app_state(|s| {
+                    let committed = s.identity.checkpoint_angle().ok().flatten().unwrap_or(hold_angle);
+                    let angle = servo::reengage_angle(has_feedback, sensed, committed);
+                    if angle != s.vent.current_angle() {
+                        info!("Re-engaging at {}° (was {}°)", angle, s.vent.current_angle());
+                        s.vent.resync(angle);
+                        if !s.vent.is_moving() {
+                            if let Err(e) = s.identity.commit(angle) {
+                                error!("WAL commit failed: {:?}", e);
+                            }
+                        }
+                    }
+                    angle
+                })
+                .unwrap_or(hold_angle);
+                servo.set_angle(engage_at)
+            } else {
+                servo.set_angle(hold_angle)
+            };
+            if let Err(e) = result {
+                error!("Servo detach/engage failed: {:?}", e);
+            }
+            was_detached = detached;
+        }
+
+        // Creep correction: re-drive the held angle so a loaded analog
+        // servo doesn't sag off position. Logical state is untouched.
+        let now_ms = state::with_app_state(|s| s.start_time.elapsed().as_millis() as u64).unwrap_or_default();
+        let holding = !detached && servo_power.as_ref().is_none_or(|p| p.is_powered());
+        if is_moving || identifying || !holding {
+            last_reassert_ms = now_ms;
+        } else if servo::should_reassert(last_reassert_ms, now_ms, creep_interval_s as u64 * 1000) {
+            if let Err(e) = servo.reassert(hold_angle) {
+                warn!("Creep correction failed: {:?}", e);
+            }
+            last_reassert_ms = now_ms;
+        }
+
+        // Live trim adjustments take effect at once; NVS is written only
+        // once they stop coming
+        let (live_trim, trim_due) =
+            state::with_app_state(|s| (s.trim.get(), s.trim.take_due(now_ms))).unwrap_or((servo.trim(), None));
+        if live_trim != servo.trim() {
+            servo.set_trim(live_trim);
+            if holding && !is_moving {
+                if let Err(e) = servo.reassert(hold_angle) {
+                    warn!("Trim re-drive failed: {:?}", e);
+                }
+            }
+        }
+        if let Some(trim) = trim_due {
+            info!("Saving servo trim {:+}°", trim);
+            state::with_app_state(|s| {
+                if let Err(e) = s.identity.set_trim(trim) {
+                    warn!("Failed to persist trim: {:?}", e);
+                }
+            });
+        }
+
+        // Prove-alive nudge from a health query: wiggle the horn and come
+        // back, leaving the committed angle and the WAL alone
+        let nudge = !is_moving
+            && !identifying
+            && !detached
+            && state::with_app_state(|s| std::mem::take(&mut s.nudge_pending)).unwrap_or(false);
+        if nudge {
+            match rail_up_for(servo_power.as_mut(), rail_settle_ms, "Nudge") {
+                None => servo_power_fault = true,
+                Some(rail_was_off) => {
+                    let (lo, hi) = soft_limits
+                        .map(|l| (l.closed, l.open))
+                        .unwrap_or((ANGLE_CLOSED, vent_protocol::ANGLE_OPEN));
+                    for angle in servo::nudge_sequence(hold_angle, lo, hi) {
+                        if let Err(e) = servo.set_angle(angle) {
+                            warn!("Nudge failed: {:?}", e);
+                            break;
+                        }
+                        sleep(Duration::from_millis(servo::STEP_DELAY_MS as u64));
+                    }
+                    // Always land back on the held angle, even after a failed step
+                    let _ = servo.set_angle(hold_angle);
+                    if let Some(p) = servo_power.as_mut().filter(|_| rail_was_off) {
+                        let _ = p.set(false);
+                    }
+                    info!("Nudge complete at {}°", hold_angle);
+                }
+            }
+        }
+
+        // PWM frequency auto-detect for an unknown servo: needs the encoder
+        // to see which frequency gives clean full-range motion
+        let freq_detect = !is_moving
+            && !identifying
+            && !detached
+            && state::with_app_state(|s| std::mem::take(&mut s.freq_detect_pending)).unwrap_or(false);
+        if freq_detect {
+            let rail = match encoder {
+                Some(_) => rail_up_for(servo_power.as_mut(), rail_settle_ms, "Frequency detect"),
+                None => Some(false),
+            };
+            match (encoder.as_mut(), rail) {
+                (None, _) => warn!("Frequency detect needs position feedback — no encoder fitted"),
+                (Some(_), None) => servo_power_fault = true,
+                (Some(enc), Some(rail_was_off)) => {
+                    let (lo, hi) = soft_limits
+                        .map(|l| (l.closed, l.open))
+                        .unwrap_or((ANGLE_CLOSED, vent_protocol::ANGLE_OPEN));
+                    match servo::detect_frequency(&mut servo, enc, servo_freq_hz, lo, hi, hold_angle) {
+                        Ok(Some(hz)) => {
+                            info!("Servo frequency detected: {} Hz", hz);
+                            state::with_app_state(|s| {
+                                if let Err(e) = s.identity.set_servo_frequency(hz) {
+                                    warn!("Failed to save servo frequency: {:?}", e);
+                                }
+                            });
+                        }
+                        Ok(None) => warn!("No servo frequency tracked cleanly — keeping {} Hz", servo_freq_hz),
+                        Err(e) => error!("Frequency detect failed: {:?}", e),
+                    }
+                    if let Some(p) = servo_power.as_mut().filter(|_| rail_was_off) {
+                        let _ = p.set(false);
+                    }
+                }
+            }
+        }
+
+        // Soft-limit learning: the encoder stands in for a stall detector,
+        // and every step goes through a state machine so the vent's position
+        // stays known throughout
+        let learn_limits = !is_moving
+            && !identifying
+            && !detached
+            && state::with_app_state(|s| std::mem::take(&mut s.learn_limits_pending)).unwrap_or(false);
+        if learn_limits {
+            let rail = match encoder {
+                Some(_) => rail_up_for(servo_power.as_mut(), rail_settle_ms, "Limit learning"),
+                None => Some(false),
+            };
+            match (encoder.as_mut(), rail) {
+                (None, _) => warn!("Limit learning needs position feedback — no encoder fitted"),
+                (Some(_), None) => servo_power_fault = true,
+                (Some(enc), Some(rail_was_off)) => {
+                    let mut vent = VentStateMachine::new(hold_angle);
+                    let mut sense = servo::FeedbackStall::new(enc);
+                    match servo::learn_soft_limits(&mut servo, &mut vent, &mut sense) {
+                        Ok(limits) => {
+                            info!("Learned travel limits: {}°–{}°", limits.closed, limits.open);
+                            soft_limits = Some(limits);
+                            state::with_app_state(|s| {
+                                if let Err(e) = s.identity.set_soft_limits(&limits) {
+                                    warn!("Failed to save travel limits: {:?}", e);
+                                }
+                                s.soft_limits = Some(limits);
+                            });
+                        }
+                        Err(e) => error!("Limit learning failed: {:?}", e),
+                    }
+                    // Go back to where the vent was held, inside the new limits
+                    let back_to = soft_limits.map_or(hold_angle, |l| l.clamp(hold_angle));
+                    if let Err(e) = servo::step_to(&mut servo, &mut vent, back_to, servo::STEP_DELAY_MS as u64) {
+                        error!("Return after limit learning failed: {:?}", e);
+                    }
+                    let angle = vent.current_angle();
+                    state::with_app_state(|s| {
+                        s.vent.resync(angle);
+                        if !s.vent.is_moving() && angle != hold_angle {
+                            if let Err(e) = s.identity.commit(angle) {
+                                error!("WAL commit failed: {:?}", e);
+                            }
+                        }
+                    });
+                    if let Some(p) = servo_power.as_mut().filter(|_| rail_was_off) {
+                        let _ = p.set(false);
+                    }
+                }
+            }
+        }
+
+        // Inversion auto-detect during setup: a small probe move inside
+        // the travel limits, compared against the encoder
+        let inversion_detect = !is_moving
+            && !identifying
+            && !detached
+            && state::with_app_state(|s| std::mem::take(&mut s.inversion_detect_pending)).unwrap_or(false);
+        if inversion_detect {
+            let rail = match encoder {
+                Some(_) => rail_up_for(servo_power.as_mut(), rail_settle_ms, "Inversion detect"),
+                None => Some(false),
+            };
+            match (encoder.as_mut(), rail) {
+                (None, _) => warn!("Inversion detect needs position feedback — no encoder fitted"),
+                (Some(_), None) => servo_power_fault = true,
+                (Some(enc), Some(rail_was_off)) => {
+                    let (lo, hi) = soft_limits
+                        .map(|l| (l.closed, l.open))
+                        .unwrap_or((ANGLE_CLOSED, vent_protocol::ANGLE_OPEN));
+                    match servo::detect_inversion(&mut servo, enc, hold_angle, lo, hi) {
+                        Ok(Some(inverted)) if inverted != servo.inverted() => {
+                            info!("Servo inversion detected: now {}", if inverted { "inverted" } else { "normal" });
+                            servo.set_inverted(inverted);
+                            if let Err(e) = servo.set_angle(hold_angle) {
+                                error!("Failed to re-drive servo after inversion change: {:?}", e);
+                            }
+                            state::with_app_state(|s| {
+                                if let Err(e) = s.identity.set_servo_inverted(inverted) {
+                                    warn!("Failed to save servo inversion: {:?}", e);
+                                }
+                            });
+                        }
+                        Ok(Some(_)) => info!("Servo inversion setting confirmed"),
+                        Ok(None) => warn!("No clear motion sensed — servo inversion left unchanged"),
+                        Err(e) => error!("Inversion detect failed: {:?}", e),
+                    }
+                    if let Some(p) = servo_power.as_mut().filter(|_| rail_was_off) {
+                        let _ = p.set(false);
+                    }
+                }
+            }
+        }
+
+        if selftest_on_boot {
+            let due = state::with_app_state(|s| s.start_time.elapsed() >= Duration::from_secs(SELFTEST_BOOT_DELAY_S))
+                .unwrap_or(false);
+            if due {
+                selftest_on_boot = false;
+                info!("Running boot self-test");
+                state::with_app_state(command::request_self_test);
+            }
+        }
+
+        // Installer self-test: every check runs even if an earlier one failed
+        let self_test = !is_moving
+            && !identifying
+            && state::with_app_state(|s| std::mem::take(&mut s.selftest_pending)).unwrap_or(false);
+        if self_test {
+            let (nvs, radio) = state::with_app_state(|s| {
+                let nvs = match s.identity.nvs_scratch_check() {
+                    Ok(ok) => TestResult::from_check(ok),
+                    Err(e) => {
+                        warn!("Self-test: NVS scratch write failed: {:?}", e);
+                        TestResult::Fail
+                    }
+                };
+                (nvs, TestResult::from_check(s.thread.is_connected()))
+            })
+            .unwrap_or((TestResult::Skipped, TestResult::Skipped));
+            let rail = if detached {
+                None
+            } else {
+                rail_up_for(servo_power.as_mut(), rail_settle_ms, "Self-test")
+            };
+            let servo_result = if detached {
+                TestResult::Skipped
+            } else if let Some(rail_was_off) = rail {
+                let (lo, hi) = soft_limits
+                    .map(|l| (l.closed, l.open))
+                    .unwrap_or((ANGLE_CLOSED, vent_protocol::ANGLE_OPEN));
+                let feedback = encoder.as_mut().map(|e| e as &mut dyn PositionFeedback);
+                let result = selftest::servo_check(&mut servo, feedback, hold_angle, lo, hi);
+                if let Some(p) = servo_power.as_mut().filter(|_| rail_was_off) {
+                    let _ = p.set(false);
+                }
+                result
+            } else {
+                // A rail that won't power up fails the servo check outright
+                servo_power_fault = true;
+                TestResult::Fail
+            };
+            let report = SelfTestReport {
+                nvs,
+                servo: servo_result,
+                radio,
+            };
+            info!(
+                "Self-test {}: nvs {}, servo {}, radio {}",
+                report.overall().as_str(),
+                report.nvs.as_str(),
+                report.servo.as_str(),
+                report.radio.as_str()
+            );
+            state::with_app_state(|s| s.last_selftest = Some(report));
+        }
+
+        if let Some(b) = buzzer.as_mut() {
+            let event = if identifying && !was_identifying {
+                Some(BuzzerEvent::Identify)
+            } else if is_moving && !was_moving && !identifying {
+                Some(BuzzerEvent::MoveStart)
+            } else {
+                None
+            };
+            if let Some(event) = event {
+                if let Err(e) = b.play(event) {
+                    warn!("Buzzer failed: {:?}", e);
+                }
+            }
+        }
+        if is_moving && !was_moving {
+            state::with_app_state(|s| {
+                let from = s.vent.current_angle();
+                progress_log.start(s.start_time.elapsed().as_millis() as u64, from);
+                info!("Move started: {}° -> {}°", from, s.vent.target_angle());
+                if !identifying {
+                    let idle_ms = now_ms.saturating_sub(last_moving_ms);
+                    pending_kick = servo::kick_step(from, s.vent.target_angle(), idle_ms, &kick_config);
+                }
+            });
+        }
         if is_moving {
-            state::with_app_state(|s| s.vent.step());
+            last_moving_ms = now_ms;
+        } else {
+            pending_kick = None;
+        }
+        was_moving = is_moving;
+        was_identifying = identifying;
+
+        // Check the supercap once per move, before any energy is spent,
+        // on the latest sample rather than the lagging average. Without a
+        // voltage reading (no NVS `supply_div`) there is nothing to go on,
+        // so allow it.
+        if !is_moving {
+            move_energy_cleared = false;
+            energy_deferred = false;
+        } else if harvested && !move_energy_cleared {
+            let (supply_mv, degrees) = state::with_app_state(|s| {
+                let supply = s.sensors.latest_raw(sensors::SensorKind::SupplyVoltage);
+                (supply.map(|r| r.value.max(0) as u32), s.vent.current_angle().abs_diff(s.vent.target_angle()))
+            })
+            .unwrap_or((None, 0));
+            match supply_mv {
+                Some(mv) if !power::has_energy_budget(mv, degrees, brownout_mv) => {
+                    if !energy_deferred {
+                        info!("Deferring {}° move: supercap at {} mV", degrees, mv);
+                        energy_deferred = true;
+                    }
+                }
+                _ => {
+                    move_energy_cleared = true;
+                    energy_deferred = false;
+                }
+            }
+        }
+        let energy_ok = !harvested || move_energy_cleared;
+
+        // Hold moves while the chip is too hot; driving the servo adds heat
+        let too_hot = is_moving
+            && state::with_app_state(|s| s.sensors.latest(sensors::SensorKind::ChipTemperature))
+                .flatten()
+                .is_some_and(|r| chip_temp::over_temp(r.value, chip_temp::CHIP_TEMP_LIMIT_C));
+        if too_hot != thermal_hold {
+            if too_hot {
+                warn!("Chip over {}°C — holding servo moves", chip_temp::CHIP_TEMP_LIMIT_C);
+            } else if is_moving {
+                info!("Chip cooled — resuming move");
+            }
+            thermal_hold = too_hot;
+        }
+
+        // Sequence the servo rail: settle after power-on, hold after the
+        // move so the servo finishes seating, then cut it.
+        let may_step = match servo_power.as_mut() {
+            Some(p) => {
+                let now_ms = state::with_app_state(|s| s.start_time.elapsed().as_millis() as u64)
+                    .unwrap_or_default();
+                let rail_state = rail.tick(now_ms, is_moving && energy_ok && !thermal_hold, p.power_good());
+                if rail_state.powered != p.is_powered() {
+                    match p.set(rail_state.powered) {
+                        Ok(()) => {
+                            state::with_app_state(|s| s.servo_rail_powered = rail_state.powered);
+                        }
+                        Err(e) => error!("Servo rail switch failed: {:?}", e),
+                    }
+                }
+                if rail_state.fault {
+                    // Stepping an unpowered servo would only desync the
+                    // state machine from the horn
+                    state::with_app_state(|s| {
+                        let at = s.vent.current_angle();
+                        error!(
+                            "Servo rail not power-good after {} ms — abandoning move to {}°",
+                            rail_settle_ms,
+                            s.vent.target_angle()
+                        );
+                        s.vent.set_target(at);
+                        s.approach_final = None;
+                        if s.breakin.take().is_some() {
+                            warn!("Break-in aborted by servo power fault");
+                        }
+                        if s.sweep.take().is_some() {
+                            warn!("Maintenance sweep aborted by servo power fault");
+                        }
+                        if let Err(e) = s.identity.commit(at) {
+                            error!("WAL commit failed: {:?}", e);
+                        }
+                        s.last_move_outcome = MoveOutcome::RailFault;
+                        s.servo_power_fault = true;
+                    });
+                    matter::report_operational_status(false);
+                    servo_power_fault = true;
+                } else if rail_state.may_step && servo_power_fault {
+                    info!("Servo rail power-good again");
+                    state::with_app_state(|s| s.servo_power_fault = false);
+                    servo_power_fault = false;
+                }
+                rail_state.may_step
+            }
+            None => true,
+        };
+
+        if is_moving && !energy_ok {
+            // Let the harvester top the supercap up
+            sleep(Duration::from_millis(power::ENERGY_RECHECK_MS));
+        } else if thermal_hold {
+            sleep(Duration::from_millis(chip_temp::THERMAL_RECHECK_MS));
+        } else if is_moving && !may_step {
+            // Rail powering up — wait for it to settle
+            sleep(Duration::from_millis(10));
+        } else if is_moving {
+            // Break static friction with a brief jump ahead before the
+            // first step of a move from rest
+            if let Some(kick) = pending_kick.take() {
+                if let Err(e) = servo.set_angle(kick.angle) {
+                    warn!("Start kick failed: {:?}", e);
+                }
+                sleep(Duration::from_millis(kick.hold_ms as u64));
+            }
+            // Hold the state lock only for the step itself; servo I/O,
+            // Matter reports and the step delay all run unlocked
+            let (current_angle, step_target, progress, breaking_in) = state::with_app_state(|s| {
+                // Break-in and sweeps are always stepped; they exist to move slowly
+                let slow = s.breakin.is_some() || s.sweep.is_some();
+                let mode = if slow {
+                    motion::Stepping::Stepped
+                } else {
+                    stepping
+                };
+                s.servo_travel.add(s.vent.advance(mode) as u32);
+                (
+                    s.vent.current_angle(),
+                    s.vent.target_angle(),
+                    s.vent.progress_permille(),
+                    slow,
+                )
+            })
+            .unwrap_or((ANGLE_CLOSED, ANGLE_CLOSED, 1000, false));
 
-            let current_angle = state::with_app_state(|s| s.vent.current_angle()).unwrap_or(ANGLE_CLOSED);
             if let Err(e) = servo.set_angle(current_angle) {
                 error!("Servo step failed: {:?}", e);
             }
-            sleep(Duration::from_millis(servo::STEP_DELAY_MS as u64));
+            let now_ms = state::with_app_state(|s| s.start_time.elapsed().as_millis() as u64).unwrap_or_default();
+            if progress_log.observe(now_ms, current_angle) {
+                info!("Moving: {}° -> {}° ({}‰)", current_angle, step_target, progress);
+            }
+            if !identifying && position_reporter.observe_at(current_angle, false, now_ms) {
+                matter::report_position(current_angle);
+            }
+            let delay = if stepping == motion::Stepping::Snap && !breaking_in {
+                // One jump: give the servo time to get there
+                motion::SNAP_SETTLE_MS
+            } else {
+                let speed = motion::interp_speed(progress, &motion_curve);
+                let delay = motion::step_delay_ms(servo::STEP_DELAY_MS, speed);
+                let delay = motion::damped_delay_ms(delay, current_angle.abs_diff(step_target), damping_zone);
+                if breaking_in {
+                    delay.max(breakin::BREAKIN_STEP_DELAY_MS)
+                } else {
+                    delay
+                }
+            };
+            // Sleep in slices so a new target cuts a slow step short
+            motion::sleep_until_retarget(
+                delay,
+                move_yield_ms,
+                |ms| sleep(Duration::from_millis(ms as u64)),
+                || state::with_app_state(|s| s.vent.target_angle() != step_target).unwrap_or(false),
+            );
 
             // Commit when movement completes: checkpoint angle + set WAL flag
             let still_moving = state::with_app_state(|s| s.vent.is_moving()).unwrap_or(false);
             if !still_moving {
-                state::with_app_state(|s| {
+                // Seat a closing louver against its stop before committing
+                let (final_angle, closed_angle, identifying, overshooting) = state::with_app_state(|s| {
+                    // Where a close command actually leaves the vent
+                    let closed = command::limit_target(ANGLE_CLOSED, s.soft_limits, s.min_airflow_angle, false);
+                    (s.vent.current_angle(), closed, s.identify_mode, s.approach_final.is_some())
+                })
+                .unwrap_or((ANGLE_CLOSED, ANGLE_CLOSED, false, false));
+                if final_angle == closed_angle && !identifying && !overshooting {
+                    for step in servo::seat_sequence(closed_angle, &seat_config) {
+                        if let Err(e) = servo.set_angle(step.angle) {
+                            error!("Servo seat step failed: {:?}", e);
+                        }
+                        sleep(Duration::from_millis(step.hold_ms as u64));
+                    }
+                }
+
+                // Closed loop: confirm the horn really reached the target
+                let mut settled = true;
+                if let Some(enc) = encoder.as_mut().filter(|_| !identifying && !overshooting) {
+                    if !servo::wait_settled(enc, final_angle, servo::SETTLE_TOL_DEG) {
+                        let sensed = servo::PositionFeedback::read_angle(enc);
+                        warn!("Encoder: horn not settled at {}° (sensed {:?})", final_angle, sensed);
+                        settled = false;
+                    }
+                }
+
+                let committed_angle = state::with_app_state(|s| {
                     let final_angle = s.vent.current_angle();
 
                     // During identify, wiggle back and forth instead of committing
@@ -191,22 +1974,61 @@ fn main() {
                             };
                             s.vent.set_target(next);
                         }
-                        return;
+                        return None;
+                    }
+
+                    // Turn back from the overshoot for the final approach
+                    if let Some(target) = s.approach_final.take() {
+                        s.vent.set_target(target);
+                        return None;
                     }
 
                     if let Err(e) = s.identity.commit(final_angle) {
                         error!("WAL commit failed: {:?}", e);
                     }
+                    s.metrics.incr(identity::Metric::Moves);
+                    s.last_move_outcome = if settled {
+                        MoveOutcome::Completed
+                    } else {
+                        MoveOutcome::Unsettled
+                    };
+                    if s.servo_travel.should_persist() {
+                        match s.identity.set_servo_travel(s.servo_travel.total_deg()) {
+                            Ok(()) => s.servo_travel.mark_persisted(),
+                            Err(e) => warn!("Failed to persist servo travel: {:?}", e),
+                        }
+                    }
                     info!(
                         "Vent reached target: {}° ({}) — committed",
                         final_angle,
                         s.vent.state().as_str()
                     );
+                    s.advance_breakin();
+                    command::advance_sweep(s);
+
+                    // Tell the coordinator straight away rather than at
+                    // the next poll, unless the push mode filters it out
+                    let push = coordinator.is_some() && s.thread.is_connected() && s.push_filter.on_commit(final_angle);
+                    let report = push.then(|| beacon::build_poll_report(s.vent_status().angle, s.health_min(), s.position_report().confidence));
+
+                    Some((final_angle, report))
+                })
+                .flatten();
 
-                    // Report final position to Matter fabric
+                if let Some((final_angle, report)) = committed_angle {
+                    if let (Some(coordinator), Some(report)) = (coordinator, report) {
+                        beacon_sender.send_poll_report(coordinator, &report);
+                    }
+                    // Report final position to Matter fabric, outside the lock
+                    position_reporter.observe(final_angle, true);
                     matter::report_position(final_angle);
                     matter::report_operational_status(false);
-                });
+                    if let Some(b) = buzzer.as_mut() {
+                        if let Err(e) = b.play(BuzzerEvent::MoveEnd) {
+                            warn!("Buzzer failed: {:?}", e);
+                        }
+                    }
+                }
             }
         } else {
             // Idle — sleep briefly to yield CPU
@@ -0,0 +1,226 @@
+use crate::identity::build_info;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use vent_protocol::MaintenanceInfo;
+
+/// How often accumulated runtime is banked to NVS. A crash or power cut
+/// loses at most this much; clean reboots bank the remainder.
+pub const RUNTIME_PERSIST_INTERVAL_S: u64 = 3600;
+
+/// Full open/close cycles in a scheduled exercise sweep.
+pub const SWEEP_CYCLES: u8 = 1;
+/// How often the main loop checks whether a sweep is due.
+pub const SWEEP_CHECK_INTERVAL_S: u64 = 300;
+
+/// Wall-clock readings before this (2024-01-01) mean SNTP hasn't set the
+/// clock yet; the RTC starts from the epoch at boot.
+const MIN_VALID_UNIX_S: u64 = 1_704_067_200;
+
+/// Total powered-on seconds: the total stored at boot plus this boot's
+/// uptime. The stored base never changes during a boot, so banking the
+/// total repeatedly never double-counts.
+pub fn runtime_total_s(stored_s: u32, uptime_s: u64) -> u32 {
+    stored_s.saturating_add(uptime_s.min(u32::MAX as u64) as u32)
+}
+
+/// Whole runtime hours for reporting.
+pub fn runtime_hours(total_s: u32) -> u32 {
+    total_s / 3600
+}
+
+/// Whether `now_unix_s` looks like a real date rather than time since boot.
+/// The build timestamp, when known, is a tighter lower bound.
+pub fn clock_is_set(now_unix_s: u64, build_timestamp: u64) -> bool {
+    now_unix_s >= MIN_VALID_UNIX_S.max(build_timestamp)
+}
+
+/// Current wall-clock time in Unix seconds (0 before SNTP sync).
+pub fn unix_now_s() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// How often the main loop checks whether the clock is usable.
+pub const CLOCK_CHECK_INTERVAL_S: u64 = 60;
+/// NTP server queried over the Thread border router's NAT64.
+pub const NTP_SERVER: &str = "pool.ntp.org";
+
+/// Remembers when SNTP last set the wall clock. Fed from the SNTP sync
+/// callback, so only a real sync counts, however small the correction.
+#[derive(Debug, Default)]
+pub struct ClockWatch {
+    last_sync_s: Option<u64>,
+}
+
+impl ClockWatch {
+    pub const fn new() -> Self {
+        Self { last_sync_s: None }
+    }
+
+    /// SNTP has just set the clock to `unix_s`.
+    pub fn on_sync(&mut self, unix_s: u64) {
+        self.last_sync_s = Some(unix_s);
+    }
+
+    /// When the clock was last set, if ever.
+    pub fn last_sync(&self) -> Option<u64> {
+        self.last_sync_s
+    }
+}
+
+/// Whether time-of-day automations may use the clock: it has been set,
+/// and not more than `max_age_s` ago (0 = no age limit). A sync stamp
+/// from the future means the clock stepped back, which also counts as
+/// unusable until the watch sees it set again.
+pub fn clock_usable(synced: bool, last_sync_ts: Option<u64>, now: u64, max_age_s: u32) -> bool {
+    synced
+        && last_sync_ts.is_some_and(|last| {
+            now >= last && (max_age_s == 0 || now - last <= max_age_s as u64)
+        })
+}
+
+static CLOCK_WATCH: Mutex<ClockWatch> = Mutex::new(ClockWatch::new());
+static CLOCK_MAX_AGE_S: AtomicU32 = AtomicU32::new(0);
+
+/// Record an SNTP sync. Called from the SNTP client's callback.
+pub fn note_clock_sync(unix_s: u64) {
+    CLOCK_WATCH.lock().unwrap_or_else(|e| e.into_inner()).on_sync(unix_s);
+}
+
+/// Stop trusting a clock that hasn't been set for `secs` (0 = never).
+pub fn set_clock_max_age_s(secs: u32) {
+    CLOCK_MAX_AGE_S.store(secs, Ordering::Relaxed);
+}
+
+/// Wall-clock time for schedules, quiet hours and sweeps, or None while
+/// the clock is unsynced or stale.
+pub fn usable_now() -> Option<u64> {
+    let now = unix_now_s();
+    let synced = clock_is_set(now, build_info().build_timestamp);
+    let last_sync = CLOCK_WATCH.lock().unwrap_or_else(|e| e.into_inner()).last_sync();
+    clock_usable(synced, last_sync, now, CLOCK_MAX_AGE_S.load(Ordering::Relaxed)).then_some(now)
+}
+
+/// Whether a rarely-moved vent is due its exercise sweep: `interval_s`
+/// has passed since the last one, or it has never had one. An interval of
+/// 0 disables sweeps.
+pub fn maintenance_due(last_ts: Option<u64>, now: u64, interval_s: u32) -> bool {
+    if interval_s == 0 {
+        return false;
+    }
+    last_ts.is_none_or(|last| now.saturating_sub(last) >= interval_s as u64)
+}
+
+/// Maintenance report from the stored values and this boot's uptime.
+pub fn maintenance_info(install_unix_s: Option<u64>, stored_runtime_s: u32, uptime_s: u64) -> MaintenanceInfo {
+    MaintenanceInfo {
+        install_unix_s,
+        runtime_hours: runtime_hours(runtime_total_s(stored_runtime_s, uptime_s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breakin::BreakIn;
+
+    const WEEK_S: u32 = 7 * 24 * 3600;
+
+    #[test]
+    fn test_sweep_due_at_interval_boundary() {
+        let last = 1_750_000_000;
+        assert!(!maintenance_due(Some(last), last + WEEK_S as u64 - 1, WEEK_S));
+        assert!(maintenance_due(Some(last), last + WEEK_S as u64, WEEK_S));
+        assert!(maintenance_due(None, last, WEEK_S));
+    }
+
+    #[test]
+    fn test_sweep_disabled_or_clock_behind() {
+        assert!(!maintenance_due(None, 1_750_000_000, 0));
+        // A stamp from the future (clock stepped back) isn't due yet
+        assert!(!maintenance_due(Some(1_750_000_000), 1_700_000_000, WEEK_S));
+    }
+
+    #[test]
+    fn test_sweep_returns_to_prior_angle() {
+        let mut run = BreakIn::new(SWEEP_CYCLES, 90, 180, 117);
+        let mut last = None;
+        while let Some(target) = run.next_target() {
+            last = Some(target);
+        }
+        assert_eq!(last, Some(117));
+    }
+
+    #[test]
+    fn test_runtime_accumulates_across_reboots() {
+        // First boot runs 5 h and banks the total before a clean reboot
+        let stored = runtime_total_s(0, 18_000);
+        assert_eq!(runtime_hours(stored), 5);
+        // Second boot banks hourly from the same base; each write replaces
+        // the last rather than adding to it
+        let base = stored;
+        assert_eq!(runtime_total_s(base, 3600), 21_600);
+        let stored = runtime_total_s(base, 9000);
+        assert_eq!(runtime_hours(stored), 7);
+        // Third boot resumes from what was banked
+        assert_eq!(runtime_total_s(stored, 0), 27_000);
+        assert_eq!(runtime_total_s(stored, 3600), 30_600);
+    }
+
+    #[test]
+    fn test_runtime_saturates() {
+        assert_eq!(runtime_total_s(u32::MAX - 10, 100), u32::MAX);
+        assert_eq!(runtime_total_s(0, u64::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn test_clock_is_set() {
+        // Unsynced RTC counts from the epoch
+        assert!(!clock_is_set(42, 0));
+        assert!(clock_is_set(1_760_000_000, 0));
+        // Earlier than the firmware was built can't be right
+        assert!(!clock_is_set(1_750_000_000, 1_760_000_000));
+        assert!(clock_is_set(1_760_000_001, 1_760_000_000));
+    }
+
+    #[test]
+    fn test_clock_usable_synced() {
+        let now = 1_760_000_000;
+        assert!(clock_usable(true, Some(now - 3600), now, 0));
+        assert!(clock_usable(true, Some(now - 3600), now, 86_400));
+    }
+
+    #[test]
+    fn test_clock_unusable_unsynced_or_stale() {
+        let now = 1_760_000_000;
+        assert!(!clock_usable(false, None, 42, 0));
+        assert!(!clock_usable(false, Some(now - 60), now, 0));
+        assert!(!clock_usable(true, None, now, 0));
+        // Stale past the age limit, or the clock went backwards
+        assert!(!clock_usable(true, Some(now - 86_401), now, 86_400));
+        assert!(!clock_usable(true, Some(now + 60), now, 0));
+    }
+
+    #[test]
+    fn test_clock_watch_records_each_sync() {
+        let mut watch = ClockWatch::new();
+        assert_eq!(watch.last_sync(), None);
+        watch.on_sync(1_760_000_000);
+        assert_eq!(watch.last_sync(), Some(1_760_000_000));
+        // A resync that only nudges the clock by a fraction still counts
+        watch.on_sync(1_760_003_600);
+        assert_eq!(watch.last_sync(), Some(1_760_003_600));
+        assert!(clock_usable(true, watch.last_sync(), 1_760_003_660, 86_400));
+    }
+
+    #[test]
+    fn test_maintenance_info() {
+        let info = maintenance_info(Some(1_760_000_000), 7200, 1800);
+        assert_eq!(info.install_unix_s, Some(1_760_000_000));
+        assert_eq!(info.runtime_hours, 2);
+        assert_eq!(maintenance_info(None, 0, 0).install_unix_s, None);
+    }
+}
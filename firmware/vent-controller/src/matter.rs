@@ -1,21 +1,33 @@
-use log::{error, info, warn};
+use crate::command::CommandSource;
+use crate::presets::{angle_to_mode, mode_to_angle, AirflowMode, ModeTable};
+use log::{error, info};
 use std::ffi::c_void;
-use vent_protocol::{ANGLE_CLOSED, ANGLE_OPEN};
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::Mutex;
+use vent_protocol::{
+    angle_to_fraction_with_mid, fraction_to_angle_with_mid, round_to, DeviceType, ANGLE_CLOSED, ANGLE_OPEN,
+};
 
 // --- FFI declarations matching matter_bridge.h ---
 
 type PositionCb = unsafe extern "C" fn(percent100ths: u16, ctx: *mut c_void);
 type IdentifyCb = unsafe extern "C" fn(duration_s: u16, ctx: *mut c_void);
+type ModeCb = unsafe extern "C" fn(mode: u8, ctx: *mut c_void);
 
 extern "C" {
     fn matter_bridge_init(
         position_cb: PositionCb,
         identify_cb: IdentifyCb,
+        mode_cb: ModeCb,
+        discriminator: u16,
+        setup_passcode: u32,
         ctx: *mut c_void,
     ) -> i32;
     fn matter_bridge_start() -> i32;
     fn matter_bridge_update_position(percent100ths: u16);
     fn matter_bridge_update_operational_status(status: u8);
+    fn matter_bridge_update_mode(mode: u8);
     fn matter_bridge_is_commissioned() -> bool;
     fn matter_bridge_get_pairing_code(buf: *mut u8, len: usize) -> i32;
     fn matter_bridge_get_qr_payload(buf: *mut u8, len: usize) -> i32;
@@ -47,20 +59,217 @@ pub fn percent100ths_to_angle(pct: u16) -> u8 {
     ANGLE_OPEN - from_open as u8
 }
 
+/// Configured louver midpoint (the angle reported as 50%), or 0 for the
+/// linear center. Set once at boot; read from the Matter callbacks.
+static MIDPOINT_ANGLE: AtomicU8 = AtomicU8::new(0);
+
+/// Remap 50% to `mid` for a non-linear louver, or None for linear.
+pub fn set_midpoint_angle(mid: Option<u8>) {
+    MIDPOINT_ANGLE.store(mid.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn midpoint_angle() -> Option<u8> {
+    match MIDPOINT_ANGLE.load(Ordering::Relaxed) {
+        0 => None,
+        mid => Some(mid),
+    }
+}
+
+/// Step reported positions are rounded to; 0 or 1 reports every degree.
+static REPORT_ROUND_DEG: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_report_round_deg(deg: u8) {
+    REPORT_ROUND_DEG.store(deg, Ordering::Relaxed);
+}
+
+/// Whether percentages exchanged with Matter count openness (0% = closed)
+/// instead of the spec's closure, for integrations that assume that.
+static PERCENT_INVERTED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_percent_inverted(inverted: bool) {
+    PERCENT_INVERTED.store(inverted, Ordering::Relaxed);
+}
+
+/// Default window after a report in which a command for the same
+/// position is taken as the controller echoing it back.
+pub const DEFAULT_ECHO_WINDOW_MS: u32 = 2000;
+
+static ECHO_WINDOW_MS: AtomicU32 = AtomicU32::new(DEFAULT_ECHO_WINDOW_MS);
+
+/// Last percent100ths sent to Matter and the uptime (ms) it went out.
+static LAST_SELF_REPORT: Mutex<Option<(u16, u64)>> = Mutex::new(None);
+
+/// Set the echo suppression window; 0 disables suppression.
+pub fn set_echo_window_ms(ms: u32) {
+    ECHO_WINDOW_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Whether an incoming position is our own report coming back: the same
+/// percent100ths we last sent, within `window_ms` of sending it. A
+/// different position, or the same one after the window, is a genuine
+/// command. A zero window never suppresses.
+pub fn is_echo(incoming: u16, last_reported: Option<u16>, last_report_ms: u64, now_ms: u64, window_ms: u32) -> bool {
+    window_ms > 0
+        && last_reported == Some(incoming)
+        && now_ms.saturating_sub(last_report_ms) < window_ms as u64
+}
+
+/// Angles behind the airflow mode-select list. Set once at boot.
+static MODE_TABLE: Mutex<ModeTable> = Mutex::new(ModeTable::DEFAULT);
+
+/// Last mode sent to Matter, or `u8::MAX` before the first report.
+static LAST_REPORTED_MODE: AtomicU8 = AtomicU8::new(u8::MAX);
+
+pub fn set_mode_table(table: ModeTable) {
+    *MODE_TABLE.lock().unwrap_or_else(|e| e.into_inner()) = table;
+}
+
+fn uptime_ms() -> u64 {
+    (unsafe { esp_idf_sys::esp_timer_get_time() } / 1000) as u64
+}
+
+/// Percent100ths to send Matter for `angle`, flipped when `inverted`.
+pub fn angle_to_matter_percent(angle: u8, mid: Option<u8>, inverted: bool) -> u16 {
+    let pct = angle_to_percent100ths_with_mid(angle, mid);
+    if inverted {
+        10000 - pct
+    } else {
+        pct
+    }
+}
+
+/// Angle for a percent100ths received from Matter, flipped when `inverted`.
+pub fn matter_percent_to_angle(pct: u16, mid: Option<u8>, inverted: bool) -> u8 {
+    let pct = pct.min(10000);
+    percent100ths_to_angle_with_mid(if inverted { 10000 - pct } else { pct }, mid)
+}
+
+/// As `angle_to_percent100ths`, piecewise through `mid` when set.
+pub fn angle_to_percent100ths_with_mid(angle: u8, mid: Option<u8>) -> u16 {
+    match mid {
+        // Matter counts closure, so invert the open fraction
+        Some(mid) => (10000 - angle_to_fraction_with_mid(angle, 10000, mid)) as u16,
+        None => angle_to_percent100ths(angle),
+    }
+}
+
+/// As `percent100ths_to_angle`, piecewise through `mid` when set.
+pub fn percent100ths_to_angle_with_mid(pct: u16, mid: Option<u8>) -> u8 {
+    match mid {
+        Some(mid) => fraction_to_angle_with_mid(10000 - pct.min(10000) as u32, 10000, mid),
+        None => percent100ths_to_angle(pct),
+    }
+}
+
+// --- Commissioning credentials ---
+//
+// Each vent derives its discriminator and setup passcode from its EUI-64,
+// so a fleet flashed with one image doesn't share a passcode or collide on
+// discriminators, and a re-flash keeps the printed pairing code valid.
+// The values are unique, not secret: anyone who learns the EUI-64 can
+// derive them.
+
+/// Largest valid setup passcode (8 digits, excluding 99999999).
+const MAX_PASSCODE: u32 = 99_999_998;
+/// Passcodes the Matter spec forbids because they are trivially guessable.
+const INVALID_PASSCODES: [u32; 11] = [
+    0, 11_111_111, 22_222_222, 33_333_333, 44_444_444, 55_555_555, 66_666_666, 77_777_777,
+    88_888_888, 12_345_678, 87_654_321,
+];
+
+/// FNV-1a over a domain tag and the EUI-64, so the discriminator and
+/// passcode come from independent hashes.
+fn eui64_hash(tag: u8, eui64: &[u8; 8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in core::iter::once(&tag).chain(eui64.iter()) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// 12-bit commissioning discriminator for this device.
+pub fn derive_discriminator(eui64: &[u8; 8]) -> u16 {
+    (eui64_hash(b'D', eui64) & 0x0fff) as u16
+}
+
+/// This device's phase within a report interval, so vents moved together
+/// by a zone command don't all report at the same instant. 0 when the
+/// interval is 0.
+pub fn report_offset_ms(eui64: &[u8; 8], interval_ms: u32) -> u32 {
+    if interval_ms == 0 {
+        return 0;
+    }
+    (eui64_hash(b'R', eui64) % interval_ms as u64) as u32
+}
+
+/// Setup passcode for this device, in 1..=99999998 and never one of the
+/// spec's invalid values.
+pub fn derive_passcode(eui64: &[u8; 8]) -> u32 {
+    skip_invalid_passcode((eui64_hash(b'P', eui64) % MAX_PASSCODE as u64) as u32 + 1)
+}
+
+/// Step past forbidden passcodes, wrapping within the valid range.
+fn skip_invalid_passcode(mut passcode: u32) -> u32 {
+    while INVALID_PASSCODES.contains(&passcode) {
+        passcode = passcode % MAX_PASSCODE + 1;
+    }
+    passcode
+}
+
+/// Discriminator and passcode handed to the Matter stack at init.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupCredentials {
+    pub discriminator: u16,
+    pub passcode: u32,
+}
+
+impl SetupCredentials {
+    pub fn from_eui64(eui64: &[u8; 8]) -> Self {
+        Self {
+            discriminator: derive_discriminator(eui64),
+            passcode: derive_passcode(eui64),
+        }
+    }
+}
+
 // --- Callbacks from Matter SDK (C context) ---
 
 unsafe extern "C" fn on_position_change(percent100ths: u16, _ctx: *mut c_void) {
-    let angle = percent100ths_to_angle(percent100ths);
+    let inverted = PERCENT_INVERTED.load(Ordering::Relaxed);
+    let angle = matter_percent_to_angle(percent100ths, midpoint_angle(), inverted);
     info!("Matter: position change -> {}° (pct100ths={})", angle, percent100ths);
 
-    crate::state::with_app_state(|s| {
-        // WAL: persist intent before moving
-        if let Err(e) = s.identity.write_ahead(angle) {
-            warn!("Matter: WAL write-ahead failed: {:?}", e);
+    let last = *LAST_SELF_REPORT.lock().unwrap_or_else(|e| e.into_inner());
+    let window_ms = ECHO_WINDOW_MS.load(Ordering::Relaxed);
+    if let Some((reported, at_ms)) = last {
+        if is_echo(percent100ths, Some(reported), at_ms, uptime_ms(), window_ms) {
+            info!("Matter: ignoring echo of our own report ({} pct100ths)", percent100ths);
             return;
         }
-        let prev = s.vent.set_target(angle);
-        info!("Matter: target set {}° -> {}°", prev, angle);
+    }
+
+    crate::state::with_app_state(|s| {
+        let resp = crate::command::apply_target_response(s, CommandSource::Matter, angle);
+        if resp.accepted {
+            info!("Matter: target set {}° -> {}°", resp.previous, resp.target);
+        }
+    });
+}
+
+unsafe extern "C" fn on_mode_change(mode: u8, _ctx: *mut c_void) {
+    let Some(mode) = AirflowMode::from_u8(mode) else {
+        error!("Matter: unknown airflow mode {}", mode);
+        return;
+    };
+    let angle = mode_to_angle(mode, &MODE_TABLE.lock().unwrap_or_else(|e| e.into_inner()));
+    info!("Matter: airflow mode {} -> {}°", mode.as_str(), angle);
+
+    crate::state::with_app_state(|s| {
+        let resp = crate::command::apply_target_response(s, CommandSource::Matter, angle);
+        if resp.accepted {
+            info!("Matter: target set {}° -> {}°", resp.previous, resp.target);
+        }
     });
 }
 
@@ -85,7 +294,8 @@ unsafe extern "C" fn on_identify(duration_s: u16, _ctx: *mut c_void) {
     // Start identify: save current angle, begin wiggle
     crate::state::with_app_state(|s| {
         let current = s.vent.current_angle();
-        s.identify_restore_angle = Some(current);
+        // Mid-overshoot, come back to the real target rather than the waypoint
+        s.identify_restore_angle = Some(s.approach_final.take().unwrap_or(current));
         s.identify_mode = true;
 
         // Wiggle: move to current + offset (clamped to valid range)
@@ -95,49 +305,224 @@ unsafe extern "C" fn on_identify(duration_s: u16, _ctx: *mut c_void) {
     });
 }
 
+/// Matter Window Covering device type.
+pub const DEVICE_TYPE_WINDOW_COVERING: u32 = 0x0202;
+/// Matter Fan device type.
+pub const DEVICE_TYPE_FAN: u32 = 0x002B;
+
+/// Matter device type to advertise for a presentation hint. Matter has no
+/// damper type; a Fan's percent setting is the closest airflow control.
+pub fn matter_device_type(kind: DeviceType) -> u32 {
+    match kind {
+        DeviceType::Cover => DEVICE_TYPE_WINDOW_COVERING,
+        DeviceType::Damper => DEVICE_TYPE_FAN,
+    }
+}
+
 // --- Public Rust API ---
 
 /// Initialize the Matter node. Must be called after `init_app_state()`.
-pub fn init() {
+pub fn init(creds: &SetupCredentials) -> Result<(), MatterError> {
     info!("Initializing Matter...");
     let ret = unsafe {
-        matter_bridge_init(on_position_change, on_identify, std::ptr::null_mut())
+        matter_bridge_init(
+            on_position_change,
+            on_identify,
+            on_mode_change,
+            creds.discriminator,
+            creds.passcode,
+            std::ptr::null_mut(),
+        )
     };
     if ret != 0 {
         error!("Matter init failed: {}", ret);
+        return Err(MatterError::Init(ret));
     }
+    Ok(())
 }
 
 /// Start the Matter event loop.
-pub fn start() {
+pub fn start() -> Result<(), MatterError> {
     info!("Starting Matter...");
     let ret = unsafe { matter_bridge_start() };
     if ret != 0 {
         error!("Matter start failed: {}", ret);
+        return Err(MatterError::Start(ret));
     }
+    Ok(())
+}
+
+/// A Matter bring-up step failed with the bridge's return code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatterError {
+    Init(i32),
+    Start(i32),
+}
+
+/// Set once Matter is up; every bridge call after bring-up checks it so a
+/// failed init never touches a half-built node.
+static MATTER_AVAILABLE: AtomicBool = AtomicBool::new(false);
+
+pub fn is_available() -> bool {
+    MATTER_AVAILABLE.load(Ordering::Relaxed)
+}
+
+/// Run `init`, and `start` only if init succeeded. Returns the first
+/// failure.
+pub fn bring_up(
+    init: impl FnOnce() -> Result<(), MatterError>,
+    start: impl FnOnce() -> Result<(), MatterError>,
+) -> Result<(), MatterError> {
+    init()?;
+    start()
+}
+
+/// Initialize and start Matter, recording whether it came up.
+pub fn init_and_start(creds: &SetupCredentials) -> Result<(), MatterError> {
+    let result = bring_up(|| init(creds), start);
+    MATTER_AVAILABLE.store(result.is_ok(), Ordering::Relaxed);
+    result
 }
 
 /// Report the current vent position to Matter fabric.
 pub fn report_position(angle: u8) {
-    let pct = angle_to_percent100ths(angle);
+    if !is_available() {
+        return;
+    }
+    let reported = round_to(angle as u32 * 1000, REPORT_ROUND_DEG.load(Ordering::Relaxed));
+    let inverted = PERCENT_INVERTED.load(Ordering::Relaxed);
+    let pct = angle_to_matter_percent(reported, midpoint_angle(), inverted);
+    *LAST_SELF_REPORT.lock().unwrap_or_else(|e| e.into_inner()) = Some((pct, uptime_ms()));
     unsafe { matter_bridge_update_position(pct) };
+
+    let mode = angle_to_mode(angle, &MODE_TABLE.lock().unwrap_or_else(|e| e.into_inner())) as u8;
+    if LAST_REPORTED_MODE.swap(mode, Ordering::Relaxed) != mode {
+        unsafe { matter_bridge_update_mode(mode) };
+    }
+}
+
+/// Whether a mid-move position is far enough from the last report to be
+/// worth sending. Nothing reported yet always reports; a tolerance of 0
+/// behaves like 1 (report every change).
+pub fn should_report(last_reported: Option<u8>, current: u8, tol: u8) -> bool {
+    match last_reported {
+        None => true,
+        Some(last) => last.abs_diff(current) >= tol.max(1),
+    }
+}
+
+/// Time-based spacing of mid-move reports: at most one per `interval_ms`,
+/// the first delayed by this device's `offset_ms` into the move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportStagger {
+    pub interval_ms: u32,
+    pub offset_ms: u32,
+}
+
+impl ReportStagger {
+    /// Stagger for this device, or None when `interval_ms` is 0.
+    pub fn for_device(eui64: &[u8; 8], interval_ms: u32) -> Option<Self> {
+        (interval_ms > 0).then(|| Self {
+            interval_ms,
+            offset_ms: report_offset_ms(eui64, interval_ms),
+        })
+    }
+}
+
+/// Throttles position reports during a move to one per `tolerance_deg`,
+/// and optionally spaces them in time. The final committed angle is
+/// always reported.
+pub struct PositionReporter {
+    tolerance_deg: u8,
+    last_reported: Option<u8>,
+    stagger: Option<ReportStagger>,
+    /// Earliest uptime the next mid-move report may go out; unset between
+    /// moves.
+    next_due_ms: Option<u64>,
+}
+
+impl PositionReporter {
+    pub fn new(tolerance_deg: u8) -> Self {
+        Self {
+            tolerance_deg,
+            last_reported: None,
+            stagger: None,
+            next_due_ms: None,
+        }
+    }
+
+    pub fn with_stagger(mut self, stagger: Option<ReportStagger>) -> Self {
+        self.stagger = stagger;
+        self
+    }
+
+    /// Decide whether to report `angle`, recording it if so. `is_final`
+    /// marks the committed end-of-move angle, which bypasses the tolerance.
+    /// Without a clock, so only for final reports once a stagger is set.
+    pub fn observe(&mut self, angle: u8, is_final: bool) -> bool {
+        self.observe_at(angle, is_final, 0)
+    }
+
+    /// As `observe`, holding mid-move reports to the stagger schedule,
+    /// which restarts with each move.
+    pub fn observe_at(&mut self, angle: u8, is_final: bool, now_ms: u64) -> bool {
+        if is_final {
+            self.next_due_ms = None;
+        } else if !should_report(self.last_reported, angle, self.tolerance_deg) {
+            return false;
+        } else if let Some(stagger) = self.stagger {
+            let due = *self.next_due_ms.get_or_insert(now_ms + stagger.offset_ms as u64);
+            if now_ms < due {
+                return false;
+            }
+            self.next_due_ms = Some(now_ms + stagger.interval_ms as u64);
+        }
+        self.last_reported = Some(angle);
+        true
+    }
 }
 
 /// Report whether the vent is currently moving.
 pub fn report_operational_status(is_moving: bool) {
     // WindowCovering OperationalStatus bitmap:
     // bits 0-1: global movement (0=stopped, 1=opening, 2=closing)
+    if !is_available() {
+        return;
+    }
     let status: u8 = if is_moving { 1 } else { 0 };
     unsafe { matter_bridge_update_operational_status(status) };
 }
 
 /// Check if the device is commissioned into a Matter fabric.
 pub fn is_commissioned() -> bool {
-    unsafe { matter_bridge_is_commissioned() }
+    is_available() && unsafe { matter_bridge_is_commissioned() }
+}
+
+/// Decide whether a device that has dropped off the fabric should rejoin.
+///
+/// Only a commissioned device can "lose" the fabric — an uncommissioned one
+/// is simply waiting for pairing and must be left alone. A zero `limit`
+/// disables the check.
+pub fn should_rejoin(commissioned: bool, connected: bool, down_duration: Duration, limit: Duration) -> bool {
+    commissioned && !connected && !limit.is_zero() && down_duration >= limit
+}
+
+/// Decide whether a vent still waiting for setup should open fail-safe.
+///
+/// A vent that has neither been commissioned nor joined a mesh within
+/// `limit` of boot is presumably sitting in an unfinished install; opening
+/// it keeps the room ventilated meanwhile. A zero `limit` disables it.
+pub fn should_failsafe_open(connected: bool, commissioned: bool, boot_elapsed: Duration, limit: Duration) -> bool {
+    !connected && !commissioned && !limit.is_zero() && boot_elapsed >= limit
 }
 
 /// Log pairing info to serial console.
-pub fn log_pairing_info() {
+pub fn log_pairing_info(creds: &SetupCredentials) {
+    info!(
+        "Setup discriminator: {} passcode: {:08}",
+        creds.discriminator, creds.passcode
+    );
+
     let mut code_buf = [0u8; 32];
     let mut qr_buf = [0u8; 128];
 
@@ -161,6 +546,28 @@ pub fn log_pairing_info() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use vent_protocol::angle_to_permille;
+
+    #[test]
+    fn test_echo_of_own_report_suppressed() {
+        assert!(is_echo(5000, Some(5000), 10_000, 10_500, 2000));
+        assert!(!is_echo(5000, Some(5000), 10_000, 12_000, 2000));
+    }
+
+    #[test]
+    fn test_genuine_command_not_echo() {
+        // A different position, nothing reported yet, or suppression off
+        assert!(!is_echo(2500, Some(5000), 10_000, 10_100, 2000));
+        assert!(!is_echo(5000, None, 0, 100, 2000));
+        assert!(!is_echo(5000, Some(5000), 10_000, 10_100, 0));
+    }
+
+    #[test]
+    fn test_device_type_hint_maps_to_matter_id() {
+        assert_eq!(matter_device_type(DeviceType::Cover), 0x0202);
+        assert_eq!(matter_device_type(DeviceType::Damper), 0x002B);
+        assert_eq!(matter_device_type(DeviceType::default()), DEVICE_TYPE_WINDOW_COVERING);
+    }
 
     #[test]
     fn test_angle_to_percent100ths_open() {
@@ -216,6 +623,160 @@ mod tests {
         assert_eq!(percent100ths_to_angle(20000), 90);
     }
 
+    #[test]
+    fn test_should_rejoin_commissioned_but_disconnected() {
+        let limit = Duration::from_secs(600);
+        assert!(!should_rejoin(true, false, Duration::from_secs(599), limit));
+        assert!(should_rejoin(true, false, Duration::from_secs(600), limit));
+        assert!(should_rejoin(true, false, Duration::from_secs(3600), limit));
+    }
+
+    #[test]
+    fn test_should_rejoin_connected() {
+        let limit = Duration::from_secs(600);
+        assert!(!should_rejoin(true, true, Duration::from_secs(3600), limit));
+    }
+
+    #[test]
+    fn test_should_rejoin_never_commissioned() {
+        // Waiting for pairing is not a fabric loss
+        let limit = Duration::from_secs(600);
+        assert!(!should_rejoin(false, false, Duration::from_secs(3600), limit));
+    }
+
+    #[test]
+    fn test_should_rejoin_disabled() {
+        assert!(!should_rejoin(true, false, Duration::from_secs(3600), Duration::ZERO));
+    }
+
+    #[test]
+    fn test_failsafe_open_after_timeout() {
+        let limit = Duration::from_secs(1800);
+        assert!(!should_failsafe_open(false, false, Duration::from_secs(1799), limit));
+        assert!(should_failsafe_open(false, false, Duration::from_secs(1800), limit));
+        assert!(!should_failsafe_open(false, false, Duration::from_secs(3600), Duration::ZERO));
+    }
+
+    #[test]
+    fn test_failsafe_open_not_once_set_up() {
+        let limit = Duration::from_secs(1800);
+        let late = Duration::from_secs(7200);
+        assert!(!should_failsafe_open(true, false, late, limit));
+        // Commissioned but off the mesh is the rejoin path's job
+        assert!(!should_failsafe_open(false, true, late, limit));
+    }
+
+    #[test]
+    fn test_inverted_percent_reports_open_as_100() {
+        assert_eq!(angle_to_matter_percent(ANGLE_OPEN, None, true), 10000);
+        assert_eq!(angle_to_matter_percent(ANGLE_CLOSED, None, true), 0);
+        assert_eq!(angle_to_matter_percent(135, None, true), 5000);
+        // Default follows the spec: open is 0%
+        assert_eq!(angle_to_matter_percent(ANGLE_OPEN, None, false), 0);
+        // Midpoint remapping still applies
+        assert_eq!(angle_to_matter_percent(120, Some(120), true), 5000);
+    }
+
+    #[test]
+    fn test_inverted_percent_accepts_100_as_open() {
+        assert_eq!(matter_percent_to_angle(10000, None, true), ANGLE_OPEN);
+        assert_eq!(matter_percent_to_angle(0, None, true), ANGLE_CLOSED);
+        assert_eq!(matter_percent_to_angle(20000, None, true), ANGLE_OPEN);
+        assert_eq!(matter_percent_to_angle(10000, None, false), ANGLE_CLOSED);
+        for angle in [ANGLE_CLOSED, 108, 135, 162, ANGLE_OPEN] {
+            let pct = angle_to_matter_percent(angle, None, true);
+            assert_eq!(matter_percent_to_angle(pct, None, true), angle);
+        }
+    }
+
+    #[test]
+    fn test_permille_consistent_with_percent100ths() {
+        // Matter counts from open, permille counts from closed; both
+        // truncate, so they agree to within one permille step.
+        for angle in 90..=180 {
+            let from_matter = 10000 - angle_to_percent100ths(angle) as i32;
+            let from_permille = angle_to_permille(angle) as i32 * 10;
+            assert!(
+                (from_matter - from_permille).abs() < 10,
+                "angle {}: matter {} vs permille {}", angle, from_matter, from_permille
+            );
+        }
+    }
+
+    #[test]
+    fn test_restored_angle_report() {
+        // Boot restores the checkpoint through VentStateMachine, which
+        // clamps, so the first report is always a valid lift percentage.
+        for (checkpoint, expected) in [(90, 10000), (135, 5000), (180, 0), (0, 10000), (255, 0)] {
+            let restored = crate::state::VentStateMachine::new(checkpoint).current_angle();
+            assert_eq!(angle_to_percent100ths(restored), expected, "checkpoint {}", checkpoint);
+        }
+    }
+
+    #[test]
+    fn test_should_report_tolerance() {
+        assert!(should_report(None, 120, 5));
+        assert!(!should_report(Some(120), 124, 5));
+        assert!(should_report(Some(120), 125, 5));
+        assert!(should_report(Some(120), 115, 5));
+        // Zero tolerance still suppresses unchanged angles
+        assert!(!should_report(Some(120), 120, 0));
+        assert!(should_report(Some(120), 121, 0));
+    }
+
+    #[test]
+    fn test_reporter_throttles_move_and_always_reports_final() {
+        let mut reporter = PositionReporter::new(5);
+        let reported: Vec<u8> = (90..=107)
+            .filter(|&a| reporter.observe(a, false))
+            .collect();
+        assert_eq!(reported, vec![90, 95, 100, 105]);
+        // 107 is within tolerance of 105 but is the committed angle
+        assert!(!reporter.observe(107, false));
+        assert!(reporter.observe(107, true));
+    }
+
+    #[test]
+    fn test_report_offset_within_interval() {
+        for i in 0..=255u8 {
+            let eui = [0x58, 0xe6, 0xc5, 0xff, 0xfe, 0x01, i, i.wrapping_mul(7)];
+            assert!(report_offset_ms(&eui, 2000) < 2000);
+            assert!(report_offset_ms(&eui, 1) == 0);
+        }
+        assert_eq!(report_offset_ms(&[1; 8], 0), 0);
+        assert_eq!(ReportStagger::for_device(&[1; 8], 0), None);
+    }
+
+    #[test]
+    fn test_report_offset_differs_per_device() {
+        let offsets: std::collections::HashSet<u32> = (0..16u8)
+            .map(|i| report_offset_ms(&[0x58, 0xe6, 0xc5, 0xff, 0xfe, 0x01, 0x02, i], 2000))
+            .collect();
+        assert!(offsets.len() > 8);
+        // Stable for one device
+        let eui = [0x58, 0xe6, 0xc5, 0xff, 0xfe, 0x01, 0x02, 0x03];
+        assert_eq!(report_offset_ms(&eui, 2000), report_offset_ms(&eui, 2000));
+    }
+
+    #[test]
+    fn test_staggered_reporter_spaces_mid_move_reports() {
+        let stagger = ReportStagger {
+            interval_ms: 1000,
+            offset_ms: 300,
+        };
+        let mut reporter = PositionReporter::new(1).with_stagger(Some(stagger));
+        // One degree every 100 ms from t = 0
+        let reported: Vec<u8> = (0..30u8)
+            .filter(|&i| reporter.observe_at(90 + i, false, i as u64 * 100))
+            .map(|i| 90 + i)
+            .collect();
+        assert_eq!(reported, vec![93, 103, 113]);
+        assert!(reporter.observe_at(120, true, 3000));
+        // The next move starts its own schedule
+        assert!(!reporter.observe_at(119, false, 10_000));
+        assert!(reporter.observe_at(116, false, 10_300));
+    }
+
     #[test]
     fn test_roundtrip_open() {
         assert_eq!(percent100ths_to_angle(angle_to_percent100ths(180)), 180);
@@ -239,4 +800,67 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_bring_up_skips_start_after_failed_init() {
+        let mut started = false;
+        let result = bring_up(|| Err(MatterError::Init(-1)), || {
+            started = true;
+            Ok(())
+        });
+        assert_eq!(result, Err(MatterError::Init(-1)));
+        assert!(!started);
+    }
+
+    #[test]
+    fn test_bring_up_reports_start_failure() {
+        assert_eq!(bring_up(|| Ok(()), || Err(MatterError::Start(3))), Err(MatterError::Start(3)));
+        assert_eq!(bring_up(|| Ok(()), || Ok(())), Ok(()));
+    }
+
+    #[test]
+    fn test_percent100ths_with_mid() {
+        // 50% closed lands on the configured midpoint
+        assert_eq!(percent100ths_to_angle_with_mid(5000, Some(120)), 120);
+        assert_eq!(percent100ths_to_angle_with_mid(0, Some(120)), ANGLE_OPEN);
+        assert_eq!(percent100ths_to_angle_with_mid(10000, Some(120)), ANGLE_CLOSED);
+        assert_eq!(angle_to_percent100ths_with_mid(120, Some(120)), 5000);
+        // Unset falls back to the linear mapping
+        assert_eq!(percent100ths_to_angle_with_mid(5000, None), 135);
+        assert_eq!(angle_to_percent100ths_with_mid(150, None), angle_to_percent100ths(150));
+        for angle in ANGLE_CLOSED..=ANGLE_OPEN {
+            let pct = angle_to_percent100ths_with_mid(angle, Some(120));
+            assert_eq!(percent100ths_to_angle_with_mid(pct, Some(120)), angle);
+        }
+    }
+
+    const EUI_A: [u8; 8] = [0x40, 0x4c, 0xca, 0xff, 0xfe, 0x41, 0x2a, 0x10];
+    const EUI_B: [u8; 8] = [0x40, 0x4c, 0xca, 0xff, 0xfe, 0x41, 0x2a, 0x11];
+
+    #[test]
+    fn test_credentials_are_deterministic() {
+        assert_eq!(SetupCredentials::from_eui64(&EUI_A), SetupCredentials::from_eui64(&EUI_A));
+        // Neighbouring EUIs from one batch still differ
+        assert_ne!(derive_passcode(&EUI_A), derive_passcode(&EUI_B));
+        assert_ne!(derive_discriminator(&EUI_A), derive_discriminator(&EUI_B));
+    }
+
+    #[test]
+    fn test_credentials_in_valid_range() {
+        for i in 0..2000u32 {
+            let mut eui = EUI_A;
+            eui[4..].copy_from_slice(&i.to_be_bytes());
+            assert!(derive_discriminator(&eui) <= 0x0fff);
+            let passcode = derive_passcode(&eui);
+            assert!((1..=MAX_PASSCODE).contains(&passcode));
+            assert!(!INVALID_PASSCODES.contains(&passcode));
+        }
+    }
+
+    #[test]
+    fn test_invalid_passcodes_are_skipped() {
+        assert_eq!(skip_invalid_passcode(11_111_111), 11_111_112);
+        assert_eq!(skip_invalid_passcode(12_345_678), 12_345_679);
+        assert_eq!(skip_invalid_passcode(20_000_000), 20_000_000);
+    }
 }
@@ -0,0 +1,472 @@
+use vent_protocol::{ANGLE_CLOSED, ANGLE_OPEN};
+
+/// Maximum number of points in a user-supplied step curve.
+pub const MAX_CURVE_POINTS: usize = 8;
+/// Speed bounds, as a percentage of the nominal step rate.
+pub const MIN_SPEED_PCT: u16 = 10;
+pub const MAX_SPEED_PCT: u16 = 400;
+/// Nominal speed used when no curve is configured (linear motion).
+pub const LINEAR_SPEED_PCT: u16 = 100;
+
+/// How the servo is driven toward a new target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Stepping {
+    /// One degree per step delay, for slow, smooth motion.
+    #[default]
+    Stepped,
+    /// Command the final angle once and let the servo travel at full
+    /// speed; quieter on cheap analog servos that buzz when stepped.
+    Snap,
+}
+
+impl Stepping {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Stepping::Snap,
+            _ => Stepping::Stepped,
+        }
+    }
+}
+
+/// Side every move's final approach comes from, so gear backlash always
+/// takes up the same way and a given angle lands in the same place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApproachDirection {
+    /// Take the direct path; no overshoot.
+    #[default]
+    Any,
+    /// Finish every move travelling toward open.
+    FromClosed,
+    /// Finish every move travelling toward closed.
+    FromOpen,
+}
+
+impl ApproachDirection {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ApproachDirection::FromClosed,
+            2 => ApproachDirection::FromOpen,
+            _ => ApproachDirection::Any,
+        }
+    }
+}
+
+/// How far past the target a move overshoots before turning back.
+pub const APPROACH_OVERSHOOT_DEG: u8 = 5;
+
+/// Targets to visit, in order, to reach `target` from `start` with the
+/// final leg in the `preferred` direction. A move already heading that way
+/// goes straight there; one heading the other way overshoots by
+/// `overshoot` (stopping at the end of travel) and comes back.
+pub fn approach_sequence(start: u8, target: u8, preferred: ApproachDirection, overshoot: u8) -> Vec<u8> {
+    let via = match preferred {
+        ApproachDirection::FromClosed if target < start => {
+            Some(target.saturating_sub(overshoot).max(ANGLE_CLOSED))
+        }
+        ApproachDirection::FromOpen if target > start => Some(target.saturating_add(overshoot).min(ANGLE_OPEN)),
+        _ => None,
+    };
+    match via {
+        Some(via) if via != target => vec![via, target],
+        _ => vec![target],
+    }
+}
+
+/// Degrees of travel between move-progress log lines unless NVS overrides
+/// it.
+pub const DEFAULT_PROGRESS_LOG_DEG: u8 = 10;
+/// Minimum time between move-progress log lines, however fast the move.
+pub const PROGRESS_LOG_MIN_MS: u64 = 500;
+
+/// Whether the vent has travelled far enough since the last progress line
+/// to log another. A zero threshold turns progress lines off.
+pub fn should_log_progress(last_log_angle: u8, current_angle: u8, threshold: u8) -> bool {
+    threshold > 0 && last_log_angle.abs_diff(current_angle) >= threshold
+}
+
+/// Throttle for move-progress logging, so a slow move doesn't flood the
+/// console. Start and completion are logged unconditionally by the caller;
+/// in between, a line needs both `threshold_deg` of travel and
+/// `PROGRESS_LOG_MIN_MS` since the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressLog {
+    threshold_deg: u8,
+    last_angle: u8,
+    last_ms: u64,
+}
+
+impl ProgressLog {
+    pub fn new(threshold_deg: u8) -> Self {
+        Self {
+            threshold_deg,
+            last_angle: 0,
+            last_ms: 0,
+        }
+    }
+
+    /// A move began at `angle`; progress is measured from here.
+    pub fn start(&mut self, now_ms: u64, angle: u8) {
+        self.last_angle = angle;
+        self.last_ms = now_ms;
+    }
+
+    /// Whether to log progress at `angle`. Records the line if so.
+    pub fn observe(&mut self, now_ms: u64, angle: u8) -> bool {
+        let due = should_log_progress(self.last_angle, angle, self.threshold_deg)
+            && now_ms.saturating_sub(self.last_ms) >= PROGRESS_LOG_MIN_MS;
+        if due {
+            self.start(now_ms, angle);
+        }
+        due
+    }
+}
+
+/// Time a snapped move is given to physically finish before committing;
+/// an SG90 covers the full 90° range in well under this.
+pub const SNAP_SETTLE_MS: u32 = 500;
+
+/// Bytes per point in the NVS encoding: fraction (u16 LE), speed (u16 LE).
+const POINT_BYTES: usize = 4;
+
+/// One point of a step curve: at `fraction_permille` of the way through a
+/// move, step at `speed_pct` of the nominal rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurvePoint {
+    /// Move progress, 0 (start) to 1000 (target reached).
+    pub fraction_permille: u16,
+    pub speed_pct: u16,
+}
+
+/// Why a step curve was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveError {
+    TooManyPoints,
+    /// Fractions must be strictly increasing.
+    NotMonotonic,
+    FractionOutOfRange,
+    SpeedOutOfRange,
+    /// NVS blob length is not a whole number of points.
+    Malformed,
+}
+
+/// Check a curve is short, strictly increasing in fraction and bounded.
+/// An empty curve is valid and means linear.
+pub fn validate_curve(points: &[CurvePoint]) -> Result<(), CurveError> {
+    if points.len() > MAX_CURVE_POINTS {
+        return Err(CurveError::TooManyPoints);
+    }
+    for p in points {
+        if p.fraction_permille > 1000 {
+            return Err(CurveError::FractionOutOfRange);
+        }
+        if !(MIN_SPEED_PCT..=MAX_SPEED_PCT).contains(&p.speed_pct) {
+            return Err(CurveError::SpeedOutOfRange);
+        }
+    }
+    if points
+        .windows(2)
+        .any(|w| w[1].fraction_permille <= w[0].fraction_permille)
+    {
+        return Err(CurveError::NotMonotonic);
+    }
+    Ok(())
+}
+
+/// Speed at `fraction_permille` of a move, linearly interpolated between
+/// the surrounding points and held flat outside the table's range.
+pub fn interp_speed(fraction_permille: u16, table: &[CurvePoint]) -> u16 {
+    let (first, last) = match (table.first(), table.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return LINEAR_SPEED_PCT,
+    };
+    if fraction_permille <= first.fraction_permille {
+        return first.speed_pct;
+    }
+    if fraction_permille >= last.fraction_permille {
+        return last.speed_pct;
+    }
+    for w in table.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        if fraction_permille <= b.fraction_permille {
+            let span = (b.fraction_permille - a.fraction_permille) as i32;
+            let offset = (fraction_permille - a.fraction_permille) as i32;
+            let delta = b.speed_pct as i32 - a.speed_pct as i32;
+            return (a.speed_pct as i32 + delta * offset / span) as u16;
+        }
+    }
+    last.speed_pct
+}
+
+/// Delay before the next step when moving at `speed_pct` of nominal.
+pub fn step_delay_ms(base_ms: u32, speed_pct: u16) -> u32 {
+    base_ms * LINEAR_SPEED_PCT as u32 / speed_pct.max(1) as u32
+}
+
+/// Largest damping zone accepted from NVS, in degrees from the target.
+pub const MAX_DAMPING_ZONE_DEG: u8 = 30;
+
+/// Slow the final approach so a cheap servo doesn't overshoot and hunt.
+/// Within `zone_deg` of the target the delay grows linearly, up to double
+/// at the target. A zero zone disables damping.
+pub fn damped_delay_ms(delay_ms: u32, remaining_deg: u8, zone_deg: u8) -> u32 {
+    let zone = zone_deg.min(MAX_DAMPING_ZONE_DEG) as u32;
+    let remaining = remaining_deg as u32;
+    if zone == 0 || remaining >= zone {
+        return delay_ms;
+    }
+    delay_ms * (2 * zone - remaining) / zone
+}
+
+/// Default longest uninterrupted sleep during a move. A new target is
+/// picked up within this much time plus one step's work.
+pub const DEFAULT_MOVE_YIELD_MS: u32 = 20;
+
+/// Split a step delay into slices of at most `chunk_ms` so the move loop
+/// can check for a new target between them. A zero chunk means no slicing.
+pub fn delay_chunks(total_ms: u32, chunk_ms: u32) -> impl Iterator<Item = u32> {
+    let chunk = if chunk_ms == 0 { total_ms.max(1) } else { chunk_ms };
+    let mut left = total_ms;
+    core::iter::from_fn(move || {
+        if left == 0 {
+            return None;
+        }
+        let slice = left.min(chunk);
+        left -= slice;
+        Some(slice)
+    })
+}
+
+/// Sleep `total_ms` in `delay_chunks`, asking `retargeted` after each slice
+/// and stopping early once it says the target moved. Returns the time
+/// actually slept.
+pub fn sleep_until_retarget(
+    total_ms: u32,
+    chunk_ms: u32,
+    mut sleep: impl FnMut(u32),
+    mut retargeted: impl FnMut() -> bool,
+) -> u32 {
+    let mut slept = 0;
+    for chunk in delay_chunks(total_ms, chunk_ms) {
+        sleep(chunk);
+        slept += chunk;
+        if retargeted() {
+            break;
+        }
+    }
+    slept
+}
+
+/// Worst-case extra time a full-travel move takes under `table` compared
+/// to linear motion, assuming the slowest point applies throughout.
+pub fn extra_travel_ms(table: &[CurvePoint], base_ms: u32) -> u32 {
+    let slowest = table
+        .iter()
+        .map(|p| p.speed_pct)
+        .min()
+        .unwrap_or(LINEAR_SPEED_PCT);
+    let travel = (vent_protocol::ANGLE_OPEN - vent_protocol::ANGLE_CLOSED) as u32;
+    step_delay_ms(base_ms, slowest).saturating_sub(base_ms) * travel
+}
+
+/// Encode a curve for NVS storage.
+pub fn encode_curve(points: &[CurvePoint]) -> Vec<u8> {
+    points
+        .iter()
+        .flat_map(|p| {
+            let f = p.fraction_permille.to_le_bytes();
+            let s = p.speed_pct.to_le_bytes();
+            [f[0], f[1], s[0], s[1]]
+        })
+        .collect()
+}
+
+/// Decode and validate a curve read from NVS.
+pub fn decode_curve(raw: &[u8]) -> Result<Vec<CurvePoint>, CurveError> {
+    if raw.len() % POINT_BYTES != 0 {
+        return Err(CurveError::Malformed);
+    }
+    let points: Vec<CurvePoint> = raw
+        .chunks_exact(POINT_BYTES)
+        .map(|c| CurvePoint {
+            fraction_permille: u16::from_le_bytes([c[0], c[1]]),
+            speed_pct: u16::from_le_bytes([c[2], c[3]]),
+        })
+        .collect();
+    validate_curve(&points)?;
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_log_progress() {
+        assert!(!should_log_progress(100, 109, 10));
+        assert!(should_log_progress(100, 110, 10));
+        // Either direction of travel counts
+        assert!(should_log_progress(150, 140, 10));
+        assert!(!should_log_progress(90, 180, 0));
+    }
+
+    #[test]
+    fn test_progress_log_throttled_by_time_and_travel() {
+        let mut log = ProgressLog::new(10);
+        log.start(0, 90);
+        // Far enough but too soon
+        assert!(!log.observe(300, 120));
+        assert!(log.observe(500, 120));
+        // Soon enough but not far enough
+        assert!(!log.observe(2_000, 125));
+        assert!(log.observe(2_100, 130));
+        // A new move resets the reference angle
+        log.start(5_000, 180);
+        assert!(!log.observe(6_000, 175));
+    }
+
+    #[test]
+    fn test_approach_same_direction_is_direct() {
+        assert_eq!(approach_sequence(100, 150, ApproachDirection::FromClosed, 5), vec![150]);
+        assert_eq!(approach_sequence(150, 100, ApproachDirection::FromOpen, 5), vec![100]);
+        assert_eq!(approach_sequence(150, 100, ApproachDirection::Any, 5), vec![100]);
+        assert_eq!(approach_sequence(120, 120, ApproachDirection::FromClosed, 5), vec![120]);
+    }
+
+    #[test]
+    fn test_approach_opposite_direction_overshoots() {
+        // Closing move, but the final leg must travel toward open
+        assert_eq!(approach_sequence(150, 120, ApproachDirection::FromClosed, 5), vec![115, 120]);
+        // Opening move, final leg must travel toward closed
+        assert_eq!(approach_sequence(100, 140, ApproachDirection::FromOpen, 5), vec![145, 140]);
+    }
+
+    #[test]
+    fn test_approach_overshoot_stops_at_end_of_travel() {
+        assert_eq!(approach_sequence(150, 92, ApproachDirection::FromClosed, 5), vec![ANGLE_CLOSED, 92]);
+        // Nothing below closed to come back from
+        assert_eq!(approach_sequence(150, ANGLE_CLOSED, ApproachDirection::FromClosed, 5), vec![ANGLE_CLOSED]);
+        assert_eq!(approach_sequence(100, ANGLE_OPEN, ApproachDirection::FromOpen, 5), vec![ANGLE_OPEN]);
+    }
+
+    fn pt(fraction_permille: u16, speed_pct: u16) -> CurvePoint {
+        CurvePoint {
+            fraction_permille,
+            speed_pct,
+        }
+    }
+
+    #[test]
+    fn test_empty_curve_is_linear() {
+        assert_eq!(interp_speed(0, &[]), LINEAR_SPEED_PCT);
+        assert_eq!(interp_speed(500, &[]), LINEAR_SPEED_PCT);
+        assert_eq!(step_delay_ms(15, LINEAR_SPEED_PCT), 15);
+    }
+
+    #[test]
+    fn test_interp_between_points() {
+        let table = [pt(0, 50), pt(500, 200), pt(1000, 50)];
+        assert_eq!(interp_speed(0, &table), 50);
+        assert_eq!(interp_speed(250, &table), 125);
+        assert_eq!(interp_speed(500, &table), 200);
+        assert_eq!(interp_speed(750, &table), 125);
+        assert_eq!(interp_speed(1000, &table), 50);
+    }
+
+    #[test]
+    fn test_interp_clamps_outside_range() {
+        let table = [pt(200, 80), pt(800, 160)];
+        assert_eq!(interp_speed(0, &table), 80);
+        assert_eq!(interp_speed(100, &table), 80);
+        assert_eq!(interp_speed(900, &table), 160);
+        assert_eq!(interp_speed(1000, &table), 160);
+    }
+
+    #[test]
+    fn test_validate_rejects_non_monotonic() {
+        assert_eq!(
+            validate_curve(&[pt(0, 100), pt(500, 100), pt(500, 100)]),
+            Err(CurveError::NotMonotonic)
+        );
+        assert_eq!(
+            validate_curve(&[pt(600, 100), pt(400, 100)]),
+            Err(CurveError::NotMonotonic)
+        );
+    }
+
+    #[test]
+    fn test_validate_bounds() {
+        assert_eq!(validate_curve(&[]), Ok(()));
+        assert_eq!(validate_curve(&[pt(1001, 100)]), Err(CurveError::FractionOutOfRange));
+        assert_eq!(validate_curve(&[pt(0, 5)]), Err(CurveError::SpeedOutOfRange));
+        assert_eq!(validate_curve(&[pt(0, 500)]), Err(CurveError::SpeedOutOfRange));
+        let too_many: Vec<_> = (0..9).map(|i| pt(i * 100, 100)).collect();
+        assert_eq!(validate_curve(&too_many), Err(CurveError::TooManyPoints));
+    }
+
+    #[test]
+    fn test_curve_nvs_roundtrip() {
+        let table = vec![pt(0, 50), pt(500, 200), pt(1000, 50)];
+        assert_eq!(decode_curve(&encode_curve(&table)), Ok(table));
+        assert_eq!(decode_curve(&[1, 2, 3]), Err(CurveError::Malformed));
+    }
+
+    #[test]
+    fn test_extra_travel_only_for_slow_curves() {
+        assert_eq!(extra_travel_ms(&[], 15), 0);
+        assert_eq!(extra_travel_ms(&[pt(0, 200)], 15), 0);
+        // Half speed doubles the 15ms step over 90 degrees
+        assert_eq!(extra_travel_ms(&[pt(0, 50), pt(1000, 100)], 15), 15 * 90);
+    }
+
+    #[test]
+    fn test_damping_slows_final_approach() {
+        assert_eq!(damped_delay_ms(15, 20, 5), 15);
+        assert_eq!(damped_delay_ms(15, 5, 5), 15);
+        assert_eq!(damped_delay_ms(15, 3, 5), 21);
+        assert_eq!(damped_delay_ms(15, 0, 5), 30);
+        // Disabled
+        assert_eq!(damped_delay_ms(15, 0, 0), 15);
+    }
+
+    #[test]
+    fn test_delay_chunks_bound_each_sleep() {
+        let chunks: Vec<_> = delay_chunks(150, 20).collect();
+        assert_eq!(chunks.iter().sum::<u32>(), 150);
+        assert!(chunks.iter().all(|&c| c <= 20));
+        assert_eq!(delay_chunks(15, 20).collect::<Vec<_>>(), vec![15]);
+        assert_eq!(delay_chunks(0, 20).count(), 0);
+        assert_eq!(delay_chunks(30, 0).collect::<Vec<_>>(), vec![30]);
+    }
+
+    #[test]
+    fn test_sleep_runs_full_delay_without_retarget() {
+        let mut slices = Vec::new();
+        let slept = sleep_until_retarget(150, 20, |ms| slices.push(ms), || false);
+        assert_eq!(slept, 150);
+        assert_eq!(slices.len(), 8);
+    }
+
+    #[test]
+    fn test_sleep_cut_short_by_retarget() {
+        // The target changes during the second slice
+        let mut checks = 0;
+        let mut slices = Vec::new();
+        let slept = sleep_until_retarget(
+            150,
+            20,
+            |ms| slices.push(ms),
+            || {
+                checks += 1;
+                checks == 2
+            },
+        );
+        assert_eq!(slept, 40);
+        assert_eq!(slices, vec![20, 20]);
+    }
+
+    #[test]
+    fn test_faster_speed_shortens_delay() {
+        assert_eq!(step_delay_ms(15, 200), 7);
+        assert_eq!(step_delay_ms(15, 50), 30);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::servo::PositionFeedback;
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::i2c::I2cDriver;
+use esp_idf_sys::EspError;
+use log::warn;
+use vent_protocol::{ANGLE_CLOSED, ANGLE_OPEN};
+
+/// I2C address of the AS5600 magnetic rotary encoder.
+const AS5600_ADDR: u8 = 0x36;
+/// RAW ANGLE register (high byte; the low byte follows).
+const REG_RAW_ANGLE: u8 = 0x0c;
+/// Counts per revolution of the 12-bit angle.
+pub const COUNTS_PER_REV: u16 = 4096;
+
+/// Encoder counts at the two ends of the louver's travel, relative to the
+/// zero offset. Set by calibrating against the closed and open stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderCalibration {
+    /// Raw reading treated as zero, placed so the travel doesn't span it.
+    pub zero_offset: u16,
+    /// Counts past zero at the closed stop.
+    pub cal_min: u16,
+    /// Counts past zero at the open stop; below `cal_min` if the magnet
+    /// turns the other way.
+    pub cal_max: u16,
+}
+
+/// Map a 12-bit encoder reading to a vent angle. The reading is taken
+/// relative to `zero_offset` modulo one revolution, so travel that crosses
+/// the encoder's own zero stays continuous, then scaled from
+/// `cal_min..=cal_max` onto closed..=open and clamped.
+pub fn raw12_to_angle(raw: u16, zero_offset: u16, cal_min: u16, cal_max: u16) -> u8 {
+    let rel = (raw as i32 - zero_offset as i32).rem_euclid(COUNTS_PER_REV as i32);
+    let (min, max) = (cal_min as i32, cal_max as i32);
+    if min == max {
+        return ANGLE_CLOSED;
+    }
+    let span = (ANGLE_OPEN - ANGLE_CLOSED) as i32;
+    let scaled = ((rel - min) * span + (max - min) / 2) / (max - min);
+    (ANGLE_CLOSED as i32 + scaled.clamp(0, span)) as u8
+}
+
+/// AS5600 encoder on the horn, for absolute position feedback.
+pub struct As5600<'d> {
+    i2c: I2cDriver<'d>,
+    cal: EncoderCalibration,
+}
+
+impl<'d> As5600<'d> {
+    pub fn new(i2c: I2cDriver<'d>, cal: EncoderCalibration) -> Self {
+        Self { i2c, cal }
+    }
+
+    /// Unscaled 12-bit shaft angle.
+    pub fn read_raw_angle(&mut self) -> Result<u16, EspError> {
+        let mut buf = [0u8; 2];
+        self.i2c.write_read(AS5600_ADDR, &[REG_RAW_ANGLE], &mut buf, BLOCK)?;
+        Ok(u16::from_be_bytes(buf) & (COUNTS_PER_REV - 1))
+    }
+
+    /// Calibrated vent angle.
+    pub fn read_angle(&mut self) -> Result<u8, EspError> {
+        let raw = self.read_raw_angle()?;
+        Ok(raw12_to_angle(raw, self.cal.zero_offset, self.cal.cal_min, self.cal.cal_max))
+    }
+}
+
+impl PositionFeedback for As5600<'_> {
+    fn read_angle(&mut self) -> Option<u8> {
+        match As5600::read_angle(self) {
+            Ok(angle) => Some(angle),
+            Err(e) => {
+                warn!("Encoder read failed: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_maps_calibrated_span() {
+        // Closed at 1000 counts, open at 2024 (90° of horn ≈ 1024 counts)
+        assert_eq!(raw12_to_angle(1000, 0, 1000, 2024), ANGLE_CLOSED);
+        assert_eq!(raw12_to_angle(2024, 0, 1000, 2024), ANGLE_OPEN);
+        assert_eq!(raw12_to_angle(1512, 0, 1000, 2024), 135);
+    }
+
+    #[test]
+    fn test_raw_clamped_outside_calibration() {
+        assert_eq!(raw12_to_angle(500, 0, 1000, 2024), ANGLE_CLOSED);
+        assert_eq!(raw12_to_angle(3000, 0, 1000, 2024), ANGLE_OPEN);
+        assert_eq!(raw12_to_angle(1234, 0, 1000, 1000), ANGLE_CLOSED);
+    }
+
+    #[test]
+    fn test_wraparound_across_encoder_zero() {
+        // Travel runs from raw 3800 through 4095/0 to raw 728; a zero
+        // offset of 3700 makes that 100..=1124 counts
+        assert_eq!(raw12_to_angle(3800, 3700, 100, 1124), ANGLE_CLOSED);
+        assert_eq!(raw12_to_angle(4095, 3700, 100, 1124), 116);
+        assert_eq!(raw12_to_angle(0, 3700, 100, 1124), 116);
+        assert_eq!(raw12_to_angle(728, 3700, 100, 1124), ANGLE_OPEN);
+    }
+
+    #[test]
+    fn test_reversed_magnet() {
+        assert_eq!(raw12_to_angle(2024, 0, 2024, 1000), ANGLE_CLOSED);
+        assert_eq!(raw12_to_angle(1000, 0, 2024, 1000), ANGLE_OPEN);
+        assert_eq!(raw12_to_angle(1512, 0, 2024, 1000), 135);
+    }
+}
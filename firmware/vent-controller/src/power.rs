@@ -1,5 +1,6 @@
+use crate::thread::ThreadManager;
 use esp_idf_sys::EspError;
-use log::info;
+use log::{info, warn};
 use std::time::Duration;
 
 /// Power mode configuration.
@@ -9,14 +10,23 @@ pub enum PowerMode {
     AlwaysOn,
     /// Sleepy End Device with configurable poll period. For battery-powered devices.
     Sed { poll_period_ms: u32 },
+    /// SED on a harvested supercap: long polls, and moves wait until the
+    /// stored energy can finish them.
+    Harvested { poll_period_ms: u32 },
 }
 
+/// Shortest poll period used on harvested power.
+pub const MIN_HARVESTED_POLL_MS: u32 = 30_000;
+
 impl PowerMode {
     pub fn from_nvs_str(s: &str, poll_ms: u32) -> Self {
         match s {
             "sed" => PowerMode::Sed {
                 poll_period_ms: poll_ms,
             },
+            "harvested" => PowerMode::Harvested {
+                poll_period_ms: poll_ms.max(MIN_HARVESTED_POLL_MS),
+            },
             _ => PowerMode::AlwaysOn,
         }
     }
@@ -25,40 +35,357 @@ impl PowerMode {
         match self {
             PowerMode::AlwaysOn => "always_on",
             PowerMode::Sed { .. } => "sed",
+            PowerMode::Harvested { .. } => "harvested",
         }
     }
 
     pub fn poll_period_ms(&self) -> u32 {
         match self {
             PowerMode::AlwaysOn => 0,
-            PowerMode::Sed { poll_period_ms } => *poll_period_ms,
+            PowerMode::Sed { poll_period_ms } | PowerMode::Harvested { poll_period_ms } => {
+                *poll_period_ms
+            }
         }
     }
 }
 
+/// Default supercap voltage (mV) below which the system browns out.
+pub const DEFAULT_BROWNOUT_MV: u32 = 3_000;
+/// Estimated supercap droop per degree of servo travel.
+pub const SUPERCAP_MV_PER_DEG: u32 = 5;
+/// Extra droop from the servo's start-up inrush.
+pub const SERVO_INRUSH_MV: u32 = 100;
+/// How often a move waiting on energy rechecks the supercap.
+pub const ENERGY_RECHECK_MS: u64 = 1_000;
+
+/// Whether a move of `move_degrees` can complete from `supercap_mv` without
+/// the supply dropping below `threshold_mv`.
+pub fn has_energy_budget(supercap_mv: u32, move_degrees: u8, threshold_mv: u32) -> bool {
+    let droop = SERVO_INRUSH_MV + move_degrees as u32 * SUPERCAP_MV_PER_DEG;
+    supercap_mv >= threshold_mv.saturating_add(droop)
+}
+
+/// Default battery level (mV) below which a battery vent refuses moves.
+pub const DEFAULT_MIN_MOVE_MV: u32 = 3_300;
+/// How far above the minimum a blocked battery must recover before moves
+/// are accepted again.
+pub const MOVE_GATE_HYSTERESIS_MV: u32 = 100;
+
+/// Refuses moves on a battery too low to finish them, so the louver stays
+/// put instead of stalling mid-travel. Once tripped it holds until the
+/// battery recovers past the hysteresis band, so a reading hovering at the
+/// threshold doesn't flip between accepting and rejecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BatteryMoveGate {
+    blocked: bool,
+}
+
+impl BatteryMoveGate {
+    pub fn can_move_on_battery(&mut self, mv: u32, min_mv: u32) -> bool {
+        let needed = if self.blocked {
+            min_mv.saturating_add(MOVE_GATE_HYSTERESIS_MV)
+        } else {
+            min_mv
+        };
+        self.blocked = mv < needed;
+        !self.blocked
+    }
+}
+
+/// Bounds on the Thread child timeout a SED may request, in seconds.
+pub const MIN_CHILD_TIMEOUT_S: u32 = 10;
+pub const MAX_CHILD_TIMEOUT_S: u32 = 24 * 3600;
+/// OpenThread's default child timeout.
+pub const DEFAULT_CHILD_TIMEOUT_S: u32 = 240;
+/// The child timeout must cover at least this many poll periods, so a few
+/// missed polls don't get the child dropped by its parent.
+pub const CHILD_TIMEOUT_POLL_RATIO: u32 = 4;
+
+/// Why a child timeout was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildTimeoutError {
+    OutOfRange,
+    /// Shorter than `CHILD_TIMEOUT_POLL_RATIO` poll periods.
+    TooShortForPoll,
+}
+
+/// Check a child timeout against the bounds and the configured poll period.
+pub fn validate_child_timeout(timeout_s: u32, poll_period_ms: u32) -> Result<(), ChildTimeoutError> {
+    if !(MIN_CHILD_TIMEOUT_S..=MAX_CHILD_TIMEOUT_S).contains(&timeout_s) {
+        return Err(ChildTimeoutError::OutOfRange);
+    }
+    if (timeout_s as u64) * 1000 < poll_period_ms as u64 * CHILD_TIMEOUT_POLL_RATIO as u64 {
+        return Err(ChildTimeoutError::TooShortForPoll);
+    }
+    Ok(())
+}
+
+/// Child timeout to use when none is configured: OpenThread's default,
+/// stretched if the poll period is long enough to need more.
+pub fn default_child_timeout_s(poll_period_ms: u32) -> u32 {
+    let needed = (poll_period_ms as u64 * CHILD_TIMEOUT_POLL_RATIO as u64).div_ceil(1000) as u32;
+    needed.clamp(DEFAULT_CHILD_TIMEOUT_S, MAX_CHILD_TIMEOUT_S)
+}
+
+/// Default delay after switching the servo rail on before the first step.
+pub const SERVO_RAIL_SETTLE_MS: u32 = 50;
+/// Longest settle NVS may ask for. Every move waits this out, so it also
+/// bounds how much the move timeout has to allow for it.
+pub const MAX_RAIL_SETTLE_MS: u32 = 1000;
+/// Default time the rail stays on after a move so the servo finishes seating.
+pub const SERVO_RAIL_HOLD_MS: u32 = 500;
+
+/// Servo rail output for the current main-loop pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RailState {
+    /// Whether the power-enable GPIO should be asserted.
+    pub powered: bool,
+    /// Whether the servo has settled and may be stepped.
+    pub may_step: bool,
+    /// The rail didn't report power-good within the settle window; the
+    /// rail has been switched off and the move should be abandoned.
+    pub fault: bool,
+}
+
+/// Rail settle time from NVS `rail_settle_ms`: the default when unset,
+/// capped at `MAX_RAIL_SETTLE_MS`.
+pub fn rail_settle_ms(stored: Option<u32>) -> u32 {
+    stored.unwrap_or(SERVO_RAIL_SETTLE_MS).min(MAX_RAIL_SETTLE_MS)
+}
+
+/// Progress of a rail that has just been switched on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RailCheck {
+    Wait,
+    Ready,
+    Fault,
+}
+
+/// Whether a rail switched on `elapsed_ms` ago may be stepped. The settle
+/// window always runs in full; at its end a sensed rail must read
+/// power-good. `power_good` is None without a sense pin.
+pub fn rail_power_check(power_good: Option<bool>, elapsed_ms: u64, settle_ms: u32) -> RailCheck {
+    if elapsed_ms < settle_ms as u64 {
+        RailCheck::Wait
+    } else if power_good == Some(false) {
+        RailCheck::Fault
+    } else {
+        RailCheck::Ready
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RailPhase {
+    Off,
+    Settling { since_ms: u64 },
+    Ready,
+    Holding { since_ms: u64 },
+}
+
+/// Sequences the servo power rail around a move:
+/// off → power on + settle → move → hold after commit → off.
+#[derive(Debug)]
+pub struct RailSequencer {
+    phase: RailPhase,
+    settle_ms: u32,
+    hold_ms: u32,
+}
+
+impl RailSequencer {
+    pub fn new(settle_ms: u32, hold_ms: u32) -> Self {
+        Self {
+            phase: RailPhase::Off,
+            settle_ms,
+            hold_ms,
+        }
+    }
+
+    /// A sequencer picking up a rail boot left switched on: it holds for
+    /// `hold_ms` from t=0 before cutting power, and a move in that window
+    /// starts without settling again.
+    pub fn already_powered(settle_ms: u32, hold_ms: u32) -> Self {
+        Self {
+            phase: RailPhase::Holding { since_ms: 0 },
+            settle_ms,
+            hold_ms,
+        }
+    }
+
+    /// Advance the sequence. `moving` is true while the state machine has a
+    /// move to make; `power_good` is the rail's sense input where one is
+    /// fitted.
+    pub fn tick(&mut self, now_ms: u64, moving: bool, power_good: Option<bool>) -> RailState {
+        let mut fault = false;
+        self.phase = match self.phase {
+            RailPhase::Off if moving => RailPhase::Settling { since_ms: now_ms },
+            RailPhase::Off => RailPhase::Off,
+            RailPhase::Settling { since_ms } => match rail_power_check(power_good, now_ms - since_ms, self.settle_ms) {
+                RailCheck::Wait => RailPhase::Settling { since_ms },
+                RailCheck::Ready => RailPhase::Ready,
+                RailCheck::Fault => {
+                    fault = true;
+                    RailPhase::Off
+                }
+            },
+            RailPhase::Ready if moving => RailPhase::Ready,
+            RailPhase::Ready => RailPhase::Holding { since_ms: now_ms },
+            RailPhase::Holding { .. } if moving => RailPhase::Ready,
+            RailPhase::Holding { since_ms } if now_ms - since_ms >= self.hold_ms as u64 => {
+                RailPhase::Off
+            }
+            holding @ RailPhase::Holding { .. } => holding,
+        };
+        RailState {
+            powered: self.phase != RailPhase::Off,
+            may_step: self.phase == RailPhase::Ready,
+            fault,
+        }
+    }
+}
+
+/// GPIOs of the optional switched outputs (XIAO D8, D9, D10).
+pub const SERVO_RAIL_GPIO: i32 = 19;
+pub const HEATER_GPIO: i32 = 20;
+pub const RELAY_GPIO: i32 = 18;
+
+/// Which switched outputs are fitted, for deciding what to latch in deep
+/// sleep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SleepHoldConfig {
+    /// Latch outputs at all (NVS `sleep_hold`).
+    pub enabled: bool,
+    pub servo_rail: bool,
+    pub heater: bool,
+    pub relay: bool,
+}
+
+/// Highest ESP32-C6 LP IO. Only GPIO0–7 sit in the LP domain and can keep
+/// a level through deep sleep; the HP pads power down with the digital
+/// domain and float, whatever was latched.
+pub const LP_GPIO_MAX: i32 = 7;
+
+/// Whether `gpio` can be held at its level through deep sleep.
+pub fn holds_in_deep_sleep(gpio: i32) -> bool {
+    (0..=LP_GPIO_MAX).contains(&gpio)
+}
+
+/// Supply voltage reading below which a battery or harvested vent sleeps
+/// off the shortfall instead of browning out mid-move.
+pub fn should_sleep_for_supply(supply_mv: u32, brownout_mv: u32) -> bool {
+    supply_mv < brownout_mv
+}
+
+/// How long a vent sleeps after `should_sleep_for_supply`, for a supercap
+/// to recharge or a cell to recover.
+pub const LOW_SUPPLY_SLEEP_S: u64 = 600;
+
+/// Switched outputs to drive off before deep sleep. Those on LP IOs are
+/// also latched (`holds_in_deep_sleep`); the fitted D8–D10 outputs are HP
+/// pads, so their MOSFET gates need a pull-down to stay off while the pad
+/// floats, or else a floating gate can switch the servo rail or heater
+/// back on.
+pub fn sleep_hold_pins(config: &SleepHoldConfig) -> Vec<i32> {
+    if !config.enabled {
+        return Vec::new();
+    }
+    [
+        (config.servo_rail, SERVO_RAIL_GPIO),
+        (config.heater, HEATER_GPIO),
+        (config.relay, RELAY_GPIO),
+    ]
+    .into_iter()
+    .filter_map(|(fitted, gpio)| fitted.then_some(gpio))
+    .collect()
+}
+
+/// Human-readable name for an `esp_reset_reason_t` code.
+pub fn reset_reason_str(code: u8) -> &'static str {
+    match code {
+        1 => "power_on",
+        2 => "external",
+        3 => "software",
+        4 => "panic",
+        5 => "int_wdt",
+        6 => "task_wdt",
+        7 => "wdt",
+        8 => "deep_sleep",
+        9 => "brownout",
+        10 => "sdio",
+        _ => "unknown",
+    }
+}
+
 /// Power manager handling deep sleep and SED configuration.
 pub struct PowerManager {
     mode: PowerMode,
+    /// Configured Thread child timeout; None uses the default for the
+    /// poll period.
+    child_timeout_s: Option<u32>,
+    /// Switched outputs driven off before deep sleep.
+    sleep_hold_pins: Vec<i32>,
 }
 
 impl PowerManager {
     pub fn new(mode: PowerMode) -> Self {
-        Self { mode }
+        Self {
+            mode,
+            child_timeout_s: None,
+            sleep_hold_pins: Vec::new(),
+        }
+    }
+
+    /// Drive these GPIOs off before deep sleep (see `sleep_hold_pins`).
+    pub fn with_sleep_hold(mut self, pins: Vec<i32>) -> Self {
+        self.sleep_hold_pins = pins;
+        self
+    }
+
+    /// Release the holds left over from deep sleep so the drivers can
+    /// switch the pins again. Call once the drivers have set their idle
+    /// level; releasing a pin that isn't held is harmless.
+    pub fn release_sleep_holds(&self) {
+        for &gpio in &self.sleep_hold_pins {
+            unsafe {
+                esp_idf_sys::gpio_hold_dis(gpio);
+            }
+        }
+        if !self.sleep_hold_pins.is_empty() {
+            unsafe {
+                esp_idf_sys::gpio_deep_sleep_hold_dis();
+            }
+        }
     }
 
-    pub fn mode(&self) -> PowerMode {
-        self.mode
+    /// Request a specific Thread child timeout for SED mode.
+    pub fn with_child_timeout(mut self, timeout_s: Option<u32>) -> Self {
+        self.child_timeout_s = timeout_s;
+        self
     }
 
-    /// Configure Thread SED poll period if in SED mode.
-    pub fn configure_sed(&self) -> Result<(), EspError> {
-        if let PowerMode::Sed { poll_period_ms } = self.mode {
+    /// Configure Thread SED poll period and child timeout if in SED mode.
+    pub fn configure_sed(&self, thread: &ThreadManager) -> Result<(), EspError> {
+        if let PowerMode::Sed { poll_period_ms } | PowerMode::Harvested { poll_period_ms } =
+            self.mode
+        {
             info!("Configuring SED mode with poll period {}ms", poll_period_ms);
             unsafe {
                 let instance = esp_idf_sys::esp_openthread_get_instance();
                 // Set the poll period for the sleepy end device
                 esp_idf_sys::otLinkSetPollPeriod(instance, poll_period_ms);
             }
+
+            let default_s = default_child_timeout_s(poll_period_ms);
+            let timeout_s = match self.child_timeout_s {
+                Some(t) => match validate_child_timeout(t, poll_period_ms) {
+                    Ok(()) => t,
+                    Err(e) => {
+                        warn!("Child timeout {}s rejected ({:?}), using {}s", t, e, default_s);
+                        default_s
+                    }
+                },
+                None => default_s,
+            };
+            thread.set_child_timeout(timeout_s);
         } else {
             info!("Power mode: always-on (MTD)");
         }
@@ -66,13 +393,25 @@ impl PowerManager {
     }
 
     /// Enter deep sleep for the specified duration.
-    /// State should be saved to NVS before calling this.
+    /// State should be saved to NVS before calling this. The switched
+    /// outputs are driven off here, and latched where the pad allows it.
     #[allow(unreachable_code)]
     pub fn enter_deep_sleep(&self, duration: Duration) -> ! {
         let us = duration.as_micros() as u64;
         info!("Entering deep sleep for {}ms", duration.as_millis());
 
         unsafe {
+            let mut held = false;
+            for &gpio in &self.sleep_hold_pins {
+                esp_idf_sys::gpio_set_level(gpio, 0);
+                if holds_in_deep_sleep(gpio) {
+                    esp_idf_sys::gpio_hold_en(gpio);
+                    held = true;
+                }
+            }
+            if held {
+                esp_idf_sys::gpio_deep_sleep_hold_en();
+            }
             esp_idf_sys::esp_sleep_enable_timer_wakeup(us);
             esp_idf_sys::esp_deep_sleep_start();
         }
@@ -80,12 +419,9 @@ impl PowerManager {
         unreachable!()
     }
 
-    /// Check if the device woke from deep sleep.
-    pub fn woke_from_sleep() -> bool {
-        unsafe {
-            let cause = esp_idf_sys::esp_sleep_get_wakeup_cause();
-            cause != esp_idf_sys::esp_sleep_source_t_ESP_SLEEP_WAKEUP_UNDEFINED
-        }
+    /// Get the reason for the most recent reset as a raw `esp_reset_reason_t`.
+    pub fn reset_reason() -> u8 {
+        unsafe { esp_idf_sys::esp_reset_reason() as u8 }
     }
 
     /// Get the wakeup cause as a string.
@@ -100,3 +436,222 @@ impl PowerManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_energy_budget_full_and_empty() {
+        // Plenty of charge for a full stroke
+        assert!(has_energy_budget(5_000, 90, DEFAULT_BROWNOUT_MV));
+        // Already at the brownout threshold: nothing can move
+        assert!(!has_energy_budget(3_000, 1, DEFAULT_BROWNOUT_MV));
+    }
+
+    #[test]
+    fn test_energy_budget_scales_with_move_length() {
+        // 3600 mV leaves 600 mV of headroom: 100 inrush + 5 mV/deg
+        assert!(has_energy_budget(3_600, 100, DEFAULT_BROWNOUT_MV));
+        assert!(has_energy_budget(3_600, 40, DEFAULT_BROWNOUT_MV));
+        assert!(!has_energy_budget(3_400, 90, DEFAULT_BROWNOUT_MV));
+        assert!(has_energy_budget(3_400, 20, DEFAULT_BROWNOUT_MV));
+    }
+
+    #[test]
+    fn test_battery_gate_blocks_below_minimum() {
+        let mut gate = BatteryMoveGate::default();
+        assert!(gate.can_move_on_battery(3_700, DEFAULT_MIN_MOVE_MV));
+        assert!(gate.can_move_on_battery(DEFAULT_MIN_MOVE_MV, DEFAULT_MIN_MOVE_MV));
+        assert!(!gate.can_move_on_battery(3_299, DEFAULT_MIN_MOVE_MV));
+    }
+
+    #[test]
+    fn test_battery_gate_hysteresis() {
+        let mut gate = BatteryMoveGate::default();
+        assert!(!gate.can_move_on_battery(3_250, 3_300));
+        // Noise back over the threshold isn't enough
+        assert!(!gate.can_move_on_battery(3_310, 3_300));
+        assert!(!gate.can_move_on_battery(3_399, 3_300));
+        assert!(gate.can_move_on_battery(3_400, 3_300));
+        // Re-armed: dipping inside the band is still fine
+        assert!(gate.can_move_on_battery(3_320, 3_300));
+    }
+
+    #[test]
+    fn test_harvested_mode_polls_slowly() {
+        let mode = PowerMode::from_nvs_str("harvested", 5_000);
+        assert_eq!(mode.poll_period_ms(), MIN_HARVESTED_POLL_MS);
+        assert_eq!(mode.as_str(), "harvested");
+        assert_eq!(PowerMode::from_nvs_str("harvested", 60_000).poll_period_ms(), 60_000);
+    }
+
+    #[test]
+    fn test_child_timeout_bounds() {
+        assert_eq!(validate_child_timeout(240, 5_000), Ok(()));
+        assert_eq!(validate_child_timeout(5, 1_000), Err(ChildTimeoutError::OutOfRange));
+        assert_eq!(
+            validate_child_timeout(MAX_CHILD_TIMEOUT_S + 1, 5_000),
+            Err(ChildTimeoutError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_child_timeout_must_exceed_poll_period() {
+        // 60s poll needs at least 240s
+        assert_eq!(
+            validate_child_timeout(120, 60_000),
+            Err(ChildTimeoutError::TooShortForPoll)
+        );
+        assert_eq!(validate_child_timeout(240, 60_000), Ok(()));
+    }
+
+    #[test]
+    fn test_default_child_timeout_covers_poll() {
+        assert_eq!(default_child_timeout_s(5_000), DEFAULT_CHILD_TIMEOUT_S);
+        assert_eq!(default_child_timeout_s(120_000), 480);
+        for poll_ms in [1_000, 30_000, 90_000, 600_000] {
+            let t = default_child_timeout_s(poll_ms);
+            assert_eq!(validate_child_timeout(t, poll_ms), Ok(()), "poll {}ms", poll_ms);
+        }
+    }
+
+    #[test]
+    fn test_sleep_hold_only_fitted_outputs() {
+        let config = SleepHoldConfig {
+            enabled: true,
+            servo_rail: true,
+            heater: false,
+            relay: true,
+        };
+        assert_eq!(sleep_hold_pins(&config), vec![SERVO_RAIL_GPIO, RELAY_GPIO]);
+        let bare = SleepHoldConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        assert!(sleep_hold_pins(&bare).is_empty());
+    }
+
+    #[test]
+    fn test_only_lp_pins_hold_in_deep_sleep() {
+        assert!(holds_in_deep_sleep(0));
+        assert!(holds_in_deep_sleep(LP_GPIO_MAX));
+        assert!(!holds_in_deep_sleep(LP_GPIO_MAX + 1));
+        for gpio in [SERVO_RAIL_GPIO, HEATER_GPIO, RELAY_GPIO] {
+            assert!(!holds_in_deep_sleep(gpio));
+        }
+    }
+
+    #[test]
+    fn test_sleep_for_supply_below_brownout() {
+        assert!(should_sleep_for_supply(2_900, DEFAULT_BROWNOUT_MV));
+        assert!(!should_sleep_for_supply(DEFAULT_BROWNOUT_MV, DEFAULT_BROWNOUT_MV));
+        assert!(!should_sleep_for_supply(3_600, DEFAULT_BROWNOUT_MV));
+    }
+
+    #[test]
+    fn test_sleep_hold_disabled() {
+        let config = SleepHoldConfig {
+            enabled: false,
+            servo_rail: true,
+            heater: true,
+            relay: true,
+        };
+        assert!(sleep_hold_pins(&config).is_empty());
+        let all = SleepHoldConfig { enabled: true, ..config };
+        assert_eq!(sleep_hold_pins(&all).len(), 3);
+    }
+
+    #[test]
+    fn test_rail_off_while_idle() {
+        let mut rail = RailSequencer::new(50, 500);
+        for now in 0..10 {
+            let st = rail.tick(now * 100, false, None);
+            assert!(!st.powered);
+            assert!(!st.may_step);
+        }
+    }
+
+    #[test]
+    fn test_rail_powers_on_and_settles_before_stepping() {
+        let mut rail = RailSequencer::new(50, 500);
+        let st = rail.tick(1000, true, None);
+        assert!(st.powered);
+        assert!(!st.may_step);
+        assert!(!rail.tick(1049, true, None).may_step);
+        let st = rail.tick(1050, true, None);
+        assert!(st.powered && st.may_step);
+    }
+
+    #[test]
+    fn test_rail_settle_default_and_cap() {
+        assert_eq!(rail_settle_ms(None), SERVO_RAIL_SETTLE_MS);
+        assert_eq!(rail_settle_ms(Some(200)), 200);
+        assert_eq!(rail_settle_ms(Some(60_000)), MAX_RAIL_SETTLE_MS);
+    }
+
+    #[test]
+    fn test_rail_power_check() {
+        assert_eq!(rail_power_check(None, 49, 50), RailCheck::Wait);
+        assert_eq!(rail_power_check(None, 50, 50), RailCheck::Ready);
+        // Power-good early still waits out the settle window
+        assert_eq!(rail_power_check(Some(true), 10, 50), RailCheck::Wait);
+        assert_eq!(rail_power_check(Some(true), 50, 50), RailCheck::Ready);
+        // Not good yet is fine until the window closes
+        assert_eq!(rail_power_check(Some(false), 49, 50), RailCheck::Wait);
+        assert_eq!(rail_power_check(Some(false), 50, 50), RailCheck::Fault);
+    }
+
+    #[test]
+    fn test_rail_without_power_good_faults_and_cuts_power() {
+        let mut rail = RailSequencer::new(50, 500);
+        assert!(rail.tick(0, true, Some(false)).powered);
+        let st = rail.tick(50, true, Some(false));
+        assert!(st.fault && !st.powered && !st.may_step);
+        // Move abandoned: the rail stays off
+        assert_eq!(
+            rail.tick(60, false, Some(false)),
+            RailState {
+                powered: false,
+                may_step: false,
+                fault: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_rail_holds_after_move_then_powers_off() {
+        let mut rail = RailSequencer::new(50, 500);
+        rail.tick(0, true, None);
+        rail.tick(50, true, None);
+        // Move completes at t=300
+        let st = rail.tick(300, false, None);
+        assert!(st.powered && !st.may_step);
+        assert!(rail.tick(799, false, None).powered);
+        assert!(!rail.tick(800, false, None).powered);
+    }
+
+    #[test]
+    fn test_rail_new_move_during_hold_skips_settle() {
+        let mut rail = RailSequencer::new(50, 500);
+        rail.tick(0, true, None);
+        rail.tick(50, true, None);
+        rail.tick(300, false, None);
+        // Still powered, so no need to settle again
+        let st = rail.tick(400, true, None);
+        assert!(st.powered && st.may_step);
+    }
+
+    #[test]
+    fn test_rail_left_on_by_boot_is_held_then_cut() {
+        let mut rail = RailSequencer::already_powered(50, 500);
+        assert!(rail.tick(0, false, None).powered);
+        assert!(rail.tick(499, false, None).powered);
+        assert!(!rail.tick(500, false, None).powered);
+
+        // A replayed move right after boot doesn't wait for a settle
+        let mut rail = RailSequencer::already_powered(50, 500);
+        let st = rail.tick(10, true, None);
+        assert!(st.powered && st.may_step);
+    }
+}
@@ -0,0 +1,276 @@
+use vent_protocol::{clamp_angle, ANGLE_CLOSED, ANGLE_OPEN};
+
+/// Maximum number of named presets stored in NVS.
+pub const MAX_PRESETS: usize = 8;
+/// Maximum preset name length in bytes.
+pub const MAX_NAME_LEN: usize = 15;
+
+/// A named vent position, e.g. "eco" = 120°.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub name: String,
+    pub angle: u8,
+}
+
+/// Why a preset table was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresetError {
+    TooMany,
+    /// Empty, too long, or not ASCII alphanumeric/underscore/dash.
+    InvalidName,
+    /// Two presets share a name (compared case-insensitively).
+    Duplicate,
+    /// NVS blob could not be parsed.
+    Malformed,
+    /// Mode angles do not open progressively from Closed to Open.
+    Unordered,
+}
+
+/// Look up a preset angle by name, ignoring ASCII case.
+pub fn resolve_preset(name: &str, presets: &[Preset]) -> Option<u8> {
+    presets
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name.trim()))
+        .map(|p| p.angle)
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// Check a preset table before storing it. Angles are clamped on use, so
+/// only names and count are validated here.
+pub fn validate_presets(presets: &[Preset]) -> Result<(), PresetError> {
+    if presets.len() > MAX_PRESETS {
+        return Err(PresetError::TooMany);
+    }
+    for (i, p) in presets.iter().enumerate() {
+        if !valid_name(&p.name) {
+            return Err(PresetError::InvalidName);
+        }
+        if presets[..i].iter().any(|q| q.name.eq_ignore_ascii_case(&p.name)) {
+            return Err(PresetError::Duplicate);
+        }
+    }
+    Ok(())
+}
+
+/// Encode presets for NVS: per entry, name length, name bytes, angle.
+pub fn encode_presets(presets: &[Preset]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for p in presets {
+        out.push(p.name.len() as u8);
+        out.extend_from_slice(p.name.as_bytes());
+        out.push(clamp_angle(p.angle));
+    }
+    out
+}
+
+/// Decode and validate presets read from NVS.
+pub fn decode_presets(mut raw: &[u8]) -> Result<Vec<Preset>, PresetError> {
+    let mut presets = Vec::new();
+    while let Some((&len, rest)) = raw.split_first() {
+        let len = len as usize;
+        if rest.len() < len + 1 {
+            return Err(PresetError::Malformed);
+        }
+        let name = core::str::from_utf8(&rest[..len]).map_err(|_| PresetError::Malformed)?;
+        presets.push(Preset {
+            name: name.to_string(),
+            angle: rest[len],
+        });
+        raw = &rest[len + 1..];
+    }
+    validate_presets(&presets)?;
+    Ok(presets)
+}
+
+/// Discrete airflow levels offered to Matter as a mode-select list, so a
+/// controller can show a dropdown alongside the position slider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AirflowMode {
+    Closed = 0,
+    Low = 1,
+    Medium = 2,
+    High = 3,
+    Open = 4,
+}
+
+impl AirflowMode {
+    pub const ALL: [AirflowMode; 5] = [
+        AirflowMode::Closed,
+        AirflowMode::Low,
+        AirflowMode::Medium,
+        AirflowMode::High,
+        AirflowMode::Open,
+    ];
+
+    /// Mode for a Matter mode value, or None if out of range.
+    pub fn from_u8(v: u8) -> Option<Self> {
+        Self::ALL.get(v as usize).copied()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AirflowMode::Closed => "closed",
+            AirflowMode::Low => "low",
+            AirflowMode::Medium => "medium",
+            AirflowMode::High => "high",
+            AirflowMode::Open => "open",
+        }
+    }
+}
+
+/// Angle for each `AirflowMode`, indexed by the mode's value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeTable(pub [u8; 5]);
+
+impl ModeTable {
+    /// Closed, open and three even steps between.
+    pub const DEFAULT: ModeTable = {
+        let q = (ANGLE_OPEN - ANGLE_CLOSED) / 4;
+        ModeTable([ANGLE_CLOSED, ANGLE_CLOSED + q, ANGLE_CLOSED + 2 * q, ANGLE_CLOSED + 3 * q, ANGLE_OPEN])
+    };
+}
+
+impl Default for ModeTable {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Target angle for a mode, clamped to the valid range.
+pub fn mode_to_angle(mode: AirflowMode, table: &ModeTable) -> u8 {
+    clamp_angle(table.0[mode as usize])
+}
+
+/// Mode whose angle is nearest `angle`; a tie goes to the more closed one.
+pub fn angle_to_mode(angle: u8, table: &ModeTable) -> AirflowMode {
+    AirflowMode::ALL
+        .into_iter()
+        .min_by_key(|&m| mode_to_angle(m, table).abs_diff(angle))
+        .unwrap_or(AirflowMode::Closed)
+}
+
+/// Check a mode table before storing it: each mode must be at least as
+/// open as the one before.
+pub fn validate_mode_table(table: &ModeTable) -> Result<(), PresetError> {
+    let angles = table.0.map(clamp_angle);
+    if angles.windows(2).all(|w| w[0] <= w[1]) {
+        Ok(())
+    } else {
+        Err(PresetError::Unordered)
+    }
+}
+
+/// Decode and validate a mode table read from NVS: one angle per mode.
+pub fn decode_mode_table(raw: &[u8]) -> Result<ModeTable, PresetError> {
+    let angles: [u8; 5] = raw.try_into().map_err(|_| PresetError::Malformed)?;
+    let table = ModeTable(angles);
+    validate_mode_table(&table)?;
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(name: &str, angle: u8) -> Preset {
+        Preset {
+            name: name.to_string(),
+            angle,
+        }
+    }
+
+    fn table() -> Vec<Preset> {
+        vec![preset("eco", 120), preset("comfort", 150), preset("boost", 180)]
+    }
+
+    #[test]
+    fn test_resolve_known_preset() {
+        assert_eq!(resolve_preset("eco", &table()), Some(120));
+        assert_eq!(resolve_preset("boost", &table()), Some(180));
+    }
+
+    #[test]
+    fn test_resolve_unknown_preset() {
+        assert_eq!(resolve_preset("turbo", &table()), None);
+        assert_eq!(resolve_preset("", &table()), None);
+        assert_eq!(resolve_preset("eco", &[]), None);
+    }
+
+    #[test]
+    fn test_resolve_ignores_case() {
+        assert_eq!(resolve_preset("Comfort", &table()), Some(150));
+        assert_eq!(resolve_preset("ECO", &table()), Some(120));
+        assert_eq!(resolve_preset(" boost ", &table()), Some(180));
+    }
+
+    #[test]
+    fn test_validate_presets() {
+        assert_eq!(validate_presets(&table()), Ok(()));
+        assert_eq!(
+            validate_presets(&[preset("eco", 120), preset("ECO", 130)]),
+            Err(PresetError::Duplicate)
+        );
+        assert_eq!(validate_presets(&[preset("", 120)]), Err(PresetError::InvalidName));
+        assert_eq!(
+            validate_presets(&[preset("a name", 120)]),
+            Err(PresetError::InvalidName)
+        );
+        let many: Vec<_> = (0..9).map(|i| preset(&format!("p{}", i), 120)).collect();
+        assert_eq!(validate_presets(&many), Err(PresetError::TooMany));
+    }
+
+    #[test]
+    fn test_presets_nvs_roundtrip() {
+        assert_eq!(decode_presets(&encode_presets(&table())), Ok(table()));
+        assert_eq!(decode_presets(&[]), Ok(vec![]));
+        assert_eq!(decode_presets(&[5, b'e', b'c']), Err(PresetError::Malformed));
+    }
+
+    #[test]
+    fn test_mode_to_angle_uses_table() {
+        let table = ModeTable([90, 110, 130, 160, 180]);
+        assert_eq!(mode_to_angle(AirflowMode::Closed, &table), 90);
+        assert_eq!(mode_to_angle(AirflowMode::Medium, &table), 130);
+        assert_eq!(mode_to_angle(AirflowMode::Open, &table), 180);
+        // Out-of-range entries are clamped
+        assert_eq!(mode_to_angle(AirflowMode::Closed, &ModeTable([0, 110, 130, 160, 255])), ANGLE_CLOSED);
+        assert_eq!(ModeTable::default(), ModeTable([90, 112, 134, 156, 180]));
+    }
+
+    #[test]
+    fn test_angle_to_mode_nearest() {
+        let table = ModeTable([90, 110, 130, 160, 180]);
+        assert_eq!(angle_to_mode(90, &table), AirflowMode::Closed);
+        assert_eq!(angle_to_mode(99, &table), AirflowMode::Closed);
+        assert_eq!(angle_to_mode(101, &table), AirflowMode::Low);
+        assert_eq!(angle_to_mode(150, &table), AirflowMode::High);
+        assert_eq!(angle_to_mode(175, &table), AirflowMode::Open);
+        // Equidistant between Low and Medium
+        assert_eq!(angle_to_mode(120, &table), AirflowMode::Low);
+        for m in AirflowMode::ALL {
+            assert_eq!(angle_to_mode(mode_to_angle(m, &table), &table), m);
+        }
+    }
+
+    #[test]
+    fn test_mode_from_u8() {
+        assert_eq!(AirflowMode::from_u8(0), Some(AirflowMode::Closed));
+        assert_eq!(AirflowMode::from_u8(4), Some(AirflowMode::Open));
+        assert_eq!(AirflowMode::from_u8(5), None);
+    }
+
+    #[test]
+    fn test_mode_table_validation() {
+        assert_eq!(decode_mode_table(&[90, 110, 130, 160, 180]), Ok(ModeTable([90, 110, 130, 160, 180])));
+        assert_eq!(decode_mode_table(&[90, 130, 110, 160, 180]), Err(PresetError::Unordered));
+        assert_eq!(decode_mode_table(&[90, 110, 130]), Err(PresetError::Malformed));
+        assert_eq!(validate_mode_table(&ModeTable([90, 90, 135, 180, 180])), Ok(()));
+    }
+}
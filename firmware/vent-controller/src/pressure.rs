@@ -0,0 +1,162 @@
+use crate::sensors::{Sensor, SensorKind, SensorScheduler};
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::i2c::I2cDriver;
+use esp_idf_sys::EspError;
+use log::warn;
+use vent_protocol::{PressureData, ANGLE_CLOSED, ANGLE_OPEN};
+
+/// I2C address of the Sensirion SDP3x/SDP8xx differential pressure sensor.
+const SDP_ADDR: u8 = 0x25;
+/// Start continuous differential-pressure measurement with averaging.
+const CMD_CONTINUOUS_AVG: [u8; 2] = [0x36, 0x15];
+
+/// Sensirion CRC-8 (poly 0x31, init 0xFF) over one 16-bit word.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Parse a 9-byte SDP measurement frame (pressure, temperature, scale
+/// factor — each a word plus CRC) into pascals. None on CRC mismatch or a
+/// zero scale factor.
+pub fn parse_frame(frame: &[u8; 9]) -> Option<i32> {
+    for word in frame.chunks_exact(3) {
+        if crc8(&word[..2]) != word[2] {
+            return None;
+        }
+    }
+    let raw = i16::from_be_bytes([frame[0], frame[1]]) as i32;
+    let scale = i16::from_be_bytes([frame[6], frame[7]]) as i32;
+    if scale == 0 {
+        return None;
+    }
+    Some(raw / scale)
+}
+
+/// Duct differential-pressure sensor on the shared I2C bus.
+pub struct PressureSensor<'d> {
+    i2c: I2cDriver<'d>,
+}
+
+impl<'d> PressureSensor<'d> {
+    /// Put the sensor into continuous measurement mode.
+    pub fn new(mut i2c: I2cDriver<'d>) -> Result<Self, EspError> {
+        i2c.write(SDP_ADDR, &CMD_CONTINUOUS_AVG, BLOCK)?;
+        Ok(Self { i2c })
+    }
+
+    /// Latest averaged differential pressure in pascals.
+    pub fn read_pascals(&mut self) -> Result<Option<i32>, EspError> {
+        let mut frame = [0u8; 9];
+        self.i2c.read(SDP_ADDR, &mut frame, BLOCK)?;
+        Ok(parse_frame(&frame))
+    }
+}
+
+impl Sensor for PressureSensor<'_> {
+    fn kind(&self) -> SensorKind {
+        SensorKind::Pressure
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        match self.read_pascals() {
+            Ok(pa) => pa,
+            Err(e) => {
+                warn!("Pressure read failed: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Pressure-hold automation settings persisted in NVS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PressureControl {
+    pub target_pa: i32,
+    /// Proportional gain in thousandths of a degree per pascal.
+    pub kp_milli: u16,
+}
+
+impl PressureControl {
+    pub fn kp(&self) -> f32 {
+        self.kp_milli as f32 / 1000.0
+    }
+}
+
+/// Latest cached pressure reading, for status reporting.
+pub fn pressure_data(
+    sensors: &SensorScheduler,
+    now_ms: u64,
+    control: Option<&PressureControl>,
+) -> Option<PressureData> {
+    sensors.latest(SensorKind::Pressure).map(|r| PressureData {
+        pascals: r.value,
+        age_ms: now_ms.saturating_sub(r.at_ms) as u32,
+        target_pa: control.map(|c| c.target_pa),
+    })
+}
+
+/// Proportional pressure hold. `error_pa` is measured minus target: too
+/// much static pressure opens the vent to relieve it, too little closes
+/// it. `kp` is degrees per pascal. The result stays within the vent range.
+pub fn p_control(error_pa: i32, kp: f32, current_angle: u8) -> u8 {
+    let next = current_angle as f32 + kp * error_pa as f32;
+    next.round().clamp(ANGLE_CLOSED as f32, ANGLE_OPEN as f32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(raw: i16, temp: i16, scale: i16) -> [u8; 9] {
+        let mut f = [0u8; 9];
+        for (i, word) in [raw, temp, scale].iter().enumerate() {
+            let b = word.to_be_bytes();
+            f[i * 3] = b[0];
+            f[i * 3 + 1] = b[1];
+            f[i * 3 + 2] = crc8(&b);
+        }
+        f
+    }
+
+    #[test]
+    fn test_crc8_datasheet_vector() {
+        assert_eq!(crc8(&[0xbe, 0xef]), 0x92);
+    }
+
+    #[test]
+    fn test_parse_frame() {
+        // SDP810-500Pa scale factor is 60 counts/Pa
+        assert_eq!(parse_frame(&frame(60 * 25, 0, 60)), Some(25));
+        assert_eq!(parse_frame(&frame(-60 * 12, 0, 60)), Some(-12));
+    }
+
+    #[test]
+    fn test_parse_frame_rejects_bad_crc_and_zero_scale() {
+        let mut f = frame(600, 0, 60);
+        f[2] ^= 1;
+        assert_eq!(parse_frame(&f), None);
+        assert_eq!(parse_frame(&frame(600, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_p_control_direction() {
+        // Pressure above target opens, below target closes
+        assert!(p_control(20, 0.5, 135) > 135);
+        assert!(p_control(-20, 0.5, 135) < 135);
+        assert_eq!(p_control(0, 0.5, 135), 135);
+        assert_eq!(p_control(20, 0.5, 135), 145);
+    }
+
+    #[test]
+    fn test_p_control_clamps_to_vent_range() {
+        assert_eq!(p_control(1_000, 1.0, 170), ANGLE_OPEN);
+        assert_eq!(p_control(-1_000, 1.0, 100), ANGLE_CLOSED);
+    }
+}
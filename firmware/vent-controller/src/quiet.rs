@@ -0,0 +1,104 @@
+use crate::maintenance;
+
+pub const MINUTES_PER_DAY: u16 = 24 * 60;
+/// How often the main loop checks whether quiet hours have ended.
+pub const QUIET_CHECK_INTERVAL_S: u64 = 30;
+
+/// Whether `now_min` (minute of day) falls in the window from `start` to
+/// `end`. The start is inclusive and the end exclusive; a window with
+/// `start > end` spans midnight, and `start == end` is empty.
+pub fn in_quiet_hours(now_min: u16, start: u16, end: u16) -> bool {
+    if start <= end {
+        now_min >= start && now_min < end
+    } else {
+        now_min >= start || now_min < end
+    }
+}
+
+/// Local minute of day for a Unix time and a UTC offset in minutes.
+pub fn minute_of_day(unix_s: u64, utc_offset_min: i16) -> u16 {
+    let utc_min = ((unix_s / 60) % MINUTES_PER_DAY as u64) as i32;
+    (utc_min + utc_offset_min as i32).rem_euclid(MINUTES_PER_DAY as i32) as u16
+}
+
+/// Quiet-hours window persisted in NVS, in local minutes of day. Only
+/// enforced once SNTP has set the wall clock (see `usable_now`); until
+/// then targets apply immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start_min: u16,
+    pub end_min: u16,
+    /// Local time offset from UTC, since SNTP only provides UTC.
+    pub utc_offset_min: i16,
+}
+
+impl QuietHours {
+    /// Whether the window covers `unix_s`.
+    pub fn contains(&self, unix_s: u64) -> bool {
+        in_quiet_hours(
+            minute_of_day(unix_s, self.utc_offset_min),
+            self.start_min,
+            self.end_min,
+        )
+    }
+
+    /// Whether quiet hours are in effect right now. Without a usable clock
+    /// the time of day is unknown, so the vent is never held quiet.
+    pub fn active_now(&self) -> bool {
+        maintenance::usable_now().is_some_and(|now| self.contains(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daytime_window() {
+        // 13:00–15:00
+        assert!(!in_quiet_hours(779, 780, 900));
+        assert!(in_quiet_hours(780, 780, 900));
+        assert!(in_quiet_hours(899, 780, 900));
+        assert!(!in_quiet_hours(900, 780, 900));
+    }
+
+    #[test]
+    fn test_window_spanning_midnight() {
+        // 22:00–07:00
+        assert!(in_quiet_hours(1320, 1320, 420));
+        assert!(in_quiet_hours(1439, 1320, 420));
+        assert!(in_quiet_hours(0, 1320, 420));
+        assert!(in_quiet_hours(419, 1320, 420));
+        assert!(!in_quiet_hours(420, 1320, 420));
+        assert!(!in_quiet_hours(720, 1320, 420));
+    }
+
+    #[test]
+    fn test_empty_window() {
+        assert!(!in_quiet_hours(0, 600, 600));
+        assert!(!in_quiet_hours(600, 600, 600));
+    }
+
+    #[test]
+    fn test_minute_of_day_with_offset() {
+        // 2025-01-01 00:30 UTC
+        let t = 1_735_691_400;
+        assert_eq!(minute_of_day(t, 0), 30);
+        assert_eq!(minute_of_day(t, 60), 90);
+        // West of UTC wraps back to the previous day
+        assert_eq!(minute_of_day(t, -300), 1440 - 270);
+    }
+
+    #[test]
+    fn test_contains_uses_local_time() {
+        let quiet = QuietHours {
+            start_min: 1320,
+            end_min: 420,
+            utc_offset_min: -300,
+        };
+        // 03:00 UTC is 22:00 at UTC-5
+        assert!(quiet.contains(1_735_700_400));
+        // 13:00 UTC is 08:00 local
+        assert!(!quiet.contains(1_735_736_400));
+    }
+}
@@ -0,0 +1,64 @@
+use esp_idf_hal::gpio::{AnyOutputPin, Output, PinDriver};
+use esp_idf_sys::EspError;
+
+/// How often the main loop re-evaluates the bypass relay.
+pub const RELAY_CHECK_INTERVAL_S: u64 = 5;
+
+/// Whether the HVAC bypass relay should be open.
+///
+/// With every vent in the zone closed the blower has nowhere to push air,
+/// so the bypass opens regardless of what was commanded; a manual override
+/// can open it at other times but never hold it shut against that.
+pub fn relay_should_open(all_closed: bool, manual_override: bool) -> bool {
+    all_closed || manual_override
+}
+
+/// Safety bypass relay on a GPIO (XIAO D10), for a zone-master vent wired
+/// to the furnace.
+pub struct BypassRelay<'d> {
+    coil: PinDriver<'d, AnyOutputPin, Output>,
+    open: bool,
+}
+
+impl<'d> BypassRelay<'d> {
+    /// Take the relay pin and start with the bypass closed.
+    pub fn new(pin: AnyOutputPin) -> Result<Self, EspError> {
+        let mut coil = PinDriver::output(pin)?;
+        coil.set_low()?;
+        Ok(Self { coil, open: false })
+    }
+
+    /// Open or close the bypass. No-op if already in that state.
+    pub fn set(&mut self, open: bool) -> Result<(), EspError> {
+        if open != self.open {
+            if open {
+                self.coil.set_high()?;
+            } else {
+                self.coil.set_low()?;
+            }
+            self.open = open;
+        }
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_closed_forces_bypass_open() {
+        assert!(relay_should_open(true, false));
+        assert!(relay_should_open(true, true));
+    }
+
+    #[test]
+    fn test_override_opens_when_zone_is_flowing() {
+        assert!(relay_should_open(false, true));
+        assert!(!relay_should_open(false, false));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::servo::{PositionFeedback, ServoDriver, STEP_DELAY_MS};
+use log::warn;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// How far the servo check moves away from the held angle, in degrees.
+pub const SERVO_CHECK_DEG: u8 = 10;
+/// Largest encoder error accepted by the servo check, in degrees.
+pub const SERVO_CHECK_TOLERANCE_DEG: u8 = 4;
+/// Time allowed for each leg of the servo check before reading back.
+const SERVO_CHECK_SETTLE_MS: u64 = 400;
+
+/// Outcome of one self-test check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestResult {
+    Pass,
+    Fail,
+    /// Not run: the subsystem is absent or not available right now.
+    Skipped,
+}
+
+impl TestResult {
+    pub fn from_check(ok: bool) -> Self {
+        if ok {
+            TestResult::Pass
+        } else {
+            TestResult::Fail
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestResult::Pass => "pass",
+            TestResult::Fail => "fail",
+            TestResult::Skipped => "skipped",
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            TestResult::Pass => 0,
+            TestResult::Fail => 1,
+            TestResult::Skipped => 2,
+        }
+    }
+}
+
+/// Per-subsystem results of a self-test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// Scratch-key write, read-back and erase.
+    pub nvs: TestResult,
+    /// Move away from the held angle and back.
+    pub servo: TestResult,
+    /// Attached to a Thread mesh.
+    pub radio: TestResult,
+}
+
+impl SelfTestReport {
+    pub fn overall(&self) -> TestResult {
+        aggregate(&[self.nvs, self.servo, self.radio])
+    }
+
+    /// Wire form: overall, NVS, servo, radio (`TestResult::as_u8`).
+    pub fn to_bytes(self) -> [u8; 4] {
+        [self.overall(), self.nvs, self.servo, self.radio].map(|r| r.as_u8())
+    }
+}
+
+/// Combine check results: any failure fails the self-test; otherwise it
+/// passes if at least one check ran.
+pub fn aggregate(results: &[TestResult]) -> TestResult {
+    if results.contains(&TestResult::Fail) {
+        TestResult::Fail
+    } else if results.contains(&TestResult::Pass) {
+        TestResult::Pass
+    } else {
+        TestResult::Skipped
+    }
+}
+
+/// Angle the servo check moves to: `deg` above `angle`, or below it when
+/// that would pass `hi`. Stays within `[lo, hi]`.
+pub fn check_away_angle(angle: u8, lo: u8, hi: u8, deg: u8) -> u8 {
+    let up = angle.saturating_add(deg);
+    if up <= hi {
+        up
+    } else {
+        angle.saturating_sub(deg).max(lo)
+    }
+}
+
+/// Whether a sensed angle is close enough to the commanded one. A failed
+/// read fails the check.
+pub fn readback_ok(commanded: u8, sensed: Option<u8>, tol: u8) -> bool {
+    sensed.is_some_and(|a| a.abs_diff(commanded) <= tol)
+}
+
+/// Move the servo away from `at` and back. Without an encoder only the
+/// PWM writes are checked; with one, both legs must read back in
+/// tolerance. Always finishes by commanding `at`.
+pub fn servo_check(
+    servo: &mut ServoDriver<'_>,
+    mut feedback: Option<&mut dyn PositionFeedback>,
+    at: u8,
+    lo: u8,
+    hi: u8,
+) -> TestResult {
+    let away = check_away_angle(at, lo, hi, SERVO_CHECK_DEG);
+    let mut ok = true;
+    for angle in [away, at] {
+        if let Err(e) = servo.set_angle(angle) {
+            warn!("Self-test: servo write failed: {:?}", e);
+            ok = false;
+            break;
+        }
+        let settle_ms = SERVO_CHECK_SETTLE_MS.max(at.abs_diff(away) as u64 * STEP_DELAY_MS as u64);
+        sleep(Duration::from_millis(settle_ms));
+        if let Some(fb) = feedback.as_mut() {
+            let sensed = fb.read_angle();
+            if !readback_ok(angle, sensed, SERVO_CHECK_TOLERANCE_DEG) {
+                warn!("Self-test: servo at {:?}, commanded {}°", sensed, angle);
+                ok = false;
+            }
+        }
+    }
+    let _ = servo.set_angle(at);
+    TestResult::from_check(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_any_failure_fails() {
+        use TestResult::*;
+        assert_eq!(aggregate(&[Pass, Fail, Pass]), Fail);
+        assert_eq!(aggregate(&[Skipped, Fail]), Fail);
+    }
+
+    #[test]
+    fn test_aggregate_skipped_checks_dont_fail() {
+        use TestResult::*;
+        assert_eq!(aggregate(&[Pass, Skipped, Pass]), Pass);
+        assert_eq!(aggregate(&[Skipped, Skipped]), Skipped);
+        assert_eq!(aggregate(&[]), Skipped);
+        let report = SelfTestReport {
+            nvs: Pass,
+            servo: Skipped,
+            radio: Pass,
+        };
+        assert_eq!(report.overall(), Pass);
+        assert_eq!(report.to_bytes(), [0, 0, 2, 0]);
+    }
+
+    #[test]
+    fn test_check_away_angle_stays_in_range() {
+        assert_eq!(check_away_angle(120, 90, 180, 10), 130);
+        assert_eq!(check_away_angle(175, 90, 180, 10), 165);
+        assert_eq!(check_away_angle(180, 175, 180, 10), 175);
+    }
+
+    #[test]
+    fn test_readback_tolerance() {
+        assert!(readback_ok(130, Some(127), 4));
+        assert!(!readback_ok(130, Some(120), 4));
+        assert!(!readback_ok(130, None, 4));
+    }
+}
@@ -0,0 +1,332 @@
+/// Kind of quantity a sensor reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorKind {
+    /// Milli-degrees Celsius.
+    Temperature,
+    /// Relative humidity in hundredths of a percent.
+    Humidity,
+    /// Pascals.
+    Pressure,
+    /// Supply (e.g. supercap) voltage in millivolts.
+    SupplyVoltage,
+    /// On-die chip temperature in whole degrees Celsius.
+    ChipTemperature,
+}
+
+/// Smoothing applied to supply voltage unless NVS overrides it, in
+/// thousandths. Low enough that a servo move's sag barely shows.
+pub const DEFAULT_SUPPLY_EMA_PERMILLE: u16 = 100;
+
+/// Exponential moving average over integer readings.
+///
+/// `alpha_permille` is the weight of each new sample in thousandths: 1000
+/// passes readings straight through, smaller values smooth harder. The
+/// average is kept in thousandths so small alphas still converge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ema {
+    alpha_permille: u16,
+    value_milli: Option<i64>,
+}
+
+impl Ema {
+    /// `alpha_permille` is clamped to 1..=1000.
+    pub fn new(alpha_permille: u16) -> Self {
+        Self {
+            alpha_permille: alpha_permille.clamp(1, 1000),
+            value_milli: None,
+        }
+    }
+
+    /// Fold in a sample and return the new average. The first sample seeds
+    /// the average directly.
+    pub fn update(&mut self, sample: i32) -> i32 {
+        let sample_milli = sample as i64 * 1000;
+        let next = match self.value_milli {
+            Some(v) => v + (sample_milli - v) * self.alpha_permille as i64 / 1000,
+            None => sample_milli,
+        };
+        self.value_milli = Some(next);
+        Self::round(next)
+    }
+
+    /// Current average, if any sample has been seen.
+    pub fn value(&self) -> Option<i32> {
+        self.value_milli.map(Self::round)
+    }
+
+    fn round(milli: i64) -> i32 {
+        (milli + if milli < 0 { -500 } else { 500 }) as i32 / 1000
+    }
+}
+
+/// A sensor that can be sampled on demand.
+pub trait Sensor {
+    fn kind(&self) -> SensorKind;
+    /// Take one reading. Errors are reported as None and leave the cached
+    /// value untouched.
+    fn read(&mut self) -> Option<i32>;
+}
+
+/// Most recent successful reading of a sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    pub value: i32,
+    /// Scheduler time (ms) when the reading was taken.
+    pub at_ms: u64,
+}
+
+struct Slot {
+    sensor: Box<dyn Sensor + Send>,
+    interval_ms: u64,
+    next_due_ms: u64,
+    latest: Option<Reading>,
+    smoothing: Option<Ema>,
+}
+
+/// Cooperative scheduler for sensors sharing one I2C bus.
+///
+/// Polled from the main loop; each sensor is read at its own interval and
+/// the latest value is cached so readers never block on the bus.
+#[derive(Default)]
+pub struct SensorScheduler {
+    slots: Vec<Slot>,
+    /// EMA weight per smoothed sensor kind.
+    ema_alpha: Vec<(SensorKind, u16)>,
+}
+
+impl SensorScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a sensor to be read every `interval_ms`. It is first read on
+    /// the next `poll`.
+    pub fn register(&mut self, sensor: Box<dyn Sensor + Send>, interval_ms: u64) {
+        let smoothing = self
+            .ema_alpha
+            .iter()
+            .find(|(kind, _)| *kind == sensor.kind())
+            .map(|&(_, alpha)| Ema::new(alpha));
+        self.slots.push(Slot {
+            sensor,
+            interval_ms,
+            next_due_ms: 0,
+            latest: None,
+            smoothing,
+        });
+    }
+
+    /// Smooth every reading of `kind` with an EMA of weight `alpha_permille`
+    /// (see `Ema`). `latest` then reports the average and `latest_raw` the
+    /// instantaneous value. Applies to sensors registered before or after.
+    pub fn set_smoothing(&mut self, kind: SensorKind, alpha_permille: u16) {
+        self.ema_alpha.retain(|(k, _)| *k != kind);
+        self.ema_alpha.push((kind, alpha_permille));
+        for slot in self.slots.iter_mut().filter(|s| s.sensor.kind() == kind) {
+            slot.smoothing = Some(Ema::new(alpha_permille));
+        }
+    }
+
+    /// Read every sensor that is due at `now_ms`. Returns how many were read.
+    pub fn poll(&mut self, now_ms: u64) -> usize {
+        let mut polled = 0;
+        for slot in self.slots.iter_mut().filter(|s| now_ms >= s.next_due_ms) {
+            if let Some(value) = slot.sensor.read() {
+                slot.latest = Some(Reading { value, at_ms: now_ms });
+                if let Some(ema) = slot.smoothing.as_mut() {
+                    ema.update(value);
+                }
+            }
+            slot.next_due_ms = now_ms + slot.interval_ms;
+            polled += 1;
+        }
+        polled
+    }
+
+    /// Latest cached reading for a sensor kind, if any. Smoothed sensors
+    /// report their running average, timestamped with the last sample.
+    pub fn latest(&self, kind: SensorKind) -> Option<Reading> {
+        let slot = self.slots.iter().find(|s| s.sensor.kind() == kind)?;
+        let latest = slot.latest?;
+        match slot.smoothing.and_then(|e| e.value()) {
+            Some(value) => Some(Reading { value, ..latest }),
+            None => Some(latest),
+        }
+    }
+
+    /// Latest unsmoothed reading, e.g. supply voltage under servo load for
+    /// sag diagnostics.
+    pub fn latest_raw(&self, kind: SensorKind) -> Option<Reading> {
+        self.slots
+            .iter()
+            .find(|s| s.sensor.kind() == kind)
+            .and_then(|s| s.latest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct FakeSensor {
+        kind: SensorKind,
+        reads: Arc<AtomicUsize>,
+        value: Option<i32>,
+    }
+
+    impl Sensor for FakeSensor {
+        fn kind(&self) -> SensorKind {
+            self.kind
+        }
+
+        fn read(&mut self) -> Option<i32> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            self.value
+        }
+    }
+
+    /// Returns each scripted value in turn, then fails.
+    struct ScriptedSensor {
+        values: std::collections::VecDeque<Option<i32>>,
+    }
+
+    impl Sensor for ScriptedSensor {
+        fn kind(&self) -> SensorKind {
+            SensorKind::Temperature
+        }
+
+        fn read(&mut self) -> Option<i32> {
+            self.values.pop_front().flatten()
+        }
+    }
+
+    fn fake(kind: SensorKind, value: Option<i32>) -> (Box<FakeSensor>, Arc<AtomicUsize>) {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let sensor = FakeSensor {
+            kind,
+            reads: reads.clone(),
+            value,
+        };
+        (Box::new(sensor), reads)
+    }
+
+    #[test]
+    fn test_each_sensor_polled_at_its_interval() {
+        let mut sched = SensorScheduler::new();
+        let (temp, temp_reads) = fake(SensorKind::Temperature, Some(21_500));
+        let (pressure, pressure_reads) = fake(SensorKind::Pressure, Some(12));
+        sched.register(temp, 1_000);
+        sched.register(pressure, 5_000);
+
+        // Fake clock ticking every 100ms for 10s
+        for now in (0..10_000).step_by(100) {
+            sched.poll(now);
+        }
+        assert_eq!(temp_reads.load(Ordering::SeqCst), 10);
+        assert_eq!(pressure_reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_not_polled_before_due() {
+        let mut sched = SensorScheduler::new();
+        let (temp, reads) = fake(SensorKind::Temperature, Some(0));
+        sched.register(temp, 1_000);
+
+        assert_eq!(sched.poll(0), 1);
+        assert_eq!(sched.poll(500), 0);
+        assert_eq!(sched.poll(999), 0);
+        assert_eq!(sched.poll(1_000), 1);
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_latest_reading_cached() {
+        let mut sched = SensorScheduler::new();
+        let (hum, _) = fake(SensorKind::Humidity, Some(4_550));
+        sched.register(hum, 1_000);
+
+        assert_eq!(sched.latest(SensorKind::Humidity), None);
+        sched.poll(42);
+        assert_eq!(
+            sched.latest(SensorKind::Humidity),
+            Some(Reading { value: 4_550, at_ms: 42 })
+        );
+        assert_eq!(sched.latest(SensorKind::Pressure), None);
+    }
+
+    #[test]
+    fn test_ema_converges() {
+        let mut ema = Ema::new(200);
+        assert_eq!(ema.value(), None);
+        assert_eq!(ema.update(3_000), 3_000);
+        for _ in 0..60 {
+            ema.update(3_600);
+        }
+        assert_eq!(ema.value(), Some(3_600));
+        // Alpha 1000 is a pass-through
+        let mut raw = Ema::new(1000);
+        raw.update(10);
+        assert_eq!(raw.update(-7), -7);
+    }
+
+    #[test]
+    fn test_ema_smooths_dip_but_tracks_sustained_drop() {
+        let mut ema = Ema::new(100);
+        ema.update(3_300);
+        // Two samples of servo sag barely move the average
+        ema.update(2_700);
+        let dipped = ema.update(2_700);
+        assert!(dipped > 3_180, "{}", dipped);
+        for _ in 0..5 {
+            ema.update(3_300);
+        }
+        assert!(ema.value().unwrap() > 3_220);
+        // A battery that really has dropped is followed
+        for _ in 0..60 {
+            ema.update(3_000);
+        }
+        assert!(ema.value().unwrap() <= 3_001);
+    }
+
+    #[test]
+    fn test_smoothed_sensor_reports_average_and_raw() {
+        struct Sagging(Vec<i32>);
+        impl Sensor for Sagging {
+            fn kind(&self) -> SensorKind {
+                SensorKind::SupplyVoltage
+            }
+            fn read(&mut self) -> Option<i32> {
+                self.0.pop()
+            }
+        }
+
+        let mut sched = SensorScheduler::new();
+        // Configured before the sensor exists, as at boot
+        sched.set_smoothing(SensorKind::SupplyVoltage, 500);
+        sched.register(Box::new(Sagging(vec![2_900, 3_300])), 1_000);
+        sched.poll(0);
+        sched.poll(1_000);
+        assert_eq!(
+            sched.latest(SensorKind::SupplyVoltage),
+            Some(Reading { value: 3_100, at_ms: 1_000 })
+        );
+        assert_eq!(sched.latest_raw(SensorKind::SupplyVoltage).map(|r| r.value), Some(2_900));
+    }
+
+    #[test]
+    fn test_failed_read_keeps_previous_value() {
+        let mut sched = SensorScheduler::new();
+        let temp = ScriptedSensor {
+            values: [Some(21_500), None].into(),
+        };
+        sched.register(Box::new(temp), 1_000);
+        sched.poll(0);
+        let good = Reading { value: 21_500, at_ms: 0 };
+        assert_eq!(sched.latest(SensorKind::Temperature), Some(good));
+        // The next read fails; the cached value and its timestamp stay
+        assert_eq!(sched.poll(1_000), 1);
+        assert_eq!(sched.latest(SensorKind::Temperature), Some(good));
+    }
+}
@@ -1,19 +1,41 @@
+use esp_idf_hal::gpio::{AnyInputPin, AnyOutputPin, Input, Output, PinDriver};
 use esp_idf_hal::ledc::LedcDriver;
 use esp_idf_sys::EspError;
 
+use crate::power::{rail_power_check, RailCheck};
+use crate::state::VentStateMachine;
+
 /// SG90 servo PWM parameters.
-const PWM_FREQ_HZ: u32 = 50;
+pub const PWM_FREQ_HZ: u32 = 50;
 const MIN_PULSE_US: u32 = 500;   // 0° position
 const MAX_PULSE_US: u32 = 2500;  // 180° position
 const PERIOD_US: u32 = 20_000;   // 50 Hz = 20ms
+/// LEDC timer driving the servo channel.
+const SERVO_TIMER: esp_idf_sys::ledc_timer_t = esp_idf_sys::ledc_timer_t_LEDC_TIMER_0;
 
 /// Step delay in milliseconds for gradual movement.
 pub const STEP_DELAY_MS: u32 = 15;
 
+/// Convert a logical angle (0–180) plus a mounting trim to a pulse width.
+/// The trimmed angle is clamped so the pulse never leaves the safe window.
+pub fn angle_to_pulse_us(angle: u8, trim_deg: i8) -> u32 {
+    let trimmed = (angle.min(180) as i32 + trim_deg as i32).clamp(0, 180) as u32;
+    MIN_PULSE_US + (trimmed * (MAX_PULSE_US - MIN_PULSE_US)) / 180
+}
+
 /// Servo driver wrapping LEDC PWM.
 pub struct ServoDriver<'d> {
     ledc: LedcDriver<'d>,
     max_duty: u32,
+    /// Constant offset applied to every commanded angle to correct for horn
+    /// mounting. The logical (reported) angle is unaffected.
+    trim_deg: i8,
+    /// Servo mounted mirror-image: logical 0° drives the horn to 180°.
+    inverted: bool,
+    /// Last duty written, so re-commanding the same angle is skipped.
+    last_duty: DutyCache,
+    /// PWM period at the current frequency.
+    period_us: u32,
 }
 
 impl<'d> ServoDriver<'d> {
@@ -22,33 +44,1093 @@ impl<'d> ServoDriver<'d> {
         ledc: LedcDriver<'d>,
     ) -> Result<Self, EspError> {
         let max_duty = ledc.get_max_duty();
-        Ok(Self { ledc, max_duty })
+        Ok(Self {
+            ledc,
+            max_duty,
+            trim_deg: 0,
+            inverted: false,
+            last_duty: DutyCache::default(),
+            period_us: PERIOD_US,
+        })
+    }
+
+    /// Retune the PWM frequency. Pulse widths are unchanged; only the
+    /// period they repeat at moves. The held angle is re-driven on the next
+    /// `set_angle`.
+    pub fn set_frequency(&mut self, hz: u32) -> Result<(), EspError> {
+        esp_idf_sys::esp!(unsafe {
+            esp_idf_sys::ledc_set_freq(esp_idf_sys::ledc_mode_t_LEDC_LOW_SPEED_MODE, SERVO_TIMER, hz)
+        })?;
+        self.period_us = 1_000_000 / hz.max(1);
+        self.last_duty.invalidate();
+        Ok(())
+    }
+
+    /// Set the global angle trim in degrees (positive = toward 180°).
+    pub fn set_trim(&mut self, trim_deg: i8) {
+        self.trim_deg = trim_deg;
     }
 
-    /// Set servo angle (0–180 degrees).
+    pub fn trim(&self) -> i8 {
+        self.trim_deg
+    }
+
+    /// Mirror every commanded angle for a servo mounted the other way round.
+    pub fn set_inverted(&mut self, inverted: bool) {
+        self.inverted = inverted;
+    }
+
+    pub fn inverted(&self) -> bool {
+        self.inverted
+    }
+
+    /// Set servo angle (0–180 degrees). Re-commanding the duty already on
+    /// the pin is a no-op, since rewriting it can make some servos twitch.
     pub fn set_angle(&mut self, angle: u8) -> Result<(), EspError> {
         let duty = self.angle_to_duty(angle);
-        self.ledc.set_duty(duty)?;
+        if self.last_duty.should_write(duty) {
+            self.ledc.set_duty(duty)?;
+            self.last_duty.record(duty);
+        }
         Ok(())
     }
 
-    /// Convert angle (0–180) to LEDC duty cycle value.
+    /// Convert angle (0–180) to LEDC duty cycle value, applying trim.
     fn angle_to_duty(&self, angle: u8) -> u32 {
-        let angle = angle.min(180) as u32;
-        let pulse_us = MIN_PULSE_US + (angle * (MAX_PULSE_US - MIN_PULSE_US)) / 180;
-        (pulse_us * self.max_duty) / PERIOD_US
+        let pulse_us = angle_to_pulse_us(physical_angle(angle, self.inverted), self.trim_deg);
+        (pulse_us * self.max_duty) / self.period_us
+    }
+
+    /// Rewrite the duty for `angle` even if it is already on the pin, so
+    /// an analog servo that has crept under load pulls back to position.
+    pub fn reassert(&mut self, angle: u8) -> Result<(), EspError> {
+        self.last_duty.invalidate();
+        self.set_angle(angle)
     }
 
     /// Disable PWM output (stop holding servo position).
     pub fn disable(&mut self) -> Result<(), EspError> {
         self.ledc.set_duty(0)?;
+        // The next set_angle must re-assert the position
+        self.last_duty.invalidate();
+        Ok(())
+    }
+}
+
+/// Remembers the last duty written to the LEDC channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCache {
+    last: Option<u32>,
+}
+
+impl DutyCache {
+    /// Whether `duty` differs from what is already on the pin.
+    pub fn should_write(&self, duty: u32) -> bool {
+        self.last != Some(duty)
+    }
+
+    pub fn record(&mut self, duty: u32) {
+        self.last = Some(duty);
+    }
+
+    /// Forget the last duty, e.g. after PWM was switched off.
+    pub fn invalidate(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Horn angle for a logical angle, mirrored when the servo is inverted.
+pub fn physical_angle(angle: u8, inverted: bool) -> u8 {
+    let angle = angle.min(180);
+    if inverted {
+        180 - angle
+    } else {
+        angle
+    }
+}
+
+/// Absolute shaft position feedback, e.g. a magnetic encoder on the horn.
+pub trait PositionFeedback {
+    /// Sensed horn angle in degrees, or None if the read failed.
+    fn read_angle(&mut self) -> Option<u8>;
+}
+
+/// Size of the probe move used to detect inversion.
+pub const INVERSION_PROBE_DEG: u8 = 10;
+/// Sensed motion below this is treated as "didn't move" (stalled or no
+/// feedback), and no conclusion is drawn.
+pub const MIN_SENSED_DELTA_DEG: i16 = 3;
+/// Time for the probe move to complete before sampling feedback.
+const INVERSION_PROBE_SETTLE_MS: u64 = 400;
+
+/// Whether sensed motion opposes the commanded motion.
+pub fn infer_inversion(commanded_delta: i16, sensed_delta: i16) -> bool {
+    commanded_delta.signum() * sensed_delta.signum() < 0
+}
+
+/// Angle to probe inversion from `at_angle` without leaving `lo..=hi`:
+/// toward whichever end has more room. None if there isn't room for a
+/// full probe either way.
+pub fn inversion_probe(at_angle: u8, lo: u8, hi: u8) -> Option<u8> {
+    let up = hi.saturating_sub(at_angle);
+    let down = at_angle.saturating_sub(lo);
+    if up >= down && up >= INVERSION_PROBE_DEG {
+        Some(at_angle + INVERSION_PROBE_DEG)
+    } else if down >= INVERSION_PROBE_DEG {
+        Some(at_angle - INVERSION_PROBE_DEG)
+    } else {
+        None
+    }
+}
+
+/// Probe the servo with a small move from `at_angle`, inside the travel
+/// `lo..=hi`, and compare the commanded direction with the sensed one.
+/// Returns the inversion setting the servo should use, or None if there
+/// is no room to probe or the feedback didn't show a clear move. The probe
+/// runs with the current setting (so the horn never swings to its mirror
+/// position) and the servo is returned to `at_angle`.
+pub fn detect_inversion(
+    servo: &mut ServoDriver<'_>,
+    feedback: &mut dyn PositionFeedback,
+    at_angle: u8,
+    lo: u8,
+    hi: u8,
+) -> Result<Option<bool>, EspError> {
+    let Some(probe) = inversion_probe(at_angle, lo, hi) else {
+        return Ok(None);
+    };
+
+    servo.set_angle(at_angle)?;
+    std::thread::sleep(std::time::Duration::from_millis(INVERSION_PROBE_SETTLE_MS));
+    let before = feedback.read_angle();
+    servo.set_angle(probe)?;
+    std::thread::sleep(std::time::Duration::from_millis(INVERSION_PROBE_SETTLE_MS));
+    let after = feedback.read_angle();
+    servo.set_angle(at_angle)?;
+
+    let (Some(before), Some(after)) = (before, after) else {
+        return Ok(None);
+    };
+    let sensed = after as i16 - before as i16;
+    if sensed.abs() < MIN_SENSED_DELTA_DEG {
+        return Ok(None);
+    }
+    // Feedback reports vent coordinates, so motion against the logical
+    // command means the current setting is wrong
+    let commanded = probe as i16 - at_angle as i16;
+    Ok(Some(servo.inverted() != infer_inversion(commanded, sensed)))
+}
+
+/// PWM frequencies tried when auto-detecting an unknown servo. The 2.5 ms
+/// maximum pulse still fits in the 330 Hz period.
+pub const CANDIDATE_FREQS_HZ: [u32; 4] = [50, 100, 200, 330];
+/// Lowest tracking score accepted as clean motion.
+pub const MIN_FREQ_SCORE: f32 = 0.8;
+/// Time for each test position to settle before sampling feedback.
+const FREQ_PROBE_SETTLE_MS: u64 = 500;
+
+/// How well sensed positions tracked commanded ones, from 1.0 (exact) down
+/// to 0.0 (off by the full vent range on average, or no usable samples).
+/// A missed sample counts as a full-range miss.
+pub fn score_frequency(commanded: &[u8], sensed: &[u8]) -> f32 {
+    if commanded.is_empty() {
+        return 0.0;
+    }
+    let span = (vent_protocol::ANGLE_OPEN - vent_protocol::ANGLE_CLOSED) as u32;
+    let total_err: u32 = commanded
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| sensed.get(i).map_or(span, |&a| (c.abs_diff(a) as u32).min(span)))
+        .sum();
+    1.0 - total_err as f32 / (commanded.len() as u32 * span) as f32
+}
+
+/// The best-scoring frequency, if any scored at least `MIN_FREQ_SCORE`.
+pub fn pick_frequency(results: &[(u32, f32)]) -> Option<u32> {
+    results
+        .iter()
+        .filter(|(_, score)| *score >= MIN_FREQ_SCORE)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|&(hz, _)| hz)
+}
+
+/// Drive the servo through `lo`, the midpoint and `hi` at each candidate
+/// frequency and score how well the feedback followed. Leaves the servo at
+/// the best frequency (or `current_hz` if none tracked cleanly) and back
+/// at `at_angle`. Returns the chosen frequency, if any.
+pub fn detect_frequency(
+    servo: &mut ServoDriver<'_>,
+    feedback: &mut dyn PositionFeedback,
+    current_hz: u32,
+    lo: u8,
+    hi: u8,
+    at_angle: u8,
+) -> Result<Option<u32>, EspError> {
+    let commanded = [lo, lo + (hi - lo) / 2, hi];
+    let mut results = Vec::with_capacity(CANDIDATE_FREQS_HZ.len());
+    for hz in CANDIDATE_FREQS_HZ {
+        servo.set_frequency(hz)?;
+        let mut sensed = Vec::with_capacity(commanded.len());
+        for &angle in &commanded {
+            servo.set_angle(angle)?;
+            std::thread::sleep(std::time::Duration::from_millis(FREQ_PROBE_SETTLE_MS));
+            // A failed read counts against this frequency
+            sensed.push(feedback.read_angle().unwrap_or(0));
+        }
+        let score = score_frequency(&commanded, &sensed);
+        log::info!("Servo frequency {} Hz: tracking score {:.2}", hz, score);
+        results.push((hz, score));
+    }
+    let chosen = pick_frequency(&results);
+    servo.set_frequency(chosen.unwrap_or(current_hz))?;
+    servo.set_angle(at_angle)?;
+    Ok(chosen)
+}
+
+/// Consecutive in-tolerance feedback samples required to call a move done.
+pub const SETTLE_SAMPLES: usize = 3;
+/// Default settle tolerance in degrees.
+pub const SETTLE_TOL_DEG: u8 = 2;
+/// Interval between settle samples.
+const SETTLE_SAMPLE_MS: u64 = 40;
+/// Give up waiting after this long; a servo still hunting by then is
+/// committed anyway rather than holding the move open.
+const SETTLE_TIMEOUT_MS: u64 = 1000;
+
+/// True when the last `SETTLE_SAMPLES` sensed angles are all within `tol`
+/// of `target`. Fewer samples than that is never settled.
+pub fn is_settled(samples: &[u8], target: u8, tol: u8) -> bool {
+    samples.len() >= SETTLE_SAMPLES
+        && samples[samples.len() - SETTLE_SAMPLES..]
+            .iter()
+            .all(|&a| a.abs_diff(target) <= tol)
+}
+
+/// Sample feedback until the horn is settled at `target` or the timeout
+/// passes. Returns whether it settled.
+pub fn wait_settled(feedback: &mut dyn PositionFeedback, target: u8, tol: u8) -> bool {
+    let mut samples = Vec::with_capacity(SETTLE_SAMPLES);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(SETTLE_TIMEOUT_MS);
+    while std::time::Instant::now() < deadline {
+        if let Some(angle) = feedback.read_angle() {
+            if samples.len() == SETTLE_SAMPLES {
+                samples.remove(0);
+            }
+            samples.push(angle);
+            if is_settled(&samples, target, tol) {
+                return true;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(SETTLE_SAMPLE_MS));
+    }
+    false
+}
+
+/// Detects the servo pushing against a mechanical stop, e.g. by watching
+/// its supply current.
+pub trait StallSense {
+    /// True while the servo is stalled.
+    fn stalled(&mut self) -> bool;
+
+    /// Forget any history before a new sweep starts.
+    fn reset(&mut self) {}
+}
+
+/// Stall sensing from position feedback: the servo is taken to be pushing
+/// on a stop once the sensed angle stops changing between learning steps.
+pub struct FeedbackStall<'a> {
+    feedback: &'a mut dyn PositionFeedback,
+    last: Option<u8>,
+}
+
+impl<'a> FeedbackStall<'a> {
+    pub fn new(feedback: &'a mut dyn PositionFeedback) -> Self {
+        Self { feedback, last: None }
+    }
+}
+
+impl StallSense for FeedbackStall<'_> {
+    fn stalled(&mut self) -> bool {
+        let sensed = self.feedback.read_angle();
+        let stalled = feedback_stalled(self.last, sensed);
+        if sensed.is_some() {
+            self.last = sensed;
+        }
+        stalled
+    }
+
+    fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// True when two feedback reads one learning step apart show less than
+/// half a step of motion. A failed read never counts as a stall.
+pub fn feedback_stalled(before: Option<u8>, after: Option<u8>) -> bool {
+    matches!((before, after), (Some(b), Some(a)) if a.abs_diff(b) < LEARN_STEP_DEG / 2)
+}
+
+/// Step size while learning limits; small so the stop is found gently.
+pub const LEARN_STEP_DEG: u8 = 2;
+/// Pause between learning steps, well above the normal step delay.
+const LEARN_STEP_MS: u64 = 80;
+
+/// Learned travel limits in logical degrees, used as soft limits on
+/// every target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftLimits {
+    pub closed: u8,
+    pub open: u8,
+}
+
+impl SoftLimits {
+    /// Keep `angle` inside the learned travel.
+    pub fn clamp(&self, angle: u8) -> u8 {
+        angle.clamp(self.closed, self.open)
+    }
+}
+
+/// Limit angle from a learning sweep that started at `from`. `angles` are
+/// the positions visited in order and the servo stalled on
+/// `stalled_at_index`, so the stop lies just before it: the limit is the
+/// last angle reached freely. A stall on the first step leaves `from` as
+/// the limit; an index past the end (no stall) gives the last angle
+/// visited.
+pub fn learned_limit_from_stall(from: u8, angles: &[u8], stalled_at_index: usize) -> u8 {
+    match stalled_at_index.min(angles.len()) {
+        0 => from,
+        i => angles[i - 1],
+    }
+}
+
+/// Positions visited when sweeping from `from` to `toward` in
+/// `LEARN_STEP_DEG` steps, ending exactly on `toward`.
+pub fn learn_sweep(from: u8, toward: u8) -> Vec<u8> {
+    let mut angles = Vec::new();
+    let mut angle = from;
+    while angle != toward {
+        angle = if toward > angle {
+            angle.saturating_add(LEARN_STEP_DEG).min(toward)
+        } else {
+            angle.saturating_sub(LEARN_STEP_DEG).max(toward)
+        };
+        angles.push(angle);
+    }
+    angles
+}
+
+/// Step `vent` to `angle` one degree at a time, driving the servo along
+/// and pausing `step_ms` after each degree.
+pub fn step_to(
+    servo: &mut ServoDriver<'_>,
+    vent: &mut VentStateMachine,
+    angle: u8,
+    step_ms: u64,
+) -> Result<(), EspError> {
+    vent.set_target(angle);
+    while vent.step() {
+        servo.set_angle(vent.current_angle())?;
+        std::thread::sleep(std::time::Duration::from_millis(step_ms));
+    }
+    Ok(())
+}
+
+/// Drive slowly from where `vent` is toward `toward` until the stall
+/// sensor trips and return the learned limit. With no stall the full sweep
+/// is free and `toward` itself is the limit. The servo is left at the
+/// limit so it stops pushing on the stop.
+pub fn learn_limit(
+    servo: &mut ServoDriver<'_>,
+    vent: &mut VentStateMachine,
+    sense: &mut dyn StallSense,
+    toward: u8,
+) -> Result<u8, EspError> {
+    let from = vent.current_angle();
+    let angles = learn_sweep(from, toward);
+    let per_degree_ms = LEARN_STEP_MS / LEARN_STEP_DEG as u64;
+    sense.reset();
+    let mut stalled_at = angles.len();
+    for (i, &angle) in angles.iter().enumerate() {
+        step_to(servo, vent, angle, per_degree_ms)?;
+        if sense.stalled() {
+            stalled_at = i;
+            break;
+        }
+    }
+    let limit = learned_limit_from_stall(from, &angles, stalled_at);
+    step_to(servo, vent, limit, per_degree_ms)?;
+    Ok(limit)
+}
+
+/// Learn both travel limits, working out from the middle of the vent range
+/// toward each end. Every move goes through `vent`, so it always knows
+/// where the horn is; it is left at the open limit. The caller persists
+/// the result.
+pub fn learn_soft_limits(
+    servo: &mut ServoDriver<'_>,
+    vent: &mut VentStateMachine,
+    sense: &mut dyn StallSense,
+) -> Result<SoftLimits, EspError> {
+    let mid = vent_protocol::ANGLE_CLOSED + (vent_protocol::ANGLE_OPEN - vent_protocol::ANGLE_CLOSED) / 2;
+    step_to(servo, vent, mid, STEP_DELAY_MS as u64)?;
+    let closed = learn_limit(servo, vent, sense, vent_protocol::ANGLE_CLOSED)?;
+    step_to(servo, vent, mid, STEP_DELAY_MS as u64)?;
+    let open = learn_limit(servo, vent, sense, vent_protocol::ANGLE_OPEN)?;
+    Ok(SoftLimits { closed, open })
+}
+
+/// Unsaved travel (degrees) that triggers a persist — one full stroke, so
+/// NVS sees at most one write per open/close cycle.
+pub const TRAVEL_PERSIST_THRESHOLD_DEG: u32 = 90;
+
+/// Cumulative servo travel, the main predictor of SG90 gear wear.
+///
+/// Degrees are counted in RAM as the servo steps; the total is written to
+/// NVS from the move-complete path once enough travel has built up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TravelCounter {
+    total_deg: u32,
+    unsaved_deg: u32,
+}
+
+impl TravelCounter {
+    /// Resume from the total persisted in NVS.
+    pub fn new(persisted_deg: u32) -> Self {
+        Self {
+            total_deg: persisted_deg,
+            unsaved_deg: 0,
+        }
+    }
+
+    pub fn add(&mut self, deg: u32) {
+        self.total_deg = self.total_deg.saturating_add(deg);
+        self.unsaved_deg = self.unsaved_deg.saturating_add(deg);
+    }
+
+    pub fn total_deg(&self) -> u32 {
+        self.total_deg
+    }
+
+    /// Whether enough travel has accumulated to be worth an NVS write.
+    pub fn should_persist(&self) -> bool {
+        self.unsaved_deg >= TRAVEL_PERSIST_THRESHOLD_DEG
+    }
+
+    /// Record that the current total has been written to NVS.
+    pub fn mark_persisted(&mut self) {
+        self.unsaved_deg = 0;
+    }
+
+    /// Start over from zero, e.g. after replacing the servo.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Whether the servo should hold position. A unit configured to ship with
+/// its servo loose stays detached until it has been commissioned, so the
+/// louver can be set by hand during mounting.
+pub fn should_engage_servo(detach_until_commissioned: bool, commissioned: bool) -> bool {
+    !detach_until_commissioned || commissioned
+}
+
+/// Angle to re-enable PWM at after a detach, so the horn isn't snapped
+/// away from wherever the louver was left by hand. A sensed position wins;
+/// without feedback (or if the read failed) the last committed angle is
+/// the best guess.
+pub fn reengage_angle(has_feedback: bool, sensed: Option<u8>, last_committed: u8) -> u8 {
+    match sensed {
+        Some(angle) if has_feedback => vent_protocol::clamp_angle(angle),
+        _ => last_committed,
+    }
+}
+
+/// Whether a stationary servo is due a creep-correction pulse. An
+/// interval of 0 disables correction.
+pub fn should_reassert(last_reassert_ms: u64, now_ms: u64, interval_ms: u64) -> bool {
+    interval_ms > 0 && now_ms.saturating_sub(last_reassert_ms) >= interval_ms
+}
+
+/// Size of the prove-alive wiggle either side of the held angle.
+pub const NUDGE_DEG: u8 = 1;
+/// Shortest gap between two prove-alive nudges.
+pub const NUDGE_MIN_INTERVAL_MS: u64 = 5 * 60 * 1000;
+
+/// Prove-alive wiggle: one degree up, one degree down, then back to
+/// exactly `angle`. A side that would leave `[lo, hi]` is skipped so a
+/// vent sitting on a stop isn't driven into it.
+pub fn nudge_sequence(angle: u8, lo: u8, hi: u8) -> Vec<u8> {
+    let mut seq = Vec::with_capacity(3);
+    let up = angle.saturating_add(NUDGE_DEG).min(hi);
+    if up > angle {
+        seq.push(up);
+    }
+    let down = angle.saturating_sub(NUDGE_DEG).max(lo);
+    if down < angle {
+        seq.push(down);
+    }
+    seq.push(angle);
+    seq
+}
+
+/// Whether a nudge may run now, given when the last one did.
+pub fn nudge_allowed(last_nudge_ms: Option<u64>, now_ms: u64, min_interval_ms: u64) -> bool {
+    last_nudge_ms.is_none_or(|last| now_ms.saturating_sub(last) >= min_interval_ms)
+}
+
+/// Largest allowed overshoot past the closed stop, in degrees.
+pub const MAX_SEAT_OVERSHOOT_DEG: u8 = 15;
+
+/// Seat-on-close settings persisted in NVS. An overshoot of 0 disables it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SeatConfig {
+    /// Degrees to drive past the closed angle.
+    pub overshoot_deg: u8,
+    /// How long to hold the overshoot before backing off.
+    pub hold_ms: u16,
+}
+
+impl SeatConfig {
+    pub fn enabled(&self) -> bool {
+        self.overshoot_deg > 0
+    }
+}
+
+/// One servo position in a seat sequence, held for `hold_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeatStep {
+    pub angle: u8,
+    pub hold_ms: u32,
+}
+
+/// Positions to seat the louver after reaching `closed_angle`, the vent's
+/// effective closed position (learned limit and airflow floor applied):
+/// overshoot past it (away from open), hold, then return. The overshoot is
+/// capped and kept within the 0–180° pulse window. Empty when seating is
+/// disabled.
+pub fn seat_sequence(closed_angle: u8, config: &SeatConfig) -> Vec<SeatStep> {
+    if !config.enabled() {
+        return Vec::new();
+    }
+    let overshoot = config.overshoot_deg.min(MAX_SEAT_OVERSHOOT_DEG);
+    let past = closed_angle.saturating_sub(overshoot);
+    vec![
+        SeatStep {
+            angle: past,
+            hold_ms: config.hold_ms as u32,
+        },
+        SeatStep {
+            angle: closed_angle,
+            hold_ms: 0,
+        },
+    ]
+}
+
+/// Largest allowed start kick, in degrees.
+pub const MAX_KICK_DEG: u8 = 10;
+/// Longest allowed kick hold; the whole move waits on it.
+pub const MAX_KICK_HOLD_MS: u16 = 500;
+/// How long the louver must have sat still before a move starts with a
+/// kick; static friction builds up while it rests.
+pub const KICK_MIN_IDLE_MS: u64 = 60_000;
+
+/// Start-kick settings persisted in NVS. A kick of 0 disables it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KickConfig {
+    /// Degrees to drive ahead of the start angle.
+    pub kick_deg: u8,
+    /// How long to hold the kick before normal stepping resumes.
+    pub hold_ms: u16,
+}
+
+impl KickConfig {
+    pub fn enabled(&self) -> bool {
+        self.kick_deg > 0
+    }
+
+    /// Hold time actually used, capped at `MAX_KICK_HOLD_MS`; 0 when
+    /// disabled.
+    pub fn capped_hold_ms(&self) -> u32 {
+        if self.enabled() {
+            self.hold_ms.min(MAX_KICK_HOLD_MS) as u32
+        } else {
+            0
+        }
+    }
+}
+
+/// Momentary position that breaks a stiff louver loose at the start of a
+/// move from `from` toward `target`: a jump ahead in the direction of
+/// travel, held, after which normal stepping resumes. Only after the
+/// louver has been idle for `KICK_MIN_IDLE_MS`. The kick is capped, never
+/// passes the target, and stays within the 0–180° pulse window. None when
+/// disabled or not needed.
+pub fn kick_step(from: u8, target: u8, idle_ms: u64, config: &KickConfig) -> Option<SeatStep> {
+    if !config.enabled() || from == target || idle_ms < KICK_MIN_IDLE_MS {
+        return None;
+    }
+    let kick = config.kick_deg.min(MAX_KICK_DEG);
+    let angle = if target > from {
+        from.saturating_add(kick).min(180).min(target)
+    } else {
+        from.saturating_sub(kick).max(target)
+    };
+    Some(SeatStep {
+        angle,
+        hold_ms: config.capped_hold_ms(),
+    })
+}
+
+/// MOSFET switch on the servo's supply rail. Cutting the rail between
+/// moves removes the servo's quiescent draw entirely.
+pub struct ServoPower<'d> {
+    enable: PinDriver<'d, AnyOutputPin, Output>,
+    powered: bool,
+    /// Power-good sense from the rail's regulator, if wired.
+    power_good: Option<PinDriver<'d, AnyInputPin, Input>>,
+}
+
+impl<'d> ServoPower<'d> {
+    /// Take the enable pin and start with the rail off.
+    pub fn new(pin: AnyOutputPin) -> Result<Self, EspError> {
+        let mut enable = PinDriver::output(pin)?;
+        enable.set_low()?;
+        Ok(Self {
+            enable,
+            powered: false,
+            power_good: None,
+        })
+    }
+
+    /// Also read the rail's active-high power-good output on `pin`.
+    pub fn with_power_good(mut self, pin: AnyInputPin) -> Result<Self, EspError> {
+        self.power_good = Some(PinDriver::input(pin)?);
+        Ok(self)
+    }
+
+    /// Whether the rail reports power-good, or None without a sense pin.
+    pub fn power_good(&self) -> Option<bool> {
+        self.power_good.as_ref().map(|p| p.is_high())
+    }
+
+    /// Switch the rail on or off. No-op if already in that state.
+    pub fn set(&mut self, on: bool) -> Result<(), EspError> {
+        if on != self.powered {
+            if on {
+                self.enable.set_high()?;
+            } else {
+                self.enable.set_low()?;
+            }
+            self.powered = on;
+        }
         Ok(())
     }
+
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Switch the rail on for a one-off servo action outside the rail
+    /// sequencer, and wait out `settle_ms`. Returns false, with the rail
+    /// switched back off, if power-good doesn't come up in that time.
+    pub fn power_up_blocking(&mut self, settle_ms: u32) -> Result<bool, EspError> {
+        self.set(true)?;
+        std::thread::sleep(std::time::Duration::from_millis(settle_ms as u64));
+        if rail_power_check(self.power_good(), settle_ms as u64, settle_ms) == RailCheck::Fault {
+            self.set(false)?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // Servo hardware tests require ESP32 target.
-    // Use the state machine tests in state.rs for host-side testing.
-    // Integration tests run on-device via `cargo run`.
+    // Servo hardware tests require ESP32 target; integration tests run
+    // on-device via `cargo run`. The pulse math below is host-testable.
+    use super::*;
+    use crate::state::VentStateMachine;
+
+    #[test]
+    fn test_nudge_returns_to_start() {
+        for angle in [90, 91, 135, 179, 180] {
+            let seq = nudge_sequence(angle, 90, 180);
+            assert_eq!(seq.last(), Some(&angle));
+            assert!(seq.iter().all(|a| a.abs_diff(angle) <= NUDGE_DEG));
+            assert!(seq.iter().all(|&a| (90..=180).contains(&a)));
+        }
+        assert_eq!(nudge_sequence(135, 90, 180), vec![136, 134, 135]);
+    }
+
+    #[test]
+    fn test_nudge_at_stop_only_moves_away() {
+        assert_eq!(nudge_sequence(90, 90, 180), vec![91, 90]);
+        assert_eq!(nudge_sequence(180, 90, 180), vec![179, 180]);
+        // Learned limits count as stops too
+        assert_eq!(nudge_sequence(170, 95, 170), vec![169, 170]);
+    }
+
+    #[test]
+    fn test_nudge_rate_limited() {
+        assert!(nudge_allowed(None, 0, NUDGE_MIN_INTERVAL_MS));
+        assert!(!nudge_allowed(Some(1_000), 1_000 + NUDGE_MIN_INTERVAL_MS - 1, NUDGE_MIN_INTERVAL_MS));
+        assert!(nudge_allowed(Some(1_000), 1_000 + NUDGE_MIN_INTERVAL_MS, NUDGE_MIN_INTERVAL_MS));
+    }
+
+    #[test]
+    fn test_should_engage_servo() {
+        assert!(should_engage_servo(false, false));
+        assert!(should_engage_servo(false, true));
+        // Shipping mode: loose until commissioned
+        assert!(!should_engage_servo(true, false));
+        assert!(should_engage_servo(true, true));
+    }
+
+    #[test]
+    fn test_reengage_at_sensed_angle() {
+        assert_eq!(reengage_angle(true, Some(150), 120), 150);
+        assert_eq!(reengage_angle(true, Some(200), 120), 180);
+        // Failed read falls back to the committed angle
+        assert_eq!(reengage_angle(true, None, 120), 120);
+    }
+
+    #[test]
+    fn test_reengage_without_feedback_uses_committed() {
+        assert_eq!(reengage_angle(false, None, 120), 120);
+        assert_eq!(reengage_angle(false, Some(150), 120), 120);
+    }
+
+    #[test]
+    fn test_should_reassert_after_interval() {
+        assert!(!should_reassert(1_000, 60_999, 60_000));
+        assert!(should_reassert(1_000, 61_000, 60_000));
+        assert!(should_reassert(1_000, 500_000, 60_000));
+    }
+
+    #[test]
+    fn test_should_reassert_disabled_or_clock_behind() {
+        assert!(!should_reassert(0, u64::MAX, 0));
+        // A last-reassert stamp ahead of now never fires early
+        assert!(!should_reassert(10_000, 5_000, 1_000));
+    }
+
+    #[test]
+    fn test_learned_limit_backs_off_from_stall() {
+        let angles = [120, 118, 116, 114, 112];
+        assert_eq!(learned_limit_from_stall(122, &angles, 3), 116);
+        assert_eq!(learned_limit_from_stall(122, &angles, 1), 120);
+        // Stalled on the very first step: the start is as far as it got
+        assert_eq!(learned_limit_from_stall(122, &angles, 0), 122);
+        // Never stalled: the whole sweep was free
+        assert_eq!(learned_limit_from_stall(122, &angles, angles.len()), 112);
+        assert_eq!(learned_limit_from_stall(122, &angles, 99), 112);
+        // Already at the end of the range: nothing to sweep
+        assert_eq!(learned_limit_from_stall(90, &[], 0), 90);
+    }
+
+    #[test]
+    fn test_feedback_stalled() {
+        assert!(!feedback_stalled(Some(120), Some(118)));
+        assert!(feedback_stalled(Some(120), Some(120)));
+        // Nothing to compare against yet, or a failed read
+        assert!(!feedback_stalled(None, Some(120)));
+        assert!(!feedback_stalled(Some(120), None));
+    }
+
+    #[test]
+    fn test_learn_sweep_ends_on_target() {
+        assert_eq!(learn_sweep(100, 95), vec![98, 96, 95]);
+        assert_eq!(learn_sweep(170, 174), vec![172, 174]);
+        assert!(learn_sweep(135, 135).is_empty());
+    }
+
+    #[test]
+    fn test_soft_limits_clamp() {
+        let limits = SoftLimits { closed: 96, open: 172 };
+        assert_eq!(limits.clamp(90), 96);
+        assert_eq!(limits.clamp(135), 135);
+        assert_eq!(limits.clamp(180), 172);
+    }
+
+    #[test]
+    fn test_pulse_without_trim() {
+        assert_eq!(angle_to_pulse_us(0, 0), MIN_PULSE_US);
+        assert_eq!(angle_to_pulse_us(90, 0), 1500);
+        assert_eq!(angle_to_pulse_us(180, 0), MAX_PULSE_US);
+    }
+
+    #[test]
+    fn test_trim_shifts_pulse() {
+        // 2000us over 180° = 100us per 9°
+        assert_eq!(angle_to_pulse_us(90, 9), 1600);
+        assert_eq!(angle_to_pulse_us(90, -9), 1400);
+        assert_eq!(angle_to_pulse_us(135, 9), angle_to_pulse_us(144, 0));
+    }
+
+    #[test]
+    fn test_trim_clamped_to_safe_window() {
+        assert_eq!(angle_to_pulse_us(180, 5), MAX_PULSE_US);
+        assert_eq!(angle_to_pulse_us(2, -10), MIN_PULSE_US);
+    }
+
+    #[test]
+    fn test_trim_does_not_change_reported_angle() {
+        // The pulse on the pin moves by exactly the trim...
+        assert_eq!(angle_to_pulse_us(135, 3), angle_to_pulse_us(138, 0));
+        assert_eq!(angle_to_pulse_us(135, -4), angle_to_pulse_us(131, 0));
+        // ...while the state machine, which status and Matter report from,
+        // never sees it and keeps the logical angle
+        let mut sm = VentStateMachine::new(90);
+        sm.set_target(135);
+        while sm.step() {}
+        assert_eq!(sm.current_angle(), 135);
+        assert_eq!(sm.status().angle, 135);
+    }
+
+    #[test]
+    fn test_seat_sequence_disabled() {
+        assert!(seat_sequence(90, &SeatConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn test_seat_sequence_overshoots_then_returns() {
+        let config = SeatConfig {
+            overshoot_deg: 5,
+            hold_ms: 200,
+        };
+        let seq = seat_sequence(90, &config);
+        assert_eq!(
+            seq,
+            vec![
+                SeatStep { angle: 85, hold_ms: 200 },
+                SeatStep { angle: 90, hold_ms: 0 },
+            ]
+        );
+        // Always ends at the nominal closed angle, which is what gets committed
+        assert_eq!(seq.last().unwrap().angle, 90);
+    }
+
+    #[test]
+    fn test_seat_sequence_from_learned_closed() {
+        let config = SeatConfig {
+            overshoot_deg: 5,
+            hold_ms: 200,
+        };
+        let seq = seat_sequence(96, &config);
+        assert_eq!(seq[0].angle, 91);
+        assert_eq!(seq[1].angle, 96);
+    }
+
+    #[test]
+    fn test_seat_sequence_overshoot_capped() {
+        let config = SeatConfig {
+            overshoot_deg: 100,
+            hold_ms: 100,
+        };
+        let seq = seat_sequence(90, &config);
+        assert_eq!(seq[0].angle, 90 - MAX_SEAT_OVERSHOOT_DEG);
+    }
+
+    #[test]
+    fn test_seat_sequence_stays_in_pulse_window() {
+        let config = SeatConfig {
+            overshoot_deg: 10,
+            hold_ms: 100,
+        };
+        let seq = seat_sequence(4, &config);
+        assert_eq!(seq[0].angle, 0);
+        assert!(angle_to_pulse_us(seq[0].angle, 0) >= MIN_PULSE_US);
+    }
+
+    #[test]
+    fn test_score_ranks_clean_tracking_above_poor() {
+        let commanded = [90, 135, 180];
+        let good = score_frequency(&commanded, &[91, 134, 179]);
+        // Twitching servo that barely leaves the start position
+        let poor = score_frequency(&commanded, &[95, 100, 104]);
+        assert!(good > 0.95);
+        assert!(poor < MIN_FREQ_SCORE);
+        assert_eq!(score_frequency(&commanded, &commanded), 1.0);
+        assert_eq!(
+            pick_frequency(&[(50, poor), (100, good), (200, 0.9)]),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn test_score_missing_samples_and_no_clean_result() {
+        assert_eq!(score_frequency(&[], &[]), 0.0);
+        assert_eq!(score_frequency(&[90, 180], &[]), 0.0);
+        assert_eq!(score_frequency(&[90, 180], &[90]), 0.5);
+        assert_eq!(pick_frequency(&[(50, 0.3), (330, 0.79)]), None);
+        assert_eq!(pick_frequency(&[]), None);
+    }
+
+    #[test]
+    fn test_kick_disabled_or_not_idle() {
+        let config = KickConfig {
+            kick_deg: 6,
+            hold_ms: 80,
+        };
+        assert_eq!(kick_step(100, 150, KICK_MIN_IDLE_MS, &KickConfig::default()), None);
+        assert_eq!(kick_step(100, 150, KICK_MIN_IDLE_MS - 1, &config), None);
+        assert_eq!(kick_step(100, 100, KICK_MIN_IDLE_MS, &config), None);
+    }
+
+    #[test]
+    fn test_kick_leads_in_direction_of_travel() {
+        let config = KickConfig {
+            kick_deg: 6,
+            hold_ms: 80,
+        };
+        assert_eq!(
+            kick_step(100, 150, KICK_MIN_IDLE_MS, &config),
+            Some(SeatStep { angle: 106, hold_ms: 80 })
+        );
+        assert_eq!(kick_step(150, 100, KICK_MIN_IDLE_MS, &config).unwrap().angle, 144);
+    }
+
+    #[test]
+    fn test_kick_clamped() {
+        let config = KickConfig {
+            kick_deg: 40,
+            hold_ms: 80,
+        };
+        // Capped, and never past the target
+        assert_eq!(kick_step(100, 150, KICK_MIN_IDLE_MS, &config).unwrap().angle, 100 + MAX_KICK_DEG);
+        assert_eq!(kick_step(100, 103, KICK_MIN_IDLE_MS, &config).unwrap().angle, 103);
+        // Stays in the pulse window at either end
+        let up = kick_step(175, 180, KICK_MIN_IDLE_MS, &config).unwrap().angle;
+        let down = kick_step(5, 0, KICK_MIN_IDLE_MS, &config).unwrap().angle;
+        assert_eq!((up, down), (180, 0));
+        assert!(angle_to_pulse_us(up, 0) <= MAX_PULSE_US);
+        // A typo'd hold can't stall the move
+        let config = KickConfig {
+            kick_deg: 6,
+            hold_ms: u16::MAX,
+        };
+        assert_eq!(kick_step(100, 150, KICK_MIN_IDLE_MS, &config).unwrap().hold_ms, MAX_KICK_HOLD_MS as u32);
+        assert!(angle_to_pulse_us(down, 0) >= MIN_PULSE_US);
+    }
+
+    #[test]
+    fn test_travel_accumulates_across_moves() {
+        let mut travel = TravelCounter::new(1_000);
+        // Close → open, then back to half
+        travel.add(90);
+        travel.add(45);
+        assert_eq!(travel.total_deg(), 1_135);
+    }
+
+    #[test]
+    fn test_travel_persist_threshold() {
+        let mut travel = TravelCounter::default();
+        travel.add(45);
+        assert!(!travel.should_persist());
+        travel.add(45);
+        assert!(travel.should_persist());
+        travel.mark_persisted();
+        assert!(!travel.should_persist());
+        assert_eq!(travel.total_deg(), 90);
+    }
+
+    #[test]
+    fn test_travel_reset() {
+        let mut travel = TravelCounter::new(50_000);
+        travel.add(30);
+        travel.reset();
+        assert_eq!(travel.total_deg(), 0);
+        assert!(!travel.should_persist());
+        travel.add(10);
+        assert_eq!(travel.total_deg(), 10);
+    }
+
+    #[test]
+    fn test_physical_angle() {
+        assert_eq!(physical_angle(90, false), 90);
+        assert_eq!(physical_angle(90, true), 90);
+        assert_eq!(physical_angle(180, true), 0);
+        assert_eq!(physical_angle(135, true), 45);
+        assert_eq!(physical_angle(200, true), 0);
+    }
+
+    #[test]
+    fn test_infer_inversion_aligned() {
+        assert!(!infer_inversion(10, 9));
+        assert!(!infer_inversion(-10, -11));
+    }
+
+    #[test]
+    fn test_infer_inversion_inverted() {
+        assert!(infer_inversion(10, -9));
+        assert!(infer_inversion(-10, 12));
+    }
+
+    #[test]
+    fn test_infer_inversion_no_motion_is_not_inverted() {
+        assert!(!infer_inversion(10, 0));
+    }
+
+    #[test]
+    fn test_inversion_probe_stays_within_limits() {
+        assert_eq!(inversion_probe(100, 95, 170), Some(110));
+        assert_eq!(inversion_probe(165, 95, 170), Some(155));
+        // Room both ways: toward the end with more of it
+        assert_eq!(inversion_probe(140, 95, 170), Some(130));
+        // Travel narrower than a probe
+        assert_eq!(inversion_probe(100, 95, 104), None);
+    }
+
+    #[test]
+    fn test_settled_after_consecutive_samples() {
+        assert!(is_settled(&[120, 119, 120], 120, SETTLE_TOL_DEG));
+        // Only the most recent samples count
+        assert!(is_settled(&[130, 125, 121, 120, 122], 120, SETTLE_TOL_DEG));
+        assert!(!is_settled(&[120, 120], 120, SETTLE_TOL_DEG));
+        assert!(!is_settled(&[], 120, SETTLE_TOL_DEG));
+    }
+
+    #[test]
+    fn test_hunting_is_not_settled() {
+        // Oscillating either side of the target
+        assert!(!is_settled(&[124, 117, 123, 118], 120, SETTLE_TOL_DEG));
+        // Drifting out again after briefly settling
+        assert!(!is_settled(&[120, 120, 121, 124], 120, SETTLE_TOL_DEG));
+    }
+
+    struct Scripted(Vec<Option<u8>>);
+
+    impl PositionFeedback for Scripted {
+        fn read_angle(&mut self) -> Option<u8> {
+            if self.0.is_empty() {
+                None
+            } else {
+                self.0.remove(0)
+            }
+        }
+    }
+
+    #[test]
+    fn test_wait_settled_rides_out_hunting() {
+        let mut fb = Scripted(vec![Some(125), Some(116), None, Some(121), Some(120), Some(120)]);
+        assert!(wait_settled(&mut fb, 120, SETTLE_TOL_DEG));
+    }
+
+    #[test]
+    fn test_repeated_duty_is_not_rewritten() {
+        let mut cache = DutyCache::default();
+        assert!(cache.should_write(1229));
+        cache.record(1229);
+        assert!(!cache.should_write(1229));
+        // A different angle still goes out
+        assert!(cache.should_write(1251));
+    }
+
+    #[test]
+    fn test_disable_forces_next_write() {
+        let mut cache = DutyCache::default();
+        cache.record(1229);
+        cache.invalidate();
+        assert!(cache.should_write(1229));
+    }
 }
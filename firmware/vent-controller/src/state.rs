@@ -1,8 +1,22 @@
-use crate::identity::DeviceIdentity;
-use crate::thread::ThreadManager;
+use crate::breakin::{BreakIn, BreakInPurpose};
+use crate::command::{AccessMode, CommandSource, ConflictPolicy, DecodeFailures, DedupCache, MaxMove, RangePolicy};
+use crate::identity::{DebouncedNvsValue, DeviceIdentity, Metric, MetricsCache};
+use crate::maintenance;
+use crate::motion::{ApproachDirection, Stepping};
+use crate::power::BatteryMoveGate;
+use crate::pressure::PressureControl;
+use crate::quiet::QuietHours;
+use crate::selftest::SelfTestReport;
+use crate::sensors::SensorScheduler;
+use crate::servo::{SoftLimits, TravelCounter, STEP_DELAY_MS};
+use crate::thread::{DatasetChoice, ThreadManager};
+use log::{info, warn};
 use std::sync::Mutex;
-use std::time::Instant;
-use vent_protocol::{clamp_angle, PowerSource, VentState};
+use std::time::{Duration, Instant};
+use vent_protocol::{
+    angle_to_permille, angle_to_permille_with_mid, clamp_angle, round_to, ChannelEnergy, Confidence, DeviceAddresses, HealthMin,
+    MaintenanceInfo, PositionReport, PowerSource, ScanResult, Units, VentState, VentStatus,
+};
 
 /// Shared application state accessible by the main loop and Matter handlers.
 pub struct AppState {
@@ -16,6 +30,133 @@ pub struct AppState {
     pub identify_mode: bool,
     /// Angle to restore after identify completes.
     pub identify_restore_angle: Option<u8>,
+    /// Whether remote clients may change the vent.
+    pub access_mode: AccessMode,
+    /// Whether out-of-range targets are clamped or rejected.
+    pub range_policy: RangePolicy,
+    /// Suppresses move-complete pushes per the NVS `push_mode`.
+    pub push_filter: PushFilter,
+    /// Which of a racing CoAP and Matter target is kept.
+    pub conflict_policy: ConflictPolicy,
+    /// Latest CoAP target and its arrival uptime (ms), for `superseded`.
+    pub coap_target_at: Option<(u8, u64)>,
+    /// Latest Matter target and its arrival uptime (ms).
+    pub matter_target_at: Option<(u8, u64)>,
+    /// Whether commands naming a newer minimum firmware are refused.
+    pub enforce_min_fw: bool,
+    /// Attached sensors and their cached readings.
+    pub sensors: SensorScheduler,
+    /// Pressure-hold settings; None when the automation is off.
+    pub pressure_control: Option<PressureControl>,
+    /// Whether the servo power rail is currently switched on.
+    pub servo_rail_powered: bool,
+    /// The last power-up of the switched rail never reported power-good;
+    /// cleared by the next one that does.
+    pub servo_power_fault: bool,
+    /// Cumulative servo travel since the last reset.
+    pub servo_travel: TravelCounter,
+    /// Undecodable command payloads, persisted for fleet health.
+    pub decode_failures: DecodeFailures,
+    /// PWM is off so the louver can be moved by hand.
+    pub detached: bool,
+    /// Whether a target received while detached re-engages the servo.
+    pub detach_honors_commands: bool,
+    /// Shipped loose: detached until commissioning completes.
+    pub detached_until_commissioned: bool,
+    /// Whether the anti-condensation heater is currently on.
+    pub heater_on: bool,
+    /// Hub report that every vent in this node's zone is closed.
+    pub zone_all_closed: bool,
+    /// Bypass relay held open by a manual command.
+    pub relay_override: bool,
+    /// Whether the HVAC bypass relay is currently open.
+    pub relay_open: bool,
+    /// Whether health queries may request a prove-alive nudge.
+    pub nudge_enabled: bool,
+    /// Uptime (ms) of the last accepted nudge request, for rate limiting.
+    pub last_nudge_ms: Option<u64>,
+    /// A nudge is waiting for the servo to be idle.
+    pub nudge_pending: bool,
+    /// A PWM frequency auto-detect is waiting for the servo to be idle.
+    pub freq_detect_pending: bool,
+    /// Soft-limit learning is waiting for the servo to be idle.
+    pub learn_limits_pending: bool,
+    /// Inversion auto-detect is waiting for the servo to be idle.
+    pub inversion_detect_pending: bool,
+    /// A self-test is waiting for the servo to be idle.
+    pub selftest_pending: bool,
+    /// Result of the most recent self-test this boot.
+    pub last_selftest: Option<SelfTestReport>,
+    /// Lowest angle a command may request without an override.
+    pub min_airflow_angle: u8,
+    /// Furthest one non-critical command may move the vent, if capped.
+    pub max_move: Option<MaxMove>,
+    /// Battery level (mV) below which a battery vent refuses moves; 0 for
+    /// no minimum.
+    pub min_move_mv: u32,
+    /// Whether the low-battery move gate is currently tripped.
+    pub battery_gate: BatteryMoveGate,
+    /// Recently seen command idempotency tokens.
+    pub command_dedup: DedupCache,
+    /// Learned travel limits, if a learning run has completed.
+    pub soft_limits: Option<SoftLimits>,
+    /// Runtime (seconds) banked in NVS before this boot.
+    pub runtime_base_s: u32,
+    /// First-commission date (Unix seconds), once known.
+    pub install_unix_s: Option<u64>,
+    /// Louver angle reported as 50% open, if not the linear center.
+    pub midpoint_angle: Option<u8>,
+    /// Step reported positions are rounded to; the real angle is untouched.
+    pub report_round_deg: u8,
+    /// Booted in minimal mode after a crash loop was detected.
+    pub crash_loop: bool,
+    /// Break-in routine in progress; blocks normal target commands.
+    pub breakin: Option<BreakIn>,
+    /// What the running break-in is for.
+    pub breakin_purpose: BreakInPurpose,
+    /// Scheduled exercise sweep in progress; its legs are ordinary
+    /// commands, and any other command cancels it.
+    pub sweep: Option<BreakIn>,
+    /// Servo trim (degrees), adjustable live and saved once it settles.
+    pub trim: DebouncedNvsValue<i8>,
+    /// Units the primary position value is reported in.
+    pub preferred_units: Units,
+    /// Matter failed to come up at boot and is being skipped.
+    pub matter_unavailable: bool,
+    /// Commissioning timed out and the vent opened fail-safe; cleared once
+    /// the vent is commissioned or joins a mesh.
+    pub awaiting_commission: bool,
+    /// Window during which non-critical moves are held back.
+    pub quiet_hours: Option<QuietHours>,
+    /// Latest target received during quiet hours, applied when they end.
+    pub deferred_target: Option<(CommandSource, u8)>,
+    /// Side every move's final approach comes from.
+    pub approach: ApproachDirection,
+    /// Real target of a move currently on its overshoot leg.
+    pub approach_final: Option<u8>,
+    /// When a pushed Thread dataset was applied, while it awaits the
+    /// coordinator's confirmation.
+    pub dataset_push_since: Option<Instant>,
+    /// Networks heard by the last finished Thread active scan.
+    pub thread_scan: Vec<ScanResult>,
+    /// Per-channel peak energy from the last finished energy scan.
+    pub channel_energy: Vec<ChannelEnergy>,
+    /// The coordinator has reached the vent over the pushed dataset.
+    pub dataset_push_confirmed: bool,
+    /// Boot move (WAL replay or fixed boot angle) held for the post-join
+    /// command window.
+    pub held_boot_move: Option<u8>,
+    /// A command has moved the vent since boot.
+    pub command_since_boot: bool,
+    /// How the last move ended, for the reported position confidence.
+    pub last_move_outcome: MoveOutcome,
+    /// An encoder on the horn can confirm where it is.
+    pub has_position_feedback: bool,
+    /// Command and move counters awaiting their next NVS flush.
+    pub metrics: MetricsCache,
+    /// The wall clock is unsynced or stale, so time-based automations
+    /// (quiet hours, sweeps, install date) are paused.
+    pub clock_unsynced: bool,
 }
 
 static APP_STATE: Mutex<Option<AppState>> = Mutex::new(None);
@@ -27,18 +168,226 @@ pub fn init_app_state(state: AppState) {
 }
 
 /// Access the shared AppState. Returns None if not yet initialized.
+/// Matter callbacks come through here from C, so a poisoned lock is
+/// recovered rather than panicking across the FFI boundary.
 pub fn with_app_state<F, R>(f: F) -> Option<R>
 where
     F: FnOnce(&mut AppState) -> R,
 {
-    let mut guard = APP_STATE.lock().unwrap();
+    let mut guard = APP_STATE.lock().unwrap_or_else(|e| e.into_inner());
     guard.as_mut().map(f)
 }
 
+impl AppState {
+    /// Zero the servo travel counter in RAM and NVS.
+    pub fn reset_servo_travel(&mut self) -> Result<(), esp_idf_sys::EspError> {
+        self.identity.reset_servo_travel()?;
+        self.servo_travel.reset();
+        Ok(())
+    }
+
+    /// Store a fallback dataset, e.g. the next network key ahead of a
+    /// rotation, without applying it; joins fall back to it when the
+    /// primary fails. Refused while a pushed dataset occupies the slot.
+    pub fn set_secondary_dataset(&mut self, tlvs: &[u8]) -> Result<(), esp_idf_sys::EspError> {
+        if self.dataset_push_since.is_some() {
+            return Err(esp_idf_sys::EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_INVALID_STATE }>());
+        }
+        self.identity.set_dataset(DatasetChoice::Secondary, tlvs)?;
+        info!("Secondary Thread dataset stored ({} bytes)", tlvs.len());
+        Ok(())
+    }
+
+    /// Try a dataset pushed by the coordinator. It goes in the secondary
+    /// slot so the primary stays on hand to revert to, and is only
+    /// promoted once `confirm_dataset_push` is called over the new mesh.
+    pub fn begin_dataset_push(&mut self, tlvs: &[u8]) -> Result<(), esp_idf_sys::EspError> {
+        // Without a saved primary there would be nothing to fall back to
+        if self.identity.get_dataset(DatasetChoice::Primary)?.is_none() {
+            if let Some(active) = self.thread.active_dataset_tlvs() {
+                self.identity.set_dataset(DatasetChoice::Primary, &active)?;
+            }
+        }
+        let previous = self.identity.get_dataset(DatasetChoice::Secondary)?;
+        let staged = self
+            .identity
+            .set_dataset(DatasetChoice::Secondary, tlvs)
+            .and_then(|()| self.identity.set_dataset_pending(true))
+            .and_then(|()| self.thread.apply_dataset_tlvs(tlvs));
+        if let Err(e) = staged {
+            // Leave nothing behind that would be reverted or promoted later
+            warn!("Pushed dataset not applied ({:?}) — rolling back", e);
+            if let Err(e) = self.identity.set_dataset_pending(false) {
+                warn!("Dataset push rollback: pending flag not cleared: {:?}", e);
+            }
+            let restored = match previous {
+                Some(prev) => self.identity.set_dataset(DatasetChoice::Secondary, &prev),
+                None => self.identity.clear_dataset(DatasetChoice::Secondary),
+            };
+            if let Err(e) = restored {
+                warn!("Dataset push rollback: secondary slot not restored: {:?}", e);
+            }
+            return Err(e);
+        }
+        warn!("Pushed dataset applied — awaiting coordinator confirmation");
+        self.dataset_push_since = Some(Instant::now());
+        self.dataset_push_confirmed = false;
+        Ok(())
+    }
+
+    /// The coordinator reached the vent over the pushed dataset. Returns
+    /// false if no push is pending.
+    pub fn confirm_dataset_push(&mut self) -> bool {
+        if self.dataset_push_since.is_none() {
+            return false;
+        }
+        self.dataset_push_confirmed = true;
+        true
+    }
+
+    /// Vent status as reported: the angle rounded to `report_round_deg`
+    /// and the open fraction remapped through the configured midpoint.
+    pub fn vent_status(&self) -> VentStatus {
+        let mut status = self.vent.status();
+        if self.report_round_deg > 1 {
+            status.angle = round_to(status.angle as u32 * 1000, self.report_round_deg);
+            status.open_fraction_permille = angle_to_permille(status.angle);
+        }
+        if let Some(mid) = self.midpoint_angle {
+            status.open_fraction_permille = angle_to_permille_with_mid(status.angle, mid);
+        }
+        status
+    }
+
+    /// eFuse MAC, EUI-64 and Thread extended address, for debugging mesh
+    /// addressing.
+    pub fn device_addresses(&self) -> DeviceAddresses {
+        DeviceAddresses {
+            efuse_mac: self.identity.efuse_mac(),
+            eui64: *self.identity.eui64_bytes(),
+            thread_ext_addr: self.thread.extended_address(),
+        }
+    }
+
+    /// Current position in the preferred units.
+    pub fn position_report(&self) -> PositionReport {
+        PositionReport::from_status(&self.vent_status(), self.preferred_units)
+            .with_confidence(position_confidence(self.last_move_outcome, self.has_position_feedback))
+    }
+
+    /// Called when a break-in leg completes: start the next leg, or finish
+    /// and record completion in NVS as its purpose requires.
+    pub fn advance_breakin(&mut self) {
+        let Some(run) = self.breakin.as_mut() else {
+            return;
+        };
+        match run.next_target() {
+            Some(next) => {
+                let (leg, total) = run.progress();
+                info!("Break-in: leg {}/{} -> {}°", leg, total, next);
+                if let Err(e) = self.identity.write_ahead(next) {
+                    warn!("Break-in WAL write-ahead failed: {:?}", e);
+                }
+                self.vent.set_target(next);
+            }
+            None => {
+                self.breakin = None;
+                let recorded = match std::mem::take(&mut self.breakin_purpose) {
+                    BreakInPurpose::Install => {
+                        info!("Break-in complete");
+                        self.identity.set_breakin_done(true)
+                    }
+                    BreakInPurpose::FirstBootCheck => {
+                        info!("First-boot cycle complete");
+                        self.identity.set_first_boot_cycle_done(true)
+                    }
+                };
+                if let Err(e) = recorded {
+                    warn!("Failed to record break-in: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Bank total runtime (stored base plus this boot's uptime) to NVS.
+    pub fn persist_runtime(&mut self) -> Result<(), esp_idf_sys::EspError> {
+        let total = maintenance::runtime_total_s(self.runtime_base_s, self.start_time.elapsed().as_secs());
+        self.identity.set_runtime(total)
+    }
+
+    /// Write a live trim change that is still inside its quiet period,
+    /// ahead of a restart.
+    pub fn flush_trim(&mut self) -> Result<(), esp_idf_sys::EspError> {
+        match self.trim.take_unsaved() {
+            Some(trim) => self.identity.set_trim(trim),
+            None => Ok(()),
+        }
+    }
+
+    /// Write cached metrics whose flush interval has passed.
+    pub fn flush_metrics_if_due(&mut self, now_ms: u64) {
+        let due = self.metrics.flush_if_due(now_ms);
+        self.write_metrics(&due);
+    }
+
+    /// Write every unsaved metric, ahead of a restart.
+    pub fn flush_metrics(&mut self) {
+        let unsaved = self.metrics.flush();
+        self.write_metrics(&unsaved);
+    }
+
+    fn write_metrics(&mut self, totals: &[(Metric, u32)]) {
+        for &(field, total) in totals {
+            if let Err(e) = self.identity.set_metric(field, total) {
+                warn!("Failed to persist {:?} count: {:?}", field, e);
+            }
+        }
+    }
+
+    /// Stamp the install date on first commissioning, once SNTP has set
+    /// the clock. No-op if already recorded or the clock isn't set yet.
+    pub fn record_install_date(&mut self) -> Result<(), esp_idf_sys::EspError> {
+        if self.install_unix_s.is_some() {
+            return Ok(());
+        }
+        let Some(now) = maintenance::usable_now() else {
+            return Ok(());
+        };
+        self.identity.set_install_date(now)?;
+        self.install_unix_s = Some(now);
+        Ok(())
+    }
+
+    /// Install date and runtime hours for service reminders.
+    pub fn maintenance_info(&self) -> MaintenanceInfo {
+        maintenance::maintenance_info(
+            self.install_unix_s,
+            self.runtime_base_s,
+            self.start_time.elapsed().as_secs(),
+        )
+    }
+
+    /// Compact health snapshot for sleepy devices. No battery gauge is
+    /// fitted yet, so the level is always reported as unknown.
+    pub fn health_min(&self) -> HealthMin {
+        HealthMin {
+            battery_pct: None,
+            rssi: self.thread.get_rssi(),
+            state: self.vent.state(),
+            servo_power_fault: self.servo_power_fault,
+            clock_unsynced: self.clock_unsynced,
+            crash_loop: self.crash_loop,
+            matter_unavailable: self.matter_unavailable,
+        }
+    }
+}
+
 /// Vent state machine managing position and transitions.
 pub struct VentStateMachine {
     current_angle: u8,
     target_angle: u8,
+    /// Angle the current move started from, for progress through the move.
+    move_origin: u8,
 }
 
 impl VentStateMachine {
@@ -47,6 +396,7 @@ impl VentStateMachine {
         Self {
             current_angle: angle,
             target_angle: angle,
+            move_origin: angle,
         }
     }
 
@@ -66,13 +416,56 @@ impl VentStateMachine {
         }
     }
 
+    /// Estimated time to reach the target at one degree per step.
+    pub fn eta_ms(&self) -> u32 {
+        self.current_angle.abs_diff(self.target_angle) as u32 * STEP_DELAY_MS
+    }
+
+    /// Snapshot of current and target position for status reporting.
+    pub fn status(&self) -> VentStatus {
+        VentStatus {
+            angle: self.current_angle,
+            target_angle: self.target_angle,
+            state: self.state(),
+            moving: self.is_moving(),
+            open_fraction_permille: angle_to_permille(self.current_angle),
+            eta_ms: self.eta_ms(),
+        }
+    }
+
     /// Set a new target angle. Returns the previous angle.
     pub fn set_target(&mut self, angle: u8) -> u8 {
         let prev = self.current_angle;
-        self.target_angle = clamp_angle(angle);
+        let target = clamp_angle(angle);
+        if target != self.target_angle {
+            self.move_origin = self.current_angle;
+        }
+        self.target_angle = target;
         prev
     }
 
+    /// Take `angle` as where the vent physically is, e.g. after it was
+    /// moved by hand. A pending target is kept; otherwise the vent holds
+    /// at `angle`.
+    pub fn resync(&mut self, angle: u8) {
+        let angle = clamp_angle(angle);
+        if !self.is_moving() {
+            self.target_angle = angle;
+        }
+        self.current_angle = angle;
+        self.move_origin = angle;
+    }
+
+    /// Progress through the current move in thousandths (1000 when idle).
+    pub fn progress_permille(&self) -> u16 {
+        let total = self.move_origin.abs_diff(self.target_angle) as u32;
+        if total == 0 {
+            return 1000;
+        }
+        let done = self.move_origin.abs_diff(self.current_angle) as u32;
+        (done.min(total) * 1000 / total) as u16
+    }
+
     /// Advance one step toward the target. Returns true if still moving.
     pub fn step(&mut self) -> bool {
         if self.current_angle < self.target_angle {
@@ -86,17 +479,349 @@ impl VentStateMachine {
         }
     }
 
+    /// Advance toward the target with the given strategy: one degree when
+    /// stepped, all the way when snapping. Returns the degrees moved.
+    pub fn advance(&mut self, stepping: Stepping) -> u8 {
+        match stepping {
+            Stepping::Stepped => self.step() as u8,
+            Stepping::Snap => {
+                let moved = self.current_angle.abs_diff(self.target_angle);
+                self.current_angle = self.target_angle;
+                moved
+            }
+        }
+    }
+
     /// Check if the vent is currently moving toward a target.
     pub fn is_moving(&self) -> bool {
         self.current_angle != self.target_angle
     }
 }
 
+/// When a move-complete notification is pushed to subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PushMode {
+    /// After every committed move.
+    #[default]
+    Always,
+    /// Only when the classified state changes, e.g. Partial → Closed.
+    OnStateChange,
+}
+
+impl PushMode {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => PushMode::OnStateChange,
+            _ => PushMode::Always,
+        }
+    }
+}
+
+/// Whether a committed move should be pushed. `prev_state` is the state
+/// last pushed, or None if nothing has been pushed yet.
+pub fn should_push(prev_state: Option<VentState>, new_state: VentState, mode: PushMode) -> bool {
+    match mode {
+        PushMode::Always => true,
+        PushMode::OnStateChange => prev_state != Some(new_state),
+    }
+}
+
+/// Tracks the last pushed state so `PushMode::OnStateChange` can suppress
+/// commits that don't change the classification.
+#[derive(Debug, Default)]
+pub struct PushFilter {
+    mode: PushMode,
+    last_pushed: Option<VentState>,
+}
+
+impl PushFilter {
+    pub fn new(mode: PushMode) -> Self {
+        Self {
+            mode,
+            last_pushed: None,
+        }
+    }
+
+    /// Decide whether to push a commit at `angle`, recording it if so.
+    pub fn on_commit(&mut self, angle: u8) -> bool {
+        let state = VentState::from_angle(angle);
+        if should_push(self.last_pushed, state, self.mode) {
+            self.last_pushed = Some(state);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Where the vent goes when it boots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BootBehavior {
+    /// Return to the last committed checkpoint.
+    #[default]
+    RestoreCheckpoint,
+    /// Always start at a configured angle, ignoring the checkpoint.
+    FixedAngle,
+}
+
+impl BootBehavior {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => BootBehavior::FixedAngle,
+            _ => BootBehavior::RestoreCheckpoint,
+        }
+    }
+}
+
+/// Angle to head for after boot.
+pub fn boot_target(behavior: BootBehavior, checkpoint: u8, fixed: u8) -> u8 {
+    match behavior {
+        BootBehavior::RestoreCheckpoint => checkpoint,
+        BootBehavior::FixedAngle => clamp_angle(fixed),
+    }
+}
+
+/// How the most recent move ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// Reached its target (and settled there, if an encoder is fitted).
+    Completed,
+    /// Reached its target, but the encoder didn't see the horn settle.
+    Unsettled,
+    /// Boot found a move interrupted by power loss that hasn't been
+    /// completed since.
+    Recovered,
+    /// Stopped short when the move deadline passed.
+    TimedOut,
+    /// Abandoned because the servo rail never came up power-good, so
+    /// the horn may not be where the state machine thinks.
+    RailFault,
+}
+
+/// How far to trust the state machine's position after `last_outcome`.
+pub fn position_confidence(last_outcome: MoveOutcome, has_feedback: bool) -> Confidence {
+    match last_outcome {
+        MoveOutcome::Completed if has_feedback => Confidence::High,
+        MoveOutcome::Completed => Confidence::Medium,
+        MoveOutcome::Unsettled | MoveOutcome::Recovered | MoveOutcome::TimedOut | MoveOutcome::RailFault => {
+            Confidence::Low
+        }
+    }
+}
+
+/// Longest a held boot move waits for the mesh before going ahead anyway.
+pub const MAX_BOOT_MOVE_HOLD_S: u64 = 120;
+
+/// What to do with a boot move held for the post-join command window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeldBootMove {
+    Wait,
+    Proceed,
+    /// A command got there first; the boot move would only undo it.
+    Drop,
+}
+
+/// Decide a held boot move. A command arriving first supersedes it;
+/// otherwise it goes ahead once `window` has passed since joining, or
+/// after `MAX_BOOT_MOVE_HOLD_S` with no mesh. A zero window never holds.
+pub fn held_boot_move(
+    command_arrived: bool,
+    since_join: Option<Duration>,
+    since_boot: Duration,
+    window: Duration,
+) -> HeldBootMove {
+    if window.is_zero() {
+        HeldBootMove::Proceed
+    } else if command_arrived {
+        HeldBootMove::Drop
+    } else if since_join.is_some_and(|t| t >= window) || since_boot >= Duration::from_secs(MAX_BOOT_MOVE_HOLD_S) {
+        HeldBootMove::Proceed
+    } else {
+        HeldBootMove::Wait
+    }
+}
+
+/// Time budget for a move: one step delay per degree plus a grace period.
+pub fn move_deadline_ms(now_ms: u64, current: u8, target: u8, grace_ms: u32) -> u64 {
+    now_ms + current.abs_diff(target) as u64 * STEP_DELAY_MS as u64 + grace_ms as u64
+}
+
+/// Watchdog that aborts a move which fails to reach its target in time.
+///
+/// The deadline is recomputed from the *current* angle whenever a new
+/// target is accepted, so a long sequence of queued targets extends the
+/// budget rather than being measured against the first target's distance.
+pub struct MoveTimeout {
+    grace_ms: u32,
+    target: Option<u8>,
+    deadline_ms: u64,
+}
+
+impl MoveTimeout {
+    pub fn new(grace_ms: u32) -> Self {
+        Self {
+            grace_ms,
+            target: None,
+            deadline_ms: 0,
+        }
+    }
+
+    /// Feed the latest state-machine snapshot. Re-arms on a new target,
+    /// disarms when the move completes. `slack_ms` is allowed on top of
+    /// the grace for a move armed now, e.g. a slow break-in stroke.
+    pub fn observe(&mut self, now_ms: u64, current: u8, target: u8, moving: bool, slack_ms: u32) {
+        if !moving {
+            self.target = None;
+            return;
+        }
+        if self.target != Some(target) {
+            self.target = Some(target);
+            self.deadline_ms = move_deadline_ms(now_ms, current, target, self.grace_ms.saturating_add(slack_ms));
+        }
+    }
+
+    /// True if a move is in progress and has overrun its deadline.
+    pub fn expired(&self, now_ms: u64) -> bool {
+        self.target.is_some() && now_ms > self.deadline_ms
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use vent_protocol::{ANGLE_CLOSED, ANGLE_OPEN};
 
+    #[test]
+    fn test_boot_target_restores_checkpoint() {
+        assert_eq!(boot_target(BootBehavior::RestoreCheckpoint, 150, 135), 150);
+        assert_eq!(BootBehavior::default(), BootBehavior::RestoreCheckpoint);
+        assert_eq!(BootBehavior::from_u8(9), BootBehavior::RestoreCheckpoint);
+    }
+
+    #[test]
+    fn test_boot_target_fixed_angle() {
+        assert_eq!(boot_target(BootBehavior::FixedAngle, 150, 135), 135);
+        assert_eq!(boot_target(BootBehavior::FixedAngle, 150, 20), ANGLE_CLOSED);
+        assert_eq!(BootBehavior::from_u8(1), BootBehavior::FixedAngle);
+    }
+
+    #[test]
+    fn test_confidence_after_clean_move() {
+        assert_eq!(position_confidence(MoveOutcome::Completed, true), Confidence::High);
+        assert_eq!(position_confidence(MoveOutcome::Completed, false), Confidence::Medium);
+    }
+
+    #[test]
+    fn test_confidence_low_after_trouble() {
+        for outcome in [
+            MoveOutcome::Unsettled,
+            MoveOutcome::Recovered,
+            MoveOutcome::TimedOut,
+            MoveOutcome::RailFault,
+        ] {
+            for has_feedback in [false, true] {
+                assert_eq!(position_confidence(outcome, has_feedback), Confidence::Low);
+            }
+        }
+    }
+
+    #[test]
+    fn test_held_boot_move_waits_for_window_after_join() {
+        let window = Duration::from_secs(10);
+        let boot = Duration::from_secs(30);
+        assert_eq!(held_boot_move(false, None, boot, window), HeldBootMove::Wait);
+        assert_eq!(
+            held_boot_move(false, Some(Duration::from_secs(4)), boot, window),
+            HeldBootMove::Wait
+        );
+        assert_eq!(
+            held_boot_move(false, Some(Duration::from_secs(10)), boot, window),
+            HeldBootMove::Proceed
+        );
+        // No mesh at all: go ahead eventually
+        let late = Duration::from_secs(MAX_BOOT_MOVE_HOLD_S);
+        assert_eq!(held_boot_move(false, None, late, window), HeldBootMove::Proceed);
+    }
+
+    #[test]
+    fn test_held_boot_move_dropped_by_command() {
+        let window = Duration::from_secs(10);
+        let joined = Some(Duration::from_secs(2));
+        assert_eq!(
+            held_boot_move(true, joined, Duration::from_secs(30), window),
+            HeldBootMove::Drop
+        );
+        assert_eq!(
+            held_boot_move(true, joined, Duration::from_secs(30), Duration::ZERO),
+            HeldBootMove::Proceed
+        );
+    }
+
+    #[test]
+    fn test_retarget_mid_move_applies_on_next_step() {
+        let mut sm = VentStateMachine::new(90);
+        sm.set_target(180);
+        for _ in 0..10 {
+            sm.step();
+        }
+        assert_eq!(sm.current_angle(), 100);
+        // Reverse mid-move: the very next step heads to the new target
+        sm.set_target(95);
+        assert!(sm.step());
+        assert_eq!(sm.current_angle(), 99);
+    }
+
+    #[test]
+    fn test_should_push_always() {
+        use VentState::*;
+        assert!(should_push(None, Closed, PushMode::Always));
+        assert!(should_push(Some(Partial), Partial, PushMode::Always));
+        assert!(should_push(Some(Open), Closed, PushMode::Always));
+    }
+
+    #[test]
+    fn test_should_push_on_state_change() {
+        use VentState::*;
+        assert!(should_push(None, Partial, PushMode::OnStateChange));
+        assert!(should_push(Some(Partial), Closed, PushMode::OnStateChange));
+        assert!(should_push(Some(Closed), Open, PushMode::OnStateChange));
+        assert!(!should_push(Some(Partial), Partial, PushMode::OnStateChange));
+        assert!(!should_push(Some(Closed), Closed, PushMode::OnStateChange));
+    }
+
+    #[test]
+    fn test_push_filter_suppresses_partial_adjustments() {
+        let mut filter = PushFilter::new(PushMode::OnStateChange);
+        let pushed: Vec<u8> = [120, 130, 140, 90, 90, 180, 150]
+            .into_iter()
+            .filter(|&a| filter.on_commit(a))
+            .collect();
+        assert_eq!(pushed, vec![120, 90, 180, 150]);
+    }
+
+    #[test]
+    fn test_push_mode_from_nvs() {
+        assert_eq!(PushMode::from_u8(0), PushMode::Always);
+        assert_eq!(PushMode::from_u8(1), PushMode::OnStateChange);
+    }
+
+    #[test]
+    fn test_progress_through_move() {
+        let mut sm = VentStateMachine::new(ANGLE_CLOSED);
+        assert_eq!(sm.progress_permille(), 1000);
+        sm.set_target(ANGLE_OPEN);
+        assert_eq!(sm.progress_permille(), 0);
+        for _ in 0..45 {
+            sm.step();
+        }
+        assert_eq!(sm.progress_permille(), 500);
+        // Re-sending the same target does not restart the profile
+        sm.set_target(ANGLE_OPEN);
+        assert_eq!(sm.progress_permille(), 500);
+        while sm.step() {}
+        assert_eq!(sm.progress_permille(), 1000);
+    }
+
     #[test]
     fn test_initial_state_closed() {
         let sm = VentStateMachine::new(ANGLE_CLOSED);
@@ -129,6 +854,22 @@ mod tests {
         assert_eq!(sm.state(), VentState::Moving);
     }
 
+    #[test]
+    fn test_resync_holds_or_keeps_pending_target() {
+        // Moved by hand while idle: hold where it was left
+        let mut sm = VentStateMachine::new(120);
+        sm.resync(150);
+        assert_eq!((sm.current_angle(), sm.target_angle()), (150, 150));
+        assert!(!sm.is_moving());
+
+        // A target set while detached still applies, from the new angle
+        let mut sm = VentStateMachine::new(120);
+        sm.set_target(180);
+        sm.resync(150);
+        assert_eq!((sm.current_angle(), sm.target_angle()), (150, 180));
+        assert_eq!(sm.progress_permille(), 0);
+    }
+
     #[test]
     fn test_step_moves_toward_target() {
         let mut sm = VentStateMachine::new(90);
@@ -174,6 +915,129 @@ mod tests {
         assert_eq!(sm.state(), VentState::Closed);
     }
 
+    #[test]
+    fn test_snap_reaches_target_in_one_step() {
+        let mut sm = VentStateMachine::new(90);
+        sm.set_target(180);
+        assert_eq!(sm.advance(Stepping::Snap), 90);
+        assert!(!sm.is_moving());
+        assert_eq!(sm.current_angle(), 180);
+        assert_eq!(sm.state(), VentState::Open);
+        assert_eq!(sm.progress_permille(), 1000);
+        // Nothing left to do
+        assert_eq!(sm.advance(Stepping::Snap), 0);
+
+        sm.set_target(90);
+        assert_eq!(sm.advance(Stepping::Snap), 90);
+        assert_eq!(sm.state(), VentState::Closed);
+    }
+
+    #[test]
+    fn test_stepped_advance_moves_one_degree() {
+        let mut sm = VentStateMachine::new(90);
+        sm.set_target(92);
+        assert_eq!(sm.advance(Stepping::Stepped), 1);
+        assert_eq!(sm.current_angle(), 91);
+        assert_eq!(sm.advance(Stepping::Stepped), 1);
+        assert_eq!(sm.advance(Stepping::Stepped), 0);
+    }
+
+    #[test]
+    fn test_status_mid_move() {
+        let mut sm = VentStateMachine::new(90);
+        sm.set_target(100);
+        for _ in 0..4 {
+            sm.step();
+        }
+
+        let status = sm.status();
+        assert_eq!(status.angle, 94);
+        assert_eq!(status.target_angle, 100);
+        assert_eq!(status.state, VentState::Moving);
+        assert!(status.moving);
+        assert_eq!(status.open_fraction_permille, 44);
+        assert_eq!(status.eta_ms, 6 * STEP_DELAY_MS);
+    }
+
+    #[test]
+    fn test_status_idle() {
+        let sm = VentStateMachine::new(135);
+        let status = sm.status();
+        assert_eq!(status.angle, 135);
+        assert_eq!(status.target_angle, 135);
+        assert!(!status.moving);
+        assert_eq!(status.eta_ms, 0);
+    }
+
+    #[test]
+    fn test_move_deadline() {
+        assert_eq!(move_deadline_ms(1000, 90, 100, 500), 1000 + 10 * STEP_DELAY_MS as u64 + 500);
+        assert_eq!(move_deadline_ms(0, 100, 90, 0), 10 * STEP_DELAY_MS as u64);
+    }
+
+    #[test]
+    fn test_move_timeout_extended_by_new_targets() {
+        let step = STEP_DELAY_MS as u64;
+        let mut sm = VentStateMachine::new(90);
+        let mut mt = MoveTimeout::new(100);
+        let mut now = 0;
+
+        // Each accepted target arrives just before the previous one finishes,
+        // so the whole sequence takes far longer than the first move's budget.
+        for target in [100, 110, 120, 130, 140] {
+            sm.set_target(target);
+            while sm.current_angle() + 1 < target {
+                mt.observe(now, sm.current_angle(), sm.target_angle(), sm.is_moving(), 0);
+                assert!(!mt.expired(now), "falsely timed out at {}°", sm.current_angle());
+                sm.step();
+                now += step;
+            }
+        }
+        while sm.step() {
+            now += step;
+        }
+        mt.observe(now, sm.current_angle(), sm.target_angle(), sm.is_moving(), 0);
+        assert!(!mt.expired(now));
+        assert!(now > move_deadline_ms(0, 90, 100, 100));
+    }
+
+    #[test]
+    fn test_move_timeout_stuck_move_expires() {
+        let step = STEP_DELAY_MS as u64;
+        let mut sm = VentStateMachine::new(90);
+        let mut mt = MoveTimeout::new(100);
+        sm.set_target(100);
+
+        // The state machine never advances (servo stalled)
+        let mut now = 0;
+        mt.observe(now, sm.current_angle(), sm.target_angle(), sm.is_moving(), 0);
+        while now <= 10 * step + 100 {
+            assert!(!mt.expired(now));
+            now += step;
+            mt.observe(now, sm.current_angle(), sm.target_angle(), sm.is_moving(), 0);
+        }
+        assert!(mt.expired(now));
+    }
+
+    #[test]
+    fn test_move_timeout_disarmed_when_idle() {
+        let mut mt = MoveTimeout::new(0);
+        mt.observe(0, 90, 180, true, 0);
+        mt.observe(10, 180, 180, false, 0);
+        assert!(!mt.expired(1_000_000));
+    }
+
+    #[test]
+    fn test_move_timeout_slack_extends_deadline() {
+        let mut mt = MoveTimeout::new(100);
+        mt.observe(0, 90, 100, true, 500);
+        assert!(!mt.expired(move_deadline_ms(0, 90, 100, 600)));
+        assert!(mt.expired(move_deadline_ms(0, 90, 100, 600) + 1));
+        // Slack only counts for the move it was armed with
+        mt.observe(0, 90, 110, true, 0);
+        assert!(mt.expired(move_deadline_ms(0, 90, 110, 100) + 1));
+    }
+
     #[test]
     fn test_target_clamped() {
         let mut sm = VentStateMachine::new(90);
@@ -0,0 +1,97 @@
+use crate::sensors::{Sensor, SensorKind};
+use esp_idf_sys::EspError;
+
+/// How often the supply voltage is sampled. Often enough that the
+/// harvested-energy gate sees the supercap recover within a few seconds.
+pub const SUPPLY_POLL_MS: u64 = 2_000;
+
+/// ADC1 channel of XIAO D0 (GPIO0), where the supply divider is wired.
+const SUPPLY_ADC_CHANNEL: esp_idf_sys::adc_channel_t = esp_idf_sys::adc_channel_t_ADC_CHANNEL_0;
+
+/// Supply voltage from the voltage at the ADC pin. `divider_permille` is the
+/// divider's ratio in thousandths, e.g. 2000 for two equal resistors.
+pub fn supply_mv(pin_mv: i32, divider_permille: u32) -> i32 {
+    (pin_mv as i64 * divider_permille as i64 / 1000) as i32
+}
+
+/// Battery or supercap voltage through a resistor divider on XIAO D0,
+/// read with the ADC's factory curve-fitting calibration.
+pub struct SupplyVoltage {
+    unit: esp_idf_sys::adc_oneshot_unit_handle_t,
+    cali: esp_idf_sys::adc_cali_handle_t,
+    divider_permille: u32,
+}
+
+// The handles are only ever used from the main loop through the sensor
+// scheduler, which sits behind the app state lock.
+unsafe impl Send for SupplyVoltage {}
+
+impl SupplyVoltage {
+    /// Claim ADC1 and set D0 up for the full 0–3.3 V input range.
+    pub fn new(divider_permille: u32) -> Result<Self, EspError> {
+        let unit_config = esp_idf_sys::adc_oneshot_unit_init_cfg_t {
+            unit_id: esp_idf_sys::adc_unit_t_ADC_UNIT_1,
+            ..Default::default()
+        };
+        let chan_config = esp_idf_sys::adc_oneshot_chan_cfg_t {
+            atten: esp_idf_sys::adc_atten_t_ADC_ATTEN_DB_12,
+            bitwidth: esp_idf_sys::adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+        };
+        let cali_config = esp_idf_sys::adc_cali_curve_fitting_config_t {
+            unit_id: esp_idf_sys::adc_unit_t_ADC_UNIT_1,
+            chan: SUPPLY_ADC_CHANNEL,
+            atten: esp_idf_sys::adc_atten_t_ADC_ATTEN_DB_12,
+            bitwidth: esp_idf_sys::adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+        };
+        let mut unit = std::ptr::null_mut();
+        let mut cali = std::ptr::null_mut();
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::adc_oneshot_new_unit(&unit_config, &mut unit))?;
+            esp_idf_sys::esp!(esp_idf_sys::adc_oneshot_config_channel(unit, SUPPLY_ADC_CHANNEL, &chan_config))?;
+            esp_idf_sys::esp!(esp_idf_sys::adc_cali_create_scheme_curve_fitting(&cali_config, &mut cali))?;
+        }
+        Ok(Self {
+            unit,
+            cali,
+            divider_permille,
+        })
+    }
+
+    /// Current supply voltage in millivolts.
+    pub fn read_mv(&mut self) -> Result<i32, EspError> {
+        let mut pin_mv = 0;
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::adc_oneshot_get_calibrated_result(
+                self.unit,
+                self.cali,
+                SUPPLY_ADC_CHANNEL,
+                &mut pin_mv
+            ))?;
+        }
+        Ok(supply_mv(pin_mv, self.divider_permille))
+    }
+}
+
+impl Sensor for SupplyVoltage {
+    fn kind(&self) -> SensorKind {
+        SensorKind::SupplyVoltage
+    }
+
+    fn read(&mut self) -> Option<i32> {
+        self.read_mv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supply_mv_scales_by_divider() {
+        // Two equal resistors halve a 4.0 V cell
+        assert_eq!(supply_mv(2_000, 2_000), 4_000);
+        // 100k over 47k
+        assert_eq!(supply_mv(1_200, 3_128), 3_753);
+        assert_eq!(supply_mv(1_650, 1_000), 1_650);
+    }
+}
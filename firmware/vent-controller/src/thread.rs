@@ -1,172 +1,115 @@
 use esp_idf_sys::EspError;
-use log::info;
+use log::{info, warn};
+use std::ffi::c_void;
+use std::sync::Mutex;
+use vent_protocol::{ChannelEnergy, LinkMetrics, NeighborInfo, NeighborRole, ScanResult};
 
-/// Thread network configuration.
-///
-/// All devices on the same Thread network must share the same network key,
-/// channel, PAN ID, and network name. These must match the OTBR's active
-/// dataset.
-///
-/// Default values are for development only. For production, generate unique
-/// credentials via `docker exec otbr ot-ctl dataset init new` and update
-/// both the OTBR and this config to match.
-pub struct ThreadConfig {
-    pub network_name: String,
-    pub channel: u8,
-    pub panid: u16,
-    pub network_key: [u8; 16],
-    pub ext_panid: [u8; 8],
-    pub mesh_local_prefix: [u8; 8],
-}
-
-impl Default for ThreadConfig {
-    fn default() -> Self {
-        Self {
-            network_name: "OpenThreadDemo".into(),
-            channel: 15,
-            panid: 0x1234,
-            // Development-only network key. Replace for production.
-            network_key: [
-                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77,
-                0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
-            ],
-            ext_panid: [0x67, 0xf4, 0xc2, 0x4b, 0x9e, 0xe4, 0x54, 0xab],
-            // fd9e:0bdc:12bc:1c11::/64
-            mesh_local_prefix: [0xfd, 0x9e, 0x0b, 0xdc, 0x12, 0xbc, 0x1c, 0x11],
-        }
+/// Longest operational dataset OpenThread accepts, in TLV bytes
+/// (`OT_OPERATIONAL_DATASET_MAX_LENGTH`).
+pub const MAX_DATASET_LEN: usize = 254;
+
+/// Which stored Thread dataset to join with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetChoice {
+    Primary,
+    Secondary,
+}
+
+/// Pick the dataset for the next join attempt. Fall back to the secondary
+/// only when the primary has failed and a secondary is actually stored.
+pub fn select_dataset(primary_failed: bool, has_secondary: bool) -> DatasetChoice {
+    if primary_failed && has_secondary {
+        DatasetChoice::Secondary
+    } else {
+        DatasetChoice::Primary
     }
 }
 
-/// Thread network manager.
+/// Tracks primary/secondary failover across join retry windows.
 ///
-/// Handles OpenThread initialization, network joining, and IPv6 address management
-/// using the ESP-IDF OpenThread bindings.
-pub struct ThreadManager {
-    config: ThreadConfig,
-    connected: bool,
-    matter_managed: bool,
+/// Used for zero-touch key rotation: vents still holding the old key fail
+/// to attach with the primary, try the secondary, and promote it once
+/// they attach.
+#[derive(Debug)]
+pub struct DatasetFailover {
+    trying: DatasetChoice,
 }
 
-impl ThreadManager {
-    pub fn new(config: ThreadConfig) -> Self {
+impl DatasetFailover {
+    pub fn new() -> Self {
         Self {
-            config,
-            connected: false,
-            matter_managed: false,
+            trying: DatasetChoice::Primary,
         }
     }
 
-    /// Create a ThreadManager where Matter SDK owns the OpenThread stack.
-    ///
-    /// In this mode, `init()` is a no-op — Matter handles Thread platform
-    /// initialization, dataset provisioning, and the mainloop. Query methods
-    /// (`get_rssi`, `is_connected`, `role_str`) still work because they use
-    /// `esp_openthread_get_instance()` which returns the Matter-managed instance.
-    pub fn new_matter_managed() -> Self {
-        Self {
-            config: ThreadConfig::default(),
-            connected: false,
-            matter_managed: true,
+    /// The join retry window elapsed without attaching. Returns the dataset
+    /// to switch to, or None to keep retrying the current one.
+    pub fn on_join_timeout(&mut self, has_secondary: bool) -> Option<DatasetChoice> {
+        let next = match self.trying {
+            DatasetChoice::Primary => select_dataset(true, has_secondary),
+            // Secondary failed too — go back to the primary
+            DatasetChoice::Secondary => DatasetChoice::Primary,
+        };
+        if next == self.trying {
+            return None;
         }
+        self.trying = next;
+        Some(next)
     }
 
-    /// Initialize the IEEE 802.15.4 radio and OpenThread stack.
-    ///
-    /// Sets up VFS eventfd, event loop, netif, and the OpenThread platform.
-    /// The dataset is configured and Thread is enabled so that the device
-    /// joins the network once the mainloop starts processing.
-    pub fn init(&mut self) -> Result<(), EspError> {
-        if self.matter_managed {
-            info!("Thread stack managed by Matter SDK — skipping manual init");
-            return Ok(());
-        }
-
-        info!("Initializing OpenThread stack...");
-
-        unsafe {
-            // Register VFS eventfd — required by OpenThread task queue and radio driver.
-            // 3 fds: netif + task queue + radio driver
-            let eventfd_config = esp_idf_sys::esp_vfs_eventfd_config_t {
-                max_fds: 3,
-            };
-            esp_idf_sys::esp!(esp_idf_sys::esp_vfs_eventfd_register(&eventfd_config))?;
-
-            // Create default event loop (used by OpenThread netif glue)
-            esp_idf_sys::esp!(esp_idf_sys::esp_event_loop_create_default())?;
-
-            // Initialize TCP/IP stack (required for OpenThread netif)
-            esp_idf_sys::esp!(esp_idf_sys::esp_netif_init())?;
-
-            let cfg = esp_idf_sys::esp_openthread_platform_config_t {
-                radio_config: esp_idf_sys::esp_openthread_radio_config_t {
-                    radio_mode: esp_idf_sys::esp_openthread_radio_mode_t_RADIO_MODE_NATIVE,
-                    ..Default::default()
-                },
-                host_config: esp_idf_sys::esp_openthread_host_connection_config_t {
-                    host_connection_mode:
-                        esp_idf_sys::esp_openthread_host_connection_mode_t_HOST_CONNECTION_MODE_NONE,
-                    ..Default::default()
-                },
-                port_config: esp_idf_sys::esp_openthread_port_config_t {
-                    storage_partition_name: b"nvs\0".as_ptr() as *const _,
-                    netif_queue_size: 10,
-                    task_queue_size: 10,
-                },
-            };
-
-            esp_idf_sys::esp!(esp_idf_sys::esp_openthread_init(&cfg))?;
-
-            let instance = esp_idf_sys::esp_openthread_get_instance();
-            let mut dataset: esp_idf_sys::otOperationalDataset = std::mem::zeroed();
-
-            // Channel
-            dataset.mChannel = self.config.channel as u16;
-            dataset.mComponents.mIsChannelPresent = true;
-
-            // PAN ID
-            dataset.mPanId = self.config.panid;
-            dataset.mComponents.mIsPanIdPresent = true;
-
-            // Network name
-            dataset.mComponents.mIsNetworkNamePresent = true;
-            let name_bytes = self.config.network_name.as_bytes();
-            let len = name_bytes.len().min(16);
-            dataset.mNetworkName.m8[..len].copy_from_slice(
-                &name_bytes[..len],
-            );
-
-            // Network key — required to join an existing network
-            dataset.mNetworkKey.m8 = self.config.network_key;
-            dataset.mComponents.mIsNetworkKeyPresent = true;
-
-            // Extended PAN ID
-            dataset.mExtendedPanId.m8 = self.config.ext_panid;
-            dataset.mComponents.mIsExtendedPanIdPresent = true;
+    /// The device attached. Returns true if the secondary was in use and
+    /// should now be promoted to primary.
+    pub fn on_attached(&mut self) -> bool {
+        let promote = self.trying == DatasetChoice::Secondary;
+        self.trying = DatasetChoice::Primary;
+        promote
+    }
+}
 
-            // Mesh-local prefix
-            dataset.mMeshLocalPrefix.m8 = self.config.mesh_local_prefix;
-            dataset.mComponents.mIsMeshLocalPrefixPresent = true;
+impl Default for DatasetFailover {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            esp_idf_sys::otDatasetSetActive(instance, &dataset);
+/// Default time a pushed dataset has to be confirmed before it is reverted.
+pub const DEFAULT_DATASET_CONFIRM_S: u32 = 300;
 
-            esp_idf_sys::otIp6SetEnabled(instance, true);
-            esp_idf_sys::otThreadSetEnabled(instance, true);
+/// Progress of a dataset pushed by the coordinator. The new dataset is
+/// only kept once the coordinator reaches the vent over it; a wrong channel
+/// or key would otherwise orphan the vent for good.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetApply {
+    /// Applied and waiting for the coordinator's confirming ping.
+    Pending,
+    /// Confirmed; promote it to primary.
+    Confirmed,
+    /// Not confirmed in time; go back to the previous dataset.
+    RevertNeeded,
+}
 
-            info!(
-                "OpenThread started on channel {}, PAN ID 0x{:04x}, network '{}'",
-                self.config.channel, self.config.panid, self.config.network_name
-            );
+impl DatasetApply {
+    /// Advance a pending push. A confirmation that lands in the same pass
+    /// as the timeout still wins, since it proves the new mesh works.
+    pub fn next(self, confirmed: bool, elapsed_ms: u64, timeout_ms: u64) -> Self {
+        match self {
+            DatasetApply::Pending if confirmed => DatasetApply::Confirmed,
+            DatasetApply::Pending if elapsed_ms >= timeout_ms => DatasetApply::RevertNeeded,
+            other => other,
         }
-
-        Ok(())
     }
+}
 
-    /// Run the OpenThread processing loop. Call this periodically.
-    pub fn process(&mut self) -> Result<(), EspError> {
-        unsafe {
-            esp_idf_sys::esp_openthread_launch_mainloop();
-        }
-        Ok(())
+/// Handle on the OpenThread stack, which the Matter SDK owns: it handles
+/// Thread platform initialization, dataset provisioning, and the mainloop.
+/// Queries (`get_rssi`, `is_connected`, `role_str`) use
+/// `esp_openthread_get_instance()`, which returns the Matter-managed
+/// instance.
+pub struct ThreadManager;
+
+impl ThreadManager {
+    pub fn new_matter_managed() -> Self {
+        Self
     }
 
     /// Get the device's mesh-local IPv6 address as a string.
@@ -218,6 +161,77 @@ impl ThreadManager {
         }
     }
 
+    /// Set how long (seconds) the parent keeps state for this child between
+    /// polls. Takes effect on the next (re)attach.
+    pub fn set_child_timeout(&self, seconds: u32) {
+        info!("Thread child timeout: {}s", seconds);
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            esp_idf_sys::otThreadSetChildTimeout(instance, seconds);
+        }
+    }
+
+    /// Detach and re-attach to the Thread network without rebooting.
+    pub fn rejoin(&self) {
+        info!("Rejoining Thread network...");
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            esp_idf_sys::otThreadSetEnabled(instance, false);
+            esp_idf_sys::otThreadSetEnabled(instance, true);
+        }
+    }
+
+    /// Read the active operational dataset as raw TLVs.
+    pub fn active_dataset_tlvs(&self) -> Option<Vec<u8>> {
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            let mut tlvs: esp_idf_sys::otOperationalDatasetTlvs = std::mem::zeroed();
+            let err = esp_idf_sys::otDatasetGetActiveTlvs(instance, &mut tlvs);
+            if err == esp_idf_sys::otError_OT_ERROR_NONE as u32 {
+                Some(tlvs.mTlvs[..tlvs.mLength as usize].to_vec())
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Replace the active operational dataset and re-attach with it. A
+    /// dataset longer than `MAX_DATASET_LEN` is refused, not truncated.
+    pub fn apply_dataset_tlvs(&self, raw: &[u8]) -> Result<(), EspError> {
+        if raw.len() > MAX_DATASET_LEN {
+            warn!("Thread dataset of {} bytes is too long — not applied", raw.len());
+            return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_ERR_INVALID_SIZE }>());
+        }
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            let mut tlvs: esp_idf_sys::otOperationalDatasetTlvs = std::mem::zeroed();
+            tlvs.mTlvs[..raw.len()].copy_from_slice(raw);
+            tlvs.mLength = raw.len() as u8;
+
+            esp_idf_sys::otThreadSetEnabled(instance, false);
+            let err = esp_idf_sys::otDatasetSetActiveTlvs(instance, &tlvs);
+            esp_idf_sys::otThreadSetEnabled(instance, true);
+            if err != esp_idf_sys::otError_OT_ERROR_NONE as u32 {
+                return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_FAIL }>());
+            }
+        }
+        info!("Applied Thread dataset ({} bytes)", raw.len());
+        Ok(())
+    }
+
+    /// The IEEE 802.15.4 extended address OpenThread is using, or None if
+    /// the stack isn't running.
+    pub fn extended_address(&self) -> Option<[u8; 8]> {
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            if instance.is_null() {
+                return None;
+            }
+            let addr = esp_idf_sys::otLinkGetExtendedAddress(instance);
+            addr.as_ref().map(|a| a.m8)
+        }
+    }
+
     /// Get the average RSSI of the link to the parent router.
     pub fn get_rssi(&self) -> i8 {
         unsafe {
@@ -231,4 +245,337 @@ impl ThreadManager {
             }
         }
     }
+
+    /// Get the incoming link quality (0–3) from the parent router, or 0 if
+    /// there is no parent.
+    pub fn get_link_quality(&self) -> u8 {
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            let mut parent: esp_idf_sys::otRouterInfo = std::mem::zeroed();
+            let err = esp_idf_sys::otThreadGetParentInfo(instance, &mut parent);
+            if err == esp_idf_sys::otError_OT_ERROR_NONE {
+                parent.mLinkQualityIn()
+            } else {
+                0
+            }
+        }
+    }
+
+    /// RSSI and LQI of the parent link.
+    pub fn link_metrics(&self) -> LinkMetrics {
+        LinkMetrics {
+            rssi: self.get_rssi(),
+            lqi: self.get_link_quality(),
+        }
+    }
+
+    /// Snapshot of the neighbor table: routers in range and, on a router,
+    /// its children. Empty on an end device with no table or on error.
+    pub fn neighbors(&self) -> Vec<NeighborInfo> {
+        let mut out = Vec::new();
+        unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            let mut iter = esp_idf_sys::OT_NEIGHBOR_INFO_ITERATOR_INIT as esp_idf_sys::otNeighborInfoIterator;
+            let mut info: esp_idf_sys::otNeighborInfo = std::mem::zeroed();
+            loop {
+                let err = esp_idf_sys::otThreadGetNextNeighborInfo(instance, &mut iter, &mut info);
+                if err == esp_idf_sys::otError_OT_ERROR_NOT_FOUND {
+                    // Normal end of the table
+                    break;
+                }
+                if err != esp_idf_sys::otError_OT_ERROR_NONE {
+                    warn!("Neighbor table read stopped: otError {}", err);
+                    break;
+                }
+                out.push(NeighborInfo {
+                    rloc16: info.mRloc16,
+                    rssi: info.mAverageRssi,
+                    lqi: info.mLinkQualityIn,
+                    role: neighbor_role(info.mIsChild(), info.mRxOnWhenIdle()),
+                });
+            }
+        }
+        out
+    }
+
+    /// Start an active scan of every channel for Thread network beacons.
+    /// Results arrive in the background; poll `scan_results`.
+    pub fn active_scan(&self) -> Result<(), EspError> {
+        *SCAN.lock().unwrap_or_else(|e| e.into_inner()) = Some(ScanCollector::default());
+        let err = unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            // All channels, default dwell per channel
+            esp_idf_sys::otLinkActiveScan(instance, 0, 0, Some(on_active_scan_result), std::ptr::null_mut())
+        };
+        if err != esp_idf_sys::otError_OT_ERROR_NONE {
+            *SCAN.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            warn!("Thread active scan failed to start: otError {}", err);
+            return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_FAIL }>());
+        }
+        info!("Thread active scan started");
+        Ok(())
+    }
+
+    /// Networks found by the last `active_scan`, once it has finished.
+    /// None while a scan is running or if none was started; each finished
+    /// scan is returned once.
+    pub fn scan_results(&self) -> Option<Vec<ScanResult>> {
+        let mut scan = SCAN.lock().unwrap_or_else(|e| e.into_inner());
+        if scan.as_ref()?.is_done() {
+            scan.take().map(ScanCollector::into_results)
+        } else {
+            None
+        }
+    }
+
+    /// Start an energy scan of every channel, sampling the noise floor
+    /// whether or not a Thread network is there. Results arrive in the
+    /// background; poll `energy_results`. Can't overlap an active scan.
+    pub fn energy_scan(&self) -> Result<(), EspError> {
+        *ENERGY.lock().unwrap_or_else(|e| e.into_inner()) = Some(EnergyCollector::default());
+        let err = unsafe {
+            let instance = esp_idf_sys::esp_openthread_get_instance();
+            // All channels, default dwell per channel
+            esp_idf_sys::otLinkEnergyScan(instance, 0, 0, Some(on_energy_scan_result), std::ptr::null_mut())
+        };
+        if err != esp_idf_sys::otError_OT_ERROR_NONE {
+            *ENERGY.lock().unwrap_or_else(|e| e.into_inner()) = None;
+            warn!("Thread energy scan failed to start: otError {}", err);
+            return Err(EspError::from_infallible::<{ esp_idf_sys::ESP_FAIL }>());
+        }
+        info!("Thread energy scan started");
+        Ok(())
+    }
+
+    /// Per-channel energy from the last `energy_scan`, once it has
+    /// finished; each finished scan is returned once.
+    pub fn energy_results(&self) -> Option<Vec<ChannelEnergy>> {
+        let mut scan = ENERGY.lock().unwrap_or_else(|e| e.into_inner());
+        if scan.as_ref()?.is_done() {
+            scan.take().map(EnergyCollector::into_results)
+        } else {
+            None
+        }
+    }
+}
+
+/// Most networks kept from one active scan.
+pub const MAX_SCAN_RESULTS: usize = 16;
+
+/// Gathers active-scan callbacks: one per beacon heard, then a final call
+/// with no result when the scan ends.
+#[derive(Debug, Default)]
+pub struct ScanCollector {
+    results: Vec<ScanResult>,
+    done: bool,
+}
+
+impl ScanCollector {
+    pub fn on_result(&mut self, result: Option<ScanResult>) {
+        let Some(r) = result else {
+            self.done = true;
+            return;
+        };
+        // A network beaconing from several routers is listed once, at
+        // its strongest
+        match self.results.iter().position(|s| s.panid == r.panid && s.channel == r.channel) {
+            Some(i) if r.rssi > self.results[i].rssi => self.results[i] = r,
+            Some(_) => {}
+            None if self.results.len() < MAX_SCAN_RESULTS => self.results.push(r),
+            None => {}
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Networks found, strongest first.
+    pub fn into_results(mut self) -> Vec<ScanResult> {
+        self.results.sort_by_key(|r| std::cmp::Reverse(r.rssi));
+        self.results
+    }
+}
+
+/// Scan in progress or finished; None when no scan has been started.
+/// Filled from the OpenThread task, drained from the main loop.
+static SCAN: Mutex<Option<ScanCollector>> = Mutex::new(None);
+
+unsafe extern "C" fn on_active_scan_result(result: *mut esp_idf_sys::otActiveScanResult, _ctx: *mut c_void) {
+    // The NUL-terminated name fits ScanResult's zero-padded field as is
+    let found = result
+        .as_ref()
+        .map(|r| ScanResult::new(r.mChannel, r.mPanId, r.mRssi, &r.mNetworkName.m8));
+    if let Some(scan) = SCAN.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        scan.on_result(found);
+    }
+}
+
+/// Gathers energy-scan callbacks: one per channel, then a final call with
+/// no result when the scan ends.
+#[derive(Debug, Default)]
+pub struct EnergyCollector {
+    channels: Vec<ChannelEnergy>,
+    done: bool,
+}
+
+impl EnergyCollector {
+    pub fn on_result(&mut self, result: Option<ChannelEnergy>) {
+        let Some(r) = result else {
+            self.done = true;
+            return;
+        };
+        match self.channels.iter_mut().find(|c| c.channel == r.channel) {
+            Some(c) => c.max_rssi = c.max_rssi.max(r.max_rssi),
+            None => self.channels.push(r),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Channels sampled, in channel order.
+    pub fn into_results(mut self) -> Vec<ChannelEnergy> {
+        self.channels.sort_by_key(|c| c.channel);
+        self.channels
+    }
+}
+
+/// Energy scan in progress or finished, like `SCAN`.
+static ENERGY: Mutex<Option<EnergyCollector>> = Mutex::new(None);
+
+unsafe extern "C" fn on_energy_scan_result(result: *mut esp_idf_sys::otEnergyScanResult, _ctx: *mut c_void) {
+    let sampled = result.as_ref().map(|r| ChannelEnergy {
+        channel: r.mChannel,
+        max_rssi: r.mMaxRssi,
+    });
+    if let Some(scan) = ENERGY.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        scan.on_result(sampled);
+    }
+}
+
+/// Classify a neighbor table entry.
+pub fn neighbor_role(is_child: bool, rx_on_when_idle: bool) -> NeighborRole {
+    match (is_child, rx_on_when_idle) {
+        (false, _) => NeighborRole::Router,
+        (true, true) => NeighborRole::Child,
+        (true, false) => NeighborRole::SleepyChild,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neighbor_role() {
+        assert_eq!(neighbor_role(false, true), NeighborRole::Router);
+        assert_eq!(neighbor_role(true, true), NeighborRole::Child);
+        assert_eq!(neighbor_role(true, false), NeighborRole::SleepyChild);
+    }
+
+    #[test]
+    fn test_scan_collector_assembles_results() {
+        let mut scan = ScanCollector::default();
+        scan.on_result(Some(ScanResult::new(15, 0x1234, -80, b"SmartVent")));
+        scan.on_result(Some(ScanResult::new(25, 0xabcd, -60, b"NeighborNet")));
+        // Same network from a closer router replaces the weaker beacon
+        scan.on_result(Some(ScanResult::new(15, 0x1234, -55, b"SmartVent")));
+        scan.on_result(Some(ScanResult::new(15, 0x1234, -90, b"SmartVent")));
+        assert!(!scan.is_done());
+        scan.on_result(None);
+        assert!(scan.is_done());
+
+        let results = scan.into_results();
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].name(), results[0].channel, results[0].rssi), ("SmartVent", 15, -55));
+        assert_eq!((results[1].name(), results[1].panid), ("NeighborNet", 0xabcd));
+    }
+
+    #[test]
+    fn test_scan_collector_caps_results() {
+        let mut scan = ScanCollector::default();
+        for panid in 0..(MAX_SCAN_RESULTS as u16 + 4) {
+            scan.on_result(Some(ScanResult::new(11, panid, -70, b"net")));
+        }
+        scan.on_result(None);
+        assert_eq!(scan.into_results().len(), MAX_SCAN_RESULTS);
+    }
+
+    #[test]
+    fn test_energy_collector_keeps_peak_per_channel() {
+        let mut scan = EnergyCollector::default();
+        for (channel, max_rssi) in [(20, -90), (11, -70), (20, -60), (20, -80)] {
+            scan.on_result(Some(ChannelEnergy { channel, max_rssi }));
+        }
+        scan.on_result(None);
+        assert!(scan.is_done());
+        let results = scan.into_results();
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].channel, results[0].max_rssi), (11, -70));
+        assert_eq!((results[1].channel, results[1].max_rssi), (20, -60));
+    }
+
+    #[test]
+    fn test_select_dataset() {
+        assert_eq!(select_dataset(false, false), DatasetChoice::Primary);
+        assert_eq!(select_dataset(false, true), DatasetChoice::Primary);
+        assert_eq!(select_dataset(true, false), DatasetChoice::Primary);
+        assert_eq!(select_dataset(true, true), DatasetChoice::Secondary);
+    }
+
+    #[test]
+    fn test_failover_promotes_secondary_on_success() {
+        let mut fo = DatasetFailover::new();
+        assert_eq!(fo.on_join_timeout(true), Some(DatasetChoice::Secondary));
+        assert!(fo.on_attached());
+        // Promoted: the next failover starts from the (new) primary again
+        assert_eq!(fo.on_join_timeout(true), Some(DatasetChoice::Secondary));
+    }
+
+    #[test]
+    fn test_failover_primary_success_does_not_promote() {
+        let mut fo = DatasetFailover::new();
+        assert!(!fo.on_attached());
+    }
+
+    #[test]
+    fn test_failover_without_secondary_keeps_primary() {
+        let mut fo = DatasetFailover::new();
+        assert_eq!(fo.on_join_timeout(false), None);
+        assert!(!fo.on_attached());
+    }
+
+    #[test]
+    fn test_failover_secondary_failure_returns_to_primary() {
+        let mut fo = DatasetFailover::new();
+        assert_eq!(fo.on_join_timeout(true), Some(DatasetChoice::Secondary));
+        assert_eq!(fo.on_join_timeout(true), Some(DatasetChoice::Primary));
+        assert!(!fo.on_attached());
+    }
+
+    #[test]
+    fn test_dataset_push_waits_then_confirms() {
+        let p = DatasetApply::Pending;
+        assert_eq!(p.next(false, 0, 300_000), DatasetApply::Pending);
+        assert_eq!(p.next(false, 299_999, 300_000), DatasetApply::Pending);
+        assert_eq!(p.next(true, 10_000, 300_000), DatasetApply::Confirmed);
+    }
+
+    #[test]
+    fn test_dataset_push_reverts_on_timeout() {
+        let p = DatasetApply::Pending;
+        assert_eq!(p.next(false, 300_000, 300_000), DatasetApply::RevertNeeded);
+        // A late confirmation in the same pass still keeps the new dataset
+        assert_eq!(p.next(true, 300_000, 300_000), DatasetApply::Confirmed);
+    }
+
+    #[test]
+    fn test_dataset_push_outcomes_are_final() {
+        for done in [DatasetApply::Confirmed, DatasetApply::RevertNeeded] {
+            assert_eq!(done.next(false, u64::MAX, 0), done);
+            assert_eq!(done.next(true, 0, 300_000), done);
+        }
+    }
 }